@@ -0,0 +1,149 @@
+//! # Database Benchmarks
+//!
+//! Criterion benchmarks for the hot paths of the query layer: storing a
+//! contact message, listing the inbox with filters, and full-text search.
+//!
+//! The benchmarks write to the database, so run them against a throwaway
+//! PostgreSQL instance, for example:
+//!
+//! ```bash
+//! docker run --rm -d --name dothtml-bench -p 5433:5432 -e POSTGRES_HOST_AUTH_METHOD=trust postgres:16
+//! DATABASE_URL=postgres://postgres@localhost:5433/postgres cargo bench --bench database
+//! ```
+//!
+//! The first run seeds `BENCH_MESSAGES` messages so that listings work on a
+//! realistically sized table; later runs reuse them.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dothtml_backend::database::Database;
+use dothtml_backend::models::MessageListOptions;
+use dothtml_backend::query::FilterExpr;
+use tokio::runtime::Runtime;
+
+/// Number of seeded messages the listings run against.
+const BENCH_MESSAGES: i64 = 5_000;
+
+/// Domain of the seeded senders, used to count them.
+const BENCH_DOMAIN: &str = "bench.example";
+
+const COUNTRIES: [&str; 4] = ["France", "Germany", "US", "Japan"];
+const COMPANIES: [&str; 5] = ["ACME Corp", "Globex", "Initech", "Umbrella", ""];
+const TOPICS: [&str; 4] = ["pricing question", "invoice missing", "partnership offer", "bug report"];
+
+/// Connects to `DATABASE_URL`, creates the schema and seeds the messages.
+async fn setup() -> Database {
+    let db = Database::new().await.expect("DATABASE_URL must point to a benchmark database");
+
+    if let Err(e) = db.create_messages_table().await {
+        match e {
+            sqlx::Error::Database(ref err) if err.code().as_deref() == Some("42P07") => {}
+            _ => panic!("Failed to create the messages table: {}", e),
+        }
+    }
+    db.create_companies_table().await.expect("Failed to create the companies table");
+    db.create_resource_changes_table().await.expect("Failed to create the resource changes table");
+    db.create_message_events_table().await.expect("Failed to create the events table");
+    db.create_outbox_table().await.expect("Failed to create the outbox table");
+    db.create_spam_tables().await.expect("Failed to create the spam tables");
+    db.create_rules_table().await.expect("Failed to create the rules table");
+    db.upgrade_messages_table().await.expect("Failed to upgrade the messages table");
+
+    let seeded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE email LIKE '%@' || $1")
+        .bind(BENCH_DOMAIN)
+        .fetch_one(&db.pool)
+        .await
+        .expect("Failed to count seeded messages");
+
+    for i in seeded..BENCH_MESSAGES {
+        let i = i as usize;
+        db.insert_message(
+            &format!("Sender {}", i),
+            &format!("sender{}@{}", i % 500, BENCH_DOMAIN),
+            COUNTRIES[i % COUNTRIES.len()],
+            "",
+            COMPANIES[i % COMPANIES.len()],
+            &format!("Hello, this is about a {} (#{}).", TOPICS[i % TOPICS.len()], i),
+        )
+        .await
+        .expect("Failed to seed a message");
+    }
+
+    db
+}
+
+fn bench_insert_message(c: &mut Criterion, rt: &Runtime, db: &Database) {
+    c.bench_function("insert_message", |b| {
+        b.to_async(rt).iter(|| async {
+            db.insert_message(
+                "Benchmark",
+                "benchmark@inserts.example",
+                "France",
+                "+33612345678",
+                "ACME Corp",
+                "Hello, I have a question about your pricing.",
+            )
+            .await
+            .expect("Failed to insert a message")
+        })
+    });
+}
+
+fn bench_list_messages(c: &mut Criterion, rt: &Runtime, db: &Database) {
+    let mut group = c.benchmark_group("list_messages");
+
+    let cases = [
+        ("first_page", ""),
+        ("status_filter", "status:pending"),
+        ("combined_filters", "status:pending -country:US company:\"ACME Corp\" after:2020-01-01"),
+        ("sender_filter", "email:sender42@bench.example"),
+    ];
+    for (name, query) in cases {
+        let options = MessageListOptions {
+            filter: FilterExpr::parse(query).expect("Invalid benchmark filter"),
+            ..MessageListOptions::default()
+        };
+        group.bench_function(name, |b| {
+            b.to_async(rt).iter(|| async {
+                db.list_messages(&options).await.expect("Failed to list messages")
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion, rt: &Runtime, db: &Database) {
+    let mut group = c.benchmark_group("search");
+
+    let cases = [
+        ("single_word", "invoice"),
+        ("no_match", "zeppelin"),
+        ("words_and_filters", "partnership offer country:Germany"),
+    ];
+    for (name, query) in cases {
+        let options = MessageListOptions {
+            include_archived: true,
+            filter: FilterExpr::parse(query).expect("Invalid benchmark search"),
+            ..MessageListOptions::default()
+        };
+        group.bench_function(name, |b| {
+            b.to_async(rt).iter(|| async {
+                db.list_messages(&options).await.expect("Failed to search messages")
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to start the Tokio runtime");
+    let db = rt.block_on(setup());
+
+    bench_insert_message(c, &rt, &db);
+    bench_list_messages(c, &rt, &db);
+    bench_search(c, &rt, &db);
+}
+
+criterion_group!(database, benches);
+criterion_main!(database);
@@ -0,0 +1,49 @@
+//! Captures build information for the `version` module: the git commit,
+//! the build time and the enabled cargo features.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Container builds often lack the .git directory and pass the commit instead
+    let commit = env::var("GIT_COMMIT").ok().filter(|commit| !commit.is_empty()).or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|commit| commit.trim().to_string())
+    });
+    println!("cargo:rustc-env=DOTHTML_GIT_COMMIT={}", commit.as_deref().unwrap_or("unknown"));
+
+    // Honour SOURCE_DATE_EPOCH so that reproducible builds get a fixed timestamp
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default());
+    println!("cargo:rustc-env=DOTHTML_BUILD_TIMESTAMP={}", timestamp);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|feature| feature.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=DOTHTML_FEATURES={}", features.join(","));
+
+    // Rebuild when the sources or the checked-out commit change
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+            if let Some(reference) = head.trim().strip_prefix("ref: ") {
+                println!("cargo:rerun-if-changed=.git/{}", reference);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! # HTTP Load Test
+//!
+//! Sends concurrent requests to a running server for a fixed duration and
+//! reports the throughput and latency percentiles of each scenario.
+//!
+//! ```bash
+//! cargo run --release --example load_test -- http://localhost:8080 32 20
+//! ```
+//!
+//! Arguments, all optional: base URL (default `http://localhost:8080`),
+//! number of concurrent clients (default 16) and duration of each scenario
+//! in seconds (default 10).
+//!
+//! The `contact` scenario stores messages, so run it against a throwaway
+//! database and with `CONTACT_RATE_LIMIT_PER_HOUR=0`.
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+/// A request sent repeatedly by every client.
+#[derive(Clone)]
+struct Scenario {
+    name: &'static str,
+    method: reqwest::Method,
+    path: &'static str,
+    body: Option<serde_json::Value>,
+}
+
+/// Outcome of a scenario.
+#[derive(Default)]
+struct Report {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+impl Report {
+    fn percentile(&self, p: f64) -> Duration {
+        let index = ((self.latencies.len() as f64 - 1.0) * p).round() as usize;
+        self.latencies.get(index).copied().unwrap_or_default()
+    }
+}
+
+fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario { name: "inbox", method: reqwest::Method::GET, path: "/inbox", body: None },
+        Scenario {
+            name: "inbox_filtered",
+            method: reqwest::Method::GET,
+            path: "/inbox?q=status:pending%20-country:US",
+            body: None,
+        },
+        Scenario { name: "inbox_search", method: reqwest::Method::GET, path: "/inbox?q=invoice", body: None },
+        Scenario { name: "stats", method: reqwest::Method::GET, path: "/stats", body: None },
+        Scenario {
+            name: "contact",
+            method: reqwest::Method::POST,
+            path: "/contact",
+            body: Some(json!({
+                "name": "Load Test",
+                "email": "load@test.example",
+                "country_region": "France",
+                "company": "ACME Corp",
+                "message": "Hello, I have a question about your pricing."
+            })),
+        },
+    ]
+}
+
+/// Runs one client until `deadline`, recording the latency of every request.
+async fn client(http: reqwest::Client, url: String, scenario: Scenario, deadline: Instant) -> Report {
+    let mut report = Report::default();
+    while Instant::now() < deadline {
+        let mut request = http.request(scenario.method.clone(), &url);
+        if let Some(body) = &scenario.body {
+            request = request.json(body);
+        }
+
+        let start = Instant::now();
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                // Read the body so that the latency covers the whole response
+                match response.bytes().await {
+                    Ok(_) => report.latencies.push(start.elapsed()),
+                    Err(_) => report.errors += 1,
+                }
+            }
+            _ => report.errors += 1,
+        }
+    }
+    report
+}
+
+async fn run(http: &reqwest::Client, base_url: &str, scenario: &Scenario, clients: usize, duration: Duration) -> Report {
+    let deadline = Instant::now() + duration;
+    let url = format!("{}{}", base_url.trim_end_matches('/'), scenario.path);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..clients {
+        tasks.spawn(client(http.clone(), url.clone(), scenario.clone(), deadline));
+    }
+
+    let mut total = Report::default();
+    while let Some(report) = tasks.join_next().await {
+        let report = report.expect("A load test client panicked");
+        total.latencies.extend(report.latencies);
+        total.errors += report.errors;
+    }
+    total.latencies.sort();
+    total
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let base_url = args.get(1).cloned().unwrap_or_else(|| "http://localhost:8080".to_string());
+    let clients = args.get(2).and_then(|value| value.parse().ok()).unwrap_or(16);
+    let duration = Duration::from_secs(args.get(3).and_then(|value| value.parse().ok()).unwrap_or(10));
+
+    let http = reqwest::Client::builder()
+        .pool_max_idle_per_host(clients)
+        .build()
+        .expect("Failed to build the HTTP client");
+
+    println!("{} clients, {}s per scenario against {}", clients, duration.as_secs(), base_url);
+    println!("{:<16} {:>10} {:>8} {:>10} {:>10} {:>10}", "scenario", "req/s", "errors", "p50", "p95", "p99");
+
+    for scenario in scenarios() {
+        let report = run(&http, &base_url, &scenario, clients, duration).await;
+        println!(
+            "{:<16} {:>10.1} {:>8} {:>10.2?} {:>10.2?} {:>10.2?}",
+            scenario.name,
+            report.latencies.len() as f64 / duration.as_secs_f64(),
+            report.errors,
+            report.percentile(0.50),
+            report.percentile(0.95),
+            report.percentile(0.99),
+        );
+    }
+}
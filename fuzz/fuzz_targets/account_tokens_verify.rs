@@ -0,0 +1,13 @@
+//! Magic links and calendar feed tokens of accounts: base64 decoding,
+//! variable-length account name and HMAC.
+
+#![no_main]
+
+use dothtml_backend::sessions::{AccountTokenPurpose, AccountTokens};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|token: &str| {
+    let tokens = AccountTokens::new("fuzz");
+    let _ = tokens.verify(AccountTokenPurpose::MagicLink, token);
+    let _ = tokens.verify(AccountTokenPurpose::CalendarFeed, token);
+});
@@ -0,0 +1,14 @@
+//! Session JWTs, from the `Authorization` header or the session cookie:
+//! splitting, base64 decoding, JSON header and claims, and signature.
+
+#![no_main]
+
+use chrono::Utc;
+use dothtml_backend::jwt::{self, JwtKey};
+use ed25519_dalek::SigningKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|token: &str| {
+    let key = JwtKey { kid: "k1".into(), signing_key: SigningKey::from_bytes(&[7; 32]), created_at: Utc::now(), retired_at: None };
+    let _ = jwt::verify(std::slice::from_ref(&key), token);
+});
@@ -0,0 +1,13 @@
+//! Follow-up and rating links sent to senders: base64 decoding, length
+//! checks and HMAC.
+
+#![no_main]
+
+use dothtml_backend::tokens::{SenderTokens, TokenPurpose};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|token: &str| {
+    let tokens = SenderTokens::new("fuzz");
+    let _ = tokens.verify(TokenPurpose::Followup, token);
+    let _ = tokens.verify(TokenPurpose::Rating, token);
+});
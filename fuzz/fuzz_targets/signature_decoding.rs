@@ -0,0 +1,18 @@
+//! Signature headers of inbound requests: base64 signatures of form
+//! providers, hex signatures of issue tracker callbacks and the
+//! comma-separated `v1=` list of webhook signatures.
+
+#![no_main]
+
+use dothtml_backend::escalation::verify_callback;
+use dothtml_backend::ingest::FormProvider;
+use dothtml_backend::webhooks::{verify_signature, DEFAULT_TOLERANCE_SECS};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (&str, &str, &[u8])| {
+    let (signature, timestamp, body) = input;
+    let _ = FormProvider::Tally.verify("fuzz", body, signature);
+    let _ = FormProvider::Typeform.verify("fuzz", body, signature);
+    let _ = verify_callback("fuzz", body, signature);
+    let _ = verify_signature("fuzz", timestamp, body, signature, 0, DEFAULT_TOLERANCE_SECS);
+});
@@ -0,0 +1,189 @@
+//! # Acknowledgements
+//!
+//! Email confirming to the sender of a contact form submission that their
+//! message was received, with the follow-up link letting them add
+//! information to it (`POST /contact/followup/{token}`, see the `tokens`
+//! module).
+//!
+//! It is sent when `FOLLOWUP_PAGE_URL` and `SENDER_TOKEN_SECRET` are set,
+//! and queued in the transaction storing the message (see the `outbox`
+//! module), so a stored message is always acknowledged and a failed one
+//! never is. The link points to `FOLLOWUP_PAGE_URL`, e.g.
+//! `https://dotshell.eu/followup?token=<token>`, a page of the website
+//! posting the added information with the token.
+//!
+//! No acknowledgement goes to senders on the do-not-contact list (see the
+//! `suppression` module), nor to quarantined senders (see
+//! `GREYLIST_NEW_SENDERS`): the contact form must not let anyone send
+//! emails to an address they do not own.
+//!
+//! The email is the `auto_reply` template named `acknowledgement` (see the
+//! `templates` module), rendered with the follow-up link as
+//! `{{followup_link}}`, or a built-in email when there is no such template
+//! or it fails to render.
+//!
+//! When `OFFICE_HOURS` is set and the message arrives while the office is
+//! closed (see the `office_hours` module), the template named
+//! `acknowledgement_closed` is used instead, with the time the office
+//! opens again as `{{opens_at}}`, e.g. "Monday, October 19 at 09:00
+//! CEST". The built-in email says so too.
+
+use chrono::{DateTime, Utc};
+use maud::html;
+use sqlx::PgConnection;
+
+use crate::database::Database;
+use crate::models::Message;
+use crate::office_hours::OfficeHours;
+use crate::templates::{self, RenderedEmail, TemplateKind, TemplateRenderer};
+use crate::tokens::{SenderTokens, TokenPurpose};
+
+/// Name of the `auto_reply` template replacing the built-in acknowledgement.
+pub const ACKNOWLEDGEMENT_TEMPLATE: &str = "acknowledgement";
+
+/// Name of the `auto_reply` template replacing the built-in acknowledgement
+/// of messages received while the office is closed.
+pub const CLOSED_ACKNOWLEDGEMENT_TEMPLATE: &str = "acknowledgement_closed";
+
+/// Settings of the acknowledgement of a submission.
+///
+/// # Fields
+///
+/// * `secret` - `SENDER_TOKEN_SECRET`, signing the follow-up token
+/// * `page_url` - `FOLLOWUP_PAGE_URL`, the page the follow-up link points to
+/// * `expires_at` - When the follow-up link stops working
+/// * `office_hours` - `OFFICE_HOURS`, if set, telling whether the office is closed
+#[derive(Debug, Clone, Copy)]
+pub struct Acknowledgement<'a> {
+    pub secret: &'a str,
+    pub page_url: &'a str,
+    pub expires_at: DateTime<Utc>,
+    pub office_hours: Option<&'a OfficeHours>,
+}
+
+/// Returns the link of `FOLLOWUP_PAGE_URL` carrying `token`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::acknowledgements::followup_link;
+///
+/// assert_eq!(followup_link("https://dotshell.eu/followup", "abc"), "https://dotshell.eu/followup?token=abc");
+/// assert_eq!(followup_link("https://dotshell.eu/?page=followup", "abc"), "https://dotshell.eu/?page=followup&token=abc");
+/// ```
+pub fn followup_link(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", url, separator, token)
+}
+
+/// Builds the built-in acknowledgement of `message`, with its follow-up
+/// link, saying when the office opens again if it is closed.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{TimeZone, Utc};
+/// use dothtml_backend::acknowledgements::acknowledgement_email;
+/// use dothtml_backend::models::Message;
+/// use uuid::Uuid;
+///
+/// let message = Message {
+///     id: Uuid::new_v4(),
+///     name: "Jane".to_string(),
+///     email: "jane@example.com".to_string(),
+///     country_region: String::new(),
+///     phone_number: String::new(),
+///     company: String::new(),
+///     message: "Hello".to_string(),
+///     created_at: Utc::now(),
+///     assigned_to: None,
+///     assigned_at: None,
+///     status: "pending".to_string(),
+///     priority: "normal".to_string(),
+///     merged_into: None,
+///     resolved_at: None,
+///     tags: vec![],
+///     company_id: None,
+///     archived: false,
+///     deleted_at: None,
+///     opened_by: None,
+///     opened_at: None,
+///     spam_score: None,
+///     category: None,
+///     form: "contact".to_string(),
+///     body_ref: None,
+/// };
+/// let expires_at = Utc.with_ymd_and_hms(2026, 11, 16, 0, 0, 0).unwrap();
+/// let email = acknowledgement_email(&message, "https://dotshell.eu/followup?token=abc", expires_at, Some("Monday, October 19 at 09:00 CEST"));
+/// assert!(email.text.contains("We are currently closed, expect a reply from Monday, October 19 at 09:00 CEST."));
+/// assert!(email.html.contains(r#"<a href="https://dotshell.eu/followup?token=abc">"#));
+/// ```
+pub fn acknowledgement_email(
+    message: &Message, link: &str, expires_at: DateTime<Utc>, opens_at: Option<&str>
+) -> RenderedEmail {
+    let until = expires_at.format("%B %-d, %Y");
+    let answer = match opens_at {
+        Some(opens_at) => format!("We are currently closed, expect a reply from {}.", opens_at),
+        None => "We will answer it as soon as possible.".to_string(),
+    };
+    let text = format!(
+        "Hello {},\n\nWe received your message. {}\n\nTo add information to it, use this link until {}:\n{}",
+        message.name, answer, until, link
+    );
+    let html = html! {
+        p { "Hello " (message.name) "," }
+        p { "We received your message. " (answer) }
+        p { "To add information to it, use " a href=(link) { "this link" } " until " (until) "." }
+    };
+    RenderedEmail { subject: "We received your message".to_string(), html: html.into_string(), text }
+}
+
+/// Database operations for acknowledgements.
+impl Database {
+    /// Queues the acknowledgement of `message`, unless its sender is
+    /// quarantined or on the do-not-contact list.
+    ///
+    /// Takes a connection so that it runs in the transaction storing the
+    /// message.
+    pub(crate) async fn enqueue_acknowledgement(
+        conn: &mut PgConnection,
+        message: &Message,
+        acknowledgement: &Acknowledgement<'_>,
+    ) -> Result<(), sqlx::Error> {
+        if message.status == "quarantine" || Self::is_suppressed(&mut *conn, &message.email).await? {
+            return Ok(());
+        }
+
+        let token = SenderTokens::new(acknowledgement.secret)
+            .issue(TokenPurpose::Followup, message.id, acknowledgement.expires_at);
+        let link = followup_link(acknowledgement.page_url, &token);
+
+        // Messages received while the office is closed are told when it opens again
+        let opens_at = acknowledgement
+            .office_hours
+            .filter(|office_hours| !office_hours.is_open(message.created_at))
+            .map(|office_hours| office_hours.format_local(office_hours.next_opening(message.created_at)));
+        let name = match opens_at {
+            Some(_) => CLOSED_ACKNOWLEDGEMENT_TEMPLATE,
+            None => ACKNOWLEDGEMENT_TEMPLATE,
+        };
+
+        let template = Self::find_template(&mut *conn, name, TemplateKind::AutoReply).await?;
+        let rendered = template.and_then(|template| {
+            let mut data = templates::template_data(message, None, None);
+            data["followup_link"] = link.clone().into();
+            if let Some(opens_at) = &opens_at {
+                data["opens_at"] = opens_at.clone().into();
+            }
+            TemplateRenderer::new()
+                .render(&template.subject, &template.html, &template.text, &data)
+                .map_err(|e| eprintln!("Failed to render the {} template: {}", template.name, e))
+                .ok()
+        });
+        let email = rendered.unwrap_or_else(|| {
+            acknowledgement_email(message, &link, acknowledgement.expires_at, opens_at.as_deref())
+        });
+
+        Self::enqueue_email(&mut *conn, &message.email, &format!("acknowledgement:{}", message.id), email).await
+    }
+}
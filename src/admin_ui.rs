@@ -0,0 +1,93 @@
+//! # Admin UI
+//!
+//! Serves a built single-page backoffice UI at `/app`, enabled with the
+//! `admin-ui` cargo feature and `ADMIN_UI_PATH`, so that small deployments
+//! get a usable inbox without deploying a separate frontend.
+//!
+//! `ADMIN_UI_PATH` points to the output directory of the frontend build,
+//! which must contain an `index.html`. Files are served as they are, and
+//! any other path without a file extension returns `index.html` so that
+//! the UI's client-side router can handle it.
+//!
+//! ## Caching
+//!
+//! - Files under `/app/assets/` have content-hashed names and are cached
+//!   for a year (`public, max-age=31536000, immutable`)
+//! - Every other file, including `index.html`, is revalidated on each load
+//!   (`no-cache`) so that a new deployment is picked up immediately
+
+use std::path::{Path, PathBuf};
+
+use actix_files::{Files, NamedFile};
+use actix_web::{
+    body::MessageBody,
+    dev::{fn_service, ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    middleware::{self, Next},
+    web, HttpResponse,
+};
+
+/// Path prefix the UI is served under.
+pub const MOUNT_PATH: &str = "/app";
+
+/// Returns a route configuration serving the UI built in `dir`.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::App;
+/// use dothtml_backend::admin_ui;
+///
+/// let app = App::new().configure(admin_ui::config("./admin-ui/dist"));
+/// ```
+pub fn config(dir: impl AsRef<Path>) -> impl FnOnce(&mut web::ServiceConfig) {
+    let dir = dir.as_ref().to_path_buf();
+    move |cfg| {
+        let index = dir.join("index.html");
+        cfg.service(
+            web::scope(MOUNT_PATH)
+                .wrap(middleware::from_fn(cache_headers))
+                .service(
+                    Files::new("", dir)
+                        .index_file("index.html")
+                        .use_etag(true)
+                        .use_last_modified(true)
+                        .default_handler(fn_service(move |req: ServiceRequest| {
+                            spa_fallback(req, index.clone())
+                        })),
+                ),
+        );
+    }
+}
+
+/// Answers paths that match no file with `index.html`, except for paths
+/// that look like files, which get a 404 instead of an HTML page.
+async fn spa_fallback(req: ServiceRequest, index: PathBuf) -> Result<ServiceResponse, actix_web::Error> {
+    let (req, _) = req.into_parts();
+
+    let last_segment = req.path().rsplit('/').next().unwrap_or_default();
+    if last_segment.contains('.') {
+        return Ok(ServiceResponse::new(req, HttpResponse::NotFound().body("File not found")));
+    }
+
+    let res = NamedFile::open_async(index).await?.into_response(&req);
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// Sets the `Cache-Control` header of successful responses.
+async fn cache_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let immutable = req.path().starts_with(&format!("{}/assets/", MOUNT_PATH));
+
+    let mut res = next.call(req).await?;
+    if res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED {
+        let value = if immutable { "public, max-age=31536000, immutable" } else { "no-cache" };
+        res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(value));
+    }
+    Ok(res)
+}
@@ -0,0 +1,186 @@
+//! # Anonymized Exports
+//!
+//! Builds the dataset served by `GET /admin/export/anonymized`, shared
+//! with analytics contractors: one line per message with its workflow data
+//! (status, priority, tags, country, timestamps), and without anything
+//! identifying the sender or the agents.
+//!
+//! - Names, phone numbers and message bodies are dropped.
+//! - Emails, companies, agents and message IDs are replaced by pseudonyms,
+//!   an HMAC-SHA256 of the value keyed with `ANONYMIZATION_KEY`.
+//!
+//! Pseudonyms are stable: the same sender gets the same pseudonym in every
+//! export, so datasets can be joined over time, and they cannot be reversed
+//! or recomputed without the key. Changing the key gives everyone new
+//! pseudonyms.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+
+/// Bytes of the HMAC kept in a pseudonym.
+const PSEUDONYM_BYTES: usize = 16;
+
+/// Replaces identifying values by stable keyed pseudonyms.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::anonymize::Pseudonymizer;
+///
+/// let pseudonymizer = Pseudonymizer::new("secret");
+/// let sender = pseudonymizer.pseudonym("sender", "John.Doe@example.com");
+/// assert!(sender.starts_with("sender_"));
+/// assert_eq!(sender, pseudonymizer.pseudonym("sender", "john.doe@example.com "));
+/// assert_ne!(sender, Pseudonymizer::new("other").pseudonym("sender", "john.doe@example.com"));
+/// ```
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    mac: Hmac<Sha256>,
+}
+
+impl Pseudonymizer {
+    /// Creates a pseudonymizer keyed with `key`.
+    pub fn new(key: &str) -> Self {
+        Pseudonymizer {
+            mac: Hmac::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// Returns the pseudonym of `value`, prefixed with `kind`.
+    ///
+    /// Values are compared trimmed and case-insensitively, and the kind is
+    /// part of the hash, so an agent and a sender with the same name get
+    /// unrelated pseudonyms.
+    pub fn pseudonym(&self, kind: &str, value: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(value.trim().to_lowercase().as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let mut pseudonym = format!("{}_", kind);
+        for byte in &digest[..PSEUDONYM_BYTES] {
+            pseudonym.push_str(&format!("{:02x}", byte));
+        }
+        pseudonym
+    }
+}
+
+/// A message without identifying data.
+///
+/// # Fields
+///
+/// * `id` - Pseudonym of the message ID
+/// * `sender` - Pseudonym of the sender's email
+/// * `company` - Pseudonym of the company, `None` if the message has none
+/// * `country_region` - Country or region of the sender
+/// * `status` - Current status of the message
+/// * `priority` - Priority of the message
+/// * `tags` - Tags of the message
+/// * `assignee` - Pseudonym of the agent the message is assigned to
+/// * `merged_into` - Pseudonym of the message this one was merged into
+/// * `archived` - Whether the message is archived
+/// * `created_at` - Timestamp when the message was received
+/// * `assigned_at` - Timestamp of the current assignment
+/// * `opened_at` - Timestamp of the first opening
+/// * `resolved_at` - Timestamp when the message was resolved
+#[derive(Debug, Clone, Serialize)]
+pub struct AnonymizedMessage {
+    pub id: String,
+    pub sender: String,
+    pub company: Option<String>,
+    pub country_region: String,
+    pub status: String,
+    pub priority: String,
+    pub tags: Vec<String>,
+    pub assignee: Option<String>,
+    pub merged_into: Option<String>,
+    pub archived: bool,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub assigned_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub opened_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Database operations for anonymized exports.
+impl Database {
+    /// Returns every message outside the trash without identifying data,
+    /// oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `pseudonymizer` - Pseudonymizer keyed with `ANONYMIZATION_KEY`
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::anonymize::Pseudonymizer;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let messages = db.anonymized_messages(&Pseudonymizer::new("secret")).await?;
+    ///     println!("{} messages", messages.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn anonymized_messages(&self, pseudonymizer: &Pseudonymizer) -> Result<Vec<AnonymizedMessage>, sqlx::Error> {
+        // Bodies and contact details are not even read
+        let rows = sqlx::query(r#"
+            SELECT
+                id, email, company, company_id, country_region, status, priority, tags, assigned_to,
+                merged_into, archived, created_at, assigned_at, opened_at, resolved_at
+            FROM messages
+            WHERE deleted_at IS NULL
+            ORDER BY created_at, id
+        "#)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let message_pseudonym = |id: Uuid| pseudonymizer.pseudonym("message", &id.to_string());
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let company: String = row.get("company");
+                let company_id: Option<Uuid> = row.get("company_id");
+                AnonymizedMessage {
+                    id: message_pseudonym(row.get("id")),
+                    sender: pseudonymizer.pseudonym("sender", row.get("email")),
+                    company: match company_id {
+                        Some(id) => Some(pseudonymizer.pseudonym("company", &id.to_string())),
+                        None if !company.trim().is_empty() => Some(pseudonymizer.pseudonym("company", &company)),
+                        None => None,
+                    },
+                    country_region: row.get("country_region"),
+                    status: row.get("status"),
+                    priority: row.get("priority"),
+                    tags: row.get("tags"),
+                    assignee: row
+                        .get::<Option<String>, _>("assigned_to")
+                        .map(|agent| pseudonymizer.pseudonym("agent", &agent)),
+                    merged_into: row.get::<Option<Uuid>, _>("merged_into").map(message_pseudonym),
+                    archived: row.get("archived"),
+                    created_at: row.get("created_at"),
+                    assigned_at: row.get("assigned_at"),
+                    opened_at: row.get("opened_at"),
+                    resolved_at: row.get("resolved_at"),
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,433 @@
+//! # Scoped API Tokens
+//!
+//! Long-lived tokens for automation, such as a reporting cron, that may
+//! only call a few admin endpoints instead of holding the admin token.
+//! Admins mint them with `POST /admin/tokens`, list them with
+//! `GET /admin/tokens` and revoke them with `DELETE /admin/tokens/{id}`.
+//!
+//! A token is only shown when it is minted: the `api_tokens` table keeps a
+//! SHA-256 hash of it, with the time it was last used. It is sent as
+//! `Authorization: Bearer dht_...`.
+//!
+//! ## Scopes
+//!
+//! - [`TokenScope::StatsRead`] (`stats:read`) - `GET /admin/stats/satisfaction` and `GET /status`
+//! - [`TokenScope::ExportRead`] (`export:read`) - `GET /admin/export/anonymized`
+//! - [`TokenScope::SuppressionsRead`] (`suppressions:read`) - `GET /admin/suppressions`
+//! - [`TokenScope::SuppressionsWrite`] (`suppressions:write`) - `POST /admin/suppressions` and
+//!   `DELETE /admin/suppressions/{email}`
+//! - [`TokenScope::MetricsRead`] (`metrics:read`) - `GET /metrics`, for a Prometheus scraper
+//! - [`TokenScope::IntegrationsRead`] (`integrations:read`) - `GET /integrations/...`, for no-code
+//!   automations (see the `integrations` module)
+//! - [`TokenScope::IntegrationsWrite`] (`integrations:write`) - `POST /integrations/...`
+//! - [`TokenScope::FeedRead`] (`feed:read`) - `GET /inbox/feed.atom`, for a feed reader
+//!
+//! Feed readers can rarely send headers, so `feed:read` tokens may also be
+//! given as a `?token=` query parameter of the feed URL.
+//!
+//! Routes opt in by wrapping themselves with [`authorize`] and their scope.
+//! A request carrying a token with that scope passes the `auth::Admin`
+//! guard; any other route still requires the admin token.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::auth_events::{AuthAudit, AuthFailure};
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+
+/// Prefix of every API token, telling them apart from the admin token and
+/// sessions.
+pub const TOKEN_PREFIX: &str = "dht_";
+
+/// What an API token allows.
+///
+/// * `StatsRead` - Read the satisfaction statistics and the status page
+/// * `ExportRead` - Download the anonymized export
+/// * `SuppressionsRead` - List the do-not-contact list
+/// * `SuppressionsWrite` - Add and remove addresses of the do-not-contact list
+/// * `MetricsRead` - Scrape the pipeline metrics
+/// * `IntegrationsRead` - Poll and look up messages through the integration API
+/// * `IntegrationsWrite` - Create and update messages through the integration API
+/// * `FeedRead` - Read the Atom feed of new messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TokenScope {
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "export:read")]
+    ExportRead,
+    #[serde(rename = "suppressions:read")]
+    SuppressionsRead,
+    #[serde(rename = "suppressions:write")]
+    SuppressionsWrite,
+    #[serde(rename = "metrics:read")]
+    MetricsRead,
+    #[serde(rename = "integrations:read")]
+    IntegrationsRead,
+    #[serde(rename = "integrations:write")]
+    IntegrationsWrite,
+    #[serde(rename = "feed:read")]
+    FeedRead,
+}
+
+impl TokenScope {
+    /// Returns the name of the scope, as stored in the `scopes` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::StatsRead => "stats:read",
+            TokenScope::ExportRead => "export:read",
+            TokenScope::SuppressionsRead => "suppressions:read",
+            TokenScope::SuppressionsWrite => "suppressions:write",
+            TokenScope::MetricsRead => "metrics:read",
+            TokenScope::IntegrationsRead => "integrations:read",
+            TokenScope::IntegrationsWrite => "integrations:write",
+            TokenScope::FeedRead => "feed:read",
+        }
+    }
+
+    /// Parses a scope name.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "stats:read" => Some(TokenScope::StatsRead),
+            "export:read" => Some(TokenScope::ExportRead),
+            "suppressions:read" => Some(TokenScope::SuppressionsRead),
+            "suppressions:write" => Some(TokenScope::SuppressionsWrite),
+            "metrics:read" => Some(TokenScope::MetricsRead),
+            "integrations:read" => Some(TokenScope::IntegrationsRead),
+            "integrations:write" => Some(TokenScope::IntegrationsWrite),
+            "feed:read" => Some(TokenScope::FeedRead),
+            _ => None,
+        }
+    }
+}
+
+/// An API token, without its secret.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the token
+/// * `name` - What the token is for, such as `reporting cron`
+/// * `scopes` - What the token allows
+/// * `created_at` - When the token was minted
+/// * `expires_at` - When the token stops working, `None` if it does not expire
+/// * `last_used_at` - When the token last authorized a request, if it ever did
+/// * `revoked_at` - When the token was revoked, if it was
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Column list selected for every `ApiToken` row.
+const API_TOKEN_COLUMNS: &str = "id, name, scopes, created_at, expires_at, last_used_at, revoked_at";
+
+fn api_token_from_row(row: &PgRow) -> ApiToken {
+    let scopes: Vec<String> = row.get("scopes");
+    ApiToken {
+        id: row.get("id"),
+        name: row.get("name"),
+        // Written by this module, an unknown scope can only come from a manual edit
+        scopes: scopes.iter().filter_map(|scope| TokenScope::parse(scope)).collect(),
+        created_at: row.get("created_at"),
+        expires_at: row.get("expires_at"),
+        last_used_at: row.get("last_used_at"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+/// Returns a new random token.
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Returns the hash of `token` stored in the `token_hash` column.
+///
+/// Tokens are random, so a fast hash is enough: nothing can be guessed
+/// from it.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::api_tokens::{generate_token, hash_token};
+///
+/// let token = generate_token();
+/// assert!(token.starts_with("dht_"));
+/// assert_eq!(hash_token(&token), hash_token(&token));
+/// assert_ne!(hash_token(&token), hash_token(&generate_token()));
+/// ```
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Proof, stored in the request extensions by [`authorize`], that the
+/// request carries an API token allowing the route's scope.
+///
+/// # Fields
+///
+/// * `token_id` - Identifier of the token
+/// * `scope` - Scope of the route the token was checked against
+#[derive(Debug, Clone, Copy)]
+pub struct TokenGrant {
+    pub token_id: Uuid,
+    pub scope: TokenScope,
+}
+
+/// Middleware letting the API tokens with `scope` call the route.
+///
+/// The token is read from the `Authorization: Bearer` header, or for the
+/// `feed:read` scope from the `?token=` query parameter too.
+///
+/// Requests without an API token pass through untouched, for the route's
+/// guard to check the admin token. An unknown, revoked or expired token is
+/// recorded as a failed authentication and answered with 401 Unauthorized,
+/// and a token without `scope` with 403 Forbidden.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{middleware, web, App, HttpResponse};
+/// use dothtml_backend::api_tokens::{self, TokenScope};
+///
+/// let app = App::new().route(
+///     "/admin/stats/satisfaction",
+///     web::get()
+///         .to(HttpResponse::Ok)
+///         .wrap(middleware::from_fn(|req, next| api_tokens::authorize(TokenScope::StatsRead, req, next))),
+/// );
+/// ```
+pub async fn authorize<B: MessageBody>(
+    scope: TokenScope,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| token.starts_with(TOKEN_PREFIX))
+        .map(str::to_string)
+        .or_else(|| match scope {
+            TokenScope::FeedRead => query_token(req.query_string()),
+            _ => None,
+        });
+    let (Some(token), Some(db)) = (token, req.app_data::<web::Data<Database>>().cloned()) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let audit = AuthAudit::from_request(req.request());
+    if let Err(e) = audit.check_lockout().await {
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    let rejection = match db.use_api_token(&token).await {
+        Ok(Some(api_token)) if api_token.scopes.contains(&scope) => {
+            req.extensions_mut().insert(TokenGrant { token_id: api_token.id, scope });
+            return Ok(next.call(req).await?.map_into_left_body());
+        }
+        Ok(Some(_)) => HttpResponse::Forbidden().body(format!("The API token lacks the {} scope", scope.as_str())),
+        Ok(None) => {
+            audit.record(None, AuthFailure::InvalidApiToken).await;
+            HttpResponse::Unauthorized().body("Invalid, expired or revoked API token")
+        }
+        Err(_) => HttpResponse::InternalServerError().body("Failed to check the API token"),
+    };
+    Ok(req.into_response(rejection).map_into_right_body())
+}
+
+/// Returns the API token given as the `?token=` query parameter.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::api_tokens::query_token;
+///
+/// assert_eq!(query_token("limit=5&token=dht_abc").as_deref(), Some("dht_abc"));
+/// assert_eq!(query_token("token=secret"), None);
+/// ```
+pub fn query_token(query: &str) -> Option<String> {
+    web::Query::<std::collections::HashMap<String, String>>::from_query(query)
+        .ok()?
+        .into_inner()
+        .remove("token")
+        .filter(|token| token.starts_with(TOKEN_PREFIX))
+}
+
+/// Database operations for the API tokens.
+impl Database {
+    /// Creates the 'api_tokens' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_api_tokens_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id UUID PRIMARY KEY,
+                name TEXT NOT NULL,
+                scopes TEXT[] NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                expires_at TIMESTAMPTZ,
+                last_used_at TIMESTAMPTZ,
+                revoked_at TIMESTAMPTZ
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stores a new API token.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - What the token is for
+    /// * `scopes` - What the token allows
+    /// * `expires_at` - When the token stops working, `None` for never
+    ///
+    /// # Returns
+    ///
+    /// Returns the stored token and its secret, which cannot be read again.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::api_tokens::TokenScope;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let (token, secret) = db.create_api_token("reporting cron", &[TokenScope::StatsRead], None).await?;
+    ///     println!("Token {} is {}", token.id, secret);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_api_token(
+        &self, name: &str, scopes: &[TokenScope], expires_at: Option<DateTime<Utc>>
+    ) -> Result<(ApiToken, String), sqlx::Error> {
+        let secret = generate_token();
+        let scopes: Vec<&str> = scopes.iter().map(TokenScope::as_str).collect();
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO api_tokens (id, name, scopes, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {API_TOKEN_COLUMNS}
+        "#))
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(&scopes)
+        .bind(hash_token(&secret))
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((api_token_from_row(&row), secret))
+    }
+
+    /// Lists a page of the API tokens, including revoked ones, most recent
+    /// first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_api_tokens(&self, page: &PageRequest) -> Result<Paginated<ApiToken>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {API_TOKEN_COLUMNS}, COUNT(*) OVER () AS total FROM api_tokens \
+             ORDER BY created_at DESC, id LIMIT $1 OFFSET $2"
+        ))
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, api_token_from_row))
+    }
+
+    /// Fetches an API token, `None` if there is no such token.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn get_api_token(&self, id: Uuid) -> Result<Option<ApiToken>, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {API_TOKEN_COLUMNS} FROM api_tokens WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(api_token_from_row))
+    }
+
+    /// Looks up a usable API token by its secret, recording that it was used.
+    ///
+    /// # Returns
+    ///
+    /// Returns the token, `None` if it is unknown, revoked or expired.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn use_api_token(&self, secret: &str) -> Result<Option<ApiToken>, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE api_tokens SET last_used_at = NOW()
+            WHERE token_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING {API_TOKEN_COLUMNS}
+        "#))
+        .bind(hash_token(secret))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(api_token_from_row))
+    }
+
+    /// Revokes an API token; it is kept for the record.
+    ///
+    /// # Returns
+    ///
+    /// Returns the revoked token, `None` if there is no such token.
+    /// Revoking a token twice keeps the time of the first revocation.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn revoke_api_token(&self, id: Uuid) -> Result<Option<ApiToken>, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE api_tokens SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1
+            RETURNING {API_TOKEN_COLUMNS}
+        "#))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(api_token_from_row))
+    }
+}
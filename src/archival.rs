@@ -0,0 +1,456 @@
+//! # Archival Export
+//!
+//! Moves messages resolved long ago out of PostgreSQL into the blob store
+//! (see the `storage` module), usually an S3-compatible bucket, so that the
+//! database only keeps the messages still worth searching. This is unlike
+//! the `archived` flag, which only hides messages from the default inbox.
+//!
+//! When `ARCHIVE_EXPORT_AFTER_MONTHS` is set, a daily job exports the
+//! messages resolved more than that many months ago, by batches of
+//! [`ARCHIVE_BATCH_SIZE`]. Each batch becomes one gzip-compressed NDJSON
+//! file under `archives/`, one message per line with its full body and its
+//! assignment history, and a manifest row in the `archives` table. The
+//! messages are deleted locally only once the file is stored, and a
+//! `deleted` event carrying the archive ID is recorded for each of them.
+//!
+//! An archive is restored with `dothtml-backend restore-archive <id>`,
+//! which inserts back the messages that do not exist anymore. The file is
+//! kept in the store, so restoring twice is harmless.
+
+use std::io::{self, BufRead, BufReader, Write};
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::caching::RESOURCE_COMPANIES;
+use crate::database::Database;
+use crate::events::MessageEventKind;
+use crate::storage::BlobStore;
+
+/// Maximum number of messages exported to one archive file.
+pub const ARCHIVE_BATCH_SIZE: i64 = 1000;
+
+/// Key of the assignment history added to each exported message.
+const HISTORY_FIELD: &str = "assignment_history";
+
+/// Returns the blob store key of an archive file.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::archival::archive_key;
+/// use uuid::Uuid;
+///
+/// let id: Uuid = "123e4567-e89b-12d3-a456-426614174000".parse().unwrap();
+/// assert_eq!(archive_key(id), "archives/123e4567-e89b-12d3-a456-426614174000.ndjson.gz");
+/// ```
+pub fn archive_key(id: Uuid) -> String {
+    format!("archives/{}.ndjson.gz", id)
+}
+
+/// Manifest of an exported archive.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the archive
+/// * `key` - Key of the archive file in the blob store
+/// * `message_count` - Number of messages in the file
+/// * `first_created_at` - Creation time of the oldest message in the file
+/// * `last_created_at` - Creation time of the newest message in the file
+/// * `size_bytes` - Size of the compressed file
+/// * `created_at` - Timestamp when the archive was exported
+/// * `restored_at` - Timestamp of the last restore, if any
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveManifest {
+    pub id: Uuid,
+    pub key: String,
+    pub message_count: i64,
+    #[serde(with = "crate::timestamp")]
+    pub first_created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub last_created_at: DateTime<Utc>,
+    pub size_bytes: i64,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, with = "crate::timestamp::option")]
+    pub restored_at: Option<DateTime<Utc>>,
+}
+
+fn manifest_from_row(row: &PgRow) -> ArchiveManifest {
+    ArchiveManifest {
+        id: row.get("id"),
+        key: row.get("key"),
+        message_count: row.get("message_count"),
+        first_created_at: row.get("first_created_at"),
+        last_created_at: row.get("last_created_at"),
+        size_bytes: row.get("size_bytes"),
+        created_at: row.get("created_at"),
+        restored_at: row.get("restored_at"),
+    }
+}
+
+/// Compresses messages into a gzip NDJSON file, one message per line.
+fn encode_archive(messages: &[Value]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for message in messages {
+        serde_json::to_writer(&mut encoder, message)?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()
+}
+
+/// Reads back the messages of a file written by [`encode_archive`].
+fn decode_archive(data: &[u8]) -> io::Result<Vec<Value>> {
+    BufReader::new(GzDecoder::new(data))
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Database operations for archival exports.
+impl Database {
+    /// Creates the 'archives' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_archives_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_archives_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS archives (
+                id UUID PRIMARY KEY,
+                key TEXT NOT NULL,
+                message_count BIGINT NOT NULL,
+                first_created_at TIMESTAMPTZ NOT NULL,
+                last_created_at TIMESTAMPTZ NOT NULL,
+                size_bytes BIGINT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                restored_at TIMESTAMPTZ
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the manifests of all the archives, newest first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_archives(&self) -> Result<Vec<ArchiveManifest>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM archives ORDER BY created_at DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(manifest_from_row).collect())
+    }
+
+    /// Exports the messages resolved more than `after_months` months ago to
+    /// archive files in the blob store, then deletes them from the database.
+    ///
+    /// Trashed messages are left to the trash purge.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_months` - Number of months a message stays resolved in the database
+    ///
+    /// # Returns
+    ///
+    /// Returns the manifests of the archives written.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - No blob store is configured
+    /// - The blob store fails to read an overflowed body or to store a file
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::storage::LocalBlobStore;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?
+    ///         .with_blob_store(Arc::new(LocalBlobStore::new("./blobs")), 64 * 1024);
+    ///     for archive in db.export_resolved_messages(12).await? {
+    ///         println!("Exported {} messages to {}", archive.message_count, archive.key);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn export_resolved_messages(&self, after_months: u32) -> Result<Vec<ArchiveManifest>, sqlx::Error> {
+        let Some(store) = self.blob_store.clone() else {
+            return Err(sqlx::Error::Configuration("archival exports require a blob store".into()));
+        };
+
+        let mut archives = Vec::new();
+        while let Some(archive) = self.export_archive_batch(store.as_ref(), after_months).await? {
+            let full = archive.message_count >= ARCHIVE_BATCH_SIZE;
+            archives.push(archive);
+            if !full {
+                break;
+            }
+        }
+        Ok(archives)
+    }
+
+    /// Exports one batch of messages, returning `None` when none is due.
+    async fn export_archive_batch(
+        &self,
+        store: &dyn BlobStore,
+        after_months: u32,
+    ) -> Result<Option<ArchiveManifest>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT
+                m.id,
+                m.created_at,
+                m.body_ref,
+                to_jsonb(m) AS message,
+                COALESCE(
+                    (SELECT jsonb_agg(to_jsonb(h) - 'id' ORDER BY h.id) FROM assignment_history h WHERE h.message_id = m.id),
+                    '[]'
+                ) AS history
+            FROM messages m
+            WHERE m.status = 'resolved'
+              AND m.deleted_at IS NULL
+              AND m.resolved_at < NOW() - make_interval(months => $1)
+            ORDER BY m.created_at, m.id
+            LIMIT $2
+        "#)
+        .bind(after_months as i32)
+        .bind(ARCHIVE_BATCH_SIZE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+            return Ok(None);
+        };
+
+        let mut ids: Vec<Uuid> = Vec::with_capacity(rows.len());
+        let mut body_refs: Vec<String> = Vec::new();
+        let mut messages: Vec<Value> = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let mut message: Value = row.get("message");
+            // Archive the full body, the blob holding it is deleted with the row
+            if let Some(key) = row.get::<Option<String>, _>("body_ref") {
+                let body = store.get(&key).await.map_err(sqlx::Error::Io)?;
+                message["message"] = Value::String(String::from_utf8_lossy(&body).into_owned());
+                message["body_ref"] = Value::Null;
+                body_refs.push(key);
+            }
+            message[HISTORY_FIELD] = row.get("history");
+            ids.push(row.get("id"));
+            messages.push(message);
+        }
+
+        let id = Uuid::new_v4();
+        let key = archive_key(id);
+        let data = encode_archive(&messages).map_err(sqlx::Error::Io)?;
+        let size = data.len() as i64;
+        store.put(&key, data).await.map_err(sqlx::Error::Io)?;
+
+        let result = self.delete_archived_messages(id, &key, &ids, size, first.get("created_at"), last.get("created_at")).await;
+        let archive = match result {
+            Ok(archive) => archive,
+            Err(e) => {
+                // Without its manifest the file would never be found again
+                if let Err(e) = store.delete(&key).await {
+                    eprintln!("Failed to delete archive {}: {}", key, e);
+                }
+                return Err(e);
+            }
+        };
+
+        self.delete_blobs(&body_refs).await;
+        Ok(Some(archive))
+    }
+
+    /// Records the manifest of a stored archive and deletes its messages.
+    async fn delete_archived_messages(
+        &self,
+        id: Uuid,
+        key: &str,
+        ids: &[Uuid],
+        size_bytes: i64,
+        first_created_at: DateTime<Utc>,
+        last_created_at: DateTime<Utc>,
+    ) -> Result<ArchiveManifest, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(r#"
+            INSERT INTO archives (id, key, message_count, first_created_at, last_created_at, size_bytes)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+        "#)
+        .bind(id)
+        .bind(key)
+        .bind(ids.len() as i64)
+        .bind(first_created_at)
+        .bind(last_created_at)
+        .bind(size_bytes)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE messages SET merged_into = NULL WHERE merged_into = ANY($1)")
+            .bind(ids)
+            .execute(&mut *tx)
+            .await?;
+
+        // A message reopened since it was read stays, its copy in the file is skipped on restore
+        let deleted = sqlx::query("DELETE FROM messages WHERE id = ANY($1) AND status = 'resolved' RETURNING id")
+            .bind(ids)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for row in &deleted {
+            Self::record_event(&mut tx, row.get("id"), MessageEventKind::Deleted, json!({ "archive": id })).await?;
+        }
+        if !deleted.is_empty() {
+            Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        }
+        tx.commit().await?;
+
+        Ok(manifest_from_row(&row))
+    }
+
+    /// Inserts back the messages of an archive that are no longer in the
+    /// database, with their assignment history.
+    ///
+    /// Restored bodies stay in PostgreSQL whatever their size. A message
+    /// merged into, or attached to a company, that no longer exists is
+    /// restored without the link.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the archive to restore
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(Some(count))` with the number of restored messages, or
+    /// `Ok(None)` if the archive doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - No blob store is configured
+    /// - The archive file cannot be read or is corrupted
+    /// - A message does not fit the current schema of the messages table
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::storage::LocalBlobStore;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?
+    ///         .with_blob_store(Arc::new(LocalBlobStore::new("./blobs")), 64 * 1024);
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     match db.restore_archive(id).await? {
+    ///         Some(count) => println!("Restored {} messages", count),
+    ///         None => println!("Archive not found"),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn restore_archive(&self, id: Uuid) -> Result<Option<u64>, sqlx::Error> {
+        let Some(store) = &self.blob_store else {
+            return Err(sqlx::Error::Configuration("archival exports require a blob store".into()));
+        };
+        let Some(row) = sqlx::query("SELECT * FROM archives WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let manifest = manifest_from_row(&row);
+
+        let data = store.get(&manifest.key).await.map_err(sqlx::Error::Io)?;
+        let messages = decode_archive(&data).map_err(sqlx::Error::Io)?;
+
+        let mut tx = self.pool.begin().await?;
+        let mut restored = 0;
+        for mut message in messages {
+            let history = message
+                .as_object_mut()
+                .and_then(|message| message.remove(HISTORY_FIELD))
+                .unwrap_or_else(|| json!([]));
+
+            let inserted = sqlx::query(r#"
+                INSERT INTO messages
+                SELECT * FROM jsonb_populate_record(
+                    NULL::messages,
+                    $1 || jsonb_build_object(
+                        'merged_into', (SELECT id FROM messages WHERE id = ($1->>'merged_into')::uuid),
+                        'company_id', (SELECT id FROM companies WHERE id = ($1->>'company_id')::uuid)
+                    )
+                )
+                WHERE NOT EXISTS (SELECT 1 FROM messages WHERE id = ($1->>'id')::uuid)
+                RETURNING id
+            "#)
+            .bind(&message)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(inserted) = inserted else {
+                continue;
+            };
+            let message_id: Uuid = inserted.get("id");
+
+            sqlx::query(r#"
+                INSERT INTO assignment_history (message_id, agent, action, created_at)
+                SELECT message_id, agent, action, created_at
+                FROM jsonb_populate_recordset(NULL::assignment_history, $1)
+            "#)
+            .bind(&history)
+            .execute(&mut *tx)
+            .await?;
+
+            Self::record_event(&mut tx, message_id, MessageEventKind::Created, json!({ "archive": id })).await?;
+            restored += 1;
+        }
+
+        if restored > 0 {
+            Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        }
+        sqlx::query("UPDATE archives SET restored_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(Some(restored))
+    }
+}
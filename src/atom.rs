@@ -0,0 +1,173 @@
+//! # Atom Feed
+//!
+//! The latest messages of the inbox as an Atom (RFC 4287) feed, served by
+//! `GET /inbox/feed.atom`, so that inbound interest can be followed from
+//! any feed reader. Each entry is titled with the sender and their company
+//! and holds an excerpt of the message; messages in the trash, archived,
+//! merged into another or classified as spam are left out.
+//!
+//! The feed takes an API token with the `feed:read` scope, which feed
+//! readers pass as `?token=dht_...` (see the `api_tokens` module), or the
+//! admin token.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::models::Message;
+
+/// Number of messages the feed lists.
+pub const FEED_SIZE: i64 = 50;
+
+/// Longest excerpt of a message, in characters.
+pub const EXCERPT_CHARS: usize = 280;
+
+/// Returns the beginning of a message on a single line, cut at a word
+/// boundary when it is longer than [`EXCERPT_CHARS`].
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::atom::excerpt;
+///
+/// assert_eq!(excerpt("Hello,\n\n  could you send a quote?"), "Hello, could you send a quote?");
+/// let long = "word ".repeat(100);
+/// let cut = excerpt(&long);
+/// assert!(cut.ends_with("word…"));
+/// assert!(cut.chars().count() <= 281);
+/// ```
+pub fn excerpt(message: &str) -> String {
+    let text = message.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= EXCERPT_CHARS {
+        return text;
+    }
+    let cut: String = text.chars().take(EXCERPT_CHARS).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) => &cut[..space],
+        None => &cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// Escapes text for XML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats a time as an Atom date.
+fn atom_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Writes the Atom feed of `messages`, listed newest first.
+///
+/// # Arguments
+///
+/// * `messages` - Messages of the feed
+/// * `self_link` - Path the feed is served at, without its token
+/// * `now` - Update time of an empty feed
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{TimeZone, Utc};
+/// use dothtml_backend::atom::atom_feed;
+///
+/// let now = Utc.with_ymd_and_hms(2026, 10, 17, 9, 0, 0).unwrap();
+/// let feed = atom_feed(&[], "/inbox/feed.atom", now);
+/// assert!(feed.starts_with(r#"<?xml version="1.0" encoding="utf-8"?>"#));
+/// assert!(feed.contains("<updated>2026-10-17T09:00:00Z</updated>"));
+/// ```
+pub fn atom_feed(messages: &[Message], self_link: &str, now: DateTime<Utc>) -> String {
+    let updated = messages.iter().map(|message| message.created_at).max().unwrap_or(now);
+    let mut feed = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    feed.push('\n');
+    feed.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+    feed.push_str("<id>urn:dothtml-backend:inbox</id>");
+    feed.push_str("<title>New messages</title>");
+    feed.push_str(&format!(r#"<link rel="self" href="{}"/>"#, escape(self_link)));
+    feed.push_str(&format!("<updated>{}</updated>", atom_time(updated)));
+    feed.push_str("<generator>dothtml-backend</generator>");
+
+    for message in messages {
+        let title = if message.company.trim().is_empty() {
+            message.name.clone()
+        } else {
+            format!("{} ({})", message.name, message.company)
+        };
+        feed.push_str("<entry>");
+        feed.push_str(&format!("<id>urn:uuid:{}</id>", message.id));
+        feed.push_str(&format!("<title>{}</title>", escape(&title)));
+        feed.push_str(&format!(
+            "<author><name>{}</name><email>{}</email></author>",
+            escape(&message.name),
+            escape(&message.email)
+        ));
+        feed.push_str(&format!("<published>{}</published>", atom_time(message.created_at)));
+        feed.push_str(&format!("<updated>{}</updated>", atom_time(message.created_at)));
+        if let Some(category) = &message.category {
+            feed.push_str(&format!(r#"<category term="{}"/>"#, escape(category)));
+        }
+        feed.push_str(&format!(r#"<content type="text">{}</content>"#, escape(&excerpt(&message.message))));
+        feed.push_str("</entry>");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// Database operations for the Atom feed.
+impl Database {
+    /// Lists the latest messages of the feed, newest first: messages in the
+    /// inbox, not archived, merged nor classified as spam.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::atom::FEED_SIZE;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for message in db.list_feed_messages(FEED_SIZE).await? {
+    ///         println!("{} from {}", message.id, message.email);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_feed_messages(&self, limit: i64) -> Result<Vec<Message>, sqlx::Error> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(r#"
+            SELECT id
+            FROM messages
+            WHERE deleted_at IS NULL AND NOT archived AND merged_into IS NULL
+              AND category IS DISTINCT FROM 'spam'
+            ORDER BY created_at DESC
+            LIMIT $1
+        "#)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages = self.get_messages_by_ids(&ids).await?;
+        messages.sort_by_key(|message| std::cmp::Reverse(message.created_at));
+        Ok(messages)
+    }
+}
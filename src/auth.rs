@@ -0,0 +1,228 @@
+//! # Authentication
+//!
+//! Request guards for protected endpoints. Add the guard as a handler
+//! argument and Actix rejects the request before the handler runs when the
+//! credentials are missing or invalid.
+//!
+//! Failed attempts are recorded, and clients with too many of them are
+//! locked out (see the `auth_events` module).
+
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, error, http::header, web, FromRequest, HttpMessage, HttpRequest};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::future::LocalBoxFuture;
+
+use crate::api_tokens::TokenGrant;
+use crate::auth_events::{AuthAudit, AuthFailure};
+use crate::config::LiveConfig;
+use crate::database::Database;
+use crate::jwt::SigningKeys;
+
+/// Guard for admin-only endpoints.
+///
+/// The request must carry `Authorization: Bearer <ADMIN_TOKEN>`, or HTTP
+/// Basic credentials with the admin token as password (the user name is
+/// ignored) so that admin pages can be opened from a browser. When no
+/// admin token is configured, every admin request is refused.
+///
+/// Routes wrapped with `api_tokens::authorize` also accept the API tokens
+/// having their scope.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{HttpResponse, Responder};
+/// use dothtml_backend::auth::Admin;
+///
+/// async fn admin_only(_admin: Admin) -> impl Responder {
+///     HttpResponse::Ok().body("Welcome, admin")
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if req.extensions().get::<TokenGrant>().is_some() {
+            return Box::pin(ready(Ok(Admin)));
+        }
+
+        let expected = req
+            .app_data::<web::Data<LiveConfig>>()
+            .and_then(|config| config.load().admin_token.clone());
+
+        let Some(expected) = expected else {
+            return Box::pin(ready(Err(error::ErrorForbidden("Admin endpoints are disabled"))));
+        };
+        let Some(token) = bearer_token(req).map(str::to_string).or_else(|| basic_password(req)) else {
+            return Box::pin(ready(Err(error::ErrorUnauthorized("Invalid or missing admin token"))));
+        };
+
+        let audit = AuthAudit::from_request(req);
+        Box::pin(async move {
+            audit.check_lockout().await?;
+            if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+                return Ok(Admin);
+            }
+            audit.record(None, AuthFailure::InvalidAdminToken).await;
+            Err(error::ErrorUnauthorized("Invalid or missing admin token"))
+        })
+    }
+}
+
+/// Guard for the endpoints acting on the caller's own account, such as
+/// `/me/preferences`.
+///
+/// The backoffice has no per-user credentials: agents share the admin
+/// token. The request must carry HTTP Basic credentials with the admin
+/// token as password and the agent's name, as used in assignments, as user
+/// name; or, with `MAGIC_LINK_LOGIN`, the session token of a login link as
+/// a bearer token (see the `sessions` module), unless an admin revoked the
+/// agent's sessions since it was issued.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{HttpResponse, Responder};
+/// use dothtml_backend::auth::Account;
+///
+/// async fn whoami(account: Account) -> impl Responder {
+///     HttpResponse::Ok().body(account.0)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Account(pub String);
+
+impl FromRequest for Account {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(config) = req.app_data::<web::Data<LiveConfig>>().map(|config| config.load()) else {
+            return Box::pin(ready(Err(error::ErrorForbidden("Account endpoints are disabled"))));
+        };
+        let audit = AuthAudit::from_request(req);
+
+        let signing_keys = req.app_data::<web::Data<SigningKeys>>().filter(|_| config.magic_link_login);
+        if let (Some(keys), Some(token)) = (signing_keys, bearer_token(req)) {
+            let verified = keys.verify(token);
+            let db = req.app_data::<web::Data<Database>>().cloned();
+            return Box::pin(async move {
+                audit.check_lockout().await?;
+                let claims = match verified {
+                    Ok(claims) => claims,
+                    Err(e) => {
+                        audit.record(None, AuthFailure::InvalidSession).await;
+                        return Err(error::ErrorUnauthorized(format!("Invalid session: {}", e)));
+                    }
+                };
+                let revoked_before = match &db {
+                    Some(db) => db.sessions_revoked_before(&claims.sub).await.map_err(|_| error::ErrorInternalServerError("Failed to check the session"))?,
+                    None => None,
+                };
+                if revoked_before.is_some_and(|revoked_before| claims.iat <= revoked_before.timestamp()) {
+                    audit.record(Some(&claims.sub), AuthFailure::InvalidSession).await;
+                    return Err(error::ErrorUnauthorized("Invalid session: revoked"));
+                }
+                Ok(Account(claims.sub))
+            });
+        }
+
+        let Some(expected) = config.admin_token.clone() else {
+            return Box::pin(ready(Err(error::ErrorForbidden("Account endpoints are disabled"))));
+        };
+        let Some((user, password)) = basic_credentials(req) else {
+            return Box::pin(ready(Err(error::ErrorUnauthorized("Invalid or missing credentials"))));
+        };
+        let account = user.trim().to_string();
+        if !(1..=100).contains(&account.chars().count()) {
+            return Box::pin(ready(Err(error::ErrorUnauthorized("The user name must name the agent"))));
+        }
+
+        Box::pin(async move {
+            audit.check_lockout().await?;
+            if constant_time_eq(password.as_bytes(), expected.as_bytes()) {
+                return Ok(Account(account));
+            }
+            audit.record(Some(&account), AuthFailure::InvalidCredentials).await;
+            Err(error::ErrorUnauthorized("Invalid or missing credentials"))
+        })
+    }
+}
+
+/// Guard for the webhooks of the email provider.
+///
+/// The request must carry `EMAIL_WEBHOOK_SECRET` as a bearer token, as the
+/// password of HTTP Basic credentials, or as a `?token=` query parameter,
+/// since providers differ in what they can send. When no secret is
+/// configured, the webhooks are disabled.
+#[derive(Debug)]
+pub struct EmailWebhook;
+
+impl FromRequest for EmailWebhook {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let expected = req
+            .app_data::<web::Data<LiveConfig>>()
+            .and_then(|config| config.load().email_webhook_secret.clone());
+
+        let Some(expected) = expected else {
+            return ready(Err(error::ErrorForbidden("Email webhooks are disabled")));
+        };
+
+        let token = bearer_token(req)
+            .map(str::to_string)
+            .or_else(|| basic_password(req))
+            .or_else(|| query_token(req));
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => ready(Ok(EmailWebhook)),
+            _ => ready(Err(error::ErrorUnauthorized("Invalid or missing webhook secret"))),
+        }
+    }
+}
+
+/// Extracts the token of an `Authorization: Bearer` header.
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Extracts the password of an `Authorization: Basic` header.
+fn basic_password(req: &HttpRequest) -> Option<String> {
+    basic_credentials(req).map(|(_, password)| password)
+}
+
+/// Extracts the user name and password of an `Authorization: Basic` header.
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let encoded = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    decoded.split_once(':').map(|(user, password)| (user.to_string(), password.to_string()))
+}
+
+/// Extracts the `token` query parameter.
+fn query_token(req: &HttpRequest) -> Option<String> {
+    web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .ok()?
+        .into_inner()
+        .remove("token")
+}
+
+/// Compares two byte strings without short-circuiting on the first difference.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
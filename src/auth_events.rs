@@ -0,0 +1,367 @@
+//! # Authentication Audit
+//!
+//! Every failed authentication is recorded in the `auth_events` table: a
+//! wrong admin token, wrong account credentials, an invalid session, login
+//! link or API token. An event keeps the identifier it was made for when
+//! there is one (the agent's name), the client IP, the user agent and a
+//! fingerprint of the client's headers, so that agents can spot attempts
+//! on their account with `GET /me/security/events`.
+//!
+//! ## Lockout
+//!
+//! The same events throttle brute force: a client IP with
+//! `AUTH_LOCKOUT_THRESHOLD` failures in the last
+//! `AUTH_LOCKOUT_WINDOW_MINUTES` is refused with 429 Too Many Requests on
+//! every authentication, even with valid credentials, until its failures
+//! age out. The lockout applies per IP rather than per identifier, so that
+//! nobody can lock an agent out by failing on purpose with their name.
+//!
+//! Events older than `AUTH_EVENTS_RETENTION_DAYS` are deleted by
+//! `jobs::spawn_auth_events_cleanup_job`.
+
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::{error, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+
+use crate::client_ip;
+use crate::config::LiveConfig;
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+
+/// Longest user agent stored, in characters.
+const MAX_USER_AGENT_CHARS: usize = 500;
+
+/// Why an authentication failed.
+///
+/// * `InvalidAdminToken` - A wrong admin token
+/// * `InvalidCredentials` - Account credentials with a wrong password
+/// * `InvalidSession` - An invalid or expired session token
+/// * `InvalidLoginLink` - An invalid, expired or already used login link
+/// * `InvalidApiToken` - An unknown, revoked or expired API token
+/// * `InvalidFeedToken` - An invalid, expired or revoked deadline feed URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    InvalidAdminToken,
+    InvalidCredentials,
+    InvalidSession,
+    InvalidLoginLink,
+    InvalidApiToken,
+    InvalidFeedToken,
+}
+
+impl AuthFailure {
+    /// Returns the name stored in the `reason` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthFailure::InvalidAdminToken => "invalid_admin_token",
+            AuthFailure::InvalidCredentials => "invalid_credentials",
+            AuthFailure::InvalidSession => "invalid_session",
+            AuthFailure::InvalidLoginLink => "invalid_login_link",
+            AuthFailure::InvalidApiToken => "invalid_api_token",
+            AuthFailure::InvalidFeedToken => "invalid_feed_token",
+        }
+    }
+}
+
+/// A recorded authentication failure.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the event
+/// * `identifier` - Agent the attempt was made for, when it named one
+/// * `ip` - Client IP address, if known
+/// * `user_agent` - `User-Agent` header of the client
+/// * `fingerprint` - Hash of the client's identifying headers (see [`device_fingerprint`])
+/// * `reason` - Why the attempt failed (see [`AuthFailure`])
+/// * `created_at` - When the attempt was made
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthEvent {
+    pub id: i64,
+    pub identifier: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub fingerprint: String,
+    pub reason: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Column list selected for every `AuthEvent` row.
+const AUTH_EVENT_COLUMNS: &str = "id, identifier, ip, user_agent, fingerprint, reason, created_at";
+
+fn auth_event_from_row(row: &PgRow) -> AuthEvent {
+    AuthEvent {
+        id: row.get("id"),
+        identifier: row.get("identifier"),
+        ip: row.get("ip"),
+        user_agent: row.get("user_agent"),
+        fingerprint: row.get("fingerprint"),
+        reason: row.get("reason"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Returns a short fingerprint of the client's device: a hash of its user
+/// agent and preferred languages and encodings, telling apart the devices
+/// behind one IP.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+/// use dothtml_backend::auth_events::device_fingerprint;
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert(HeaderName::from_static("user-agent"), HeaderValue::from_static("curl/8.5.0"));
+/// let curl = device_fingerprint(&headers);
+///
+/// assert_eq!(curl.len(), 16);
+/// assert_ne!(curl, device_fingerprint(&HeaderMap::new()));
+/// ```
+pub fn device_fingerprint(headers: &HeaderMap) -> String {
+    let mut hasher = Sha256::new();
+    for name in [header::USER_AGENT, header::ACCEPT_LANGUAGE, header::ACCEPT_ENCODING] {
+        hasher.update(headers.get(name).map(|value| value.as_bytes()).unwrap_or_default());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().iter().take(8).map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The client of an authentication attempt, with what is needed to check
+/// its lockout and record its failure after the request was borrowed.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::test::TestRequest;
+/// use dothtml_backend::auth_events::AuthAudit;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let audit = AuthAudit::from_request(&TestRequest::default().to_http_request());
+///     // Without a database nothing is recorded, and nobody is locked out
+///     assert!(audit.check_lockout().await.is_ok());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct AuthAudit {
+    db: Option<Database>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    fingerprint: String,
+    lockout: Option<(u64, u32)>,
+}
+
+impl AuthAudit {
+    /// Captures the client of `req`.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let config = req.app_data::<web::Data<LiveConfig>>().map(|config| config.load());
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let ip = match &config {
+            Some(config) => client_ip::resolve(peer, req.headers(), &config.trusted_proxies),
+            None => peer,
+        };
+        AuthAudit {
+            db: req.app_data::<web::Data<Database>>().map(|db| db.get_ref().clone()),
+            ip: ip.map(|ip| ip.to_string()),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(MAX_USER_AGENT_CHARS).collect()),
+            fingerprint: device_fingerprint(req.headers()),
+            lockout: config.and_then(|config| {
+                config.auth_lockout_threshold.map(|threshold| (threshold, config.auth_lockout_window_minutes))
+            }),
+        }
+    }
+
+    /// Refuses the attempt when the client IP is locked out.
+    ///
+    /// A failure to count the recent failures lets the attempt through:
+    /// losing the lockout beats locking everybody out.
+    ///
+    /// # Errors
+    ///
+    /// Returns a 429 Too Many Requests error, with `Retry-After`, when the
+    /// client has too many recent failures.
+    pub async fn check_lockout(&self) -> Result<(), actix_web::Error> {
+        let (Some(db), Some(ip), Some((threshold, window_minutes))) = (&self.db, &self.ip, self.lockout) else {
+            return Ok(());
+        };
+        match db.count_auth_failures(ip, window_minutes).await {
+            Ok(failures) if failures as u64 >= threshold => {
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header((header::RETRY_AFTER, (u64::from(window_minutes) * 60).to_string()))
+                    .body("Too many failed authentication attempts, please try again later");
+                Err(error::InternalError::from_response("locked out", response).into())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                eprintln!("Failed to count the authentication failures of {}: {}", ip, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records a failed attempt made for `identifier`, if it named one.
+    pub async fn record(&self, identifier: Option<&str>, reason: AuthFailure) {
+        let Some(db) = &self.db else {
+            return;
+        };
+        let result = db
+            .insert_auth_event(
+                identifier,
+                self.ip.as_deref(),
+                self.user_agent.as_deref(),
+                &self.fingerprint,
+                reason,
+            )
+            .await;
+        if let Err(e) = result {
+            eprintln!("Failed to record a failed authentication: {}", e);
+        }
+    }
+}
+
+/// Database operations for the authentication audit.
+impl Database {
+    /// Creates the 'auth_events' table and its indexes if they don't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_auth_events_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS auth_events (
+                id BIGSERIAL PRIMARY KEY,
+                identifier TEXT,
+                ip TEXT,
+                user_agent TEXT,
+                fingerprint TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_auth_events_identifier ON auth_events (identifier, created_at)")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_auth_events_ip ON auth_events (ip, created_at)")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed authentication.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn insert_auth_event(
+        &self,
+        identifier: Option<&str>,
+        ip: Option<&str>,
+        user_agent: Option<&str>,
+        fingerprint: &str,
+        reason: AuthFailure,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO auth_events (identifier, ip, user_agent, fingerprint, reason)
+            VALUES ($1, $2, $3, $4, $5)
+        "#)
+        .bind(identifier)
+        .bind(ip)
+        .bind(user_agent)
+        .bind(fingerprint)
+        .bind(reason.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists a page of the failed authentications made for an agent, most
+    /// recent first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::PageRequest;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for event in db.list_auth_events("alice", &PageRequest::default()).await?.data {
+    ///         println!("{} from {:?}: {}", event.created_at, event.ip, event.reason);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_auth_events(
+        &self, identifier: &str, page: &PageRequest
+    ) -> Result<Paginated<AuthEvent>, sqlx::Error> {
+        let rows = sqlx::query(&format!(r#"
+            SELECT {AUTH_EVENT_COLUMNS}, COUNT(*) OVER () AS total FROM auth_events
+            WHERE identifier = $1
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2 OFFSET $3
+        "#))
+        .bind(identifier)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, auth_event_from_row))
+    }
+
+    /// Counts the failed authentications from a client IP in the last
+    /// `window_minutes`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn count_auth_failures(&self, ip: &str, window_minutes: u32) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM auth_events WHERE ip = $1 AND created_at > NOW() - make_interval(mins => $2)",
+        )
+        .bind(ip)
+        .bind(window_minutes as i32)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Deletes the events older than `retention_days`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of events deleted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn purge_auth_events(&self, retention_days: u32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM auth_events WHERE created_at < NOW() - make_interval(days => $1)")
+            .bind(retention_days as i32)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
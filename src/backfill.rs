@@ -0,0 +1,422 @@
+//! # Backfills
+//!
+//! Fills columns added to the `messages` table after rows already existed,
+//! without locking the table or starving the server. A backfill walks the
+//! table by ID in batches of `BACKFILL_BATCH_SIZE` rows, updating those that
+//! need it in one short statement per batch and pausing
+//! `BACKFILL_PAUSE_MS` milliseconds between batches.
+//!
+//! Backfills run from the command line, next to a running server:
+//!
+//! ```bash
+//! dothtml-backend backfill              # list the backfills
+//! dothtml-backend backfill assigned_at  # run one
+//! ```
+//!
+//! Progress is saved in the `backfills` table after each batch and shown by
+//! `GET /admin/backfills`. An interrupted or failed backfill resumes after
+//! the last batch on its next run; a completed one starts over, which is
+//! harmless since it only updates the rows that still need it. Only one
+//! process can run a given backfill at a time.
+//!
+//! A new backfill is added to [`BACKFILLS`] with the rows it applies to and
+//! the assignments that fill them.
+
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+
+/// A backfill of the `messages` table.
+///
+/// # Fields
+///
+/// * `name` - Name used on the command line
+/// * `description` - What the backfill fills in
+/// * `condition` - SQL condition selecting the messages to update
+/// * `assignments` - SQL `SET` list applied to them
+#[derive(Debug, Clone, Copy)]
+pub struct Backfill {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub condition: &'static str,
+    pub assignments: &'static str,
+}
+
+/// Every available backfill.
+pub const BACKFILLS: [Backfill; 2] = [
+    Backfill {
+        name: "assigned_at",
+        description: "Assignment time of messages assigned before `assigned_at` was recorded",
+        condition: "status = 'assigned' AND assigned_at IS NULL \
+                    AND EXISTS (SELECT 1 FROM assignment_history h WHERE h.message_id = messages.id)",
+        assignments: "assigned_at = (SELECT MAX(h.created_at) FROM assignment_history h WHERE h.message_id = messages.id)",
+    },
+    Backfill {
+        name: "resolved_at",
+        description: "Resolution time of messages resolved before `resolved_at` was recorded",
+        condition: "status = 'resolved' AND resolved_at IS NULL \
+                    AND EXISTS (SELECT 1 FROM message_events e WHERE e.message_id = messages.id \
+                                AND e.event_type = 'status_changed' AND e.payload->>'to' = 'resolved')",
+        assignments: "resolved_at = (SELECT MAX(e.created_at) FROM message_events e WHERE e.message_id = messages.id \
+                      AND e.event_type = 'status_changed' AND e.payload->>'to' = 'resolved')",
+    },
+];
+
+/// Returns the backfill called `name`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::backfill;
+///
+/// assert!(backfill::find("assigned_at").is_some());
+/// assert!(backfill::find("unknown").is_none());
+/// ```
+pub fn find(name: &str) -> Option<&'static Backfill> {
+    BACKFILLS.iter().find(|backfill| backfill.name == name)
+}
+
+/// State of a backfill.
+///
+/// * `NotStarted` - It never ran on this database
+/// * `Running` - A run is in progress, or was interrupted
+/// * `Completed` - The last run went through the whole table
+/// * `Failed` - The last run stopped on an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillStatus {
+    NotStarted,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl BackfillStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BackfillStatus::NotStarted => "not_started",
+            BackfillStatus::Running => "running",
+            BackfillStatus::Completed => "completed",
+            BackfillStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "running" => BackfillStatus::Running,
+            "completed" => BackfillStatus::Completed,
+            "failed" => BackfillStatus::Failed,
+            _ => BackfillStatus::NotStarted,
+        }
+    }
+}
+
+impl fmt::Display for BackfillStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Progress of a backfill, as saved after each batch.
+///
+/// # Fields
+///
+/// * `name` - Name of the backfill
+/// * `description` - What the backfill fills in
+/// * `status` - State of the backfill
+/// * `total_rows` - Messages needing the backfill when the run started
+/// * `rows_scanned` - Messages walked through so far
+/// * `rows_updated` - Messages updated so far
+/// * `error` - Error that stopped the last run, if it failed
+/// * `started_at` - Timestamp when the current or last run started
+/// * `updated_at` - Timestamp of the last saved batch
+/// * `finished_at` - Timestamp when the last run completed or failed
+#[derive(Debug, Clone, Serialize)]
+pub struct BackfillProgress {
+    pub name: String,
+    pub description: String,
+    pub status: BackfillStatus,
+    pub total_rows: i64,
+    pub rows_scanned: i64,
+    pub rows_updated: i64,
+    pub error: Option<String>,
+    #[serde(with = "crate::timestamp::option")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    last_id: Option<Uuid>,
+}
+
+impl BackfillProgress {
+    fn not_started(backfill: &Backfill) -> Self {
+        BackfillProgress {
+            name: backfill.name.to_string(),
+            description: backfill.description.to_string(),
+            status: BackfillStatus::NotStarted,
+            total_rows: 0,
+            rows_scanned: 0,
+            rows_updated: 0,
+            error: None,
+            started_at: None,
+            updated_at: None,
+            finished_at: None,
+            last_id: None,
+        }
+    }
+
+    fn from_row(backfill: &Backfill, row: &sqlx::postgres::PgRow) -> Self {
+        BackfillProgress {
+            status: BackfillStatus::parse(row.get("status")),
+            total_rows: row.get("total_rows"),
+            rows_scanned: row.get("rows_scanned"),
+            rows_updated: row.get("rows_updated"),
+            error: row.get("error"),
+            started_at: row.get("started_at"),
+            updated_at: row.get("updated_at"),
+            finished_at: row.get("finished_at"),
+            last_id: row.get("last_id"),
+            ..BackfillProgress::not_started(backfill)
+        }
+    }
+}
+
+/// Batching of a backfill run.
+///
+/// # Fields
+///
+/// * `batch_size` - Messages walked through per statement
+/// * `pause` - Pause between two batches, leaving room for the server's queries
+#[derive(Debug, Clone, Copy)]
+pub struct BackfillOptions {
+    pub batch_size: i64,
+    pub pause: Duration,
+}
+
+/// Database operations for backfills.
+impl Database {
+    /// Creates the 'backfills' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_backfills_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_backfills_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS backfills (
+                name TEXT PRIMARY KEY,
+                status TEXT NOT NULL,
+                total_rows BIGINT NOT NULL DEFAULT 0,
+                rows_scanned BIGINT NOT NULL DEFAULT 0,
+                rows_updated BIGINT NOT NULL DEFAULT 0,
+                last_id UUID,
+                error TEXT,
+                started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                finished_at TIMESTAMPTZ
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the progress of every backfill in [`BACKFILLS`], including
+    /// those that never ran.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_backfills(&self) -> Result<Vec<BackfillProgress>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM backfills").fetch_all(&self.pool).await?;
+
+        Ok(BACKFILLS
+            .iter()
+            .map(|backfill| match rows.iter().find(|row| row.get::<&str, _>("name") == backfill.name) {
+                Some(row) => BackfillProgress::from_row(backfill, row),
+                None => BackfillProgress::not_started(backfill),
+            })
+            .collect())
+    }
+
+    /// Runs a backfill to completion, resuming an interrupted run.
+    ///
+    /// `on_batch` is called with the progress after each batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `backfill` - Backfill to run
+    /// * `options` - Batch size and pause between batches
+    /// * `on_batch` - Progress callback
+    ///
+    /// # Returns
+    ///
+    /// Returns the final progress of the backfill.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Another process is running the same backfill
+    ///   (`sqlx::Error::Protocol`)
+    /// - Database connection issues occur, in which case the backfill is
+    ///   saved as failed and resumes from the last batch on its next run
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use dothtml_backend::backfill::{self, BackfillOptions};
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let options = BackfillOptions { batch_size: 1000, pause: Duration::from_millis(200) };
+    ///     let progress = db
+    ///         .run_backfill(backfill::find("assigned_at").unwrap(), options, |progress| {
+    ///             println!("{} rows updated", progress.rows_updated);
+    ///         })
+    ///         .await?;
+    ///     println!("{}", progress.status);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn run_backfill(
+        &self,
+        backfill: &Backfill,
+        options: BackfillOptions,
+        mut on_batch: impl FnMut(&BackfillProgress),
+    ) -> Result<BackfillProgress, sqlx::Error> {
+        // Held on a dedicated connection for the whole run, closed afterwards
+        // rather than returned to the pool so that the lock goes with it
+        let mut lock = self.pool.acquire().await?;
+        lock.close_on_drop();
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtext('backfill:' || $1))")
+            .bind(backfill.name)
+            .fetch_one(&mut *lock)
+            .await?;
+        if !locked {
+            return Err(sqlx::Error::Protocol(format!("Backfill `{}` is already running", backfill.name)));
+        }
+
+        let result = self.run_backfill_batches(backfill, options, &mut on_batch).await;
+        if let Err(e) = &result {
+            sqlx::query("UPDATE backfills SET status = 'failed', error = $2, finished_at = NOW() WHERE name = $1")
+                .bind(backfill.name)
+                .bind(e.to_string())
+                .execute(&self.pool)
+                .await?;
+        }
+        result
+    }
+
+    async fn run_backfill_batches(
+        &self,
+        backfill: &Backfill,
+        options: BackfillOptions,
+        on_batch: &mut impl FnMut(&BackfillProgress),
+    ) -> Result<BackfillProgress, sqlx::Error> {
+        let total_rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM messages WHERE {}", backfill.condition))
+            .fetch_one(&self.pool)
+            .await?;
+
+        // Resume a run that did not complete, start over otherwise
+        let row = sqlx::query(r#"
+            INSERT INTO backfills (name, status, total_rows)
+            VALUES ($1, 'running', $2)
+            ON CONFLICT (name) DO UPDATE SET
+                status = 'running',
+                error = NULL,
+                finished_at = NULL,
+                updated_at = NOW(),
+                total_rows = CASE WHEN backfills.status = 'completed' THEN EXCLUDED.total_rows
+                                  ELSE backfills.rows_updated + EXCLUDED.total_rows END,
+                rows_scanned = CASE WHEN backfills.status = 'completed' THEN 0 ELSE backfills.rows_scanned END,
+                rows_updated = CASE WHEN backfills.status = 'completed' THEN 0 ELSE backfills.rows_updated END,
+                last_id = CASE WHEN backfills.status = 'completed' THEN NULL ELSE backfills.last_id END,
+                started_at = CASE WHEN backfills.status = 'completed' THEN NOW() ELSE backfills.started_at END
+            RETURNING *
+        "#)
+        .bind(backfill.name)
+        .bind(total_rows)
+        .fetch_one(&self.pool)
+        .await?;
+        let mut progress = BackfillProgress::from_row(backfill, &row);
+
+        let batch = format!(r#"
+            WITH batch AS (
+                SELECT id FROM messages
+                WHERE $1::UUID IS NULL OR id > $1
+                ORDER BY id
+                LIMIT $2
+            ), updated AS (
+                UPDATE messages SET {assignments}
+                WHERE id IN (SELECT id FROM batch) AND {condition}
+                RETURNING 1
+            )
+            SELECT
+                (SELECT id FROM batch ORDER BY id DESC LIMIT 1) AS last_id,
+                (SELECT COUNT(*) FROM batch) AS scanned,
+                (SELECT COUNT(*) FROM updated) AS updated
+        "#, assignments = backfill.assignments, condition = backfill.condition);
+
+        loop {
+            let row = sqlx::query(&batch)
+                .bind(progress.last_id)
+                .bind(options.batch_size)
+                .fetch_one(&self.pool)
+                .await?;
+            let scanned: i64 = row.get("scanned");
+            let done = scanned < options.batch_size;
+
+            let row = sqlx::query(r#"
+                UPDATE backfills SET
+                    rows_scanned = rows_scanned + $2,
+                    rows_updated = rows_updated + $3,
+                    last_id = COALESCE($4, last_id),
+                    status = CASE WHEN $5 THEN 'completed' ELSE status END,
+                    finished_at = CASE WHEN $5 THEN NOW() END,
+                    updated_at = NOW()
+                WHERE name = $1
+                RETURNING *
+            "#)
+            .bind(backfill.name)
+            .bind(scanned)
+            .bind(row.get::<i64, _>("updated"))
+            .bind(row.get::<Option<Uuid>, _>("last_id"))
+            .bind(done)
+            .fetch_one(&self.pool)
+            .await?;
+            progress = BackfillProgress::from_row(backfill, &row);
+            on_batch(&progress);
+
+            if done {
+                return Ok(progress);
+            }
+            tokio::time::sleep(options.pause).await;
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! # Backups
+//!
+//! Implements `dothtml-backend backup <file>` and `dothtml-backend restore
+//! <file>`, so that small deployments can back up the server's data
+//! without `pg_dump` or access to the database host.
+//!
+//! A backup is a single gzip-compressed file holding every table of
+//! [`BACKUP_TABLES`], read with `COPY ... TO STDOUT` in one read-only
+//! snapshot, so the tables are consistent with each other even while the
+//! server is writing. The file is plain text once decompressed:
+//!
+//! ```text
+//! {"format":"dothtml-backup","version":1,"app_version":"0.1.0","created_at":"2025-01-15T10:30:00Z"}
+//! {"table":"companies","columns":["id","name",...]}
+//! <rows in COPY text format>
+//! \.
+//! {"table":"messages","columns":[...]}
+//! ...
+//! ```
+//!
+//! Restoring loads the file into a database where the server has already
+//! created its tables, in one transaction: the tables are emptied, the rows
+//! copied and the ID sequences moved past the restored rows. Columns are
+//! matched by name, so a backup restores into a newer schema, the columns
+//! added since then taking their defaults.
+//!
+//! Message bodies moved to the blob store (see the `storage` module) are
+//! not part of the backup, the store has to be backed up on its own.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::database::Database;
+
+/// Value of the `format` field of a backup header.
+pub const BACKUP_FORMAT: &str = "dothtml-backup";
+
+/// Version of the backup format written by this build.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Tables included in a backup, in the order they are restored: referenced
+/// tables come before the tables referencing them.
+pub const BACKUP_TABLES: [&str; 18] = [
+    "companies",
+    "company_aliases",
+    "messages",
+    "assignment_history",
+    "message_events",
+    "outbox",
+    "resource_changes",
+    "feature_flags",
+    "backfills",
+    "archives",
+    "ratings",
+    "abuse_patterns",
+    "abuse_matches",
+    "spam_training",
+    "spam_tokens",
+    "rules",
+    "suppressions",
+    "email_templates",
+];
+
+/// Line ending the rows of a table in COPY text format.
+const END_OF_DATA: &[u8] = b"\\.\n";
+
+/// Bytes sent to PostgreSQL at once while restoring a table.
+const RESTORE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// First line of a backup file.
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupHeader {
+    format: String,
+    version: u32,
+    app_version: String,
+    #[serde(with = "crate::timestamp")]
+    created_at: DateTime<Utc>,
+}
+
+/// Line introducing the rows of a table.
+#[derive(Debug, Serialize, Deserialize)]
+struct TableHeader {
+    table: String,
+    columns: Vec<String>,
+}
+
+/// Quotes a column name for use in a statement.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn invalid_backup(message: impl Into<String>) -> sqlx::Error {
+    sqlx::Error::Io(io::Error::new(io::ErrorKind::InvalidData, message.into()))
+}
+
+/// Database operations for backups.
+impl Database {
+    /// Writes a backup of every table of [`BACKUP_TABLES`] to `writer`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of rows written for each table.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - A table is missing
+    /// - Writing to `writer` fails
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::fs::File;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let db = Database::new().await?;
+    ///     for (table, rows) in db.backup(File::create("backup.gz")?).await? {
+    ///         println!("{}: {} rows", table, rows);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn backup(&self, writer: impl Write) -> Result<Vec<(&'static str, u64)>, sqlx::Error> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        let header = BackupHeader {
+            format: BACKUP_FORMAT.to_string(),
+            version: BACKUP_VERSION,
+            app_version: crate::version::VERSION.to_string(),
+            created_at: Utc::now(),
+        };
+        serde_json::to_writer(&mut encoder, &header).map_err(io::Error::from)?;
+        encoder.write_all(b"\n")?;
+
+        // Every table is read from the same snapshot
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let mut counts = Vec::with_capacity(BACKUP_TABLES.len());
+        for table in BACKUP_TABLES {
+            let columns: Vec<String> = sqlx::query_scalar(r#"
+                SELECT column_name::text
+                FROM information_schema.columns
+                WHERE table_schema = current_schema() AND table_name = $1
+                ORDER BY ordinal_position
+            "#)
+            .bind(table)
+            .fetch_all(&mut *tx)
+            .await?;
+            if columns.is_empty() {
+                return Err(sqlx::Error::Configuration(format!("table {} does not exist", table).into()));
+            }
+
+            let header = TableHeader { table: table.to_string(), columns };
+            serde_json::to_writer(&mut encoder, &header).map_err(io::Error::from)?;
+            encoder.write_all(b"\n")?;
+
+            // A query rather than the table name, which partitioned tables do not support
+            let list = header.columns.iter().map(|column| quote_identifier(column)).collect::<Vec<_>>().join(", ");
+            let mut rows = 0;
+            let mut stream = tx.copy_out_raw(&format!("COPY (SELECT {} FROM {}) TO STDOUT", list, table)).await?;
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                rows += chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+                encoder.write_all(&chunk)?;
+            }
+            encoder.write_all(END_OF_DATA)?;
+            counts.push((table, rows));
+        }
+        tx.commit().await?;
+
+        encoder.finish()?.flush()?;
+        Ok(counts)
+    }
+
+    /// Restores a backup written by [`Database::backup`], replacing the
+    /// content of the tables it holds.
+    ///
+    /// The server should be stopped while restoring: the tables are locked
+    /// until the restore commits.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Backup file
+    /// * `replace` - Whether existing messages and companies may be
+    ///   overwritten; without it, restoring into a database holding any
+    ///   fails, to protect against restoring into the wrong database
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of rows restored for each table.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The file is not a backup, or was written by a newer version
+    /// - A table of the backup is missing from the database
+    /// - The database holds messages or companies and `replace` is false
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::fs::File;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let db = Database::new().await?;
+    ///     let counts = db.restore(File::open("backup.gz")?, false).await?;
+    ///     println!("Restored {} tables", counts.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn restore(&self, reader: impl Read, replace: bool) -> Result<Vec<(&'static str, u64)>, sqlx::Error> {
+        let mut reader = BufReader::new(GzDecoder::new(reader));
+        let mut line = Vec::new();
+
+        reader.read_until(b'\n', &mut line)?;
+        let header: BackupHeader = serde_json::from_slice(&line).map_err(|_| invalid_backup("not a dothtml-backend backup"))?;
+        if header.format != BACKUP_FORMAT {
+            return Err(invalid_backup("not a dothtml-backend backup"));
+        }
+        if header.version > BACKUP_VERSION {
+            return Err(invalid_backup(format!(
+                "backup format {} written by version {} is not supported, upgrade first",
+                header.version, header.app_version
+            )));
+        }
+
+        let missing = self.missing_tables(&BACKUP_TABLES).await?;
+        if !missing.is_empty() {
+            return Err(sqlx::Error::Configuration(
+                format!("missing tables {}, start the server once to create them", missing.join(", ")).into(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if !replace {
+            let in_use: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM messages) OR EXISTS (SELECT 1 FROM companies)",
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if in_use {
+                return Err(sqlx::Error::Configuration(
+                    "the database already holds messages or companies, restore with --replace to overwrite them".into(),
+                ));
+            }
+        }
+        sqlx::query(&format!("TRUNCATE {}", BACKUP_TABLES.join(", ")))
+            .execute(&mut *tx)
+            .await?;
+
+        let mut counts = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let header: TableHeader = serde_json::from_slice(&line).map_err(|_| invalid_backup("corrupted backup"))?;
+            let Some(table) = BACKUP_TABLES.iter().copied().find(|table| *table == header.table) else {
+                return Err(invalid_backup(format!("unknown table {} in the backup", header.table)));
+            };
+
+            let list = header.columns.iter().map(|column| quote_identifier(column)).collect::<Vec<_>>().join(", ");
+            let mut copy = tx.copy_in_raw(&format!("COPY {} ({}) FROM STDIN", table, list)).await?;
+            let mut chunk = Vec::with_capacity(RESTORE_CHUNK_SIZE);
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    copy.abort("truncated backup").await?;
+                    return Err(invalid_backup(format!("backup truncated in table {}", table)));
+                }
+                if line == END_OF_DATA {
+                    break;
+                }
+                chunk.extend_from_slice(&line);
+                if chunk.len() >= RESTORE_CHUNK_SIZE {
+                    copy.send(chunk.as_slice()).await?;
+                    chunk.clear();
+                }
+            }
+            if !chunk.is_empty() {
+                copy.send(chunk.as_slice()).await?;
+            }
+            counts.push((table, copy.finish().await?));
+        }
+
+        // Restored rows keep their IDs, new ones must be numbered after them
+        let sequences: Vec<(String, String)> = sqlx::query_as(r#"
+            SELECT table_name::text, column_name::text
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = ANY($1) AND column_default LIKE 'nextval(%'
+        "#)
+        .bind(BACKUP_TABLES.as_slice())
+        .fetch_all(&mut *tx)
+        .await?;
+        for (table, column) in sequences {
+            sqlx::query(&format!(
+                "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(MAX({}), 0) + 1, false) FROM {}",
+                quote_identifier(&column),
+                table
+            ))
+            .bind(&table)
+            .bind(&column)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(counts)
+    }
+}
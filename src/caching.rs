@@ -0,0 +1,196 @@
+//! # HTTP Caching
+//!
+//! Conditional GET support for endpoints whose data rarely changes.
+//!
+//! Each cacheable resource has a row in `resource_changes` holding the last
+//! time its data changed. The database methods that modify the data call
+//! `touch_resource` in the same statement batch. Handlers turn that
+//! timestamp into `ETag` / `Last-Modified` validators and answer
+//! `304 Not Modified` without running the listing query when the client
+//! copy is still current.
+
+use std::time::SystemTime;
+
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch,
+};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, SubsecRound, Utc};
+use sqlx::{PgExecutor, Row};
+
+use crate::database::Database;
+
+/// Resource name of the tag list.
+pub const RESOURCE_TAGS: &str = "tags";
+
+/// Resource name of the companies directory.
+pub const RESOURCE_COMPANIES: &str = "companies";
+
+/// Resource name of the email templates.
+pub const RESOURCE_TEMPLATES: &str = "templates";
+
+/// Database operations tracking when cacheable resources change.
+impl Database {
+    /// Creates the 'resource_changes' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_resource_changes_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_resource_changes_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS resource_changes (
+                resource TEXT PRIMARY KEY,
+                changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the last time `resource` changed.
+    ///
+    /// A resource that was never touched is considered to have changed now,
+    /// and that timestamp is kept until the next change.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::caching::RESOURCE_TAGS;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let changed_at = db.resource_changed_at(RESOURCE_TAGS).await?;
+    ///     println!("Tags last changed at {}", changed_at);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn resource_changed_at(&self, resource: &str) -> Result<DateTime<Utc>, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO resource_changes (resource)
+            VALUES ($1)
+            ON CONFLICT (resource) DO UPDATE SET resource = EXCLUDED.resource
+            RETURNING changed_at
+        "#)
+        .bind(resource)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get("changed_at"))
+    }
+
+    /// Records that the data behind `resource` changed.
+    ///
+    /// Takes any executor so that it can run inside the transaction that
+    /// performs the change.
+    pub(crate) async fn touch_resource<'e, E: PgExecutor<'e>>(executor: E, resource: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO resource_changes (resource, changed_at)
+            VALUES ($1, NOW())
+            ON CONFLICT (resource) DO UPDATE SET changed_at = NOW()
+        "#)
+        .bind(resource)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// `ETag` and `Last-Modified` validators of a resource version.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{HttpRequest, HttpResponse};
+/// use chrono::Utc;
+/// use dothtml_backend::caching::Validators;
+///
+/// fn respond(req: &HttpRequest) -> HttpResponse {
+///     let validators = Validators::new("tags", Utc::now());
+///     if validators.is_fresh(req) {
+///         return validators.not_modified();
+///     }
+///     validators.ok().json(vec!["sales", "support"])
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Validators {
+    etag: EntityTag,
+    last_modified: HttpDate,
+}
+
+impl Validators {
+    /// Builds the validators of `resource` as of `changed_at`.
+    pub fn new(resource: &str, changed_at: DateTime<Utc>) -> Self {
+        Validators {
+            etag: EntityTag::new_strong(format!("{}-{}", resource, changed_at.timestamp_micros())),
+            // HTTP dates have a one second resolution
+            last_modified: HttpDate::from(SystemTime::from(changed_at.trunc_subsecs(0))),
+        }
+    }
+
+    /// Returns whether the client copy described by the request's
+    /// conditional headers is still current.
+    ///
+    /// `If-None-Match` takes precedence over `If-Modified-Since`, as
+    /// required by RFC 9110.
+    pub fn is_fresh(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+
+        match IfModifiedSince::parse(req) {
+            Ok(IfModifiedSince(since)) => self.last_modified <= since,
+            Err(_) => false,
+        }
+    }
+
+    /// Starts a `200 OK` response carrying the validators.
+    pub fn ok(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::Ok();
+        self.apply(&mut builder);
+        builder
+    }
+
+    /// Builds the `304 Not Modified` response for a fresh client copy.
+    pub fn not_modified(&self) -> HttpResponse {
+        let mut builder = HttpResponse::NotModified();
+        self.apply(&mut builder);
+        builder.finish()
+    }
+
+    /// Adds the validators and the revalidation policy to a response.
+    fn apply(&self, builder: &mut HttpResponseBuilder) {
+        builder
+            .insert_header(header::ETag(self.etag.clone()))
+            .insert_header(header::LastModified(self.last_modified))
+            .insert_header(CacheControl(vec![CacheDirective::Private, CacheDirective::NoCache]));
+    }
+}
@@ -0,0 +1,319 @@
+//! # Pre-flight Checks
+//!
+//! Implements `dothtml-backend check` (or `--check`): validates the
+//! configuration and connects to every external service the server needs,
+//! then prints a report and exits non-zero if anything would prevent the
+//! server from starting. Deploy pipelines run it before switching traffic
+//! to a new release.
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+
+use crate::client_ip::TrustedProxy;
+use crate::config::{AppConfig, ListenAddress};
+use crate::database::Database;
+use crate::indexes::IndexState;
+use crate::{classification, crm, email_domain, escalation, knowledge, mailer, outbox, shared, storage};
+
+/// Tables the server creates at startup.
+const EXPECTED_TABLES: [&str; 34] = [
+    "messages",
+    "assignment_history",
+    "companies",
+    "company_aliases",
+    "resource_changes",
+    "message_events",
+    "outbox",
+    "feature_flags",
+    "backfills",
+    "archives",
+    "ratings",
+    "abuse_patterns",
+    "abuse_matches",
+    "spam_training",
+    "spam_tokens",
+    "rules",
+    "suppressions",
+    "email_templates",
+    "notification_prefs",
+    "jwt_keys",
+    "api_tokens",
+    "auth_events",
+    "account_revocations",
+    "webhook_endpoints",
+    "webhook_deliveries",
+    "dead_letters",
+    "agent_presence",
+    "reports",
+    "form_sources",
+    "ingested_submissions",
+    "escalations",
+    "crm_syncs",
+    "scheduling_links",
+    "form_origins",
+];
+
+/// Outcome of a single check.
+///
+/// * `Pass` - The component works (detail: what was checked)
+/// * `Warn` - The server will start but something needs attention
+/// * `Fail` - The server would not start or not work (detail: the error)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass(String),
+    Warn(String),
+    Fail(String),
+}
+
+/// Results of all the pre-flight checks, in the order they ran.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::check::{CheckReport, Outcome};
+///
+/// let mut report = CheckReport::default();
+/// report.record("configuration", Outcome::Pass("valid".to_string()));
+/// report.record("database", Outcome::Fail("connection refused".to_string()));
+/// assert!(!report.passed());
+/// assert!(report.to_string().contains("FAIL  database: connection refused"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    checks: Vec<(&'static str, Outcome)>,
+}
+
+impl CheckReport {
+    /// Adds the outcome of a check to the report.
+    pub fn record(&mut self, name: &'static str, outcome: Outcome) {
+        self.checks.push((name, outcome));
+    }
+
+    /// Returns whether no check failed. Warnings do not fail the report.
+    pub fn passed(&self) -> bool {
+        !self.checks.iter().any(|(_, outcome)| matches!(outcome, Outcome::Fail(_)))
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, outcome) in &self.checks {
+            let (label, detail) = match outcome {
+                Outcome::Pass(detail) => ("ok  ", detail),
+                Outcome::Warn(detail) => ("WARN", detail),
+                Outcome::Fail(detail) => ("FAIL", detail),
+            };
+            writeln!(f, "{}  {}: {}", label, name, detail)?;
+        }
+        let failures = self.checks.iter().filter(|(_, outcome)| matches!(outcome, Outcome::Fail(_))).count();
+        if failures == 0 {
+            writeln!(f, "All checks passed")
+        } else {
+            writeln!(f, "{} check(s) failed", failures)
+        }
+    }
+}
+
+/// Runs every pre-flight check against `config`.
+///
+/// Checks that depend on the database are skipped when it cannot be
+/// reached. Nothing is written: missing tables and indexes are reported,
+/// not created.
+pub async fn run(config: &AppConfig) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    report.record("configuration", check_configuration(config));
+    report.record("blob store", match storage::from_config(config) {
+        Ok(Some(_)) => Outcome::Pass(format!("{} store configured", config.blob_store.as_deref().unwrap_or_default())),
+        Ok(None) => Outcome::Pass("disabled, bodies stay in PostgreSQL".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    report.record("shared state", match shared::from_config(config).await {
+        Ok(_) if config.redis_url.is_some() => Outcome::Pass("connected to Redis".to_string()),
+        Ok(_) => Outcome::Pass("in memory".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    report.record("email domains", match email_domain::from_config(config) {
+        Ok(Some(_)) => Outcome::Pass("MX check enabled".to_string()),
+        Ok(None) => Outcome::Pass("disabled, only the email format is checked".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    report.record("classifier", match classification::from_config(config) {
+        Ok(Some(_)) => Outcome::Pass(format!("{} classifier ready", config.classifier)),
+        Ok(None) => Outcome::Pass("disabled, messages are not categorized".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    match mailer::from_config(config) {
+        Ok(Some(mailer)) => {
+            let sending = format!(
+                "sending as {} with the {} transport",
+                config.mail_from.as_deref().unwrap_or_default(),
+                mailer.transport().name()
+            );
+            report.record("mailer", match mailer.transport().test_connection().await {
+                Ok(true) => Outcome::Pass(format!("{}, relay reachable", sending)),
+                Ok(false) => Outcome::Pass(sending),
+                Err(e) => Outcome::Fail(format!("{}, but the relay is unreachable: {}", sending, e)),
+            });
+            report.record("mail DNS", match mailer.missing_dns_records().await {
+                Ok(missing) if missing.is_empty() => Outcome::Pass(format!("{} publishes the records receivers check", mailer.domain())),
+                Ok(missing) => Outcome::Warn(missing.join("; ")),
+                Err(e) => Outcome::Warn(format!("cannot check the records of {}: {}", mailer.domain(), e)),
+            });
+        }
+        Ok(None) => report.record("mailer", Outcome::Pass("disabled, no emails are sent".to_string())),
+        Err(e) => report.record("mailer", Outcome::Fail(e.to_string())),
+    }
+    report.record("knowledge base", match knowledge::from_config(config) {
+        Ok(Some(_)) => Outcome::Pass(format!("{} knowledge base ready", config.knowledge_base.as_deref().unwrap_or_default())),
+        Ok(None) => Outcome::Pass("disabled, no articles are suggested".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    report.record("issue tracker", match escalation::from_config(config) {
+        Ok(Some(tracker)) if config.issue_tracker_webhook_secret.is_none() => Outcome::Warn(format!(
+            "{} ready, but closed issues do not resolve their messages without ISSUE_TRACKER_WEBHOOK_SECRET",
+            tracker.provider()
+        )),
+        Ok(Some(tracker)) => Outcome::Pass(format!("{} ready", tracker.provider())),
+        Ok(None) => Outcome::Pass("disabled, messages cannot be escalated".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+    report.record("CRM", match crm::from_config(config) {
+        Ok(Some(connector)) => Outcome::Pass(format!("{} ready", connector.provider())),
+        Ok(None) => Outcome::Pass("disabled, senders are not synced".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+
+    let db = match env::var("DATABASE_URL") {
+        Err(_) => {
+            report.record("database", Outcome::Fail("DATABASE_URL is not set".to_string()));
+            return report;
+        }
+        Ok(_) => match Database::new().await {
+            Ok(db) => db,
+            Err(e) => {
+                report.record("database", Outcome::Fail(e.to_string()));
+                return report;
+            }
+        },
+    };
+    report.record("database", match db.ping().await {
+        Ok(latency) => Outcome::Pass(format!("connected in {} ms", latency.as_millis())),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+
+    report.record("outbox publisher", match outbox::publisher_from_config(config, &db).await {
+        Ok(Some(_)) => Outcome::Pass(format!("{} publisher ready", config.outbox_publisher.as_deref().unwrap_or_default())),
+        Ok(None) => Outcome::Pass("disabled, events stay in the outbox".to_string()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+
+    report.record("schema", match db.missing_tables(&EXPECTED_TABLES).await {
+        Ok(missing) if missing.is_empty() => Outcome::Pass(format!("{} tables present", EXPECTED_TABLES.len())),
+        Ok(missing) => Outcome::Warn(format!("missing {}, created at startup", missing.join(", "))),
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+
+    report.record("indexes", match db.check_indexes().await {
+        Ok(indexes) => {
+            let absent: Vec<String> = indexes
+                .iter()
+                .filter(|(_, state)| *state != IndexState::Present)
+                .map(|(index, state)| format!("{} ({})", index.name, state))
+                .collect();
+            if absent.is_empty() {
+                Outcome::Pass(format!("{} required indexes present", indexes.len()))
+            } else if config.create_missing_indexes {
+                Outcome::Warn(format!("{}, built at startup", absent.join(", ")))
+            } else {
+                Outcome::Warn(format!("{}, run `dothtml-backend indexes --create`", absent.join(", ")))
+            }
+        }
+        Err(e) => Outcome::Fail(e.to_string()),
+    });
+
+    db.close().await;
+    report
+}
+
+/// Checks settings that are only validated when the server uses them.
+fn check_configuration(config: &AppConfig) -> Outcome {
+    let mut problems = Vec::new();
+
+    if config.otel_exporter_endpoint.is_some() && cfg!(not(feature = "otel")) {
+        problems.push("OTEL_EXPORTER_OTLP_ENDPOINT requires building with the `otel` feature".to_string());
+    }
+    if config.sentry_dsn.is_some() && cfg!(not(feature = "sentry")) {
+        problems.push("SENTRY_DSN requires building with the `sentry` feature".to_string());
+    }
+    if let Some(dir) = &config.admin_ui_path {
+        if cfg!(not(feature = "admin-ui")) {
+            problems.push("ADMIN_UI_PATH requires building with the `admin-ui` feature".to_string());
+        } else if !Path::new(dir).join("index.html").is_file() {
+            problems.push(format!("ADMIN_UI_PATH `{}` does not contain an index.html", dir));
+        }
+    }
+    if config.archive_export_after_months.is_some() && config.blob_store.is_none() {
+        problems.push("ARCHIVE_EXPORT_AFTER_MONTHS requires BLOB_STORE".to_string());
+    }
+    if config.satisfaction_survey && config.sender_token_secret.is_none() {
+        problems.push("SATISFACTION_SURVEY requires SENDER_TOKEN_SECRET".to_string());
+    }
+    if config.followup_page_url.is_some() && config.sender_token_secret.is_none() {
+        problems.push("FOLLOWUP_PAGE_URL requires SENDER_TOKEN_SECRET".to_string());
+    }
+    if config.magic_link_login {
+        for (missing, variable) in [
+            (config.session_secret.is_none(), "SESSION_SECRET"),
+            (config.magic_link_url.is_none(), "MAGIC_LINK_URL"),
+            (config.mail_from.is_none(), "MAIL_FROM"),
+        ] {
+            if missing {
+                problems.push(format!("MAGIC_LINK_LOGIN requires {}", variable));
+            }
+        }
+        if config.jwt_key_overlap_hours < config.session_ttl_hours {
+            problems.push("JWT_KEY_OVERLAP_HOURS is shorter than SESSION_TTL_HOURS, rotations end sessions early".to_string());
+        }
+    }
+    for (value, variable) in [
+        (config.archive_resolved_after_days, "ARCHIVE_RESOLVED_AFTER_DAYS"),
+        (config.trash_retention_days, "TRASH_RETENTION_DAYS"),
+        (config.auto_release_after_hours.unwrap_or_default(), "AUTO_RELEASE_AFTER_HOURS"),
+    ] {
+        if !(0..=i64::from(i32::MAX)).contains(&value) {
+            problems.push(format!("{} must be between 0 and {}", variable, i32::MAX));
+        }
+    }
+    // An invalid value is dropped when loading, with the reason in the log
+    if env::var("OFFICE_HOURS").is_ok_and(|hours| !hours.trim().is_empty()) && config.office_hours.is_none() {
+        problems.push("OFFICE_HOURS, OFFICE_TIMEZONE or OFFICE_HOLIDAYS is invalid".to_string());
+    }
+    if config.cors_allowed_origins.is_empty() {
+        problems.push("CORS_ALLOWED_ORIGINS lists no origin".to_string());
+    }
+
+    let mut warnings = Vec::new();
+    if config.admin_token.is_none() {
+        warnings.push("ADMIN_TOKEN is unset, admin endpoints are disabled".to_string());
+    }
+    let listens_on_socket = std::iter::once(&config.bind_address)
+        .chain(&config.admin_bind_address)
+        .any(|address| matches!(address, ListenAddress::Unix(_)));
+    if listens_on_socket && !config.trusted_proxies.contains(&TrustedProxy::UnixSocket) {
+        warnings.push(
+            "TRUSTED_PROXIES does not list `unix`, clients on the Unix socket have no known address \
+             and escape the rate limits and the authentication lockout"
+                .to_string(),
+        );
+    }
+
+    if !problems.is_empty() {
+        Outcome::Fail(problems.join("; "))
+    } else if !warnings.is_empty() {
+        Outcome::Warn(warnings.join("; "))
+    } else {
+        Outcome::Pass("valid".to_string())
+    }
+}
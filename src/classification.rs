@@ -0,0 +1,335 @@
+//! # Message Classification
+//!
+//! Labels every incoming message with a [`Category`] (sales, support,
+//! partnership, jobs or spam), stored in its `category` column, so that the
+//! inbox can be filtered with `category:sales` and messages routed by
+//! topic. Classifiers implement the [`Classifier`] trait and are selected at
+//! startup with `CLASSIFIER`.
+//!
+//! ## Classifiers
+//!
+//! - [`KeywordClassifier`] - Keyword rules, built in or read from a JSON file (default)
+//! - [`ApiClassifier`] - An external classification API, such as an ML model
+//!
+//! The JSON file of the keyword rules lists the keywords of each category,
+//! replacing the built-in ones:
+//!
+//! ```json
+//! {
+//!   "sales": ["quote", "pricing", "demo"],
+//!   "support": ["bug", "error", "not working"],
+//!   "jobs": ["internship", "resume"]
+//! }
+//! ```
+//!
+//! A message no classifier recognizes is left without category. A
+//! classifier failing does not fail the message, which is stored without
+//! category.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+
+/// Spam score from which the keyword classifier files a message as spam,
+/// whatever its keywords (see the `spam` module).
+pub const SPAM_SCORE_THRESHOLD: f64 = 0.9;
+
+/// Time the external API has to answer before the message is left without
+/// category.
+const API_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Topic of a message.
+///
+/// * `Sales` - Quotes, pricing and purchases
+/// * `Support` - Problems with a product or service
+/// * `Partnership` - Partnership, reselling and sponsorship proposals
+/// * `Jobs` - Applications and hiring
+/// * `Spam` - Unsolicited messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Sales,
+    Support,
+    Partnership,
+    Jobs,
+    Spam,
+}
+
+impl Category {
+    /// Every category, in the order ties are broken.
+    pub const ALL: [Category; 5] = [
+        Category::Sales,
+        Category::Support,
+        Category::Partnership,
+        Category::Jobs,
+        Category::Spam,
+    ];
+
+    /// Returns the name stored in the `category` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Sales => "sales",
+            Category::Support => "support",
+            Category::Partnership => "partnership",
+            Category::Jobs => "jobs",
+            Category::Spam => "spam",
+        }
+    }
+
+    /// Parses a category name, case-insensitively.
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|category| category.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A message about to be stored, as seen by a classifier.
+///
+/// # Fields
+///
+/// * `name` - The sender's name
+/// * `email` - The sender's email address
+/// * `company` - The company of the sender
+/// * `message` - The full message body
+/// * `spam_score` - Spam score of the message, once the spam model is trained
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageDraft<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub company: &'a str,
+    pub message: &'a str,
+    pub spam_score: Option<f64>,
+}
+
+/// Labels messages with their topic.
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    /// Returns the category of `draft`, `None` if it fits none.
+    async fn classify(&self, draft: &MessageDraft<'_>) -> io::Result<Option<Category>>;
+}
+
+/// Splits `text` into lowercase words, ignoring punctuation.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Classifier matching keywords.
+///
+/// A message goes to the category with the most of its keywords found in
+/// the company and message, ties going to the category listed first in
+/// [`Category::ALL`]. Messages whose spam score reaches
+/// [`SPAM_SCORE_THRESHOLD`] are spam.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::classification::{Category, Classifier, KeywordClassifier, MessageDraft};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let classifier = KeywordClassifier::default();
+///     let draft = MessageDraft {
+///         name: "John Doe",
+///         email: "john@example.com",
+///         company: "ACME Corp",
+///         message: "Could you send us a quote for a new website?",
+///         spam_score: None,
+///     };
+///     assert_eq!(classifier.classify(&draft).await?, Some(Category::Sales));
+///
+///     let draft = MessageDraft { spam_score: Some(0.97), ..draft };
+///     assert_eq!(classifier.classify(&draft).await?, Some(Category::Spam));
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeywordClassifier {
+    /// Keywords of each category, as space-joined words, in category order
+    rules: Vec<(Category, Vec<String>)>,
+}
+
+impl KeywordClassifier {
+    /// Creates a classifier with the keywords of each category.
+    pub fn new(rules: BTreeMap<Category, Vec<String>>) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|(category, keywords)| {
+                let keywords = keywords
+                    .iter()
+                    .map(|keyword| words(keyword).join(" "))
+                    .filter(|keyword| !keyword.is_empty())
+                    .collect();
+                (category, keywords)
+            })
+            .collect();
+        KeywordClassifier { rules }
+    }
+
+    /// Reads the keywords of each category from a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file cannot be read or is not
+    /// a JSON object of keyword lists keyed by category.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read(path)?;
+        let rules = serde_json::from_slice(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid classifier rules {}: {}", path.display(), e))
+        })?;
+        Ok(KeywordClassifier::new(rules))
+    }
+}
+
+impl Default for KeywordClassifier {
+    /// Creates a classifier with the built-in keywords.
+    fn default() -> Self {
+        let rules: [(Category, &[&str]); 5] = [
+            (Category::Sales, &[
+                "quote", "quotation", "pricing", "price", "prices", "cost", "budget", "estimate", "purchase",
+                "buy", "demo", "proposal", "license", "subscription", "devis", "tarif",
+            ]),
+            (Category::Support, &[
+                "bug", "error", "issue", "problem", "broken", "crash", "not working", "doesn t work",
+                "unable", "password", "login", "outage", "support", "refund",
+            ]),
+            (Category::Partnership, &[
+                "partnership", "partner", "partners", "collaboration", "collaborate", "reseller", "resell",
+                "affiliate", "sponsorship", "sponsor", "integration", "joint venture",
+            ]),
+            (Category::Jobs, &[
+                "job", "jobs", "career", "careers", "hiring", "internship", "intern", "resume", "cv",
+                "position", "vacancy", "application", "apply", "recruitment", "candidate",
+            ]),
+            (Category::Spam, &[
+                "casino", "viagra", "bitcoin", "crypto", "backlinks", "guest post", "seo services",
+                "lottery", "loan", "click here", "unsubscribe",
+            ]),
+        ];
+        KeywordClassifier::new(
+            rules
+                .into_iter()
+                .map(|(category, keywords)| (category, keywords.iter().map(|keyword| keyword.to_string()).collect()))
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl Classifier for KeywordClassifier {
+    async fn classify(&self, draft: &MessageDraft<'_>) -> io::Result<Option<Category>> {
+        if draft.spam_score.is_some_and(|score| score >= SPAM_SCORE_THRESHOLD) {
+            return Ok(Some(Category::Spam));
+        }
+
+        // Keywords are matched as whole words, so phrases are matched on the joined words
+        let text = format!(" {} {} ", words(draft.company).join(" "), words(draft.message).join(" "));
+        let mut best: Option<(usize, Category)> = None;
+        for (category, keywords) in &self.rules {
+            let found: HashSet<&String> = keywords
+                .iter()
+                .filter(|keyword| text.contains(&format!(" {} ", keyword)))
+                .collect();
+            // Rules are in category order, so ties keep the first category
+            if found.len() > best.map_or(0, |(count, _)| count) {
+                best = Some((found.len(), *category));
+            }
+        }
+        Ok(best.map(|(_, category)| category))
+    }
+}
+
+/// Answer of a classification API.
+#[derive(Debug, Deserialize)]
+struct ApiClassification {
+    category: Option<String>,
+}
+
+/// Classifier delegating to an external API.
+///
+/// The API is called with `POST <url>` and a JSON [`MessageDraft`], and must
+/// answer `{"category": "sales"}`, or a `null` category for messages that
+/// fit none. Categories it makes up are ignored.
+#[derive(Debug, Clone)]
+pub struct ApiClassifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl ApiClassifier {
+    /// Creates a classifier calling the API at `url`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(API_TIMEOUT)
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(ApiClassifier { client, url: url.to_string() })
+    }
+}
+
+#[async_trait]
+impl Classifier for ApiClassifier {
+    async fn classify(&self, draft: &MessageDraft<'_>) -> io::Result<Option<Category>> {
+        let answer: ApiClassification = self
+            .client
+            .post(&self.url)
+            .json(draft)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?
+            .json()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(answer.category.as_deref().and_then(Category::parse))
+    }
+}
+
+/// Builds the classifier selected by `CLASSIFIER`.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when classification is disabled (`CLASSIFIER=none`).
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `CLASSIFIER` names an unknown classifier
+/// - The file of `CLASSIFIER_RULES_PATH` cannot be read or parsed
+/// - `CLASSIFIER=api` is used without `CLASSIFIER_URL`
+pub fn from_config(config: &AppConfig) -> io::Result<Option<Arc<dyn Classifier>>> {
+    match config.classifier.as_str() {
+        "none" => Ok(None),
+        "keywords" => Ok(Some(match &config.classifier_rules_path {
+            Some(path) => Arc::new(KeywordClassifier::load(path)?),
+            None => Arc::new(KeywordClassifier::default()),
+        })),
+        "api" => {
+            let url = config.classifier_url.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "CLASSIFIER_URL must be set when CLASSIFIER=api")
+            })?;
+            Ok(Some(Arc::new(ApiClassifier::new(url)?)))
+        }
+        other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown CLASSIFIER: {}", other))),
+    }
+}
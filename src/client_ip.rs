@@ -0,0 +1,228 @@
+//! # Client IP Resolution
+//!
+//! Determines the address of the client behind a request, for IP-based
+//! controls such as the contact form rate limit.
+//!
+//! `Forwarded` and `X-Forwarded-For` headers can be set by anyone, so they
+//! are only read when the connection comes from a proxy listed in
+//! `TRUSTED_PROXIES`. The addresses they list are then walked from the
+//! closest hop backwards, and the first one that is not itself a trusted
+//! proxy is the client. A client sending its own forwarded headers only
+//! adds entries that come before that address, which are never reached.
+//!
+//! `Forwarded` (RFC 7239) takes precedence over `X-Forwarded-For` when a
+//! request carries both.
+//!
+//! A client connected through a Unix domain socket that is not trusted has
+//! no address: the rate limits and the authentication lockout leave it
+//! alone rather than counting all such clients as one.
+
+use std::fmt;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use actix_web::{dev::Payload, http::header::HeaderMap, web, FromRequest, HttpRequest};
+
+use crate::config::LiveConfig;
+
+/// A proxy whose forwarded headers are trusted.
+///
+/// * `Network` - An address or a CIDR block, e.g. `10.0.0.1` or `10.0.0.0/8`
+/// * `UnixSocket` - Any connection received on a Unix domain socket, written `unix`
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::client_ip::TrustedProxy;
+///
+/// let network: TrustedProxy = "10.0.0.0/8".parse().unwrap();
+/// assert!(network.contains(Some("10.1.2.3".parse().unwrap())));
+/// assert!(!network.contains(Some("192.168.1.1".parse().unwrap())));
+/// assert!("unix".parse::<TrustedProxy>().unwrap().contains(None));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustedProxy {
+    Network { address: IpAddr, prefix: u8 },
+    UnixSocket,
+}
+
+impl TrustedProxy {
+    /// Returns whether a peer is this proxy. `None` stands for a peer
+    /// connected through a Unix domain socket.
+    pub fn contains(&self, peer: Option<IpAddr>) -> bool {
+        match (self, peer) {
+            (TrustedProxy::UnixSocket, None) => true,
+            (TrustedProxy::Network { address, prefix }, Some(peer)) => match (address, peer.to_canonical()) {
+                (IpAddr::V4(network), IpAddr::V4(peer)) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(peer) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(peer)) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(peer) & mask
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("unix") {
+            return Ok(TrustedProxy::UnixSocket);
+        }
+
+        let (address, prefix) = value.split_once('/').map_or((value, None), |(address, prefix)| (address, Some(prefix)));
+        let address: IpAddr = address.parse().map_err(|_| format!("invalid address `{}`", address))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max_prefix,
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length `{}`", prefix))?,
+        };
+        Ok(TrustedProxy::Network { address, prefix })
+    }
+}
+
+impl fmt::Display for TrustedProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustedProxy::Network { address, prefix } => write!(f, "{}/{}", address, prefix),
+            TrustedProxy::UnixSocket => f.write_str("unix"),
+        }
+    }
+}
+
+/// Resolves the client address of a request.
+///
+/// # Arguments
+///
+/// * `peer` - Address of the connected peer, `None` for a Unix domain socket
+/// * `headers` - Request headers
+/// * `trusted` - Proxies whose forwarded headers are trusted
+///
+/// # Returns
+///
+/// Returns the client address, or `None` if the request came through a
+/// Unix domain socket that is not trusted.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+/// use dothtml_backend::client_ip::{resolve, TrustedProxy};
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert(
+///     HeaderName::from_static("x-forwarded-for"),
+///     HeaderValue::from_static("1.2.3.4, 203.0.113.7, 10.0.0.2"),
+/// );
+/// let trusted = vec!["10.0.0.0/8".parse::<TrustedProxy>().unwrap()];
+///
+/// // 1.2.3.4 was added by the client itself and is ignored
+/// let client = resolve(Some("10.0.0.1".parse().unwrap()), &headers, &trusted);
+/// assert_eq!(client, Some("203.0.113.7".parse().unwrap()));
+///
+/// // Headers sent by an untrusted peer are ignored
+/// let client = resolve(Some("198.51.100.1".parse().unwrap()), &headers, &trusted);
+/// assert_eq!(client, Some("198.51.100.1".parse().unwrap()));
+/// ```
+pub fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[TrustedProxy]) -> Option<IpAddr> {
+    let is_trusted = |hop: Option<IpAddr>| trusted.iter().any(|proxy| proxy.contains(hop));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let hops = if headers.contains_key("forwarded") { forwarded_hops(headers) } else { x_forwarded_for_hops(headers) };
+
+    // Walk from the closest hop; an unreadable entry cannot be trusted to
+    // point any further, so the last known hop is used instead
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(hop) => {
+                client = Some(hop);
+                if !is_trusted(client) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    client
+}
+
+/// Lists the `for=` addresses of the `Forwarded` headers, in order.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("forwarded")
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Lists the addresses of the `X-Forwarded-For` headers, in order.
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Parses an address with an optional port: `1.2.3.4`, `1.2.3.4:80`,
+/// `2001:db8::1` or `[2001:db8::1]:80`. Obfuscated identifiers such as
+/// `unknown` or `_hidden` are not addresses.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(address) = node.parse() {
+        return Some(address);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(address, _)| address.parse().ok());
+    }
+    node.rsplit_once(':').and_then(|(address, _)| address.parse().ok())
+}
+
+/// Extractor for the client address of a request, resolved with the
+/// `TRUSTED_PROXIES` of the live configuration.
+///
+/// Holds `None` when the request came through a Unix domain socket that is
+/// not listed as trusted.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{HttpResponse, Responder};
+/// use dothtml_backend::client_ip::ClientIp;
+///
+/// async fn whoami(ClientIp(client): ClientIp) -> impl Responder {
+///     HttpResponse::Ok().body(client.map(|ip| ip.to_string()).unwrap_or_default())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequest for ClientIp {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let client = match req.app_data::<web::Data<LiveConfig>>() {
+            Some(config) => resolve(peer, req.headers(), &config.load().trusted_proxies),
+            None => peer,
+        };
+        ready(Ok(ClientIp(client)))
+    }
+}
@@ -0,0 +1,150 @@
+//! # Concurrency Limits
+//!
+//! Caps the number of requests to expensive endpoints that run at the same
+//! time on one instance. A request arriving while its scope is full is
+//! answered right away with 503 Service Unavailable instead of waiting for
+//! a database connection, so that a burst of searches or reports cannot
+//! exhaust the connection pool used by every other endpoint.
+//!
+//! ## Scopes
+//!
+//! - [`Scope::Search`] - `GET /inbox` listings and filter searches
+//!   (`SEARCH_CONCURRENCY_LIMIT`, default: 4)
+//! - [`Scope::Reports`] - `GET /stats`, `GET /senders/{email}` and
+//!   `GET /companies` aggregations (`REPORTS_CONCURRENCY_LIMIT`, default: 2)
+//! - [`Scope::Export`] - `GET /admin/export/anonymized` exports streaming
+//!   the whole inbox (`EXPORT_CONCURRENCY_LIMIT`, default: 1)
+//!
+//! Routes opt in by wrapping themselves with [`limit`], and the limits are
+//! shared by all workers through a [`ConcurrencyLimits`] in the app data.
+
+use std::sync::Arc;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, HttpResponse,
+};
+use tokio::sync::Semaphore;
+
+use crate::config::AppConfig;
+
+/// Group of endpoints sharing a concurrency limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Search,
+    Reports,
+    Export,
+}
+
+/// Concurrency limits of every scope, shared by all workers.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::concurrency::{ConcurrencyLimits, Scope};
+/// use dothtml_backend::config::AppConfig;
+///
+/// let limits = ConcurrencyLimits::from_config(&AppConfig {
+///     reports_concurrency_limit: Some(1),
+///     ..AppConfig::default()
+/// });
+/// let permit = limits.try_acquire(Scope::Reports);
+/// assert!(matches!(permit, Ok(Some(_))));
+/// assert!(limits.try_acquire(Scope::Reports).is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLimits {
+    search: Option<Arc<Semaphore>>,
+    reports: Option<Arc<Semaphore>>,
+    export: Option<Arc<Semaphore>>,
+}
+
+/// Returned by [`ConcurrencyLimits::try_acquire`] when a scope is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopeFull;
+
+impl ConcurrencyLimits {
+    /// Creates the limits configured in `config`.
+    pub fn from_config(config: &AppConfig) -> Self {
+        let semaphore = |limit: Option<usize>| limit.map(|limit| Arc::new(Semaphore::new(limit)));
+        ConcurrencyLimits {
+            search: semaphore(config.search_concurrency_limit),
+            reports: semaphore(config.reports_concurrency_limit),
+            export: semaphore(config.export_concurrency_limit),
+        }
+    }
+
+    /// Reserves a slot in `scope`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a permit to hold until the request is handled, `None` if the
+    /// scope is unlimited.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ScopeFull`] if every slot of the scope is taken.
+    pub fn try_acquire(&self, scope: Scope) -> Result<Option<tokio::sync::OwnedSemaphorePermit>, ScopeFull> {
+        let semaphore = match scope {
+            Scope::Search => &self.search,
+            Scope::Reports => &self.reports,
+            Scope::Export => &self.export,
+        };
+        match semaphore {
+            Some(semaphore) => semaphore.clone().try_acquire_owned().map(Some).map_err(|_| ScopeFull),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Middleware handling a request only if `scope` has a free slot.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{middleware, web, App, HttpResponse};
+/// use dothtml_backend::concurrency::{self, Scope};
+///
+/// let app = App::new().route(
+///     "/stats",
+///     web::get()
+///         .to(HttpResponse::Ok)
+///         .wrap(middleware::from_fn(|req, next| concurrency::limit(Scope::Reports, req, next))),
+/// );
+/// ```
+///
+/// Rejected request:
+/// ```text
+/// 503 Service Unavailable
+/// Retry-After: 1
+/// {
+///   "status": "error",
+///   "message": "Too many concurrent requests, please try again later"
+/// }
+/// ```
+pub async fn limit<B: MessageBody>(
+    scope: Scope,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let permit = match req.app_data::<web::Data<ConcurrencyLimits>>().map(|limits| limits.try_acquire(scope)) {
+        Some(Err(ScopeFull)) => {
+            let res = HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, "1"))
+                .json(serde_json::json!({
+                    "status": "error",
+                    "message": "Too many concurrent requests, please try again later"
+                }));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+        Some(Ok(permit)) => permit,
+        None => None,
+    };
+
+    let res = next.call(req).await?;
+    drop(permit);
+    Ok(res.map_into_left_body())
+}
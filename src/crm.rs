@@ -0,0 +1,559 @@
+//! # CRM Sync
+//!
+//! Upserts the sender of every new message as a contact of the team's CRM,
+//! so that sales see the inquiry next to the rest of the relationship.
+//! Connectors implement the [`CrmConnector`] trait and are selected at
+//! startup from the configuration:
+//!
+//! - [`HubSpotConnector`] - HubSpot contacts, matched by email (`CRM_PROVIDER=hubspot`)
+//! - [`PipedriveConnector`] - Pipedrive persons, matched by email, in an
+//!   organization named after the company (`CRM_PROVIDER=pipedrive`)
+//!
+//! The sender's name, email, phone number, company and country are mapped
+//! to the standard fields of the CRM, the country going to the field named
+//! by `CRM_COUNTRY_FIELD` (HubSpot: `country` by default; Pipedrive has no
+//! standard one, so it is only sent to a custom field given there).
+//!
+//! ## Queue
+//!
+//! The sync job (see `jobs::spawn_crm_sync_job`) queues a sync in the
+//! `crm_syncs` table for every message received since the CRM was set up,
+//! read from the message event log (see the `events` module), whatever the
+//! way the message came in. It then runs the due syncs, retrying a failed
+//! one with an exponential backoff, and gives it up after
+//! [`MAX_SYNC_ATTEMPTS`] attempts. Messages classified as spam, or trashed
+//! before their sync, are skipped.
+//!
+//! The backoffice reads the status of a message's sync with
+//! `GET /inbox/{id}/crm`, and syncs it again with `POST /inbox/{id}/crm/sync`,
+//! which also syncs messages received before the CRM was set up.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::Database;
+
+/// Syncs attempted for a message before it is given up.
+pub const MAX_SYNC_ATTEMPTS: i32 = 8;
+
+/// Longest wait between two attempts of a sync, in seconds.
+const MAX_RETRY_DELAY_SECS: f64 = 60.0 * 60.0;
+
+/// Time the CRM has to answer a request.
+const CRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connectors accepted by `CRM_PROVIDER`.
+pub const CRM_PROVIDERS: [&str; 2] = ["hubspot", "pipedrive"];
+
+/// A sender, as sent to the CRM.
+///
+/// # Fields
+///
+/// * `name` - Full name of the sender
+/// * `email` - Email, identifying the contact in the CRM
+/// * `phone_number` - Phone number, empty if not given
+/// * `company` - Company, empty if not given
+/// * `country_region` - Country or region, empty if not given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrmContact {
+    pub name: String,
+    pub email: String,
+    pub phone_number: String,
+    pub company: String,
+    pub country_region: String,
+}
+
+impl CrmContact {
+    /// Splits the name into a first name and a last name, at the first
+    /// space, for the CRMs storing them apart.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::crm::CrmContact;
+    ///
+    /// let contact = CrmContact {
+    ///     name: "Jane van Dijk".to_string(),
+    ///     email: "jane@example.com".to_string(),
+    ///     phone_number: String::new(),
+    ///     company: String::new(),
+    ///     country_region: String::new(),
+    /// };
+    /// assert_eq!(contact.first_and_last_name(), ("Jane", "van Dijk"));
+    /// ```
+    pub fn first_and_last_name(&self) -> (&str, &str) {
+        let name = self.name.trim();
+        match name.split_once(char::is_whitespace) {
+            Some((first, last)) => (first, last.trim()),
+            None => (name, ""),
+        }
+    }
+}
+
+/// A CRM the senders are synced to.
+#[async_trait]
+pub trait CrmConnector: Send + Sync {
+    /// Returns the name of the CRM, as in [`CRM_PROVIDERS`].
+    fn provider(&self) -> &'static str;
+
+    /// Creates the contact, or updates the one with the same email.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the contact in the CRM.
+    async fn upsert_contact(&self, contact: &CrmContact) -> io::Result<String>;
+}
+
+fn http_client() -> io::Result<reqwest::Client> {
+    reqwest::Client::builder().timeout(CRM_TIMEOUT).build().map_err(io::Error::other)
+}
+
+/// Sends a request and reads its JSON answer, failing on non-2xx statuses.
+async fn send_json(request: reqwest::RequestBuilder) -> io::Result<Value> {
+    request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(io::Error::other)?
+        .json()
+        .await
+        .map_err(io::Error::other)
+}
+
+/// HubSpot contacts, upserted by email with the token of a private app
+/// allowed to write contacts.
+#[derive(Debug, Clone)]
+pub struct HubSpotConnector {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    country_field: String,
+}
+
+impl HubSpotConnector {
+    /// Creates a connector calling the API at `api_url`, setting the
+    /// country in the `country_field` property.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(api_url: &str, token: &str, country_field: &str) -> io::Result<Self> {
+        Ok(HubSpotConnector {
+            client: http_client()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            country_field: country_field.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl CrmConnector for HubSpotConnector {
+    fn provider(&self) -> &'static str {
+        "hubspot"
+    }
+
+    async fn upsert_contact(&self, contact: &CrmContact) -> io::Result<String> {
+        let (first_name, last_name) = contact.first_and_last_name();
+        let mut properties = Map::new();
+        properties.insert("email".to_string(), json!(contact.email));
+        properties.insert("firstname".to_string(), json!(first_name));
+        // Fields left empty by the sender keep the value the CRM has
+        for (property, value) in [
+            ("lastname", last_name),
+            ("phone", &contact.phone_number),
+            ("company", &contact.company),
+            (&self.country_field, &contact.country_region),
+        ] {
+            if !value.is_empty() {
+                properties.insert(property.to_string(), json!(value));
+            }
+        }
+
+        let answer = send_json(
+            self.client
+                .post(format!("{}/crm/v3/objects/contacts/batch/upsert", self.api_url))
+                .bearer_auth(&self.token)
+                .json(&json!({ "inputs": [{ "idProperty": "email", "id": contact.email, "properties": properties }] })),
+        )
+        .await?;
+
+        answer
+            .pointer("/results/0/id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::other("HubSpot returned no contact ID"))
+    }
+}
+
+/// Pipedrive persons, found by email and created or updated with an API
+/// token, in an organization found or created by the company name.
+#[derive(Debug, Clone)]
+pub struct PipedriveConnector {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    country_field: Option<String>,
+}
+
+impl PipedriveConnector {
+    /// Creates a connector calling the API at `api_url`, setting the
+    /// country in the custom field with key `country_field`, if any.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(api_url: &str, token: &str, country_field: Option<&str>) -> io::Result<Self> {
+        Ok(PipedriveConnector {
+            client: http_client()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            country_field: country_field.map(str::to_string),
+        })
+    }
+
+    /// Returns the ID of the first exact match of `term` in `field` of the
+    /// `kind` items (`persons` or `organizations`).
+    async fn search(&self, kind: &str, field: &str, term: &str) -> io::Result<Option<i64>> {
+        let answer = send_json(
+            self.client
+                .get(format!("{}/v1/{}/search", self.api_url, kind))
+                .query(&[("term", term), ("fields", field), ("exact_match", "true"), ("limit", "1"), ("api_token", &self.token)]),
+        )
+        .await?;
+
+        Ok(answer.pointer("/data/items/0/item/id").and_then(Value::as_i64))
+    }
+
+    /// Returns the ID of the organization named `name`, creating it if needed.
+    async fn organization(&self, name: &str) -> io::Result<i64> {
+        if let Some(id) = self.search("organizations", "name", name).await? {
+            return Ok(id);
+        }
+        let answer = send_json(
+            self.client
+                .post(format!("{}/v1/organizations", self.api_url))
+                .query(&[("api_token", &self.token)])
+                .json(&json!({ "name": name })),
+        )
+        .await?;
+
+        answer.pointer("/data/id").and_then(Value::as_i64).ok_or_else(|| io::Error::other("Pipedrive returned no organization ID"))
+    }
+}
+
+#[async_trait]
+impl CrmConnector for PipedriveConnector {
+    fn provider(&self) -> &'static str {
+        "pipedrive"
+    }
+
+    async fn upsert_contact(&self, contact: &CrmContact) -> io::Result<String> {
+        let mut person = Map::new();
+        person.insert("name".to_string(), json!(contact.name));
+        person.insert("email".to_string(), json!([{ "value": contact.email, "primary": true }]));
+        if !contact.phone_number.is_empty() {
+            person.insert("phone".to_string(), json!([{ "value": contact.phone_number, "primary": true }]));
+        }
+        if !contact.company.is_empty() {
+            person.insert("org_id".to_string(), json!(self.organization(&contact.company).await?));
+        }
+        if let Some(field) = self.country_field.as_ref().filter(|_| !contact.country_region.is_empty()) {
+            person.insert(field.clone(), json!(contact.country_region));
+        }
+
+        let request = match self.search("persons", "email", &contact.email).await? {
+            Some(id) => self.client.put(format!("{}/v1/persons/{}", self.api_url, id)),
+            None => self.client.post(format!("{}/v1/persons", self.api_url)),
+        };
+        let answer = send_json(request.query(&[("api_token", &self.token)]).json(&person)).await?;
+
+        answer
+            .pointer("/data/id")
+            .and_then(Value::as_i64)
+            .map(|id| id.to_string())
+            .ok_or_else(|| io::Error::other("Pipedrive returned no person ID"))
+    }
+}
+
+/// Builds the CRM connector selected by `CRM_PROVIDER`.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when no CRM is configured.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `CRM_PROVIDER` names an unknown CRM
+/// - `CRM_API_TOKEN` is missing
+pub fn from_config(config: &AppConfig) -> io::Result<Option<Arc<dyn CrmConnector>>> {
+    let Some(provider) = config.crm_provider.as_deref() else {
+        return Ok(None);
+    };
+    let token = config.crm_api_token.as_deref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("CRM_API_TOKEN must be set when CRM_PROVIDER={}", provider))
+    })?;
+    let api_url = config.crm_api_url.as_deref();
+    let country_field = config.crm_country_field.as_deref();
+
+    match provider {
+        "hubspot" => Ok(Some(Arc::new(HubSpotConnector::new(
+            api_url.unwrap_or("https://api.hubapi.com"),
+            token,
+            country_field.unwrap_or("country"),
+        )?))),
+        "pipedrive" => Ok(Some(Arc::new(PipedriveConnector::new(
+            api_url.unwrap_or("https://api.pipedrive.com"),
+            token,
+            country_field,
+        )?))),
+        other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown CRM_PROVIDER: {}", other))),
+    }
+}
+
+/// Sync of a message's sender to the CRM.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the message
+/// * `provider` - CRM the sender is synced to
+/// * `status` - `pending` (waiting for an attempt), `synced`, `failed` (given up) or
+///   `skipped` (spam or trashed message)
+/// * `attempts` - Number of attempts made
+/// * `next_attempt_at` - When the next attempt is due, while `pending`
+/// * `last_error` - Error of the last failed attempt
+/// * `crm_id` - ID of the contact in the CRM, once synced
+/// * `created_at` - Timestamp when the sync was queued
+/// * `synced_at` - Timestamp of the last successful attempt
+#[derive(Debug, Clone, Serialize)]
+pub struct CrmSync {
+    pub message_id: Uuid,
+    pub provider: String,
+    pub status: String,
+    pub attempts: i32,
+    #[serde(with = "crate::timestamp")]
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub crm_id: Option<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub synced_at: Option<DateTime<Utc>>,
+}
+
+/// Column list selected for every `CrmSync` row.
+const CRM_SYNC_COLUMNS: &str =
+    "message_id, provider, status, attempts, next_attempt_at, last_error, crm_id, created_at, synced_at";
+
+fn crm_sync_from_row(row: &PgRow) -> CrmSync {
+    CrmSync {
+        message_id: row.get("message_id"),
+        provider: row.get("provider"),
+        status: row.get("status"),
+        attempts: row.get("attempts"),
+        next_attempt_at: row.get("next_attempt_at"),
+        last_error: row.get("last_error"),
+        crm_id: row.get("crm_id"),
+        created_at: row.get("created_at"),
+        synced_at: row.get("synced_at"),
+    }
+}
+
+/// Database operations for the CRM sync.
+impl Database {
+    /// Creates the 'crm_syncs' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_crm_syncs_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS crm_syncs (
+                message_id UUID PRIMARY KEY,
+                provider TEXT NOT NULL,
+                event_id BIGINT,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                last_error TEXT,
+                crm_id TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                synced_at TIMESTAMPTZ
+            );
+
+            CREATE INDEX IF NOT EXISTS crm_syncs_due_idx ON crm_syncs (next_attempt_at) WHERE status = 'pending';
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queues a sync for the messages received since the last queued one.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - CRM the senders are synced to
+    /// * `start_id` - Event ID to start after while no sync was queued yet,
+    ///   so that setting the CRM up does not sync the whole history
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of syncs queued.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn queue_crm_syncs(&self, provider: &str, start_id: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(r#"
+            INSERT INTO crm_syncs (message_id, provider, event_id)
+            SELECT message_id, $1, id
+            FROM message_events
+            WHERE event_type = 'created'
+              AND id > COALESCE((SELECT MAX(event_id) FROM crm_syncs), $2)
+            ORDER BY id
+            LIMIT 500
+            ON CONFLICT (message_id) DO NOTHING
+        "#)
+        .bind(provider)
+        .bind(start_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Runs up to `batch_size` due syncs with `connector`.
+    ///
+    /// Locks the syncs it runs so that replicas running the sync job
+    /// never run the same one. A failed sync is retried with an
+    /// exponential backoff capped at one hour, and given up after
+    /// [`MAX_SYNC_ATTEMPTS`] attempts.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of syncs run.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    /// Sync failures are recorded on the sync instead.
+    pub async fn run_crm_syncs(&self, connector: &dyn CrmConnector, batch_size: i64) -> Result<usize, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(r#"
+            SELECT s.message_id, m.name, m.email, m.phone_number, m.company, m.country_region,
+                   m.id IS NULL OR m.deleted_at IS NOT NULL OR m.category = 'spam' AS skip
+            FROM crm_syncs s
+            LEFT JOIN messages m ON m.id = s.message_id
+            WHERE s.status = 'pending' AND s.next_attempt_at <= NOW()
+            ORDER BY s.next_attempt_at
+            LIMIT $1
+            FOR UPDATE OF s SKIP LOCKED
+        "#)
+        .bind(batch_size)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let attempted = rows.len();
+        for row in rows {
+            let message_id: Uuid = row.get("message_id");
+            if row.get::<bool, _>("skip") {
+                sqlx::query("UPDATE crm_syncs SET status = 'skipped' WHERE message_id = $1")
+                    .bind(message_id)
+                    .execute(&mut *tx)
+                    .await?;
+                continue;
+            }
+
+            let contact = CrmContact {
+                name: row.get("name"),
+                email: row.get("email"),
+                phone_number: row.get("phone_number"),
+                company: row.get("company"),
+                country_region: row.get("country_region"),
+            };
+            match connector.upsert_contact(&contact).await {
+                Ok(crm_id) => {
+                    sqlx::query(r#"
+                        UPDATE crm_syncs
+                        SET status = 'synced', attempts = attempts + 1, last_error = NULL, crm_id = $2, synced_at = NOW()
+                        WHERE message_id = $1
+                    "#)
+                    .bind(message_id)
+                    .bind(crm_id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                Err(e) => {
+                    sqlx::query(r#"
+                        UPDATE crm_syncs
+                        SET attempts = attempts + 1,
+                            last_error = $2,
+                            next_attempt_at = NOW() + make_interval(secs => LEAST(power(2, attempts) * 30, $3)),
+                            status = CASE WHEN attempts + 1 >= $4 THEN 'failed' ELSE 'pending' END
+                        WHERE message_id = $1
+                    "#)
+                    .bind(message_id)
+                    .bind(e.to_string())
+                    .bind(MAX_RETRY_DELAY_SECS)
+                    .bind(MAX_SYNC_ATTEMPTS)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(attempted)
+    }
+
+    /// Fetches the sync of a message, `None` if it was never queued.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn get_crm_sync(&self, message_id: Uuid) -> Result<Option<CrmSync>, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {CRM_SYNC_COLUMNS} FROM crm_syncs WHERE message_id = $1"))
+            .bind(message_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(crm_sync_from_row))
+    }
+
+    /// Queues a message for an immediate sync, whatever its current status,
+    /// with a fresh count of attempts.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn resync_crm(&self, message_id: Uuid, provider: &str) -> Result<CrmSync, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO crm_syncs (message_id, provider)
+            VALUES ($1, $2)
+            ON CONFLICT (message_id) DO UPDATE
+            SET provider = EXCLUDED.provider, status = 'pending', attempts = 0, next_attempt_at = NOW(), last_error = NULL
+            RETURNING {CRM_SYNC_COLUMNS}
+        "#))
+        .bind(message_id)
+        .bind(provider)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(crm_sync_from_row(&row))
+    }
+}
@@ -0,0 +1,139 @@
+//! # CSRF Protection
+//!
+//! Double-submit tokens protecting state-changing requests that a browser
+//! authenticates on its own, with cookies, such as those of a backoffice UI
+//! or widget served with a cookie session.
+//!
+//! 1. The page gets a token with `GET /auth/csrf-token`, which also sets
+//!    it as the `__Host-csrf` cookie (`Secure`, `HttpOnly`,
+//!    `SameSite=Strict`).
+//! 2. It sends the token back as the `X-CSRF-Token` header of every
+//!    `POST`, `PUT`, `PATCH` and `DELETE` request.
+//!
+//! [`protect`] rejects such requests with 403 Forbidden when they carry
+//! cookies but not the same token in the header and the cookie: another
+//! site can make the browser send the cookie, but can neither read it nor
+//! set a custom header. Requests without cookies, or authenticated by a
+//! bearer token, which a browser never attaches by itself, are not
+//! checked, so that API clients and the website's contact form are not
+//! concerned. The check can be turned off with `CSRF_PROTECTION=false`.
+//!
+//! The `__Host-` prefix keeps subdomains from planting their own token.
+//!
+//! No endpoint of this server authenticates with cookies yet: the admin
+//! token, API tokens and agent sessions are all bearer tokens, which the
+//! check skips. The middleware and `GET /auth/csrf-token` are groundwork
+//! for a cookie session, so that the first cookie-authenticated route is
+//! protected from the start; until then, they have no effect.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+use crate::config::LiveConfig;
+
+/// Name of the cookie holding the token.
+pub const CSRF_COOKIE: &str = "__Host-csrf";
+
+/// Header the token is sent back in.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Returns a new random token.
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Returns whether `token` looks like one of [`generate_token`], so that
+/// a malformed cookie is replaced rather than handed back.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::csrf::{generate_token, is_well_formed};
+///
+/// assert!(is_well_formed(&generate_token()));
+/// assert!(!is_well_formed("abc; Path=/"));
+/// ```
+pub fn is_well_formed(token: &str) -> bool {
+    token.len() == 43 && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Returns the token of the request's cookie, if it has a well-formed one.
+pub fn cookie_token(req: &HttpRequest) -> Option<String> {
+    req.cookie(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| is_well_formed(token))
+}
+
+/// Builds the cookie holding `token`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::csrf::token_cookie;
+///
+/// let cookie = token_cookie("abc").to_string();
+/// assert!(cookie.starts_with("__Host-csrf=abc"));
+/// assert!(cookie.contains("Secure") && cookie.contains("HttpOnly") && cookie.contains("SameSite=Strict"));
+/// ```
+pub fn token_cookie(token: &str) -> Cookie<'static> {
+    Cookie::build(CSRF_COOKIE, token.to_string())
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+/// Returns whether a request must carry a CSRF token: it changes state,
+/// carries cookies and no bearer token.
+fn needs_token(req: &ServiceRequest) -> bool {
+    let changes_state = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let has_cookies = req.headers().contains_key(header::COOKIE);
+    let has_bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "));
+    changes_state && has_cookies && !has_bearer
+}
+
+/// Middleware rejecting the state-changing requests authenticated by
+/// cookies whose `X-CSRF-Token` header does not match their `__Host-csrf`
+/// cookie.
+///
+/// # Errors
+///
+/// Returns the errors of the wrapped service.
+pub async fn protect<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let enabled = req
+        .app_data::<web::Data<LiveConfig>>()
+        .is_none_or(|config| config.load().csrf_protection);
+    if !enabled || !needs_token(&req) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    let cookie = cookie_token(req.request());
+    let sent = req.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (cookie, sent) {
+        (Some(cookie), Some(sent)) if crate::auth::constant_time_eq(cookie.as_bytes(), sent.trim().as_bytes()) => {
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        _ => {
+            let rejection = HttpResponse::Forbidden().json(serde_json::json!({
+                "status": "error",
+                "message": "Missing or invalid CSRF token, get one with GET /auth/csrf-token"
+            }));
+            Ok(req.into_response(rejection).map_into_right_body())
+        }
+    }
+}
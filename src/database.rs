@@ -1,5 +1,9 @@
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use std::env;
+use std::sync::Arc;
+
+use crate::classification::{Category, Classifier, MessageDraft};
+use crate::storage::BlobStore;
 
 /// Database wrapper that handles PostgreSQL connections and provides
 /// a high-level interface for database operations.
@@ -9,7 +13,7 @@ use std::env;
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,no_run
 /// use dothtml_backend::database::Database;
 ///
 /// #[tokio::main]
@@ -23,6 +27,14 @@ use std::env;
 pub struct Database {
     /// PostgreSQL connection pool
     pub pool: PgPool,
+    /// Storage for message bodies that overflow the table, if configured
+    pub blob_store: Option<Arc<dyn BlobStore>>,
+    /// Message bodies longer than this many bytes go to the blob store
+    pub overflow_threshold: usize,
+    /// Whether the first messages of unknown senders are quarantined
+    pub greylisting: bool,
+    /// Classifier giving incoming messages their category, if configured
+    pub classifier: Option<Arc<dyn Classifier>>,
 }
 
 impl Database {
@@ -45,7 +57,7 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     ///
     /// #[tokio::main]
@@ -63,7 +75,91 @@ impl Database {
 
         let pool = PgPool::connect(&database_url).await?;
 
-        Ok(Database { pool })
+        Ok(Database {
+            pool,
+            blob_store: None,
+            overflow_threshold: usize::MAX,
+            greylisting: false,
+            classifier: None,
+        })
+    }
+
+    /// Stores message bodies larger than `threshold` bytes in `store`.
+    ///
+    /// Only a preview and a pointer to the blob are kept in PostgreSQL; the
+    /// full body is transparently loaded again by `get_message_by_id`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::storage::LocalBlobStore;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?
+    ///         .with_blob_store(Arc::new(LocalBlobStore::new("./blobs")), 64 * 1024);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_blob_store(mut self, store: Arc<dyn BlobStore>, threshold: usize) -> Self {
+        self.blob_store = Some(store);
+        self.overflow_threshold = threshold;
+        self
+    }
+
+    /// Quarantines the messages of senders who never had a message let into
+    /// the inbox, until an agent approves them (see
+    /// `Database::approve_message`).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?.with_greylisting(true);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_greylisting(mut self, enabled: bool) -> Self {
+        self.greylisting = enabled;
+        self
+    }
+
+    /// Gives incoming messages a category with `classifier`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use dothtml_backend::classification::KeywordClassifier;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?.with_classifier(Arc::new(KeywordClassifier::default()));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_classifier(mut self, classifier: Arc<dyn Classifier>) -> Self {
+        self.classifier = Some(classifier);
+        self
+    }
+
+    /// Returns the category of a message about to be stored, `None` without
+    /// classifier or when it fails.
+    pub(crate) async fn classify(&self, draft: &MessageDraft<'_>) -> Option<Category> {
+        let classifier = self.classifier.as_ref()?;
+        match classifier.classify(draft).await {
+            Ok(category) => category,
+            Err(e) => {
+                eprintln!("Failed to classify message: {}", e);
+                None
+            }
+        }
     }
 
     /// Returns a reference to the underlying PostgreSQL connection pool.
@@ -77,7 +173,7 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     ///
     /// #[tokio::main]
@@ -112,7 +208,7 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     ///
     /// #[tokio::main]
@@ -130,6 +226,106 @@ impl Database {
         Ok(())
     }
 
+    /// Runs a trivial query and measures its round-trip time.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the database cannot be reached.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     println!("Database answered in {:?}", db.ping().await?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn ping(&self) -> Result<std::time::Duration, sqlx::Error> {
+        let start = std::time::Instant::now();
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(start.elapsed())
+    }
+
+    /// Returns the tables of `tables` that do not exist in the current
+    /// schema search path.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let missing = db.missing_tables(&["messages", "companies"]).await?;
+    ///     println!("Missing tables: {:?}", missing);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn missing_tables(&self, tables: &[&str]) -> Result<Vec<String>, sqlx::Error> {
+        let names: Vec<String> = tables.iter().map(|table| table.to_string()).collect();
+        sqlx::query_scalar(r#"
+            SELECT name
+            FROM unnest($1::text[]) WITH ORDINALITY AS expected (name, position)
+            WHERE to_regclass(name) IS NULL
+            ORDER BY position
+        "#)
+        .bind(names)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Tries to take the scheduler lock, which elects the replica running
+    /// the scheduled background jobs.
+    ///
+    /// The lock is a session-level advisory lock: it is held for as long as
+    /// the returned connection stays open, and PostgreSQL releases it when
+    /// the connection is dropped or lost. The connection is detached from
+    /// the pool so that it is never handed out to other queries.
+    ///
+    /// # Returns
+    ///
+    /// Returns the connection holding the lock, or `None` if another
+    /// replica already holds it.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     if let Some(_lock) = db.try_acquire_scheduler_lock().await? {
+    ///         println!("This instance runs the scheduled jobs");
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn try_acquire_scheduler_lock(&self) -> Result<Option<PgConnection>, sqlx::Error> {
+        let mut connection = self.pool.acquire().await?.detach();
+
+        let acquired: bool = sqlx::query_scalar(
+            "SELECT pg_try_advisory_lock(hashtext('dothtml'), hashtext('scheduler'))",
+        )
+        .fetch_one(&mut connection)
+        .await?;
+
+        Ok(acquired.then_some(connection))
+    }
+
     /// Closes all connections in the connection pool.
     ///
     /// This method gracefully shuts down the connection pool, closing all
@@ -138,7 +334,7 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     ///
     /// #[tokio::main]
@@ -183,7 +379,7 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     ///
     /// #[tokio::main]
@@ -224,8 +420,9 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
+    /// use sqlx::Row;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), sqlx::Error> {
@@ -267,8 +464,9 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
+    /// use sqlx::Row;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), sqlx::Error> {
@@ -307,8 +505,9 @@ impl Database {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
+    /// use sqlx::Row;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), sqlx::Error> {
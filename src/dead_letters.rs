@@ -0,0 +1,372 @@
+//! # Dead Letters
+//!
+//! Work the background jobs gave up on, kept with its error so that an
+//! admin can look into it and send it again once the cause is fixed,
+//! instead of it being dropped:
+//!
+//! - `webhook` - A webhook delivery still failing after
+//!   [`MAX_DELIVERY_ATTEMPTS`](crate::webhooks::MAX_DELIVERY_ATTEMPTS) attempts, or whose
+//!   template could not be rendered (see the `webhooks` module)
+//! - `email` - An email the mail transport failed to send (see the `mail_queue` module)
+//! - `outbox` - An outbox entry the publisher still failed to take after
+//!   [`MAX_PUBLISH_ATTEMPTS`](crate::outbox::MAX_PUBLISH_ATTEMPTS) attempts (see the `outbox` module)
+//!
+//! `GET /admin/dead-letters` lists them and `POST /admin/dead-letters/{id}/retry`
+//! sends one again: a webhook delivery is scheduled right away with a fresh
+//! set of attempts and keeps its `X-Dothtml-Delivery` identifier, so that
+//! endpoints can still drop duplicates, an outbox entry is relayed again
+//! with a fresh set of attempts and its dedup key, and an email is queued again. A
+//! dead letter can only be retried once; if the retry fails too, it lands
+//! here again as a new dead letter.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgRow;
+use sqlx::{PgExecutor, Row};
+
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+use crate::mail_queue::{MailPriority, MailQueue, QueuedEmail};
+use crate::templates::RenderedEmail;
+
+/// What a dead letter is.
+///
+/// * `Webhook` - A webhook delivery
+/// * `Email` - An email
+/// * `Outbox` - An outbox entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterKind {
+    Webhook,
+    Email,
+    Outbox,
+}
+
+impl DeadLetterKind {
+    /// Returns the name stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterKind::Webhook => "webhook",
+            DeadLetterKind::Email => "email",
+            DeadLetterKind::Outbox => "outbox",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "email" => DeadLetterKind::Email,
+            "outbox" => DeadLetterKind::Outbox,
+            _ => DeadLetterKind::Webhook,
+        }
+    }
+}
+
+/// Work given up by a background job.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the dead letter
+/// * `kind` - `webhook`, `email` or `outbox`
+/// * `source_id` - Webhook delivery or outbox entry to schedule again
+/// * `topic` - Outbox topic of a webhook or outbox entry, or priority of an email
+/// * `destination` - URL of the webhook endpoint, recipient of the email, or
+///   `outbox_publisher` or `mail_queue` for an outbox entry
+/// * `payload` - Body of the webhook, or subject and bodies of the email
+/// * `error` - Last error met
+/// * `attempts` - Number of attempts made
+/// * `failed_at` - When the job gave up
+/// * `retried_at` - When an admin sent it again, if they did
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub kind: DeadLetterKind,
+    pub source_id: Option<i64>,
+    pub topic: String,
+    pub destination: String,
+    pub payload: Value,
+    pub error: String,
+    pub attempts: i32,
+    #[serde(with = "crate::timestamp")]
+    pub failed_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub retried_at: Option<DateTime<Utc>>,
+}
+
+fn dead_letter_from_row(row: &PgRow) -> DeadLetter {
+    DeadLetter {
+        id: row.get("id"),
+        kind: DeadLetterKind::from_db(row.get("kind")),
+        source_id: row.get("source_id"),
+        topic: row.get("topic"),
+        destination: row.get("destination"),
+        payload: row.get("payload"),
+        error: row.get("error"),
+        attempts: row.get("attempts"),
+        failed_at: row.get("failed_at"),
+        retried_at: row.get("retried_at"),
+    }
+}
+
+/// Work to record as a dead letter (see [`DeadLetter`] for the fields).
+#[derive(Debug, Clone)]
+pub struct NewDeadLetter<'a> {
+    pub kind: DeadLetterKind,
+    pub source_id: Option<i64>,
+    pub topic: &'a str,
+    pub destination: &'a str,
+    pub payload: &'a Value,
+    pub error: &'a str,
+    pub attempts: i32,
+}
+
+/// Outcome of a retry.
+///
+/// * `Retried` - The work was sent again
+/// * `NotFound` - There is no such dead letter
+/// * `AlreadyRetried` - The dead letter was already retried
+/// * `SourceGone` - The webhook endpoint of the delivery was removed, or the outbox entry was published or purged
+/// * `MailerDisabled` - Emails cannot be sent, the mailer being disabled
+#[derive(Debug, Clone)]
+pub enum RetryOutcome {
+    Retried(DeadLetter),
+    NotFound,
+    AlreadyRetried,
+    SourceGone,
+    MailerDisabled,
+}
+
+/// Database operations for the dead letters.
+impl Database {
+    /// Creates the 'dead_letters' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_dead_letters_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS dead_letters (
+                id BIGSERIAL PRIMARY KEY,
+                kind TEXT NOT NULL,
+                source_id BIGINT,
+                topic TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                payload JSONB NOT NULL,
+                error TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                retried_at TIMESTAMPTZ
+            );
+
+            CREATE INDEX IF NOT EXISTS dead_letters_failed_at_idx ON dead_letters (failed_at DESC, id DESC);
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records work given up by a background job.
+    ///
+    /// Takes any executor so that it runs in the transaction giving the
+    /// work up.
+    pub(crate) async fn insert_dead_letter<'e, E: PgExecutor<'e>>(
+        executor: E,
+        dead_letter: &NewDeadLetter<'_>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO dead_letters (kind, source_id, topic, destination, payload, error, attempts)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#)
+        .bind(dead_letter.kind.as_str())
+        .bind(dead_letter.source_id)
+        .bind(dead_letter.topic)
+        .bind(dead_letter.destination)
+        .bind(dead_letter.payload)
+        .bind(dead_letter.error)
+        .bind(dead_letter.attempts)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records an email the mail transport failed to send.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn record_failed_email(&self, email: &QueuedEmail, error: &str) -> Result<(), sqlx::Error> {
+        Self::insert_dead_letter(&self.pool, &NewDeadLetter {
+            kind: DeadLetterKind::Email,
+            source_id: None,
+            topic: email.priority.as_str(),
+            destination: &email.to,
+            payload: &serde_json::to_value(&email.email).unwrap_or_default(),
+            error,
+            attempts: 1,
+        })
+        .await
+    }
+
+    /// Lists a page of the dead letters, most recent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Only list dead letters of this kind, if set
+    /// * `include_retried` - Whether to list the dead letters already retried
+    /// * `page` - Page of the listing to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_dead_letters(
+        &self, kind: Option<DeadLetterKind>, include_retried: bool, page: &PageRequest
+    ) -> Result<Paginated<DeadLetter>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, kind, source_id, topic, destination, payload, error, attempts, failed_at, retried_at,
+                COUNT(*) OVER () AS total
+            FROM dead_letters
+            WHERE ($1::TEXT IS NULL OR kind = $1)
+              AND ($2 OR retried_at IS NULL)
+            ORDER BY failed_at DESC, id DESC
+            LIMIT $3 OFFSET $4
+        "#)
+        .bind(kind.map(|kind| kind.as_str()))
+        .bind(include_retried)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, dead_letter_from_row))
+    }
+
+    /// Sends a dead letter again: schedules its webhook delivery or outbox
+    /// entry right away with a fresh set of attempts, or queues its email
+    /// again.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier of the dead letter
+    /// * `mail_queue` - Queue of the emails waiting to be sent, when the mailer is enabled
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::dead_letters::RetryOutcome;
+    /// use dothtml_backend::models::PageRequest;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for dead_letter in db.list_dead_letters(None, false, &PageRequest::default()).await?.data {
+    ///         if let RetryOutcome::Retried(dead_letter) = db.retry_dead_letter(dead_letter.id, None).await? {
+    ///             println!("Sent {} to {} again", dead_letter.topic, dead_letter.destination);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn retry_dead_letter(&self, id: i64, mail_queue: Option<&MailQueue>) -> Result<RetryOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(r#"
+            SELECT id, kind, source_id, topic, destination, payload, error, attempts, failed_at, retried_at
+            FROM dead_letters
+            WHERE id = $1
+            FOR UPDATE
+        "#)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(dead_letter) = row.as_ref().map(dead_letter_from_row) else {
+            return Ok(RetryOutcome::NotFound);
+        };
+        if dead_letter.retried_at.is_some() {
+            return Ok(RetryOutcome::AlreadyRetried);
+        }
+
+        let email = match dead_letter.kind {
+            DeadLetterKind::Webhook => {
+                let requeued = sqlx::query(r#"
+                    UPDATE webhook_deliveries
+                    SET attempts = 0, next_attempt_at = NOW(), last_error = NULL, failed_at = NULL
+                    WHERE id = $1 AND delivered_at IS NULL
+                "#)
+                .bind(dead_letter.source_id)
+                .execute(&mut *tx)
+                .await?;
+                if requeued.rows_affected() == 0 {
+                    return Ok(RetryOutcome::SourceGone);
+                }
+                None
+            }
+            DeadLetterKind::Outbox => {
+                let requeued = sqlx::query(r#"
+                    UPDATE outbox
+                    SET attempts = 0, next_attempt_at = NOW(), last_error = NULL, failed_at = NULL, locked_until = NULL
+                    WHERE id = $1 AND published_at IS NULL
+                "#)
+                .bind(dead_letter.source_id)
+                .execute(&mut *tx)
+                .await?;
+                if requeued.rows_affected() == 0 {
+                    return Ok(RetryOutcome::SourceGone);
+                }
+                None
+            }
+            DeadLetterKind::Email => {
+                let Some(mail_queue) = mail_queue else {
+                    return Ok(RetryOutcome::MailerDisabled);
+                };
+                let email: RenderedEmail = serde_json::from_value(dead_letter.payload.clone()).map_err(|e| sqlx::Error::Decode(e.into()))?;
+                let priority = match dead_letter.topic.as_str() {
+                    "digest" => MailPriority::Digest,
+                    _ => MailPriority::Transactional,
+                };
+                Some((mail_queue, email, priority))
+            }
+        };
+
+        let row = sqlx::query(r#"
+            UPDATE dead_letters SET retried_at = NOW()
+            WHERE id = $1
+            RETURNING id, kind, source_id, topic, destination, payload, error, attempts, failed_at, retried_at
+        "#)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if let Some((mail_queue, email, priority)) = email {
+            mail_queue.push(&dead_letter.destination, email, priority);
+        }
+        Ok(RetryOutcome::Retried(dead_letter_from_row(&row)))
+    }
+
+    /// Deletes the dead letters retried more than `after_days` days ago.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of deleted dead letters.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn purge_retried_dead_letters(&self, after_days: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM dead_letters WHERE retried_at < NOW() - make_interval(days => $1)")
+            .bind(after_days as i32)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
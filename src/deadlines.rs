@@ -0,0 +1,216 @@
+//! # Deadline Feeds
+//!
+//! Calendar feed of the messages assigned to an agent, each shown as an
+//! event at the time it should be answered by, so that deadlines appear
+//! in Outlook or Google Calendar next to the agent's meetings.
+//!
+//! A message is due the number of hours set for its priority after it was
+//! received: `SLA_URGENT_HOURS`, `SLA_HIGH_HOURS`, `SLA_NORMAL_HOURS` or
+//! `SLA_LOW_HOURS` (see [`due_at`]). When `OFFICE_HOURS` is set, only
+//! office hours count (see the `office_hours` module).
+//!
+//! Calendar apps cannot send credentials, so the feed is read with a token
+//! in its URL:
+//!
+//! 1. The agent gets their feed URL with `POST /me/deadlines/feed`; the
+//!    token names them, is signed with `SESSION_SECRET` and lasts
+//!    `CALENDAR_FEED_TTL_DAYS`.
+//! 2. They subscribe to `GET /me/deadlines.ics?token=<token>` in their
+//!    calendar app, which polls it for changes.
+//!
+//! Getting a new URL does not end the previous ones; revoking the agent's
+//! sessions does (see the `sessions` module).
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::Database;
+use crate::ics::{self, Calendar};
+
+/// Maximum number of messages a feed lists.
+const MAX_FEED_EVENTS: i64 = 500;
+
+/// Length of a deadline event, so that calendars show it as a block.
+const EVENT_MINUTES: i64 = 15;
+
+/// Returns when a message of `priority` received at `created_at` should be
+/// answered by, unknown priorities counting as `normal`, skipping the time
+/// outside office hours if they are set.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{Duration, TimeZone, Utc};
+/// use dothtml_backend::config::AppConfig;
+/// use dothtml_backend::deadlines::due_at;
+/// use dothtml_backend::office_hours::OfficeHours;
+///
+/// let config = AppConfig::default();
+/// let received = Utc.with_ymd_and_hms(2026, 10, 17, 9, 0, 0).unwrap();
+/// assert_eq!(due_at(&config, "urgent", received), received + Duration::hours(4));
+/// assert_eq!(due_at(&config, "normal", received), received + Duration::hours(24));
+///
+/// // Received on a Saturday, due on Monday
+/// let config = AppConfig {
+///     office_hours: Some(OfficeHours::from_settings("Mon-Fri 09:00-18:00", "UTC", "").unwrap()),
+///     ..AppConfig::default()
+/// };
+/// assert_eq!(due_at(&config, "urgent", received), Utc.with_ymd_and_hms(2026, 10, 19, 13, 0, 0).unwrap());
+/// ```
+pub fn due_at(config: &AppConfig, priority: &str, created_at: DateTime<Utc>) -> DateTime<Utc> {
+    let hours = match priority {
+        "urgent" => config.sla_urgent_hours,
+        "high" => config.sla_high_hours,
+        "low" => config.sla_low_hours,
+        _ => config.sla_normal_hours,
+    };
+    match &config.office_hours {
+        Some(office_hours) => office_hours.add_hours(created_at, hours),
+        None => created_at + Duration::hours(hours.into()),
+    }
+}
+
+/// A message assigned to an agent, with its deadline.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the message
+/// * `name` - Name of the sender
+/// * `email` - Email of the sender
+/// * `company` - Company of the sender, empty if not given
+/// * `priority` - Priority of the message
+/// * `created_at` - When the message was received
+/// * `due_at` - When the message should be answered by
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Deadline {
+    pub message_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub company: String,
+    pub priority: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub due_at: DateTime<Utc>,
+}
+
+/// Writes the deadline feed of `account`.
+///
+/// Each event keeps the UID of its message, so that calendars move it
+/// rather than duplicating it when the priority changes.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{Duration, TimeZone, Utc};
+/// use uuid::Uuid;
+/// use dothtml_backend::deadlines::{deadlines_ics, Deadline};
+///
+/// let received = Utc.with_ymd_and_hms(2026, 10, 17, 9, 0, 0).unwrap();
+/// let deadline = Deadline {
+///     message_id: Uuid::nil(),
+///     name: "Jane Doe".to_string(),
+///     email: "jane@example.com".to_string(),
+///     company: "ACME Corp".to_string(),
+///     priority: "urgent".to_string(),
+///     created_at: received,
+///     due_at: received + Duration::hours(4),
+/// };
+/// let ics = deadlines_ics("alice", &[deadline], received);
+/// assert!(ics.contains("X-WR-CALNAME:Deadlines of alice\r\n"));
+/// assert!(ics.contains("DTSTART:20261017T130000Z\r\n"));
+/// assert!(ics.contains("SUMMARY:Answer Jane Doe (ACME Corp)\r\n"));
+/// ```
+pub fn deadlines_ics(account: &str, deadlines: &[Deadline], stamp: DateTime<Utc>) -> String {
+    let mut calendar = Calendar::new("PUBLISH");
+    calendar
+        .property("X-WR-CALNAME", &ics::text(&format!("Deadlines of {}", account)))
+        .property("REFRESH-INTERVAL;VALUE=DURATION", "PT15M")
+        .property("X-PUBLISHED-TTL", "PT15M");
+    for deadline in deadlines {
+        let summary = if deadline.company.is_empty() {
+            format!("Answer {}", deadline.name)
+        } else {
+            format!("Answer {} ({})", deadline.name, deadline.company)
+        };
+        let description = format!(
+            "{} priority message from {} received {}.\nMessage ID: {}",
+            deadline.priority,
+            deadline.email,
+            deadline.created_at.format("%Y-%m-%d %H:%M UTC"),
+            deadline.message_id
+        );
+        calendar.event(&[
+            ("UID", format!("{}-deadline@dothtml-backend", deadline.message_id)),
+            ("DTSTAMP", ics::date_time(stamp)),
+            ("DTSTART", ics::date_time(deadline.due_at)),
+            ("DTEND", ics::date_time(deadline.due_at + Duration::minutes(EVENT_MINUTES))),
+            ("SUMMARY", ics::text(&summary)),
+            ("DESCRIPTION", ics::text(&description)),
+            ("TRANSP", "TRANSPARENT".to_string()),
+        ]);
+    }
+    calendar.finish()
+}
+
+/// Database operations for the deadline feeds.
+impl Database {
+    /// Lists the messages assigned to an agent with their deadlines, the
+    /// most pressing first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::config::AppConfig;
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for deadline in db.list_deadlines("alice", &AppConfig::default()).await? {
+    ///         println!("{} is due {}", deadline.message_id, deadline.due_at);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_deadlines(&self, account: &str, config: &AppConfig) -> Result<Vec<Deadline>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, name, email, company, priority, created_at
+            FROM messages
+            WHERE status = 'assigned' AND assigned_to = $1 AND deleted_at IS NULL
+            ORDER BY created_at
+            LIMIT $2
+        "#)
+        .bind(account)
+        .bind(MAX_FEED_EVENTS)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut deadlines: Vec<Deadline> = rows
+            .iter()
+            .map(|row| {
+                let priority: String = row.get("priority");
+                let created_at = row.get("created_at");
+                Deadline {
+                    message_id: row.get("id"),
+                    name: row.get("name"),
+                    email: row.get("email"),
+                    company: row.get("company"),
+                    due_at: due_at(config, &priority, created_at),
+                    priority,
+                    created_at,
+                }
+            })
+            .collect();
+        deadlines.sort_by_key(|deadline| deadline.due_at);
+
+        Ok(deadlines)
+    }
+}
@@ -0,0 +1,126 @@
+//! # Email Domain Checks
+//!
+//! Optional check, enabled with `EMAIL_MX_CHECK=true`, that the domain of a
+//! contact form email can receive mail, so that replies to addresses such
+//! as `test@test.test` are not sent to nowhere.
+//!
+//! A domain receives mail when it has MX records, or, without any, an
+//! address record (the implicit MX of RFC 5321). Domains that do not
+//! exist, that have neither, or that publish a null MX (RFC 7505) are
+//! rejected.
+//!
+//! The lookup adds latency to submissions, so it is bounded by
+//! `EMAIL_MX_TIMEOUT_MS`, and answers are cached by the resolver for at
+//! least [`MIN_CACHE_TTL`]. A lookup that times out or fails for another
+//! reason accepts the email: a DNS outage must not take the contact form
+//! down.
+
+use std::io;
+use std::time::Duration;
+
+use hickory_resolver::TokioResolver;
+
+use crate::config::AppConfig;
+
+/// Shortest time a lookup answer is cached, whatever its DNS TTL.
+pub const MIN_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Number of DNS records kept in the cache.
+const CACHE_SIZE: usize = 4096;
+
+/// Whether a domain can receive mail.
+///
+/// * `Deliverable` - The domain has MX or address records
+/// * `Undeliverable` - The domain does not exist, has neither, or refuses mail
+/// * `Unknown` - The lookup timed out or failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainStatus {
+    Deliverable,
+    Undeliverable,
+    Unknown,
+}
+
+/// Checks email domains against the DNS.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use dothtml_backend::email_domain::{DomainStatus, MxChecker};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let checker = MxChecker::new(Duration::from_secs(2))?;
+///     assert_eq!(checker.check("john@test.test").await, DomainStatus::Undeliverable);
+///     Ok(())
+/// }
+/// ```
+pub struct MxChecker {
+    resolver: TokioResolver,
+    timeout: Duration,
+}
+
+impl MxChecker {
+    /// Creates a checker using the system's DNS configuration, giving up on
+    /// a lookup after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the system's DNS configuration
+    /// cannot be read.
+    pub fn new(timeout: Duration) -> io::Result<Self> {
+        let mut builder = TokioResolver::builder_tokio().map_err(io::Error::other)?;
+        let options = builder.options_mut();
+        options.timeout = timeout;
+        options.attempts = 1;
+        options.cache_size = CACHE_SIZE;
+        options.positive_min_ttl = Some(MIN_CACHE_TTL);
+        options.negative_min_ttl = Some(MIN_CACHE_TTL);
+        Ok(MxChecker { resolver: builder.build(), timeout })
+    }
+
+    /// Returns whether the domain of `email` can receive mail.
+    pub async fn check(&self, email: &str) -> DomainStatus {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return DomainStatus::Undeliverable;
+        };
+        // Fully qualified, so that the resolver's search domains are not tried
+        let domain = format!("{}.", domain.trim().trim_end_matches('.').to_lowercase());
+
+        tokio::time::timeout(self.timeout, self.lookup(&domain))
+            .await
+            .unwrap_or(DomainStatus::Unknown)
+    }
+
+    async fn lookup(&self, domain: &str) -> DomainStatus {
+        match self.resolver.mx_lookup(domain).await {
+            // A single MX pointing to the root is a null MX: no mail accepted
+            Ok(lookup) if lookup.iter().all(|mx| mx.exchange().is_root()) => DomainStatus::Undeliverable,
+            Ok(_) => DomainStatus::Deliverable,
+            Err(e) if e.is_nx_domain() => DomainStatus::Undeliverable,
+            Err(e) if e.is_no_records_found() => match self.resolver.lookup_ip(domain).await {
+                Ok(_) => DomainStatus::Deliverable,
+                Err(e) if e.is_nx_domain() || e.is_no_records_found() => DomainStatus::Undeliverable,
+                Err(_) => DomainStatus::Unknown,
+            },
+            Err(_) => DomainStatus::Unknown,
+        }
+    }
+}
+
+/// Builds the checker when `EMAIL_MX_CHECK` is enabled.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when the check is disabled.
+///
+/// # Errors
+///
+/// This function returns an error if the system's DNS configuration cannot
+/// be read.
+pub fn from_config(config: &AppConfig) -> io::Result<Option<MxChecker>> {
+    if !config.email_mx_check {
+        return Ok(None);
+    }
+    MxChecker::new(Duration::from_millis(config.email_mx_timeout_ms)).map(Some)
+}
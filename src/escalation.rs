@@ -0,0 +1,585 @@
+//! # Issue Escalation
+//!
+//! Escalates a message needing engineering work to the team's issue
+//! tracker: `POST /inbox/{id}/escalate` opens an issue holding the message
+//! and a link back to it, and records the key of the issue in the
+//! `escalations` table. Trackers implement the [`IssueTracker`] trait and
+//! are selected at startup from the configuration:
+//!
+//! - [`GitHubIssues`] - Issues of a GitHub repository (`ISSUE_TRACKER=github`)
+//! - [`JiraIssues`] - Issues of a Jira project (`ISSUE_TRACKER=jira`)
+//!
+//! The tracker reports the closing of the issue to
+//! `POST /webhooks/issues/{provider}`, signed with
+//! `ISSUE_TRACKER_WEBHOOK_SECRET`: closing it resolves the message, and
+//! reopening it marks the escalation open again, leaving the message
+//! resolved until an agent reopens it. To set the callback up, add a
+//! webhook with this secret to the repository (content type
+//! `application/json`, "Issues" events) or to the Jira site ("Issue
+//! updated" events).
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::Database;
+use crate::models::Message;
+
+/// Time the tracker has to create an issue.
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest message quoted in full in an issue.
+const MAX_QUOTED_CHARS: usize = 10_000;
+
+/// Trackers accepted by `ISSUE_TRACKER` and `POST /webhooks/issues/{provider}`.
+pub const ISSUE_TRACKERS: [&str; 2] = ["github", "jira"];
+
+/// An issue to open.
+///
+/// # Fields
+///
+/// * `title` - Title of the issue
+/// * `body` - Description of the issue, in plain text or Markdown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewIssue {
+    pub title: String,
+    pub body: String,
+}
+
+impl NewIssue {
+    /// Builds the issue escalating `message`, linking back to it with
+    /// `backlink` (`ISSUE_BACKLINK_URL`) where `{id}` is replaced by its ID.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use chrono::Utc;
+    /// use dothtml_backend::escalation::NewIssue;
+    /// use dothtml_backend::models::Message;
+    /// use uuid::Uuid;
+    ///
+    /// let message = Message {
+    ///     id: Uuid::new_v4(),
+    ///     name: "Jane Doe".to_string(),
+    ///     email: "jane@example.com".to_string(),
+    ///     country_region: String::new(),
+    ///     phone_number: String::new(),
+    ///     company: "Acme".to_string(),
+    ///     message: "The export button does nothing\nTried in Firefox and Chrome.".to_string(),
+    ///     created_at: Utc::now(),
+    ///     assigned_to: None,
+    ///     assigned_at: None,
+    ///     status: "pending".to_string(),
+    ///     priority: "normal".to_string(),
+    ///     merged_into: None,
+    ///     resolved_at: None,
+    ///     tags: vec![],
+    ///     company_id: None,
+    ///     archived: false,
+    ///     deleted_at: None,
+    ///     opened_by: None,
+    ///     opened_at: None,
+    ///     spam_score: None,
+    ///     category: None,
+    ///     form: "contact".to_string(),
+    ///     body_ref: None,
+    /// };
+    /// let issue = NewIssue::for_message(&message, Some("https://admin.dotshell.eu/app/inbox/{id}"));
+    /// assert_eq!(issue.title, "[Acme] The export button does nothing");
+    /// assert!(issue.body.contains(&format!("https://admin.dotshell.eu/app/inbox/{}", message.id)));
+    /// ```
+    pub fn for_message(message: &Message, backlink: Option<&str>) -> Self {
+        let first_line = message.message.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default();
+        let mut summary: String = first_line.chars().take(80).collect();
+        if summary.len() < first_line.len() {
+            summary.push_str("...");
+        }
+        let title = match message.company.trim() {
+            "" => summary,
+            company => format!("[{}] {}", company, summary),
+        };
+
+        let mut quoted: String = message.message.chars().take(MAX_QUOTED_CHARS).collect();
+        if quoted.len() < message.message.len() {
+            quoted.push_str("\n[...]");
+        }
+        let mut body = format!(
+            "Escalated from the inbox: message from {} ({}), received {}.\n\n",
+            message.name,
+            message.email,
+            message.created_at.format("%Y-%m-%d %H:%M UTC")
+        );
+        body.extend(quoted.lines().map(|line| format!("> {}\n", line)));
+        match backlink {
+            Some(backlink) => body.push_str(&format!("\nMessage: {}\n", backlink.replace("{id}", &message.id.to_string()))),
+            None => body.push_str(&format!("\nMessage ID: {}\n", message.id)),
+        }
+
+        NewIssue { title, body }
+    }
+}
+
+/// An issue opened in the tracker.
+///
+/// # Fields
+///
+/// * `key` - Key of the issue, such as `dotshell-org/website#42` or `WEB-42`
+/// * `url` - Page of the issue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIssue {
+    pub key: String,
+    pub url: String,
+}
+
+/// An issue tracker messages can be escalated to.
+#[async_trait]
+pub trait IssueTracker: Send + Sync {
+    /// Returns the name of the tracker, as in [`ISSUE_TRACKERS`].
+    fn provider(&self) -> &'static str;
+
+    /// Opens an issue.
+    async fn create_issue(&self, issue: &NewIssue) -> io::Result<ExternalIssue>;
+}
+
+fn http_client() -> io::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(TRACKER_TIMEOUT)
+        .user_agent("dothtml-backend")
+        .build()
+        .map_err(io::Error::other)
+}
+
+/// Issues of a GitHub repository, created with a personal access token or
+/// an app token allowed to write issues.
+#[derive(Debug, Clone)]
+pub struct GitHubIssues {
+    client: reqwest::Client,
+    api_url: String,
+    repository: String,
+    token: String,
+}
+
+impl GitHubIssues {
+    /// Creates a tracker opening issues in `repository` (`owner/name`)
+    /// through the API at `api_url`, `https://api.github.com` outside of
+    /// GitHub Enterprise.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(api_url: &str, repository: &str, token: &str) -> io::Result<Self> {
+        Ok(GitHubIssues {
+            client: http_client()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            repository: repository.to_string(),
+            token: token.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl IssueTracker for GitHubIssues {
+    fn provider(&self) -> &'static str {
+        "github"
+    }
+
+    async fn create_issue(&self, issue: &NewIssue) -> io::Result<ExternalIssue> {
+        let created: Value = self
+            .client
+            .post(format!("{}/repos/{}/issues", self.api_url, self.repository))
+            .bearer_auth(&self.token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .json(&serde_json::json!({ "title": issue.title, "body": issue.body }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?
+            .json()
+            .await
+            .map_err(io::Error::other)?;
+
+        let number = created.get("number").and_then(Value::as_u64).ok_or_else(|| io::Error::other("GitHub returned no issue number"))?;
+        Ok(ExternalIssue {
+            key: format!("{}#{}", self.repository, number),
+            url: created.get("html_url").and_then(Value::as_str).unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// Issues of a Jira project, created with the email and API token of a
+/// Jira account.
+#[derive(Debug, Clone)]
+pub struct JiraIssues {
+    client: reqwest::Client,
+    site_url: String,
+    project: String,
+    issue_type: String,
+    user: String,
+    token: String,
+}
+
+impl JiraIssues {
+    /// Creates a tracker opening issues of type `issue_type` in the project
+    /// with key `project` of the Jira site at `site_url`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(site_url: &str, project: &str, issue_type: &str, user: &str, token: &str) -> io::Result<Self> {
+        Ok(JiraIssues {
+            client: http_client()?,
+            site_url: site_url.trim_end_matches('/').to_string(),
+            project: project.to_string(),
+            issue_type: issue_type.to_string(),
+            user: user.to_string(),
+            token: token.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl IssueTracker for JiraIssues {
+    fn provider(&self) -> &'static str {
+        "jira"
+    }
+
+    async fn create_issue(&self, issue: &NewIssue) -> io::Result<ExternalIssue> {
+        // Version 2 of the API takes the description as plain text
+        let created: Value = self
+            .client
+            .post(format!("{}/rest/api/2/issue", self.site_url))
+            .basic_auth(&self.user, Some(&self.token))
+            .json(&serde_json::json!({
+                "fields": {
+                    "project": { "key": self.project },
+                    "issuetype": { "name": self.issue_type },
+                    "summary": issue.title,
+                    "description": issue.body,
+                }
+            }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?
+            .json()
+            .await
+            .map_err(io::Error::other)?;
+
+        let key = created.get("key").and_then(Value::as_str).ok_or_else(|| io::Error::other("Jira returned no issue key"))?;
+        Ok(ExternalIssue { key: key.to_string(), url: format!("{}/browse/{}", self.site_url, key) })
+    }
+}
+
+/// Builds the issue tracker selected by `ISSUE_TRACKER`.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when no tracker is configured.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `ISSUE_TRACKER` names an unknown tracker
+/// - `ISSUE_TRACKER_PROJECT` or `ISSUE_TRACKER_TOKEN` is missing
+/// - `ISSUE_TRACKER=jira` is used without `ISSUE_TRACKER_URL` or `ISSUE_TRACKER_USER`
+pub fn from_config(config: &AppConfig) -> io::Result<Option<Arc<dyn IssueTracker>>> {
+    let Some(tracker) = config.issue_tracker.as_deref() else {
+        return Ok(None);
+    };
+    let required = |value: &Option<String>, variable: &str| {
+        value.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} must be set when ISSUE_TRACKER={}", variable, tracker))
+        })
+    };
+
+    match tracker {
+        "github" => {
+            let api_url = config.issue_tracker_url.as_deref().unwrap_or("https://api.github.com");
+            let repository = required(&config.issue_tracker_project, "ISSUE_TRACKER_PROJECT")?;
+            let token = required(&config.issue_tracker_token, "ISSUE_TRACKER_TOKEN")?;
+            Ok(Some(Arc::new(GitHubIssues::new(api_url, &repository, &token)?)))
+        }
+        "jira" => {
+            let site_url = required(&config.issue_tracker_url, "ISSUE_TRACKER_URL")?;
+            let project = required(&config.issue_tracker_project, "ISSUE_TRACKER_PROJECT")?;
+            let user = required(&config.issue_tracker_user, "ISSUE_TRACKER_USER")?;
+            let token = required(&config.issue_tracker_token, "ISSUE_TRACKER_TOKEN")?;
+            Ok(Some(Arc::new(JiraIssues::new(&site_url, &project, &config.issue_tracker_issue_type, &user, &token)?)))
+        }
+        other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown ISSUE_TRACKER: {}", other))),
+    }
+}
+
+/// State of an escalated issue.
+///
+/// * `Open` - The issue is being worked on
+/// * `Closed` - The issue was closed, resolving the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueState {
+    Open,
+    Closed,
+}
+
+impl IssueState {
+    /// Returns the name stored in the `state` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueState::Open => "open",
+            IssueState::Closed => "closed",
+        }
+    }
+}
+
+/// Returns the header carrying the signature of the callbacks of `provider`.
+pub fn signature_header(provider: &str) -> &'static str {
+    match provider {
+        "github" => "X-Hub-Signature-256",
+        _ => "X-Hub-Signature",
+    }
+}
+
+/// Checks the signature of a callback: `sha256=` followed by the hex
+/// HMAC-SHA256 of its body keyed with `secret`, as sent by GitHub and Jira.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::escalation::verify_callback;
+/// use hmac::{Hmac, Mac};
+/// use sha2::Sha256;
+///
+/// let body = br#"{"action":"closed"}"#;
+/// let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cr3t").unwrap();
+/// mac.update(body);
+/// let hex: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+///
+/// assert!(verify_callback("s3cr3t", body, &format!("sha256={}", hex)));
+/// assert!(!verify_callback("other", body, &format!("sha256={}", hex)));
+/// assert!(!verify_callback("s3cr3t", body, &hex));
+/// ```
+pub fn verify_callback(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(hex) = signature.trim().strip_prefix("sha256=") else {
+        return false;
+    };
+    if !hex.len().is_multiple_of(2) {
+        return false;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    let Some(bytes) = bytes else {
+        return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.verify_slice(&bytes).is_ok()
+}
+
+/// Reads the key and new state of the issue from a callback of `provider`.
+///
+/// # Returns
+///
+/// Returns `None` for events that do not open or close an issue, such as
+/// edits or comments.
+///
+/// # Examples
+///
+/// ```rust
+/// use serde_json::json;
+/// use dothtml_backend::escalation::{parse_callback, IssueState};
+///
+/// let closed = json!({
+///     "action": "closed",
+///     "issue": { "number": 42 },
+///     "repository": { "full_name": "dotshell-org/website" }
+/// });
+/// assert_eq!(parse_callback("github", &closed), Some(("dotshell-org/website#42".to_string(), IssueState::Closed)));
+///
+/// let done = json!({
+///     "webhookEvent": "jira:issue_updated",
+///     "issue": { "key": "WEB-7", "fields": { "status": { "statusCategory": { "key": "done" } } } }
+/// });
+/// assert_eq!(parse_callback("jira", &done), Some(("WEB-7".to_string(), IssueState::Closed)));
+/// assert_eq!(parse_callback("github", &json!({ "action": "labeled" })), None);
+/// ```
+pub fn parse_callback(provider: &str, payload: &Value) -> Option<(String, IssueState)> {
+    match provider {
+        "github" => {
+            let state = match payload.get("action")?.as_str()? {
+                "closed" => IssueState::Closed,
+                "reopened" => IssueState::Open,
+                _ => return None,
+            };
+            let repository = payload.pointer("/repository/full_name")?.as_str()?;
+            let number = payload.pointer("/issue/number")?.as_u64()?;
+            Some((format!("{}#{}", repository, number), state))
+        }
+        "jira" => {
+            if payload.get("webhookEvent")?.as_str()? != "jira:issue_updated" {
+                return None;
+            }
+            let key = payload.pointer("/issue/key")?.as_str()?;
+            let state = match payload.pointer("/issue/fields/status/statusCategory/key")?.as_str()? {
+                "done" => IssueState::Closed,
+                _ => IssueState::Open,
+            };
+            Some((key.to_string(), state))
+        }
+        _ => None,
+    }
+}
+
+/// A message escalated to the issue tracker.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the message
+/// * `provider` - Tracker of the issue, as in [`ISSUE_TRACKERS`]
+/// * `issue_key` - Key of the issue
+/// * `issue_url` - Page of the issue
+/// * `state` - Whether the issue is open or closed
+/// * `escalated_by` - Agent who escalated the message
+/// * `created_at` - Timestamp of the escalation
+/// * `closed_at` - Timestamp when the issue was last closed, if it is
+#[derive(Debug, Clone, Serialize)]
+pub struct Escalation {
+    pub message_id: Uuid,
+    pub provider: String,
+    pub issue_key: String,
+    pub issue_url: String,
+    pub state: IssueState,
+    pub escalated_by: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+/// Column list selected for every `Escalation` row.
+const ESCALATION_COLUMNS: &str = "message_id, provider, issue_key, issue_url, state, escalated_by, created_at, closed_at";
+
+fn escalation_from_row(row: &PgRow) -> Escalation {
+    let state: String = row.get("state");
+    Escalation {
+        message_id: row.get("message_id"),
+        provider: row.get("provider"),
+        issue_key: row.get("issue_key"),
+        issue_url: row.get("issue_url"),
+        state: if state == "closed" { IssueState::Closed } else { IssueState::Open },
+        escalated_by: row.get("escalated_by"),
+        created_at: row.get("created_at"),
+        closed_at: row.get("closed_at"),
+    }
+}
+
+/// Database operations for the issue escalation.
+impl Database {
+    /// Creates the 'escalations' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_escalations_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS escalations (
+                message_id UUID PRIMARY KEY,
+                provider TEXT NOT NULL,
+                issue_key TEXT NOT NULL,
+                issue_url TEXT NOT NULL,
+                state TEXT NOT NULL DEFAULT 'open',
+                escalated_by TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                closed_at TIMESTAMPTZ,
+                UNIQUE (provider, issue_key)
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fetches the escalation of a message, `None` if it was not escalated.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn get_escalation(&self, message_id: Uuid) -> Result<Option<Escalation>, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {ESCALATION_COLUMNS} FROM escalations WHERE message_id = $1"))
+            .bind(message_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(escalation_from_row))
+    }
+
+    /// Records the issue opened for a message.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message was already escalated (unique violation)
+    /// - Database connection issues occur
+    pub async fn create_escalation(
+        &self, message_id: Uuid, provider: &str, issue: &ExternalIssue, escalated_by: &str
+    ) -> Result<Escalation, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO escalations (message_id, provider, issue_key, issue_url, escalated_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {ESCALATION_COLUMNS}
+        "#))
+        .bind(message_id)
+        .bind(provider)
+        .bind(&issue.key)
+        .bind(&issue.url)
+        .bind(escalated_by)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(escalation_from_row(&row))
+    }
+
+    /// Records the new state of an issue reported by the tracker.
+    ///
+    /// # Returns
+    ///
+    /// Returns the escalation, `None` if no message was escalated to this
+    /// issue.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn set_escalation_state(
+        &self, provider: &str, issue_key: &str, state: IssueState
+    ) -> Result<Option<Escalation>, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE escalations
+            SET state = $3, closed_at = CASE WHEN $3 = 'closed' THEN NOW() END
+            WHERE provider = $1 AND issue_key = $2
+            RETURNING {ESCALATION_COLUMNS}
+        "#))
+        .bind(provider)
+        .bind(issue_key)
+        .bind(state.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(escalation_from_row))
+    }
+}
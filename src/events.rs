@@ -0,0 +1,281 @@
+//! # Message Lifecycle Events
+//!
+//! Append-only log of everything that happens to messages, stored in the
+//! `message_events` table.
+//!
+//! Every database method that changes the state of a message records the
+//! matching event in the same transaction, so the log never disagrees with
+//! the `messages` table. Events carry a monotonically increasing `id` that
+//! consumers (audit log, webhooks, live updates) can use as a cursor with
+//! `Database::list_events_after`.
+//!
+//! Events are kept when a message is purged, which makes the log the only
+//! trace left of deleted messages. Each event is also enqueued in the
+//! outbox (see the `outbox` module) for delivery to external systems, and
+//! the agents are notified of the new and assigned messages (see the
+//! `notifications` module).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{PgConnection, Row};
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+
+/// Kind of a message lifecycle event.
+///
+/// * `Created` - A message was received (payload: `email`, `company`)
+/// * `Assigned` - A message was assigned to or claimed by an agent
+///   (payload: `agent`, `claimed`)
+/// * `Replied` - An agent replied to the message (payload: `agent`)
+/// * `StatusChanged` - The status changed for another reason, e.g. a release
+///   or a merge (payload: `from`, `to` and context such as `agent` or `merged_into`)
+/// * `Deleted` - A message was moved to the trash or purged (payload: `permanent`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageEventKind {
+    Created,
+    Assigned,
+    Replied,
+    StatusChanged,
+    Deleted,
+}
+
+impl MessageEventKind {
+    /// Returns the name stored in the `event_type` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageEventKind::Created => "created",
+            MessageEventKind::Assigned => "assigned",
+            MessageEventKind::Replied => "replied",
+            MessageEventKind::StatusChanged => "status_changed",
+            MessageEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// A recorded message lifecycle event.
+///
+/// # Fields
+///
+/// * `id` - Position of the event in the log
+/// * `message_id` - ID of the message the event is about
+/// * `event_type` - Kind of event (see `MessageEventKind`)
+/// * `payload` - Event details, depending on the kind
+/// * `created_at` - Timestamp when the event happened
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageEvent {
+    pub id: i64,
+    pub message_id: Uuid,
+    pub event_type: String,
+    pub payload: Value,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+fn event_from_row(row: &sqlx::postgres::PgRow) -> MessageEvent {
+    MessageEvent {
+        id: row.get("id"),
+        message_id: row.get("message_id"),
+        event_type: row.get("event_type"),
+        payload: row.get("payload"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Database operations for the message event log.
+impl Database {
+    /// Creates the 'message_events' table if it doesn't exist.
+    ///
+    /// `message_id` deliberately has no foreign key: events must outlive
+    /// purged messages.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_message_events_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_message_events_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS message_events (
+                id BIGSERIAL PRIMARY KEY,
+                message_id UUID NOT NULL,
+                event_type TEXT NOT NULL,
+                payload JSONB NOT NULL DEFAULT '{}',
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE INDEX IF NOT EXISTS message_events_message_id_idx
+                ON message_events (message_id, id);
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the events of a message, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_id` - ID of the message
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     for event in db.list_message_events(id).await? {
+    ///         println!("{} {}", event.created_at, event.event_type);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_message_events(&self, message_id: Uuid) -> Result<Vec<MessageEvent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, message_id, event_type, payload, created_at
+            FROM message_events
+            WHERE message_id = $1
+            ORDER BY id
+        "#)
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(event_from_row).collect())
+    }
+
+    /// Retrieves a page of the events of a message, oldest first, for
+    /// `GET /inbox/{id}/events`.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_id` - ID of the message
+    /// * `page` - Page of the events to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_message_events_page(
+        &self, message_id: Uuid, page: &PageRequest
+    ) -> Result<Paginated<MessageEvent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, message_id, event_type, payload, created_at, COUNT(*) OVER () AS total
+            FROM message_events
+            WHERE message_id = $1
+            ORDER BY id
+            LIMIT $2 OFFSET $3
+        "#)
+        .bind(message_id)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, event_from_row))
+    }
+
+    /// Retrieves up to `limit` events recorded after the event `after_id`,
+    /// in log order.
+    ///
+    /// Consumers of the event stream keep the `id` of the last event they
+    /// processed and pass it back to get the next batch; pass `0` to read
+    /// the log from the start.
+    ///
+    /// Events become visible when their transaction commits, which may be
+    /// out of `id` order under concurrent writes. Consumers that must not
+    /// miss any event should leave a small safety delay before reading the
+    /// tail of the log.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let mut cursor = 0;
+    ///     for event in db.list_events_after(cursor, 100).await? {
+    ///         println!("{} {}", event.message_id, event.event_type);
+    ///         cursor = event.id;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_events_after(&self, after_id: i64, limit: i64) -> Result<Vec<MessageEvent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, message_id, event_type, payload, created_at
+            FROM message_events
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+        "#)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(event_from_row).collect())
+    }
+
+    /// Appends an event to the log and enqueues it in the outbox, along
+    /// with the notifications it calls for (see the `notifications` module).
+    ///
+    /// Takes a connection so that it runs in the transaction performing the
+    /// state change it describes.
+    pub(crate) async fn record_event(
+        conn: &mut PgConnection,
+        message_id: Uuid,
+        kind: MessageEventKind,
+        payload: Value,
+    ) -> Result<(), sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO message_events (message_id, event_type, payload)
+            VALUES ($1, $2, $3)
+            RETURNING id, message_id, event_type, payload, created_at
+        "#)
+        .bind(message_id)
+        .bind(kind.as_str())
+        .bind(payload)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let event = event_from_row(&row);
+        Self::enqueue_outbox(
+            &mut *conn,
+            &format!("message.{}", event.event_type),
+            &format!("message_event:{}", event.id),
+            &serde_json::to_value(&event).unwrap_or_default(),
+        ).await?;
+        Self::enqueue_notifications(conn, kind, &event).await
+    }
+}
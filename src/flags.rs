@@ -0,0 +1,274 @@
+//! # Feature Flags
+//!
+//! Runtime switches for optional capabilities (auto-assignment, CAPTCHA,
+//! auto-responder, ...), stored in the `feature_flags` table so that they
+//! can be toggled through the admin API without redeploying. Each
+//! environment has its own database, hence its own flags.
+//!
+//! A flag has a global value and optional per-tenant overrides. A lookup
+//! for a tenant uses its override when there is one, then the global
+//! value, and a flag that was never set is disabled.
+//!
+//! Lookups go through [`FeatureFlags`], which keeps the whole table in
+//! memory and reloads it every [`CACHE_TTL`]: a change made through the
+//! admin API applies immediately on the instance that served it, and
+//! within [`CACHE_TTL`] on the other replicas.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use tokio::sync::RwLock;
+
+use crate::database::Database;
+
+/// How long the flags read from the database are used before reloading them.
+pub const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Longest accepted flag or tenant name.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A stored flag value.
+///
+/// # Fields
+///
+/// * `name` - Name of the flag, e.g. `auto_assignment`
+/// * `tenant` - Tenant the value applies to, `None` for the global value
+/// * `enabled` - Whether the capability is enabled
+/// * `updated_at` - Timestamp of the last change
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub tenant: Option<String>,
+    pub enabled: bool,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn flag_from_row(row: &sqlx::postgres::PgRow) -> FeatureFlag {
+    let tenant: String = row.get("tenant");
+    FeatureFlag {
+        name: row.get("name"),
+        tenant: Some(tenant).filter(|tenant| !tenant.is_empty()),
+        enabled: row.get("enabled"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Returns whether `name` is a valid flag or tenant name: 1 to
+/// `MAX_NAME_LENGTH` lowercase ASCII letters, digits, `_` or `-`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::flags::is_valid_name;
+///
+/// assert!(is_valid_name("auto_assignment"));
+/// assert!(!is_valid_name("Auto Assignment"));
+/// assert!(!is_valid_name(""));
+/// ```
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+}
+
+/// Database operations for feature flags.
+///
+/// The global value of a flag is stored with an empty `tenant`.
+impl Database {
+    /// Creates the 'feature_flags' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_feature_flags_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_feature_flags_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS feature_flags (
+                name TEXT NOT NULL,
+                tenant TEXT NOT NULL DEFAULT '',
+                enabled BOOLEAN NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (name, tenant)
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists every stored flag value, by name then tenant (global first).
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_feature_flags(&self) -> Result<Vec<FeatureFlag>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT name, tenant, enabled, updated_at
+            FROM feature_flags
+            ORDER BY name, tenant
+        "#)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(flag_from_row).collect())
+    }
+
+    /// Sets the global value of a flag, or its override for a tenant.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the flag
+    /// * `tenant` - Tenant to override the flag for, `None` for the global value
+    /// * `enabled` - New value
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.set_feature_flag("captcha", None, true).await?;
+    ///     db.set_feature_flag("captcha", Some("acme"), false).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn set_feature_flag(&self, name: &str, tenant: Option<&str>, enabled: bool) -> Result<FeatureFlag, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO feature_flags (name, tenant, enabled)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name, tenant) DO UPDATE SET enabled = EXCLUDED.enabled, updated_at = NOW()
+            RETURNING name, tenant, enabled, updated_at
+        "#)
+        .bind(name)
+        .bind(tenant.unwrap_or_default())
+        .bind(enabled)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(flag_from_row(&row))
+    }
+
+    /// Removes the global value of a flag, or its override for a tenant.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if a value was removed, `false` if there was none.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_feature_flag(&self, name: &str, tenant: Option<&str>) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM feature_flags WHERE name = $1 AND tenant = $2")
+            .bind(name)
+            .bind(tenant.unwrap_or_default())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Flag values by `(name, tenant)`, the global ones under an empty tenant.
+type Snapshot = HashMap<(String, String), bool>;
+
+/// Cached flag lookups, shared by all workers through the app data.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dothtml_backend::database::Database;
+/// use dothtml_backend::flags::FeatureFlags;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), sqlx::Error> {
+///     let flags = FeatureFlags::new(Database::new().await?);
+///     if flags.is_enabled("auto_responder", Some("acme")).await {
+///         println!("Sending the automatic reply");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct FeatureFlags {
+    db: Database,
+    cache: RwLock<Option<(Instant, Arc<Snapshot>)>>,
+}
+
+impl FeatureFlags {
+    /// Creates an empty cache, filled on the first lookup.
+    pub fn new(db: Database) -> Self {
+        FeatureFlags { db, cache: RwLock::new(None) }
+    }
+
+    /// Returns whether a flag is enabled for `tenant` (or globally with `None`).
+    ///
+    /// When the flags cannot be reloaded, the previous values are kept, or
+    /// every flag is disabled if none were ever loaded.
+    pub async fn is_enabled(&self, name: &str, tenant: Option<&str>) -> bool {
+        let snapshot = self.snapshot().await;
+        let lookup = |tenant: &str| snapshot.get(&(name.to_string(), tenant.to_string())).copied();
+        tenant.and_then(lookup).or_else(|| lookup("")).unwrap_or(false)
+    }
+
+    /// Drops the cached values, so that the next lookup reads the table.
+    pub async fn invalidate(&self) {
+        *self.cache.write().await = None;
+    }
+
+    /// Returns the cached values, reloading them when they are stale.
+    async fn snapshot(&self) -> Arc<Snapshot> {
+        if let Some((loaded_at, snapshot)) = &*self.cache.read().await {
+            if loaded_at.elapsed() < CACHE_TTL {
+                return snapshot.clone();
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another lookup may have reloaded the values while this one waited
+        if let Some((loaded_at, snapshot)) = &*cache {
+            if loaded_at.elapsed() < CACHE_TTL {
+                return snapshot.clone();
+            }
+        }
+
+        let snapshot = match self.db.list_feature_flags().await {
+            Ok(flags) => Arc::new(
+                flags
+                    .into_iter()
+                    .map(|flag| ((flag.name, flag.tenant.unwrap_or_default()), flag.enabled))
+                    .collect(),
+            ),
+            Err(e) => {
+                eprintln!("Failed to load feature flags: {}", e);
+                cache.as_ref().map(|(_, snapshot)| snapshot.clone()).unwrap_or_default()
+            }
+        };
+        *cache = Some((Instant::now(), snapshot.clone()));
+        snapshot
+    }
+}
@@ -0,0 +1,351 @@
+//! # Form Origins
+//!
+//! Sites allowed to submit each form, so that one deployment can serve the
+//! contact forms of many sites: each site posts its own `form`, and only
+//! that site's pages may submit it from a browser.
+//!
+//! The origins of a form are set with `PUT /admin/origins/{form}`. Then:
+//!
+//! - The CORS middleware lets the listed origins call the `/contact`
+//!   endpoints of the form, named by the `form` query parameter (such as
+//!   `POST /contact?form=acme`) since preflight requests have no body, and
+//!   echoes the origin of the request as `Access-Control-Allow-Origin`.
+//! - `POST /contact` rejects with 403 Forbidden a submission of the form
+//!   whose `Origin` header is not one of them (see [`is_allowed`]).
+//!   The list replaces `CORS_ALLOWED_ORIGINS` for that form.
+//!
+//! Forms without a list, `contact` when none is named, are submitted from
+//! the origins of `CORS_ALLOWED_ORIGINS` only, as before: a site listed for
+//! one form cannot submit another. Requests without an `Origin` header do
+//! not come from a browser page and are not concerned, and the contact
+//! widget served by the API itself always submits its form.
+//!
+//! The lists are kept in memory, since CORS checks cannot wait for the
+//! database, and reloaded every 30 seconds by
+//! `jobs::spawn_form_origins_refresh`; a change applies immediately on the
+//! replica that made it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+
+use crate::database::Database;
+
+/// Most origins a form may list.
+pub const MAX_ORIGINS: usize = 50;
+
+/// Origins allowed to submit a form.
+///
+/// # Fields
+///
+/// * `form` - Name of the form, see `is_valid_form_name`
+/// * `origins` - Origins such as `https://acme.com`, without a trailing slash
+/// * `updated_at` - When the list was last saved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FormOriginList {
+    pub form: String,
+    pub origins: Vec<String>,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of `PUT /admin/origins/{form}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormOriginsUpdate {
+    pub origins: Vec<String>,
+}
+
+impl FormOriginsUpdate {
+    /// Normalizes the origins (see [`normalize_origin`]) and removes the
+    /// duplicates.
+    ///
+    /// # Errors
+    ///
+    /// Returns an explanation when no origin, or more than [`MAX_ORIGINS`],
+    /// are given, or one is not an http(s) origin.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::form_origins::FormOriginsUpdate;
+    ///
+    /// let update = FormOriginsUpdate {
+    ///     origins: vec!["https://ACME.com/".to_string(), "https://acme.com".to_string()],
+    /// };
+    /// assert_eq!(update.check().unwrap().origins, vec!["https://acme.com"]);
+    ///
+    /// let update = FormOriginsUpdate { origins: vec!["acme.com".to_string()] };
+    /// assert!(update.check().is_err());
+    /// ```
+    pub fn check(self) -> Result<Self, String> {
+        if self.origins.is_empty() || self.origins.len() > MAX_ORIGINS {
+            return Err(format!("Between 1 and {} origins must be given", MAX_ORIGINS));
+        }
+        let mut origins = Vec::with_capacity(self.origins.len());
+        for origin in &self.origins {
+            let Some(origin) = normalize_origin(origin) else {
+                return Err(format!(
+                    "`{}` is not an origin, expected a scheme and host such as https://example.com",
+                    origin
+                ));
+            };
+            if !origins.contains(&origin) {
+                origins.push(origin);
+            }
+        }
+        Ok(FormOriginsUpdate { origins })
+    }
+}
+
+/// Returns `origin` as browsers send it in the `Origin` header: the scheme
+/// and host in lowercase, and the port unless it is the default one, or
+/// `None` if it is not an http(s) origin.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::form_origins::normalize_origin;
+///
+/// assert_eq!(normalize_origin(" https://Shop.Acme.com/ ").as_deref(), Some("https://shop.acme.com"));
+/// assert_eq!(normalize_origin("http://localhost:4000").as_deref(), Some("http://localhost:4000"));
+/// assert_eq!(normalize_origin("https://acme.com:443").as_deref(), Some("https://acme.com"));
+/// assert_eq!(normalize_origin("https://acme.com/contact"), None);
+/// assert_eq!(normalize_origin("ftp://acme.com"), None);
+/// ```
+pub fn normalize_origin(origin: &str) -> Option<String> {
+    let origin = origin.trim().trim_end_matches('/');
+    let url = reqwest::Url::parse(origin).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.path() != "/" || url.query().is_some() {
+        return None;
+    }
+    if !url.username().is_empty() || url.password().is_some() || url.fragment().is_some() {
+        return None;
+    }
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    })
+}
+
+/// Origin lists of the forms, shared by all workers through the app data.
+///
+/// Lookups only read the loaded lists, so that they can run in the CORS
+/// middleware.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dothtml_backend::database::Database;
+/// use dothtml_backend::form_origins::FormOrigins;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), sqlx::Error> {
+///     let origins = FormOrigins::load(Database::new().await?).await?;
+///     if !origins.allows("quote-request", "https://evil.example", &[]) {
+///         println!("Submissions from evil.example are rejected");
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FormOrigins {
+    db: Database,
+    lists: Arc<ArcSwap<HashMap<String, Vec<String>>>>,
+}
+
+impl FormOrigins {
+    /// Loads the origin lists of the forms.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn load(db: Database) -> Result<Self, sqlx::Error> {
+        let lists = Self::read(&db).await?;
+        Ok(FormOrigins { db, lists: Arc::new(ArcSwap::from_pointee(lists)) })
+    }
+
+    /// Reloads the lists, to pick up the changes made on other replicas.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur;
+    /// the previous lists are kept.
+    pub async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let lists = Self::read(&self.db).await?;
+        self.lists.store(Arc::new(lists));
+        Ok(())
+    }
+
+    async fn read(db: &Database) -> Result<HashMap<String, Vec<String>>, sqlx::Error> {
+        Ok(db
+            .list_form_origins()
+            .await?
+            .into_iter()
+            .map(|list| (list.form, list.origins))
+            .collect())
+    }
+
+    /// Returns whether `form` may be submitted from `origin`, see
+    /// [`is_allowed`].
+    pub fn allows(&self, form: &str, origin: &str, default_origins: &[String]) -> bool {
+        is_allowed(&self.lists.load(), form, origin, default_origins)
+    }
+}
+
+/// Returns whether `form` may be submitted from `origin`: it is one of the
+/// form's origins in `lists`, or the form has none and it is one of
+/// `default_origins` (`CORS_ALLOWED_ORIGINS`).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use dothtml_backend::form_origins::is_allowed;
+///
+/// let lists = HashMap::from([("acme".to_string(), vec!["https://acme.com".to_string()])]);
+/// let defaults = vec!["https://dotshell.eu".to_string()];
+///
+/// assert!(is_allowed(&lists, "acme", "https://acme.com", &defaults));
+/// assert!(!is_allowed(&lists, "acme", "https://dotshell.eu", &defaults));
+/// // A site listed for one form cannot submit the others
+/// assert!(!is_allowed(&lists, "contact", "https://acme.com", &defaults));
+/// assert!(is_allowed(&lists, "contact", "https://dotshell.eu", &defaults));
+/// ```
+pub fn is_allowed(lists: &HashMap<String, Vec<String>>, form: &str, origin: &str, default_origins: &[String]) -> bool {
+    lists
+        .get(form)
+        .map_or(default_origins, |origins| origins.as_slice())
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+}
+
+/// Returns the form named by the `form` parameter of a query string, which
+/// the sites of forms with their own origins add to the `/contact` URLs so
+/// that CORS preflight requests can be checked against the form's origins.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::form_origins::query_form;
+///
+/// assert_eq!(query_form("form=acme&email=a%40b.c").as_deref(), Some("acme"));
+/// assert_eq!(query_form("email=a%40b.c"), None);
+/// ```
+pub fn query_form(query: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct FormQuery {
+        form: Option<String>,
+    }
+    actix_web::web::Query::<FormQuery>::from_query(query).ok()?.into_inner().form
+}
+
+/// Builds a list from a row of the `form_origins` table.
+fn list_from_row(row: &PgRow) -> FormOriginList {
+    FormOriginList {
+        form: row.get("form"),
+        origins: row.get("origins"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Database operations for the origins of the forms.
+impl Database {
+    /// Creates the 'form_origins' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_form_origins_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS form_origins (
+                form TEXT PRIMARY KEY,
+                origins TEXT[] NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the origin lists of the forms, by form name.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for list in db.list_form_origins().await? {
+    ///         println!("{} is submitted from {}", list.form, list.origins.join(", "));
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_form_origins(&self) -> Result<Vec<FormOriginList>, sqlx::Error> {
+        let rows = sqlx::query("SELECT form, origins, updated_at FROM form_origins ORDER BY form")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(list_from_row).collect())
+    }
+
+    /// Replaces the origins of a form.
+    ///
+    /// # Returns
+    ///
+    /// Returns the saved list.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn set_form_origins(&self, form: &str, update: &FormOriginsUpdate) -> Result<FormOriginList, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO form_origins (form, origins)
+            VALUES ($1, $2)
+            ON CONFLICT (form) DO UPDATE SET origins = EXCLUDED.origins, updated_at = NOW()
+            RETURNING form, origins, updated_at
+        "#)
+        .bind(form)
+        .bind(&update.origins)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(list_from_row(&row))
+    }
+
+    /// Deletes the origins of a form, which is then submitted from the
+    /// origins of `CORS_ALLOWED_ORIGINS`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the form had none.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_form_origins(&self, form: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM form_origins WHERE form = $1")
+            .bind(form)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
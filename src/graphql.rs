@@ -0,0 +1,156 @@
+//! # GraphQL API
+//!
+//! Optional GraphQL endpoint for the backoffice, enabled with the `graphql`
+//! cargo feature and served at `POST /graphql`.
+//!
+//! It exposes the message domain so that the backoffice can fetch a message
+//! together with its thread of merged duplicates, its tags and its sender
+//! profile in a single round-trip:
+//!
+//! ```graphql
+//! {
+//!   message(id: "123e4567-e89b-12d3-a456-426614174000") {
+//!     name
+//!     message
+//!     tags
+//!     thread { id createdAt message }
+//!     sender { totalMessages companies }
+//!   }
+//! }
+//! ```
+//!
+//! Nested fields go through per-request `DataLoader`s, so listing a page of
+//! messages with their threads and senders costs one query per field rather
+//! than one per message.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Request, Result, Schema};
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::models::{Message, MessageListOptions, SenderProfile, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::query::FilterExpr;
+
+/// The backoffice GraphQL schema.
+pub type BackofficeSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Builds the backoffice schema on top of `db`.
+pub fn build_schema(db: Database) -> BackofficeSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        .finish()
+}
+
+/// Attaches fresh data loaders to a request.
+///
+/// Loaders cache what they fetch, so they are created for each request to
+/// never serve data from a previous one.
+pub fn with_loaders(request: Request, db: &Database) -> Request {
+    request
+        .data(DataLoader::new(MessageLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(ThreadLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(SenderProfileLoader(db.clone()), tokio::spawn))
+}
+
+/// Root of the GraphQL queries.
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A single message, or null if it does not exist.
+    async fn message(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Message>> {
+        Ok(ctx.data_unchecked::<DataLoader<MessageLoader>>().load_one(id).await?)
+    }
+
+    /// The newest inbox messages, optionally narrowed by a filter expression
+    /// using the same syntax as `GET /inbox?q=`.
+    async fn inbox(
+        &self,
+        ctx: &Context<'_>,
+        q: Option<String>,
+        limit: Option<i64>,
+        #[graphql(default)] include_archived: bool,
+    ) -> Result<Vec<Message>> {
+        let options = MessageListOptions {
+            include_archived,
+            filter: FilterExpr::parse(q.as_deref().unwrap_or_default())?,
+            limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+            ..MessageListOptions::default()
+        };
+        Ok(ctx.data_unchecked::<Database>().list_messages(&options).await?.data)
+    }
+
+    /// The profile of a sender, or null if no message came from this address.
+    async fn sender(&self, ctx: &Context<'_>, email: String) -> Result<Option<SenderProfile>> {
+        Ok(ctx.data_unchecked::<DataLoader<SenderProfileLoader>>().load_one(email.to_lowercase()).await?)
+    }
+}
+
+#[ComplexObject]
+impl Message {
+    /// The message this duplicate was merged into.
+    async fn merged_into_message(&self, ctx: &Context<'_>) -> Result<Option<Message>> {
+        match self.merged_into {
+            Some(id) => Ok(ctx.data_unchecked::<DataLoader<MessageLoader>>().load_one(id).await?),
+            None => Ok(None),
+        }
+    }
+
+    /// Duplicates merged into this message, oldest first.
+    async fn thread(&self, ctx: &Context<'_>) -> Result<Vec<Message>> {
+        let thread = ctx.data_unchecked::<DataLoader<ThreadLoader>>().load_one(self.id).await?;
+        Ok(thread.unwrap_or_default())
+    }
+
+    /// Aggregated profile of the sender.
+    async fn sender(&self, ctx: &Context<'_>) -> Result<Option<SenderProfile>> {
+        Ok(ctx.data_unchecked::<DataLoader<SenderProfileLoader>>().load_one(self.email.to_lowercase()).await?)
+    }
+}
+
+/// Loads messages by ID.
+pub struct MessageLoader(Database);
+
+impl Loader<Uuid> for MessageLoader {
+    type Value = Message;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, Message>, Self::Error> {
+        let messages = self.0.get_messages_by_ids(ids).await?;
+        Ok(messages.into_iter().map(|message| (message.id, message)).collect())
+    }
+}
+
+/// Loads the duplicates merged into messages, by ID of the thread head.
+pub struct ThreadLoader(Database);
+
+impl Loader<Uuid> for ThreadLoader {
+    type Value = Vec<Message>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Message>>, Self::Error> {
+        let mut threads: HashMap<Uuid, Vec<Message>> = HashMap::new();
+        for message in self.0.list_merged_messages(ids).await? {
+            if let Some(head) = message.merged_into {
+                threads.entry(head).or_default().push(message);
+            }
+        }
+        Ok(threads)
+    }
+}
+
+/// Loads sender profiles by lowercased email address.
+pub struct SenderProfileLoader(Database);
+
+impl Loader<String> for SenderProfileLoader {
+    type Value = SenderProfile;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, emails: &[String]) -> Result<HashMap<String, SenderProfile>, Self::Error> {
+        let profiles = self.0.sender_profiles(emails).await?;
+        Ok(profiles.into_iter().map(|profile| (profile.email.clone(), profile)).collect())
+    }
+}
@@ -0,0 +1,110 @@
+//! # iCalendar Files
+//!
+//! Writer of the ICS (RFC 5545) files the server produces: meeting times
+//! proposed to senders (see the `scheduling` module) and the deadline
+//! feeds of agents (see the `deadlines` module). Only what they need is
+//! supported: a calendar of events with UTC times.
+
+use chrono::{DateTime, Utc};
+
+/// Longest line of an ICS file, in bytes, before it is folded.
+const LINE_LIMIT: usize = 75;
+
+/// An ICS calendar being written.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{TimeZone, Utc};
+/// use dothtml_backend::ics::{self, Calendar};
+///
+/// let start = Utc.with_ymd_and_hms(2026, 10, 20, 14, 0, 0).unwrap();
+/// let mut calendar = Calendar::new("PUBLISH");
+/// calendar.event(&[
+///     ("UID", "42@dothtml-backend".to_string()),
+///     ("DTSTART", ics::date_time(start)),
+///     ("SUMMARY", ics::text("Call; about sites, apps")),
+/// ]);
+/// let ics = calendar.finish();
+/// assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+/// assert!(ics.contains("DTSTART:20261020T140000Z\r\n"));
+/// assert!(ics.contains("SUMMARY:Call\\; about sites\\, apps\r\n"));
+/// assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    lines: Vec<String>,
+}
+
+impl Calendar {
+    /// Starts a calendar with the given `METHOD`.
+    pub fn new(method: &str) -> Self {
+        Calendar {
+            lines: vec![
+                "BEGIN:VCALENDAR".to_string(),
+                "VERSION:2.0".to_string(),
+                "PRODID:-//dotshell//dothtml-backend//EN".to_string(),
+                "CALSCALE:GREGORIAN".to_string(),
+                format!("METHOD:{}", method),
+            ],
+        }
+    }
+
+    /// Adds a property to the calendar itself, such as its name.
+    pub fn property(&mut self, name: &str, value: &str) -> &mut Self {
+        self.lines.push(format!("{}:{}", name, value));
+        self
+    }
+
+    /// Adds an event made of `properties`, whose values are already
+    /// formatted (see [`date_time`] and [`text`]).
+    pub fn event(&mut self, properties: &[(&str, String)]) -> &mut Self {
+        self.lines.push("BEGIN:VEVENT".to_string());
+        self.lines.extend(properties.iter().map(|(name, value)| format!("{}:{}", name, value)));
+        self.lines.push("END:VEVENT".to_string());
+        self
+    }
+
+    /// Ends the calendar and returns the file, its long lines folded.
+    pub fn finish(mut self) -> String {
+        self.lines.push("END:VCALENDAR".to_string());
+        self.lines.iter().map(|line| fold_line(line) + "\r\n").collect()
+    }
+}
+
+/// Formats a time as an ICS UTC date-time.
+pub fn date_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes the value of a text property.
+pub fn text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Formats a quoted parameter value, such as a `CN`, which cannot hold
+/// quotes or line breaks.
+pub fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace(['"', '\r', '\n'], ""))
+}
+
+/// Folds a line longer than the limit, continuing it on lines starting
+/// with a space, without splitting a character.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / LINE_LIMIT * 3);
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > LINE_LIMIT {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
@@ -0,0 +1,188 @@
+//! # Typed Identifiers
+//!
+//! Newtypes around the UUIDs identifying messages and companies, so that
+//! one cannot be passed where the other is expected.
+//!
+//! Each type is also an extractor reading the `{id}` segment of the route:
+//! a handler taking a [`MessageId`] only runs for a well-formed UUID, and a
+//! malformed one is answered with 400 Bad Request before reaching the
+//! database (see [`InvalidId`]). Handlers answer 404 Not Found only for a
+//! well-formed ID that matches nothing.
+//!
+//! ```rust
+//! use actix_web::{HttpResponse, Responder};
+//! use dothtml_backend::ids::MessageId;
+//!
+//! async fn show(id: MessageId) -> impl Responder {
+//!     HttpResponse::Ok().body(format!("message {}", id))
+//! }
+//! ```
+//!
+//! The types serialize as plain UUID strings and are stored as `UUID`
+//! columns.
+
+use std::fmt;
+use std::future::{ready, Ready};
+use std::str::FromStr;
+
+use actix_web::{dev::Payload, http::StatusCode, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+use uuid::Uuid;
+
+/// Name of the route segment read by the extractors.
+const PATH_SEGMENT: &str = "id";
+
+/// Error code of the response to a malformed ID.
+pub const INVALID_ID_CODE: &str = "invalid_id";
+
+/// Rejection of a route whose `{id}` segment is not a UUID, answered with
+/// 400 Bad Request:
+///
+/// ```json
+/// {
+///   "status": "error",
+///   "code": "invalid_id",
+///   "message": "Invalid message ID"
+/// }
+/// ```
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::ResponseError;
+/// use dothtml_backend::ids::InvalidId;
+///
+/// let error = InvalidId { entity: "message" };
+/// assert_eq!(error.to_string(), "Invalid message ID");
+/// assert_eq!(error.error_response().status(), 400);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidId {
+    pub entity: &'static str,
+}
+
+impl fmt::Display for InvalidId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} ID", self.entity)
+    }
+}
+
+impl ResponseError for InvalidId {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "code": INVALID_ID_CODE,
+            "message": self.to_string()
+        }))
+    }
+}
+
+macro_rules! typed_id {
+    ($(#[$doc:meta])* $name:ident, $what:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(pub Uuid);
+
+        impl $name {
+            /// Returns the wrapped UUID.
+            pub fn into_inner(self) -> Uuid {
+                self.0
+            }
+        }
+
+        impl From<Uuid> for $name {
+            fn from(id: Uuid) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for Uuid {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = uuid::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map($name)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Type<Postgres> for $name {
+            fn type_info() -> PgTypeInfo {
+                <Uuid as Type<Postgres>>::type_info()
+            }
+        }
+
+        impl Encode<'_, Postgres> for $name {
+            fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+                <Uuid as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+            }
+        }
+
+        impl Decode<'_, Postgres> for $name {
+            fn decode(value: PgValueRef<'_>) -> Result<Self, BoxDynError> {
+                <Uuid as Decode<Postgres>>::decode(value).map($name)
+            }
+        }
+
+        impl FromRequest for $name {
+            type Error = InvalidId;
+            type Future = Ready<Result<Self, Self::Error>>;
+
+            fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+                let id = req.match_info().get(PATH_SEGMENT).and_then(|id| id.parse().ok());
+                ready(id.ok_or(InvalidId { entity: $what }))
+            }
+        }
+    };
+}
+
+typed_id!(
+    /// Identifier of a message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::ids::MessageId;
+    ///
+    /// let id: MessageId = "123e4567-e89b-12d3-a456-426614174000".parse().unwrap();
+    /// assert_eq!(id.to_string(), "123e4567-e89b-12d3-a456-426614174000");
+    /// assert!("not-a-uuid".parse::<MessageId>().is_err());
+    /// ```
+    MessageId,
+    "message"
+);
+
+typed_id!(
+    /// Identifier of a company.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::ids::CompanyId;
+    /// use uuid::Uuid;
+    ///
+    /// let uuid = Uuid::new_v4();
+    /// assert_eq!(Uuid::from(CompanyId::from(uuid)), uuid);
+    /// ```
+    CompanyId,
+    "company"
+);
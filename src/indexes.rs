@@ -0,0 +1,295 @@
+//! # Required Indexes
+//!
+//! Lists the indexes the queries of the server rely on, and checks that
+//! they exist. Without them PostgreSQL still answers, only with sequential
+//! scans that get slower as the inbox grows, which nothing else reports.
+//!
+//! An index is found when the table has a valid index whose leading keys
+//! are the required ones, whatever its name, so an index created by hand
+//! counts. Missing indexes are:
+//!
+//! - created in the background at startup with `CREATE INDEX CONCURRENTLY`,
+//!   which does not block writes (except on a partitioned table), unless
+//!   `CREATE_MISSING_INDEXES=false`
+//! - reported by `dothtml-backend check`
+//! - listed, or created with `--create`, by `dothtml-backend indexes`
+//!
+//! An index left invalid by an interrupted concurrent build is dropped and
+//! built again.
+
+use std::fmt;
+
+use sqlx::Row;
+
+use crate::database::Database;
+
+/// An index the queries rely on.
+///
+/// # Fields
+///
+/// * `name` - Name of the index when the server creates it
+/// * `table` - Indexed table
+/// * `keys` - Leading key columns or expressions, as PostgreSQL prints them
+/// * `predicate` - Condition of a partial index, `None` for a full index
+/// * `purpose` - Queries using the index
+#[derive(Debug, Clone, Copy)]
+pub struct RequiredIndex {
+    pub name: &'static str,
+    pub table: &'static str,
+    pub keys: &'static [&'static str],
+    pub predicate: Option<&'static str>,
+    pub purpose: &'static str,
+}
+
+impl RequiredIndex {
+    /// Returns the statement creating the index without blocking writes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::indexes::REQUIRED_INDEXES;
+    ///
+    /// let index = REQUIRED_INDEXES.iter().find(|index| index.name == "messages_lower_email_idx").unwrap();
+    /// assert_eq!(
+    ///     index.create_statement(),
+    ///     "CREATE INDEX CONCURRENTLY IF NOT EXISTS messages_lower_email_idx ON messages (lower(email))"
+    /// );
+    /// ```
+    pub fn create_statement(&self) -> String {
+        self.statement(true)
+    }
+
+    /// Returns the statement creating the index, building it concurrently
+    /// or under a lock on the table. Partitioned tables only support the latter.
+    pub(crate) fn statement(&self, concurrently: bool) -> String {
+        let mut statement = format!(
+            "CREATE INDEX {}IF NOT EXISTS {} ON {} ({})",
+            if concurrently { "CONCURRENTLY " } else { "" },
+            self.name,
+            self.table,
+            self.keys.join(", ")
+        );
+        if let Some(predicate) = self.predicate {
+            statement.push_str(&format!(" WHERE {}", predicate));
+        }
+        statement
+    }
+}
+
+impl fmt::Display for RequiredIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ON {} ({})", self.name, self.table, self.keys.join(", "))
+    }
+}
+
+/// Every index the queries rely on.
+pub const REQUIRED_INDEXES: [RequiredIndex; 8] = [
+    RequiredIndex {
+        name: "messages_created_at_id_idx",
+        table: "messages",
+        keys: &["created_at", "id"],
+        predicate: None,
+        purpose: "inbox listing order and cursors, statistics",
+    },
+    RequiredIndex {
+        name: "messages_status_created_at_idx",
+        table: "messages",
+        keys: &["status", "created_at"],
+        predicate: None,
+        purpose: "status filters, archiving and auto-release jobs",
+    },
+    RequiredIndex {
+        name: "messages_pending_created_at_idx",
+        table: "messages",
+        keys: &["created_at"],
+        predicate: Some("status = 'pending'"),
+        purpose: "pending listing, claim-next",
+    },
+    RequiredIndex {
+        name: "messages_lower_email_idx",
+        table: "messages",
+        keys: &["lower(email)"],
+        predicate: None,
+        purpose: "sender profiles, related messages, email filters",
+    },
+    RequiredIndex {
+        name: "messages_company_id_idx",
+        table: "messages",
+        keys: &["company_id"],
+        predicate: None,
+        purpose: "company messages and merges",
+    },
+    RequiredIndex {
+        name: "assignment_history_message_id_idx",
+        table: "assignment_history",
+        keys: &["message_id"],
+        predicate: None,
+        purpose: "assignment history",
+    },
+    RequiredIndex {
+        name: "message_events_message_id_idx",
+        table: "message_events",
+        keys: &["message_id", "id"],
+        predicate: None,
+        purpose: "message events",
+    },
+    RequiredIndex {
+        name: "outbox_pending_idx",
+        table: "outbox",
+        keys: &["next_attempt_at", "id"],
+        predicate: Some("published_at IS NULL"),
+        purpose: "outbox relay",
+    },
+];
+
+/// State of a required index.
+///
+/// * `Present` - A valid index covers it
+/// * `Missing` - No index covers it
+/// * `Invalid` - The index exists under its name but is unusable, left by
+///   an interrupted concurrent build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexState {
+    Present,
+    Missing,
+    Invalid,
+}
+
+impl fmt::Display for IndexState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IndexState::Present => "present",
+            IndexState::Missing => "missing",
+            IndexState::Invalid => "invalid",
+        })
+    }
+}
+
+/// An index found on one of the tables of [`REQUIRED_INDEXES`].
+struct ExistingIndex {
+    name: String,
+    table: String,
+    keys: Vec<String>,
+    partial: bool,
+    valid: bool,
+}
+
+impl ExistingIndex {
+    /// Returns whether this index serves the queries `required` is for: same
+    /// leading keys, and no condition unless it is the required index itself.
+    fn covers(&self, required: &RequiredIndex) -> bool {
+        self.valid
+            && self.table == required.table
+            && self.keys.len() >= required.keys.len()
+            && self.keys.iter().zip(required.keys).all(|(key, required)| key == required)
+            && (!self.partial || self.name == required.name)
+    }
+}
+
+/// Database operations for required indexes.
+impl Database {
+    /// Returns the state of every index of [`REQUIRED_INDEXES`].
+    ///
+    /// Indexes of tables that do not exist yet are reported missing.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::indexes::IndexState;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for (index, state) in db.check_indexes().await? {
+    ///         if state != IndexState::Present {
+    ///             println!("{}: {}", index, state);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn check_indexes(&self) -> Result<Vec<(RequiredIndex, IndexState)>, sqlx::Error> {
+        let tables: Vec<&str> = REQUIRED_INDEXES.iter().map(|index| index.table).collect();
+        let rows = sqlx::query(r#"
+            SELECT
+                i.relname AS name,
+                t.relname AS table_name,
+                ix.indpred IS NOT NULL AS partial,
+                ix.indisvalid AS valid,
+                ARRAY(
+                    SELECT pg_get_indexdef(ix.indexrelid, k, true)
+                    FROM generate_series(1, ix.indnkeyatts) AS k
+                    ORDER BY k
+                ) AS keys
+            FROM pg_index ix
+            JOIN pg_class i ON i.oid = ix.indexrelid
+            JOIN pg_class t ON t.oid = ix.indrelid
+            WHERE t.relname = ANY($1) AND pg_table_is_visible(t.oid)
+        "#)
+        .bind(tables)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let existing: Vec<ExistingIndex> = rows
+            .iter()
+            .map(|row| ExistingIndex {
+                name: row.get("name"),
+                table: row.get("table_name"),
+                keys: row.get("keys"),
+                partial: row.get("partial"),
+                valid: row.get("valid"),
+            })
+            .collect();
+
+        Ok(REQUIRED_INDEXES
+            .iter()
+            .map(|required| {
+                let state = if existing.iter().any(|index| index.covers(required)) {
+                    IndexState::Present
+                } else if existing.iter().any(|index| index.name == required.name) {
+                    IndexState::Invalid
+                } else {
+                    IndexState::Missing
+                };
+                (*required, state)
+            })
+            .collect())
+    }
+
+    /// Creates the missing and invalid required indexes, one at a time,
+    /// without blocking writes to the tables, except partitioned ones.
+    ///
+    /// # Returns
+    ///
+    /// Returns the indexes that were created.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for index creation
+    /// - An index cannot be built, e.g. when the database runs out of disk space
+    pub async fn create_missing_indexes(&self) -> Result<Vec<RequiredIndex>, sqlx::Error> {
+        let mut created = Vec::new();
+        for (index, state) in self.check_indexes().await? {
+            if state == IndexState::Present || !self.missing_tables(&[index.table]).await?.is_empty() {
+                continue;
+            }
+            let concurrently = !self.is_partitioned(index.table).await?;
+            if state == IndexState::Invalid {
+                let drop = if concurrently { "DROP INDEX CONCURRENTLY" } else { "DROP INDEX" };
+                sqlx::query(&format!("{} IF EXISTS {}", drop, index.name))
+                    .execute(&self.pool)
+                    .await?;
+            }
+            sqlx::query(&index.statement(concurrently)).execute(&self.pool).await?;
+            created.push(index);
+        }
+        Ok(created)
+    }
+}
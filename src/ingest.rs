@@ -0,0 +1,597 @@
+//! # Form Builder Ingestion
+//!
+//! Submissions of forms built with external providers, posted by their
+//! webhooks to `POST /ingest/{provider}` and stored as messages like the
+//! ones of the contact form, so that teams using those forms still work
+//! from one inbox. Supported providers:
+//!
+//! - `typeform` - Typeform webhooks (`form_response` events), signed in `Typeform-Signature`
+//! - `tally` - Tally webhooks (`FORM_RESPONSE` events), signed in `Tally-Signature`
+//!
+//! Admins set a provider up with `PUT /admin/ingest/{provider}`: the secret
+//! entered in the provider's webhook settings, which signs every delivery
+//! with HMAC-SHA256, the form the messages are filed under, and the
+//! mapping of the message fields to the questions of the form. A question
+//! is named by its ID, its reference (Typeform) or key (Tally), or its
+//! label, ignoring case:
+//!
+//! ```json
+//! {
+//!   "secret": "n0t-s0-s3cr3t",
+//!   "form": "demo-request",
+//!   "mapping": { "name": "What's your name?", "company": "company_ref", "message": "How can we help?" }
+//! }
+//! ```
+//!
+//! Unmapped, `email` and `phone_number` take the first answer of that type,
+//! and `message` lists every answer left, one `label: answer` line each,
+//! so that nothing the sender wrote is lost. The message is then checked
+//! like a contact form submission. Providers retry deliveries; a
+//! submission already stored is acknowledged again with its reference
+//! rather than stored twice.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::Row;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::database::Database;
+
+/// Providers accepted by `POST /ingest/{provider}`.
+pub const FORM_PROVIDERS: [&str; 2] = ["typeform", "tally"];
+
+/// Message fields a mapping can fill.
+pub const MAPPED_FIELDS: [&str; 6] = ["name", "email", "country_region", "phone_number", "company", "message"];
+
+/// A form builder posting submissions.
+///
+/// * `Typeform` - typeform.com
+/// * `Tally` - tally.so
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormProvider {
+    Typeform,
+    Tally,
+}
+
+impl FormProvider {
+    /// Parses a provider name.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "typeform" => Some(FormProvider::Typeform),
+            "tally" => Some(FormProvider::Tally),
+            _ => None,
+        }
+    }
+
+    /// Returns the name used in the URL and stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FormProvider::Typeform => "typeform",
+            FormProvider::Tally => "tally",
+        }
+    }
+
+    /// Returns the header carrying the signature of a delivery.
+    pub fn signature_header(&self) -> &'static str {
+        match self {
+            FormProvider::Typeform => "Typeform-Signature",
+            FormProvider::Tally => "Tally-Signature",
+        }
+    }
+
+    /// Checks the signature of a delivery: the base64 HMAC-SHA256 of its
+    /// body keyed with `secret`, prefixed with `sha256=` for Typeform.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::ingest::FormProvider;
+    ///
+    /// let body = br#"{"eventType":"FORM_RESPONSE"}"#;
+    /// let signature = FormProvider::Tally.sign("s3cr3t", body);
+    /// assert!(FormProvider::Tally.verify("s3cr3t", body, &signature));
+    /// assert!(!FormProvider::Tally.verify("other", body, &signature));
+    /// assert!(FormProvider::Typeform.sign("s3cr3t", body).starts_with("sha256="));
+    /// ```
+    pub fn verify(&self, secret: &str, body: &[u8], signature: &str) -> bool {
+        let signature = match self {
+            FormProvider::Typeform => signature.trim().strip_prefix("sha256="),
+            FormProvider::Tally => Some(signature.trim()),
+        };
+        let Some(Ok(signature)) = signature.map(|signature| STANDARD.decode(signature)) else {
+            return false;
+        };
+        mac(secret, body).verify_slice(&signature).is_ok()
+    }
+
+    /// Returns the signature of `body` as the provider would send it.
+    pub fn sign(&self, secret: &str, body: &[u8]) -> String {
+        let signature = STANDARD.encode(mac(secret, body).finalize().into_bytes());
+        match self {
+            FormProvider::Typeform => format!("sha256={}", signature),
+            FormProvider::Tally => signature,
+        }
+    }
+
+    /// Extracts the submission of a delivery.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` for deliveries that are not form submissions, such as
+    /// Typeform's test pings without answers or other Tally events.
+    pub fn submission(&self, payload: &Value) -> Option<Submission> {
+        match self {
+            FormProvider::Typeform => typeform_submission(payload),
+            FormProvider::Tally => tally_submission(payload),
+        }
+    }
+}
+
+fn mac(secret: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac
+}
+
+/// An answer of a submission.
+///
+/// # Fields
+///
+/// * `names` - ID, reference or key of the question, by which a mapping can name it
+/// * `label` - Question as shown to the sender
+/// * `kind` - Type of the question, in the provider's words, such as `email` or `INPUT_EMAIL`
+/// * `value` - The answer, as text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub names: Vec<String>,
+    pub label: String,
+    pub kind: String,
+    pub value: String,
+}
+
+impl Answer {
+    /// Returns whether a mapping naming `question` means this answer.
+    fn is_named(&self, question: &str) -> bool {
+        let question = question.trim();
+        self.names.iter().any(|name| name == question) || self.label.trim().eq_ignore_ascii_case(question)
+    }
+}
+
+/// A form submission.
+///
+/// # Fields
+///
+/// * `id` - Identifier of the submission at the provider, the same in retries
+/// * `answers` - Answers, in the order of the form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submission {
+    pub id: String,
+    pub answers: Vec<Answer>,
+}
+
+/// Fields of a message filled from a submission.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MappedMessage {
+    pub name: String,
+    pub email: String,
+    pub country_region: String,
+    pub phone_number: String,
+    pub company: String,
+    pub message: String,
+}
+
+impl Submission {
+    /// Fills the fields of a message from the answers, following `mapping`
+    /// (see the module documentation).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use serde_json::json;
+    /// use dothtml_backend::ingest::FormProvider;
+    ///
+    /// let payload = json!({
+    ///     "eventType": "FORM_RESPONSE",
+    ///     "data": {
+    ///         "responseId": "2wgx4n",
+    ///         "fields": [
+    ///             { "key": "question_3EKz4n", "label": "Your name", "type": "INPUT_TEXT", "value": "Jane Doe" },
+    ///             { "key": "question_w4Q4Xn", "label": "Email", "type": "INPUT_EMAIL", "value": "jane@example.com" },
+    ///             { "key": "question_mVGBoP", "label": "Budget", "type": "MULTIPLE_CHOICE", "value": ["b1"],
+    ///               "options": [{ "id": "b1", "text": "Under 5k" }, { "id": "b2", "text": "5k or more" }] },
+    ///             { "key": "question_nPOXAd", "label": "Project", "type": "TEXTAREA", "value": "A new website" }
+    ///         ]
+    ///     }
+    /// });
+    /// let submission = FormProvider::Tally.submission(&payload).unwrap();
+    /// assert_eq!(submission.id, "2wgx4n");
+    ///
+    /// let mapping = BTreeMap::from([("name".to_string(), "your name".to_string())]);
+    /// let message = submission.map(&mapping);
+    /// assert_eq!(message.name, "Jane Doe");
+    /// assert_eq!(message.email, "jane@example.com");
+    /// assert_eq!(message.message, "Budget: Under 5k\nProject: A new website");
+    /// ```
+    pub fn map(&self, mapping: &BTreeMap<String, String>) -> MappedMessage {
+        let mut used = vec![false; self.answers.len()];
+        let mut take = |field: &str, kinds: &[&str]| -> Option<String> {
+            let position = match mapping.get(field) {
+                Some(question) => self.answers.iter().position(|answer| answer.is_named(question)),
+                None => self.answers.iter().position(|answer| kinds.iter().any(|kind| answer.kind.eq_ignore_ascii_case(kind))),
+            }?;
+            used[position] = true;
+            Some(self.answers[position].value.clone())
+        };
+
+        let mut message = MappedMessage {
+            name: take("name", &[]).unwrap_or_default(),
+            email: take("email", &["email", "INPUT_EMAIL"]).unwrap_or_default(),
+            country_region: take("country_region", &[]).unwrap_or_default(),
+            phone_number: take("phone_number", &["phone_number", "INPUT_PHONE_NUMBER"]).unwrap_or_default(),
+            company: take("company", &[]).unwrap_or_default(),
+            message: String::new(),
+        };
+        message.message = match take("message", &[]) {
+            Some(text) => text,
+            None => self
+                .answers
+                .iter()
+                .zip(&used)
+                .filter(|(_, used)| !**used)
+                .map(|(answer, _)| format!("{}: {}", answer.label, answer.value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        message
+    }
+}
+
+/// Turns a JSON answer into text.
+fn answer_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()).filter(|text| !text.trim().is_empty()),
+        Value::Bool(true) => Some("yes".to_string()),
+        Value::Bool(false) => Some("no".to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Array(values) => {
+            let values: Vec<String> = values.iter().filter_map(answer_text).collect();
+            (!values.is_empty()).then(|| values.join(", "))
+        }
+        // Uploaded files and other structured answers
+        Value::Object(object) => object.get("url").or_else(|| object.get("name")).and_then(answer_text),
+    }
+}
+
+fn typeform_submission(payload: &Value) -> Option<Submission> {
+    if payload.get("event_type").and_then(Value::as_str) != Some("form_response") {
+        return None;
+    }
+    let response = payload.get("form_response")?;
+    let titles: BTreeMap<&str, &str> = response
+        .pointer("/definition/fields")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|field| Some((field.get("id")?.as_str()?, field.get("title")?.as_str()?)))
+        .collect();
+
+    let answers = response
+        .get("answers")?
+        .as_array()?
+        .iter()
+        .filter_map(|answer| {
+            let field = answer.get("field")?;
+            let id = field.get("id").and_then(Value::as_str).unwrap_or_default();
+            let kind = answer.get("type")?.as_str()?;
+            let value = match kind {
+                "choice" => answer.get("choice").and_then(|choice| choice.get("label").or_else(|| choice.get("other"))),
+                "choices" => answer.get("choices").and_then(|choices| choices.get("labels")),
+                _ => answer.get(kind),
+            };
+            Some(Answer {
+                names: [Some(id), field.get("ref").and_then(Value::as_str)].into_iter().flatten().map(str::to_string).collect(),
+                label: titles.get(id).map(|title| title.to_string()).unwrap_or_else(|| id.to_string()),
+                kind: field.get("type").and_then(Value::as_str).unwrap_or(kind).to_string(),
+                value: answer_text(value?)?,
+            })
+        })
+        .collect();
+
+    Some(Submission { id: response.get("token")?.as_str()?.to_string(), answers })
+}
+
+fn tally_submission(payload: &Value) -> Option<Submission> {
+    if payload.get("eventType").and_then(Value::as_str) != Some("FORM_RESPONSE") {
+        return None;
+    }
+    let data = payload.get("data")?;
+    let answers = data
+        .get("fields")?
+        .as_array()?
+        .iter()
+        .filter_map(|field| {
+            let key = field.get("key")?.as_str()?;
+            let value = field.get("value")?;
+            // Choices are answered with the IDs of the options
+            let value = match field.get("options").and_then(Value::as_array) {
+                Some(options) => {
+                    let text_of = |id: &Value| {
+                        options.iter().find(|option| option.get("id") == Some(id)).and_then(|option| option.get("text")).cloned()
+                    };
+                    match value {
+                        Value::Array(ids) => Value::Array(ids.iter().map(|id| text_of(id).unwrap_or_else(|| id.clone())).collect()),
+                        id => text_of(id).unwrap_or_else(|| id.clone()),
+                    }
+                }
+                None => value.clone(),
+            };
+            Some(Answer {
+                names: vec![key.to_string()],
+                label: field.get("label").and_then(Value::as_str).unwrap_or(key).to_string(),
+                kind: field.get("type").and_then(Value::as_str).unwrap_or_default().to_string(),
+                value: answer_text(&value)?,
+            })
+        })
+        .collect();
+
+    Some(Submission { id: data.get("responseId")?.as_str()?.to_string(), answers })
+}
+
+/// A provider set up to post submissions.
+///
+/// # Fields
+///
+/// * `provider` - The form builder
+/// * `form` - Form the messages are filed under
+/// * `mapping` - Questions filling the message fields, by field
+/// * `created_at` - Timestamp when the provider was set up
+/// * `updated_at` - Timestamp of the last change of its settings
+///
+/// The secret is kept out of the API responses.
+#[derive(Debug, Clone, Serialize)]
+pub struct FormSource {
+    pub provider: FormProvider,
+    pub form: String,
+    pub mapping: BTreeMap<String, String>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn source_from_row(row: &PgRow) -> FormSource {
+    FormSource {
+        // Written by this module from a parsed provider
+        provider: FormProvider::parse(row.get("provider")).unwrap_or(FormProvider::Typeform),
+        form: row.get("form"),
+        mapping: row.get::<Json<BTreeMap<String, String>>, _>("mapping").0,
+        secret: row.get("secret"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Settings of a provider, as set by an admin.
+///
+/// # Fields
+///
+/// * `secret` - Secret signing the deliveries, as entered in the provider's webhook settings
+/// * `form` - Form the messages are filed under (default: the provider's name)
+/// * `mapping` - Questions filling the message fields, by field (default: none)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormSourceDefinition {
+    pub secret: String,
+    #[serde(default)]
+    pub form: Option<String>,
+    #[serde(default)]
+    pub mapping: BTreeMap<String, String>,
+}
+
+impl FormSourceDefinition {
+    /// Checks the settings and returns them normalized, with the form
+    /// defaulting to the name of `provider`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first problem found.
+    pub fn check(mut self, provider: FormProvider) -> Result<Self, String> {
+        self.secret = self.secret.trim().to_string();
+        if !(16..=200).contains(&self.secret.len()) {
+            return Err("Secret must be between 16 and 200 characters".to_string());
+        }
+        let form = self.form.take().map(|form| form.trim().to_string()).unwrap_or_else(|| provider.as_str().to_string());
+        if !crate::models::is_valid_form_name(&form) {
+            return Err(format!(
+                "Form must be 1 to {} lowercase letters, digits, `_` or `-`",
+                crate::models::MAX_FORM_NAME_LENGTH
+            ));
+        }
+        self.form = Some(form);
+        if let Some(field) = self.mapping.keys().find(|field| !MAPPED_FIELDS.contains(&field.as_str())) {
+            return Err(format!("Unknown field `{}`, expected one of: {}", field, MAPPED_FIELDS.join(", ")));
+        }
+        if self.mapping.values().any(|question| question.trim().is_empty()) {
+            return Err("Mapped questions must not be empty".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Database operations for the form builder ingestion.
+impl Database {
+    /// Creates the 'form_sources' and 'ingested_submissions' tables if they
+    /// don't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_ingest_tables(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS form_sources (
+                provider TEXT PRIMARY KEY,
+                form TEXT NOT NULL,
+                mapping JSONB NOT NULL DEFAULT '{}',
+                secret TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS ingested_submissions (
+                provider TEXT NOT NULL,
+                submission_id TEXT NOT NULL,
+                message_id UUID,
+                received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (provider, submission_id)
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the providers set up, by name.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_form_sources(&self) -> Result<Vec<FormSource>, sqlx::Error> {
+        let rows = sqlx::query("SELECT provider, form, mapping, secret, created_at, updated_at FROM form_sources ORDER BY provider")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(source_from_row).collect())
+    }
+
+    /// Fetches the settings of a provider, `None` when it is not set up.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn get_form_source(&self, provider: FormProvider) -> Result<Option<FormSource>, sqlx::Error> {
+        let row = sqlx::query("SELECT provider, form, mapping, secret, created_at, updated_at FROM form_sources WHERE provider = $1")
+            .bind(provider.as_str())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(source_from_row))
+    }
+
+    /// Sets a provider up, or replaces its settings, checked with
+    /// [`FormSourceDefinition::check`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn set_form_source(&self, provider: FormProvider, source: &FormSourceDefinition) -> Result<FormSource, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO form_sources (provider, form, mapping, secret)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (provider) DO UPDATE
+            SET form = EXCLUDED.form, mapping = EXCLUDED.mapping, secret = EXCLUDED.secret, updated_at = NOW()
+            RETURNING provider, form, mapping, secret, created_at, updated_at
+        "#)
+        .bind(provider.as_str())
+        .bind(source.form.as_deref().unwrap_or(provider.as_str()))
+        .bind(Json(&source.mapping))
+        .bind(&source.secret)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(source_from_row(&row))
+    }
+
+    /// Stops accepting the submissions of a provider.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the provider was set up, `false` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_form_source(&self, provider: FormProvider) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM form_sources WHERE provider = $1")
+            .bind(provider.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Claims a submission before storing its message, so that a retried
+    /// delivery is not stored twice.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` when the submission is new, and `Ok(Some(message_id))`
+    /// when it was already claimed, `message_id` being `None` while its
+    /// message is still being stored.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn claim_submission(&self, provider: FormProvider, submission_id: &str) -> Result<Option<Option<Uuid>>, sqlx::Error> {
+        let claimed = sqlx::query(r#"
+            INSERT INTO ingested_submissions (provider, submission_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+        "#)
+        .bind(provider.as_str())
+        .bind(submission_id)
+        .execute(&self.pool)
+        .await?;
+        if claimed.rows_affected() > 0 {
+            return Ok(None);
+        }
+
+        let row = sqlx::query("SELECT message_id FROM ingested_submissions WHERE provider = $1 AND submission_id = $2")
+            .bind(provider.as_str())
+            .bind(submission_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(Some(row.get("message_id")))
+    }
+
+    /// Records the message stored for a claimed submission, or releases the
+    /// claim with `None` when it could not be stored, so that the provider's
+    /// retry is accepted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn complete_submission(
+        &self, provider: FormProvider, submission_id: &str, message_id: Option<Uuid>
+    ) -> Result<(), sqlx::Error> {
+        let query = match message_id {
+            Some(message_id) => sqlx::query(
+                "UPDATE ingested_submissions SET message_id = $3 WHERE provider = $1 AND submission_id = $2"
+            )
+            .bind(provider.as_str())
+            .bind(submission_id)
+            .bind(message_id),
+            None => sqlx::query("DELETE FROM ingested_submissions WHERE provider = $1 AND submission_id = $2")
+                .bind(provider.as_str())
+                .bind(submission_id),
+        };
+        query.execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! # Integration API
+//!
+//! A small, stable surface for no-code automation tools such as Zapier or
+//! Make, apart from the backoffice API so that it can keep its shapes while
+//! the backoffice evolves:
+//!
+//! - `GET /integrations/me` - Check the credentials, for the tool's connection test
+//! - `GET /integrations/messages` - Polling trigger: the messages received
+//!   (or assigned, replied to, changing status) since a cursor
+//! - `GET /integrations/messages/{id}` - Look a message up
+//! - `POST /integrations/messages` - Action: create a message
+//! - `POST /integrations/messages/{id}/update` - Action: change the status,
+//!   priority or assignee of a message, or add and remove tags
+//!
+//! They are called with an API token (see the `api_tokens` module) having
+//! the `integrations:read` scope for the `GET` endpoints and
+//! `integrations:write` for the `POST` ones, sent as
+//! `Authorization: Bearer dht_...`; the admin token works too.
+//!
+//! ## Polling
+//!
+//! The trigger reads the message event log (see the `events` module), whose
+//! IDs only grow. Each item is one event with the message it is about, its
+//! `id` being the ID of the event, so that tools deduplicating on `id` see
+//! every status change of a message. Items are listed newest first, the
+//! response carrying the cursor to pass as `since` on the next poll:
+//!
+//! ```json
+//! {
+//!   "data": [
+//!     {
+//!       "id": "1042",
+//!       "event": "created",
+//!       "occurred_at": "2026-10-17T09:00:00Z",
+//!       "message_id": "123e4567-e89b-12d3-a456-426614174000",
+//!       "form": "contact",
+//!       "name": "Jane Doe",
+//!       "email": "jane@example.com",
+//!       "country_region": "France",
+//!       "phone_number": "",
+//!       "company": "Acme",
+//!       "message": "I'd like a quote",
+//!       "status": "pending",
+//!       "priority": "normal",
+//!       "assigned_to": "",
+//!       "tags": [],
+//!       "created_at": "2026-10-17T09:00:00Z"
+//!     }
+//!   ],
+//!   "since": "1042"
+//! }
+//! ```
+//!
+//! Without `since`, the trigger lists the latest items, which tools use as
+//! samples. Fields are always present: text fields are empty rather than
+//! `null`, so that mapping them in a tool never fails.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashMap;
+
+use crate::database::Database;
+use crate::ids::MessageId;
+use crate::models::Message;
+
+/// Events the polling trigger can follow, `created` by default.
+pub const TRIGGER_EVENTS: [&str; 4] = ["created", "assigned", "replied", "status_changed"];
+
+/// Number of items a poll returns by default.
+pub const DEFAULT_TRIGGER_LIMIT: i64 = 50;
+
+/// Maximum number of items a poll returns.
+pub const MAX_TRIGGER_LIMIT: i64 = 100;
+
+/// A message, as the integration API shows it.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the message
+/// * `form` - Form the message was sent with
+/// * `name`, `email`, `country_region`, `phone_number`, `company`, `message` - What the sender wrote
+/// * `status` - `pending`, `assigned`, `resolved`, `quarantine` or `merged`
+/// * `priority` - `low`, `normal`, `high` or `urgent`
+/// * `assigned_to` - Agent handling the message, empty if none
+/// * `tags` - Tags of the message
+/// * `created_at` - Timestamp when the message was received
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrationMessage {
+    pub message_id: MessageId,
+    pub form: String,
+    pub name: String,
+    pub email: String,
+    pub country_region: String,
+    pub phone_number: String,
+    pub company: String,
+    pub message: String,
+    pub status: String,
+    pub priority: String,
+    pub assigned_to: String,
+    pub tags: Vec<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Message> for IntegrationMessage {
+    fn from(message: Message) -> Self {
+        IntegrationMessage {
+            message_id: MessageId::from(message.id),
+            form: message.form,
+            name: message.name,
+            email: message.email,
+            country_region: message.country_region,
+            phone_number: message.phone_number,
+            company: message.company,
+            message: message.message,
+            status: message.status,
+            priority: message.priority,
+            assigned_to: message.assigned_to.unwrap_or_default(),
+            tags: message.tags,
+            created_at: message.created_at,
+        }
+    }
+}
+
+/// An item of the polling trigger: an event and the message it is about.
+///
+/// # Fields
+///
+/// * `id` - ID of the event, as a string
+/// * `event` - Kind of event (see [`TRIGGER_EVENTS`])
+/// * `occurred_at` - Timestamp of the event
+/// * `message` - The message, as it is now, flattened into the item
+#[derive(Debug, Clone, Serialize)]
+pub struct TriggerItem {
+    pub id: String,
+    pub event: String,
+    #[serde(with = "crate::timestamp")]
+    pub occurred_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub message: IntegrationMessage,
+}
+
+/// A page of the polling trigger.
+///
+/// # Fields
+///
+/// * `data` - Items, newest first
+/// * `since` - Cursor to pass on the next poll
+#[derive(Debug, Clone, Serialize)]
+pub struct TriggerPage {
+    pub data: Vec<TriggerItem>,
+    pub since: String,
+}
+
+/// Tags given to an action, as a list or as a comma-separated string,
+/// which is what no-code tools send most easily.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::integrations::TagList;
+///
+/// let tags: TagList = serde_json::from_str(r#""sales, vip""#).unwrap();
+/// assert_eq!(tags.into_vec(), vec!["sales", "vip"]);
+///
+/// let tags: TagList = serde_json::from_str(r#"["sales", " "]"#).unwrap();
+/// assert_eq!(tags.into_vec(), vec!["sales"]);
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TagList {
+    List(Vec<String>),
+    Text(String),
+}
+
+impl TagList {
+    /// Returns the trimmed tags, dropping empty ones.
+    pub fn into_vec(self) -> Vec<String> {
+        let tags = match self {
+            TagList::List(tags) => tags,
+            TagList::Text(text) => text.split(',').map(str::to_string).collect(),
+        };
+        tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()).map(str::to_string).collect()
+    }
+}
+
+/// Database operations for the integration API.
+impl Database {
+    /// Lists the items of the polling trigger.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - Kind of event to follow (see [`TRIGGER_EVENTS`])
+    /// * `since` - Cursor of the previous poll, `None` for the latest items
+    /// * `limit` - Maximum number of events read
+    ///
+    /// # Returns
+    ///
+    /// Returns the items, newest first, and the cursor of the next poll.
+    /// Events of messages since trashed or purged are skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let page = db.list_trigger_items("created", Some(1040), 50).await?;
+    ///     for item in &page.data {
+    ///         println!("{} from {}", item.id, item.message.email);
+    ///     }
+    ///     println!("Next poll: since={}", page.since);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_trigger_items(&self, event: &str, since: Option<i64>, limit: i64) -> Result<TriggerPage, sqlx::Error> {
+        let rows = match since {
+            Some(since) => sqlx::query(r#"
+                SELECT id, message_id, event_type, created_at
+                FROM message_events
+                WHERE event_type = $1 AND id > $2
+                ORDER BY id
+                LIMIT $3
+            "#)
+            .bind(event)
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?,
+            None => sqlx::query(r#"
+                SELECT id, message_id, event_type, created_at
+                FROM message_events
+                WHERE event_type = $1
+                ORDER BY id DESC
+                LIMIT $2
+            "#)
+            .bind(event)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?,
+        };
+
+        let cursor = rows.iter().map(|row| row.get::<i64, _>("id")).max().or(since).unwrap_or(0);
+        let ids: Vec<uuid::Uuid> = rows.iter().map(|row| row.get("message_id")).collect();
+        let messages: HashMap<uuid::Uuid, Message> = self
+            .get_messages_by_ids(&ids)
+            .await?
+            .into_iter()
+            .filter(|message| message.deleted_at.is_none())
+            .map(|message| (message.id, message))
+            .collect();
+
+        let mut data: Vec<TriggerItem> = rows
+            .iter()
+            .filter_map(|row| {
+                let message = messages.get(&row.get("message_id"))?;
+                Some(TriggerItem {
+                    id: row.get::<i64, _>("id").to_string(),
+                    event: row.get("event_type"),
+                    occurred_at: row.get("created_at"),
+                    message: message.clone().into(),
+                })
+            })
+            .collect();
+        if since.is_some() {
+            data.reverse();
+        }
+
+        Ok(TriggerPage { data, since: cursor.to_string() })
+    }
+}
@@ -0,0 +1,845 @@
+//! # Background Jobs
+//!
+//! Periodic maintenance tasks running alongside the HTTP server. Each job
+//! is spawned once at startup and loops on its own interval; failures are
+//! logged, sent to the error reporter (see the `reporting` module) and
+//! retried on the next tick.
+//!
+//! When several replicas are deployed, the replicas elect a leader through
+//! a PostgreSQL advisory lock (see [`spawn_leader_election`]) and only the
+//! leader runs the scheduled jobs. The outbox relay is the exception: it
+//! locks the entries it publishes, so every replica can safely run it. So
+//! is the mail dispatch worker, each replica sending the emails it queued,
+//! the webhook delivery job, the CRM sync job, and the refresh of the keys
+//! signing sessions.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::rt;
+use sqlx::PgConnection;
+
+use crate::config::AppConfig;
+use crate::crm::CrmConnector;
+use crate::database::Database;
+use crate::form_origins::FormOrigins;
+use crate::indexes::IndexState;
+use crate::jwt::SigningKeys;
+use crate::live::{LiveHub, LiveUpdate};
+use crate::mail_queue::MailQueue;
+use crate::mailer::Mailer;
+use crate::metrics::PipelineMetrics;
+use crate::notifications::NotificationDispatcher;
+use crate::outbox::{Publisher, RelayScope};
+use crate::reporting;
+use crate::reports::ReportSender;
+use crate::shared::RateLimiter;
+use crate::webhooks::WebhookSender;
+
+/// Interval between two leadership checks.
+const LEADER_ELECTION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval between two runs of the archiving job.
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval between two runs of the trash purge job.
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval between two runs of the auto-release job.
+const AUTO_RELEASE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Interval between two runs of the partition maintenance job.
+const PARTITION_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of months ahead the partitions of the messages table are created.
+const PARTITION_MONTHS_AHEAD: u32 = 1;
+
+/// Interval between two runs of the archival export job.
+const ARCHIVE_EXPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Interval between two runs of the outbox relay.
+const OUTBOX_RELAY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of outbox entries published per relay run.
+const OUTBOX_RELAY_BATCH_SIZE: i64 = 100;
+
+/// Interval between two runs of the webhook delivery job.
+const WEBHOOK_DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of webhook deliveries sent per run.
+const WEBHOOK_DELIVERY_BATCH_SIZE: i64 = 50;
+
+/// Interval between two runs of the CRM sync job.
+const CRM_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Maximum number of CRM syncs run per run.
+const CRM_SYNC_BATCH_SIZE: i64 = 20;
+
+/// Interval between two reads of the message event log for the live updates.
+const LIVE_FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of message events broadcast per read.
+const LIVE_FEED_BATCH_SIZE: i64 = 200;
+
+/// Interval between two checks for scheduled reports due.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay before listening for live signals again after losing the connection.
+const LIVE_LISTEN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval between two runs of the outbox cleanup job.
+const OUTBOX_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval between two daily digests.
+const DAILY_DIGEST_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Interval between two checks of the age of the key signing sessions.
+const JWT_KEY_ROTATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Interval between two reloads of the keys signing sessions.
+const JWT_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between two reloads of the origins allowed to submit each form.
+const FORM_ORIGINS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval between two runs of the authentication audit cleanup job.
+const AUTH_EVENTS_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Spawns the job archiving messages resolved for too long.
+///
+/// Runs every hour and archives messages resolved more than
+/// `config.archive_resolved_after_days` days ago.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_archive_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let after_days = config.archive_resolved_after_days;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(ARCHIVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.archive_resolved_messages(after_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Archived {} resolved messages", count),
+                Err(e) => reporting::job_failed("archive", format!("Failed to archive resolved messages: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the job permanently removing old trashed messages.
+///
+/// Runs every hour and purges messages deleted more than
+/// `config.trash_retention_days` days ago.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_trash_purge_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let after_days = config.trash_retention_days;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(TRASH_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.purge_old_trash(after_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Purged {} messages from the trash", count),
+                Err(e) => reporting::job_failed("trash_purge", format!("Failed to purge the trash: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the job returning stale assignments to the pending queue.
+///
+/// Runs every five minutes and releases messages without activity for
+/// more than `config.auto_release_after_hours` hours (see
+/// `Database::release_stale_assignments`). Does nothing when auto-release
+/// is disabled. Previous assignees are notified according to their
+/// preferences (see the `notifications` module), and each release is
+/// recorded in the assignment history.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_auto_release_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let Some(after_hours) = config.auto_release_after_hours else {
+        return;
+    };
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(AUTO_RELEASE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.release_stale_assignments(after_hours).await {
+                Ok(released) => {
+                    for (id, agent) in released {
+                        println!("Message {} was released from {} after {} hours without activity", id, agent, after_hours);
+                    }
+                }
+                Err(e) => reporting::job_failed("auto_release", format!("Failed to release stale assignments: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the relay publishing outbox entries.
+///
+/// Runs every five seconds. A full batch means more entries are waiting,
+/// so the relay keeps going until the outbox is drained.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `publisher` - Destination of the outbox entries
+/// * `scope` - Entries relayed: the mail queue takes the notification
+///   emails, the publisher of `OUTBOX_PUBLISHER` everything else
+pub fn spawn_outbox_relay_job(db: Database, publisher: Arc<dyn Publisher>, scope: RelayScope) {
+    let job = match scope {
+        RelayScope::External => "outbox_relay",
+        RelayScope::Emails => "outbox_email_relay",
+    };
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(OUTBOX_RELAY_INTERVAL);
+        loop {
+            interval.tick().await;
+            loop {
+                match db.relay_outbox(publisher.as_ref(), scope, OUTBOX_RELAY_BATCH_SIZE).await {
+                    Ok(published) if published as i64 == OUTBOX_RELAY_BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        reporting::job_failed(job, format!("Failed to relay the outbox: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the job sending the webhook deliveries.
+///
+/// Runs every five seconds on every replica, as the deliveries are locked
+/// while they are sent, and keeps going until no delivery is due.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `sender` - Client posting the deliveries to their endpoints
+pub fn spawn_webhook_delivery_job(db: Database, sender: WebhookSender) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(WEBHOOK_DELIVERY_INTERVAL);
+        loop {
+            interval.tick().await;
+            loop {
+                match db.deliver_webhooks(&sender, WEBHOOK_DELIVERY_BATCH_SIZE).await {
+                    Ok(attempted) if attempted as i64 == WEBHOOK_DELIVERY_BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        reporting::job_failed("webhook_delivery", format!("Failed to deliver webhooks: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the job syncing the senders of new messages to the CRM (see the
+/// `crm` module).
+///
+/// Runs every ten seconds on every replica, as the syncs are locked while
+/// they run: queues a sync for the messages received since the last run,
+/// then runs the due syncs until none is left.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `connector` - CRM the senders are synced to
+pub fn spawn_crm_sync_job(db: Database, connector: Arc<dyn CrmConnector>) {
+    rt::spawn(async move {
+        let mut start_id = None;
+        let mut interval = rt::time::interval(CRM_SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Messages received before the job first ran are only synced on demand
+            let after_id = match start_id {
+                Some(after_id) => after_id,
+                None => match db.last_event_id().await {
+                    Ok(after_id) => *start_id.insert(after_id),
+                    Err(e) => {
+                        reporting::job_failed("crm_sync", format!("Failed to read the message event log: {}", e));
+                        continue;
+                    }
+                },
+            };
+            if let Err(e) = db.queue_crm_syncs(connector.provider(), after_id).await {
+                reporting::job_failed("crm_sync", format!("Failed to queue CRM syncs: {}", e));
+            }
+            loop {
+                match db.run_crm_syncs(connector.as_ref(), CRM_SYNC_BATCH_SIZE).await {
+                    Ok(attempted) if attempted as i64 == CRM_SYNC_BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        reporting::job_failed("crm_sync", format!("Failed to sync senders to the CRM: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the tasks feeding the live inbox updates of this replica (see
+/// the `live` module). Every replica runs them, for its own connections.
+///
+/// The first task reads the message events recorded since it started every
+/// second and broadcasts them; the second listens for the signals sent by
+/// the agents connected to any replica.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the tasks
+/// * `hub` - Hub broadcasting the updates to the connections
+pub fn spawn_live_feed(db: Database, hub: LiveHub) {
+    let events_db = db.clone();
+    let events_hub = hub.clone();
+    rt::spawn(async move {
+        let mut last_id = None;
+        let mut interval = rt::time::interval(LIVE_FEED_INTERVAL);
+        loop {
+            interval.tick().await;
+            let after_id = match last_id {
+                Some(after_id) => after_id,
+                None => match events_db.last_event_id().await {
+                    Ok(after_id) => *last_id.insert(after_id),
+                    Err(e) => {
+                        reporting::job_failed("live_feed", format!("Failed to read the message event log: {}", e));
+                        continue;
+                    }
+                },
+            };
+            // Nobody to tell; the events are skipped rather than piling up
+            if events_hub.connections() == 0 {
+                last_id = None;
+                continue;
+            }
+            match events_db.list_live_updates_after(after_id, LIVE_FEED_BATCH_SIZE).await {
+                Ok(updates) => {
+                    for (id, update) in updates {
+                        last_id = Some(id);
+                        events_hub.publish(update);
+                    }
+                }
+                Err(e) => reporting::job_failed("live_feed", format!("Failed to read the message event log: {}", e)),
+            }
+        }
+    });
+
+    rt::spawn(async move {
+        loop {
+            let mut listener = match db.listen_live().await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    reporting::job_failed("live_signals", format!("Failed to listen for live signals: {}", e));
+                    rt::time::sleep(LIVE_LISTEN_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => match serde_json::from_str::<LiveUpdate>(notification.payload()) {
+                        Ok(signal) => hub.publish(signal),
+                        Err(e) => eprintln!("Ignoring an invalid live signal: {}", e),
+                    },
+                    Err(e) => {
+                        reporting::job_failed("live_signals", format!("Lost the live signal listener: {}", e));
+                        break;
+                    }
+                }
+            }
+            rt::time::sleep(LIVE_LISTEN_RETRY_DELAY).await;
+        }
+    });
+}
+
+/// Spawns the job sending the scheduled reports (see the `reports` module).
+///
+/// Runs every minute on every replica, as a report is locked while its run
+/// is scheduled, and sends every report due.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `sender` - Sender delivering the reports to their destinations
+pub fn spawn_report_job(db: Database, sender: ReportSender) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(REPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            loop {
+                match db.run_due_report(&sender).await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break,
+                    Err(e) => {
+                        reporting::job_failed("reports", format!("Failed to run the scheduled reports: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the job removing published outbox entries.
+///
+/// Runs every hour and deletes entries published, webhook deliveries made
+/// and dead letters retried more than `config.outbox_retention_days` days
+/// ago.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_outbox_cleanup_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let after_days = config.outbox_retention_days;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(OUTBOX_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.purge_published_outbox(after_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Removed {} published outbox entries", count),
+                Err(e) => reporting::job_failed("outbox_cleanup", format!("Failed to clean up the outbox: {}", e)),
+            }
+            match db.purge_delivered_webhooks(after_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Removed {} delivered webhooks", count),
+                Err(e) => reporting::job_failed("outbox_cleanup", format!("Failed to clean up the webhook deliveries: {}", e)),
+            }
+            match db.purge_retried_dead_letters(after_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Removed {} retried dead letters", count),
+                Err(e) => reporting::job_failed("outbox_cleanup", format!("Failed to clean up the dead letters: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the job maintaining the monthly partitions of the messages table.
+///
+/// Runs every day when the table is partitioned (see the `partitioning`
+/// module): creates the partitions of the current and next month, then
+/// drops the partitions older than `config.message_retention_months`.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_partition_maintenance_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let retention_months = config.message_retention_months;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(PARTITION_MAINTENANCE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() || !db.messages_partitioned().await.unwrap_or(false) {
+                continue;
+            }
+            match db.create_message_partitions(PARTITION_MONTHS_AHEAD).await {
+                Ok(created) => created.iter().for_each(|name| println!("Created partition {}", name)),
+                Err(e) => reporting::job_failed("partition_maintenance", format!("Failed to create message partitions: {}", e)),
+            }
+            if let Some(months) = retention_months {
+                match db.drop_expired_message_partitions(months).await {
+                    Ok(0) => {}
+                    Ok(count) => println!("Dropped {} messages older than {} months", count, months),
+                    Err(e) => reporting::job_failed("partition_maintenance", format!("Failed to drop expired partitions: {}", e)),
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the job exporting old resolved messages to the blob store.
+///
+/// Runs every day when `config.archive_export_after_months` is set and
+/// exports the messages resolved more than that many months ago (see the
+/// `archival` module).
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job, with a blob store
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_archive_export_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let Some(after_months) = config.archive_export_after_months else {
+        return;
+    };
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(ARCHIVE_EXPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.export_resolved_messages(after_months).await {
+                Ok(archives) => archives
+                    .iter()
+                    .for_each(|archive| println!("Exported {} messages to {}", archive.message_count, archive.key)),
+                Err(e) => reporting::job_failed("archive_export", format!("Failed to export resolved messages: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns a one-off task building the required indexes that are missing
+/// (see the `indexes` module), or prints them when
+/// `config.create_missing_indexes` is disabled.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the task
+/// * `config` - Application configuration
+pub fn spawn_index_creation(db: Database, config: &AppConfig) {
+    let create = config.create_missing_indexes;
+
+    rt::spawn(async move {
+        if create {
+            match db.create_missing_indexes().await {
+                Ok(created) => created.iter().for_each(|index| println!("Created index {}", index)),
+                Err(e) => reporting::job_failed("index_creation", format!("Failed to create the missing indexes: {}", e)),
+            }
+            return;
+        }
+        match db.check_indexes().await {
+            Ok(indexes) => {
+                for (index, state) in indexes.into_iter().filter(|(_, state)| *state != IndexState::Present) {
+                    eprintln!("Index {} is {}, create it with: {}", index.name, state, index.create_statement());
+                }
+            }
+            Err(e) => reporting::job_failed("index_creation", format!("Failed to check the indexes: {}", e)),
+        }
+    });
+}
+
+/// Spawns a one-off task warning about the missing DNS records of the
+/// domain emails are sent from (see the `mailer` module).
+///
+/// # Arguments
+///
+/// * `mailer` - Mailer whose domain is checked
+pub fn spawn_mail_dns_check(mailer: Mailer) {
+    rt::spawn(async move {
+        match mailer.missing_dns_records().await {
+            Ok(missing) => missing
+                .iter()
+                .for_each(|record| eprintln!("Warning: {}, emails may be rejected or marked as spam", record)),
+            Err(e) => eprintln!("Failed to check the DNS records of {}: {}", mailer.domain(), e),
+        }
+    });
+}
+
+/// Spawns the job queueing the daily digest of the agents who asked for
+/// it (see the `notifications` module).
+///
+/// Runs every day, the first time a day after the server started.
+///
+/// # Arguments
+///
+/// * `notifier` - Dispatcher queueing the digests
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_daily_digest_job(notifier: NotificationDispatcher, leader: &Leadership) {
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(DAILY_DIGEST_INTERVAL);
+        // The first tick completes immediately, and a restart must not send the digest again
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match notifier.daily_digest().await {
+                Ok(0) => {}
+                Ok(count) => println!("Queued {} daily digests", count),
+                Err(e) => reporting::job_failed("daily_digest", format!("Failed to queue the daily digests: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the job rotating the key signing sessions (see the `jwt`
+/// module).
+///
+/// Runs every hour, creating a new key once the newest one is
+/// `config.jwt_key_rotation_days` old and deleting the keys retired more
+/// than `config.jwt_key_overlap_hours` ago.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_jwt_key_rotation_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let rotation_days = config.jwt_key_rotation_days;
+    let overlap_hours = config.jwt_key_overlap_hours;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(JWT_KEY_ROTATION_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.rotate_jwt_keys(rotation_days, overlap_hours).await {
+                Ok(Some(key)) => println!("Created JWT signing key {}", key.kid),
+                Ok(None) => {}
+                Err(e) => reporting::job_failed("jwt_key_rotation", format!("Failed to rotate the JWT keys: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the task reloading the keys signing sessions every minute, so
+/// that this replica signs with the keys rotated by the leader and accepts
+/// the sessions they signed. Every replica runs it.
+///
+/// # Arguments
+///
+/// * `keys` - Keys shared with the handlers
+pub fn spawn_jwt_key_refresh(keys: SigningKeys) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(JWT_KEY_REFRESH_INTERVAL);
+        // The keys were loaded at startup
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = keys.refresh().await {
+                reporting::job_failed("jwt_key_refresh", format!("Failed to reload the JWT keys: {}", e));
+            }
+        }
+    });
+}
+
+/// Spawns the task reloading the origins allowed to submit each form every
+/// 30 seconds, so that this replica applies the changes made on the others
+/// (see the `form_origins` module). Every replica runs it.
+///
+/// # Arguments
+///
+/// * `origins` - Origin lists shared with the CORS middleware and handlers
+pub fn spawn_form_origins_refresh(origins: FormOrigins) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(FORM_ORIGINS_REFRESH_INTERVAL);
+        // The lists were loaded at startup
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = origins.refresh().await {
+                reporting::job_failed("form_origins_refresh", format!("Failed to reload the form origins: {}", e));
+            }
+        }
+    });
+}
+
+/// Spawns the job deleting old failed authentications (see the
+/// `auth_events` module).
+///
+/// Runs every hour and deletes the events older than
+/// `config.auth_events_retention_days` days.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used by the job
+/// * `config` - Application configuration
+/// * `leader` - Leadership of this replica; the job only runs on the leader
+pub fn spawn_auth_events_cleanup_job(db: Database, config: &AppConfig, leader: &Leadership) {
+    let retention_days = config.auth_events_retention_days;
+    let leader = leader.clone();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(AUTH_EVENTS_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !leader.is_leader() {
+                continue;
+            }
+            match db.purge_auth_events(retention_days).await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} old authentication events", count),
+                Err(e) => reporting::job_failed("auth_events_cleanup", format!("Failed to delete old authentication events: {}", e)),
+            }
+        }
+    });
+}
+
+/// Spawns the worker sending the emails of the mail queue (see the
+/// `mail_queue` module).
+///
+/// Waits for an email, then for a free slot in the sending rate, and only
+/// then takes the next email from the queue, so that a transactional email
+/// queued while the worker is throttled still leaves before the waiting
+/// digests. Every replica runs the worker for its own queue. Emails the
+/// transport fails to send are recorded as dead letters (see the
+/// `dead_letters` module).
+///
+/// # Arguments
+///
+/// * `db` - Database recording the emails that failed
+/// * `mailer` - Mailer composing and delivering the emails
+/// * `queue` - Queue the emails are taken from
+/// * `limiter` - Limiter counting the emails sent per minute
+/// * `metrics` - Metrics recording the time the emails waited
+/// * `config` - Application configuration
+pub fn spawn_mail_dispatch(
+    db: Database,
+    mailer: Mailer,
+    queue: MailQueue,
+    limiter: RateLimiter,
+    metrics: PipelineMetrics,
+    config: &AppConfig,
+) {
+    let max_per_minute = config.mail_max_per_minute.or_else(|| mailer.transport().max_per_minute());
+
+    rt::spawn(async move {
+        loop {
+            queue.ready().await;
+            loop {
+                match limiter.check(mailer.transport().name(), max_per_minute).await {
+                    Ok(None) => break,
+                    Ok(Some(retry_in)) => rt::time::sleep(retry_in).await,
+                    Err(e) => {
+                        // Sending unthrottled beats not sending at all
+                        reporting::job_failed("mail_dispatch", format!("Failed to check the sending rate: {}", e));
+                        break;
+                    }
+                }
+            }
+            let Some(queued) = queue.pop() else {
+                continue;
+            };
+            let result = mailer.send(&queued.to, &queued.email).await;
+            queue.record(&queued, result.is_ok());
+            if result.is_ok() {
+                metrics.observe_email(queued.priority, queued.queued_at.elapsed());
+            }
+            if let Err(e) = result {
+                reporting::job_failed(
+                    "mail_dispatch",
+                    format!("Failed to send the {} email to {}: {}", queued.priority, queued.to, e),
+                );
+                if let Err(e) = db.record_failed_email(&queued, &e.to_string()).await {
+                    eprintln!("Failed to record the email to {} as a dead letter: {}", queued.to, e);
+                }
+            }
+        }
+    });
+}
+
+/// Leadership status of this replica, shared with the scheduled jobs.
+#[derive(Debug, Clone, Default)]
+pub struct Leadership {
+    is_leader: Arc<AtomicBool>,
+}
+
+impl Leadership {
+    /// Returns whether this replica currently runs the scheduled jobs.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::Relaxed)
+    }
+}
+
+/// Spawns the leader election between replicas.
+///
+/// A first election runs before returning, so that the scheduled jobs
+/// spawned next already run on their first tick. Then, every ten seconds,
+/// a follower tries to take the scheduler lock (see
+/// `Database::try_acquire_scheduler_lock`) and the leader checks that the
+/// connection holding it is still alive. PostgreSQL releases the lock as
+/// soon as the leader's connection closes, so another replica takes over
+/// within one interval when the leader stops or loses the database.
+///
+/// # Arguments
+///
+/// * `db` - Database instance used for the lock
+///
+/// # Returns
+///
+/// Returns the leadership handle to pass to the scheduled jobs.
+pub async fn spawn_leader_election(db: Database) -> Leadership {
+    let leadership = Leadership::default();
+    let is_leader = leadership.is_leader.clone();
+
+    let mut lock_connection = elect(&db, &is_leader).await;
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(LEADER_ELECTION_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match lock_connection.as_mut() {
+                Some(connection) => {
+                    if let Err(e) = sqlx::query("SELECT 1").execute(connection).await {
+                        reporting::job_failed("leader_election", format!("Lost scheduler leadership: {}", e));
+                        lock_connection = None;
+                        is_leader.store(false, Ordering::Relaxed);
+                    }
+                }
+                None => lock_connection = elect(&db, &is_leader).await,
+            }
+        }
+    });
+
+    leadership
+}
+
+/// Tries to become the leader, returning the connection holding the lock.
+async fn elect(db: &Database, is_leader: &AtomicBool) -> Option<PgConnection> {
+    match db.try_acquire_scheduler_lock().await {
+        Ok(Some(connection)) => {
+            println!("This instance is now the scheduler leader");
+            is_leader.store(true, Ordering::Relaxed);
+            Some(connection)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            reporting::job_failed("leader_election", format!("Failed to run the scheduler election: {}", e));
+            None
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! # JSON Limits
+//!
+//! Parsing limits hardening the JSON endpoints against abusive payloads:
+//!
+//! - Bodies are read up to `MAX_JSON_BODY_KB` kilobytes, larger ones are
+//!   answered with 413 Payload Too Large before being buffered in full.
+//! - Arrays and objects may be nested [`MAX_DEPTH`] levels deep.
+//! - Strings, keys included, may be [`MAX_STRING_BYTES`] bytes long as sent.
+//!
+//! [`enforce`] checks the raw body before any handler deserializes it, so
+//! that every endpoint gets the same limits. Bodies breaking a limit, and
+//! bodies the handler's type rejects (see [`json_config`]), are answered
+//! with a structured error:
+//!
+//! ```json
+//! { "status": "error", "error": "too_deep", "message": "JSON nesting is limited to 32 levels" }
+//! ```
+//!
+//! The webhooks and form submissions of third parties (`/webhooks/...` and
+//! `/ingest/...`) are left out: their payloads are not ours to shape, and
+//! their signatures are checked over the raw body.
+//!
+//! The request types of the backoffice and admin APIs also reject unknown
+//! fields, so that a misspelled field is reported rather than ignored. The
+//! website's forms accept them, so that the website can evolve first.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use serde_json::json;
+
+use crate::config::LiveConfig;
+
+/// Deepest nesting of arrays and objects.
+pub const MAX_DEPTH: usize = 32;
+
+/// Longest string, in bytes as sent, escapes included.
+pub const MAX_STRING_BYTES: usize = 64 * 1024;
+
+/// Path prefixes of the endpoints receiving third-party payloads.
+const EXEMPT_PREFIXES: [&str; 2] = ["/webhooks/", "/ingest/"];
+
+/// A limit broken by a JSON body.
+///
+/// * `TooLarge` - The body is longer than `MAX_JSON_BODY_KB`
+/// * `TooDeep` - Arrays or objects are nested more than [`MAX_DEPTH`] levels
+/// * `StringTooLong` - A string is longer than [`MAX_STRING_BYTES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    TooLarge { limit_bytes: usize },
+    TooDeep,
+    StringTooLong,
+}
+
+impl LimitError {
+    /// Returns the machine-readable code of the error.
+    pub fn code(self) -> &'static str {
+        match self {
+            LimitError::TooLarge { .. } => "too_large",
+            LimitError::TooDeep => "too_deep",
+            LimitError::StringTooLong => "string_too_long",
+        }
+    }
+
+    /// Returns the error response.
+    pub fn response(self) -> HttpResponse {
+        let (mut response, message) = match self {
+            LimitError::TooLarge { limit_bytes } => (
+                HttpResponse::PayloadTooLarge(),
+                format!("JSON bodies are limited to {} KB", limit_bytes / 1024),
+            ),
+            LimitError::TooDeep => (
+                HttpResponse::BadRequest(),
+                format!("JSON nesting is limited to {} levels", MAX_DEPTH),
+            ),
+            LimitError::StringTooLong => (
+                HttpResponse::BadRequest(),
+                format!("JSON strings are limited to {} KB", MAX_STRING_BYTES / 1024),
+            ),
+        };
+        response.json(json!({ "status": "error", "error": self.code(), "message": message }))
+    }
+}
+
+/// Checks the nesting depth and string lengths of a JSON body.
+///
+/// Only the structure is scanned: malformed JSON passes, and is rejected
+/// when the handler deserializes it.
+///
+/// # Errors
+///
+/// Returns the first limit the body breaks.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::json_limits::{check_json, LimitError, MAX_DEPTH};
+///
+/// assert_eq!(check_json(br#"{"tags": ["a", "[[[["], "note": "\"}"}"#), Ok(()));
+/// let deep = "[".repeat(MAX_DEPTH + 1);
+/// assert_eq!(check_json(deep.as_bytes()), Err(LimitError::TooDeep));
+/// let long = format!(r#"{{"message": "{}"}}"#, "a".repeat(70_000));
+/// assert_eq!(check_json(long.as_bytes()), Err(LimitError::StringTooLong));
+/// ```
+pub fn check_json(body: &[u8]) -> Result<(), LimitError> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_bytes = 0usize;
+
+    for &byte in body {
+        if in_string {
+            string_bytes += 1;
+            if string_bytes > MAX_STRING_BYTES {
+                return Err(LimitError::StringTooLong);
+            }
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => {
+                in_string = true;
+                string_bytes = 0;
+            }
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    return Err(LimitError::TooDeep);
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the request declares a JSON body.
+fn is_json(req: &ServiceRequest) -> bool {
+    req.mime_type().ok().flatten().is_some_and(|mime| {
+        mime.subtype().as_str() == "json" || mime.suffix().is_some_and(|suffix| suffix.as_str() == "json")
+    })
+}
+
+/// Middleware reading JSON bodies within `MAX_JSON_BODY_KB` and checking
+/// them with [`check_json`] before handing them to the handler.
+///
+/// # Errors
+///
+/// Returns the errors of the wrapped service, or of reading the body.
+pub async fn enforce<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let exempt = EXEMPT_PREFIXES.iter().any(|prefix| req.path().starts_with(prefix));
+    let limit_bytes = match req.app_data::<web::Data<LiveConfig>>() {
+        Some(config) if !exempt && is_json(&req) => config.load().max_json_body_kb.saturating_mul(1024),
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit_bytes) {
+        let rejection = LimitError::TooLarge { limit_bytes }.response();
+        return Ok(req.into_response(rejection).map_into_right_body());
+    }
+
+    let mut payload = req.take_payload();
+    let mut body = web::BytesMut::with_capacity(declared.unwrap_or(0));
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit_bytes {
+            let rejection = LimitError::TooLarge { limit_bytes }.response();
+            return Ok(req.into_response(rejection).map_into_right_body());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    if let Err(e) = check_json(&body) {
+        return Ok(req.into_response(e.response()).map_into_right_body());
+    }
+    req.set_payload(Payload::from(body.freeze()));
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Returns the configuration of the `web::Json` extractor: bodies up to
+/// `limit_bytes`, and structured errors telling what is wrong with them.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{web, App};
+/// use dothtml_backend::json_limits::json_config;
+///
+/// let app = App::new().app_data(json_config(256 * 1024));
+/// ```
+pub fn json_config(limit_bytes: usize) -> web::JsonConfig {
+    web::JsonConfig::default().limit(limit_bytes).error_handler(json_error)
+}
+
+/// Turns a rejected JSON body into a structured error response.
+fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let (mut response, code, message) = match &err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            (HttpResponse::PayloadTooLarge(), "too_large", err.to_string())
+        }
+        JsonPayloadError::ContentType => (
+            HttpResponse::UnsupportedMediaType(),
+            "unsupported_media_type",
+            "Request bodies must be JSON, sent with Content-Type: application/json".to_string(),
+        ),
+        JsonPayloadError::Deserialize(e) if e.is_data() => (HttpResponse::BadRequest(), "invalid_field", e.to_string()),
+        JsonPayloadError::Deserialize(e) => (HttpResponse::BadRequest(), "invalid_json", e.to_string()),
+        _ => (HttpResponse::BadRequest(), "invalid_body", err.to_string()),
+    };
+    let response = response.json(json!({ "status": "error", "error": code, "message": message }));
+    actix_web::error::InternalError::from_response(err, response).into()
+}
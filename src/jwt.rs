@@ -0,0 +1,391 @@
+//! # Signed Sessions (JWT)
+//!
+//! Sessions opened with a login link (see the `sessions` module) are JSON
+//! Web Tokens signed with Ed25519 (`EdDSA`), so that other internal
+//! services can check them with the public keys published at
+//! `GET /.well-known/jwks.json`, without sharing a secret with this server.
+//!
+//! A session carries the agent's name as `sub`, and `iss`, `iat`, `exp` and
+//! `jti` claims. Services should check the signature, `iss` and `exp`.
+//!
+//! ## Key rotation
+//!
+//! The keys live in the `jwt_keys` table, shared by the replicas. The
+//! leader creates a new key every `JWT_KEY_ROTATION_DAYS` (see
+//! `jobs::spawn_jwt_key_rotation_job`). A new key is published at once but
+//! only signs after [`KEY_PUBLICATION_DELAY`], so that every replica and
+//! every service caching the key set knows it before seeing a token it
+//! signed. The key it replaces keeps validating tokens, and stays in the
+//! key set, for `JWT_KEY_OVERLAP_HOURS` after it stopped signing, then is
+//! deleted. The overlap should be at least `SESSION_TTL_HOURS`, or the
+//! sessions opened just before a rotation end early.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::tokens::TokenError;
+
+/// Issuer (`iss`) of the sessions.
+pub const ISSUER: &str = "dothtml-backend";
+
+/// Time between publishing a key and signing with it.
+pub const KEY_PUBLICATION_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Seconds services may cache the key set, shorter than
+/// [`KEY_PUBLICATION_DELAY`].
+pub const JWKS_MAX_AGE_SECS: u64 = 5 * 60;
+
+/// A key of the `jwt_keys` table.
+///
+/// # Fields
+///
+/// * `kid` - Identifier of the key, in the `kid` header of the tokens it signs
+/// * `signing_key` - Ed25519 private key
+/// * `created_at` - When the key was created; it signs [`KEY_PUBLICATION_DELAY`] later
+/// * `retired_at` - When a newer key took over signing, if one did
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub signing_key: SigningKey,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl JwtKey {
+    /// Returns the public key as a JSON Web Key.
+    pub fn jwk(&self) -> Value {
+        json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "use": "sig",
+            "alg": "EdDSA",
+            "kid": self.kid,
+            "x": URL_SAFE_NO_PAD.encode(self.signing_key.verifying_key().as_bytes()),
+        })
+    }
+}
+
+fn jwt_key_from_row(row: &PgRow) -> Result<JwtKey, sqlx::Error> {
+    let private_key: Vec<u8> = row.get("private_key");
+    let seed: [u8; 32] = private_key
+        .try_into()
+        .map_err(|_| sqlx::Error::Decode("a JWT private key is not 32 bytes long".into()))?;
+    Ok(JwtKey {
+        kid: row.get("kid"),
+        signing_key: SigningKey::from_bytes(&seed),
+        created_at: row.get("created_at"),
+        retired_at: row.get("retired_at"),
+    })
+}
+
+/// Claims of a session.
+///
+/// # Fields
+///
+/// * `iss` - Always [`ISSUER`]
+/// * `sub` - Name of the agent
+/// * `iat` - Issue time, in seconds since the epoch
+/// * `exp` - Expiration time, in seconds since the epoch
+/// * `jti` - Random identifier of the session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub iss: String,
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+    kid: String,
+}
+
+/// Returns the key signing at `now`: the newest key published for
+/// [`KEY_PUBLICATION_DELAY`], or the newest key when none was, as on the
+/// first start.
+fn current_key(keys: &[JwtKey], now: DateTime<Utc>) -> Option<&JwtKey> {
+    let published = chrono::Duration::from_std(KEY_PUBLICATION_DELAY).unwrap_or_default();
+    keys.iter()
+        .filter(|key| key.created_at <= now - published)
+        .max_by_key(|key| key.created_at)
+        .or_else(|| keys.iter().max_by_key(|key| key.created_at))
+}
+
+/// Signs `claims` with `key`.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{Duration, Utc};
+/// use ed25519_dalek::SigningKey;
+/// use dothtml_backend::jwt::{self, JwtKey};
+/// use dothtml_backend::tokens::TokenError;
+///
+/// let key = JwtKey { kid: "k1".into(), signing_key: SigningKey::from_bytes(&[7; 32]), created_at: Utc::now(), retired_at: None };
+/// let claims = jwt::session_claims("alice", Utc::now() + Duration::hours(8));
+/// let token = jwt::sign(&key, &claims);
+///
+/// assert_eq!(jwt::verify(std::slice::from_ref(&key), &token), Ok(claims));
+/// assert_eq!(jwt::verify(&[], &token), Err(TokenError::Invalid));
+/// ```
+pub fn sign(key: &JwtKey, claims: &SessionClaims) -> String {
+    let header = Header { alg: "EdDSA".to_string(), typ: Some("JWT".to_string()), kid: key.kid.clone() };
+    let signed = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).expect("the header serializes")),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("the claims serialize")),
+    );
+    let signature = key.signing_key.sign(signed.as_bytes());
+    format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+}
+
+/// Checks that `token` was signed by one of `keys`, for [`ISSUER`], and
+/// has not expired.
+///
+/// # Errors
+///
+/// Returns the reason the token is rejected (see [`TokenError`]).
+pub fn verify(keys: &[JwtKey], token: &str) -> Result<SessionClaims, TokenError> {
+    let (signed, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+    let (header, claims) = signed.split_once('.').ok_or(TokenError::Malformed)?;
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| TokenError::Malformed);
+
+    let header: Header = serde_json::from_slice(&decode(header)?).map_err(|_| TokenError::Malformed)?;
+    if header.alg != "EdDSA" {
+        return Err(TokenError::Invalid);
+    }
+    let key = keys.iter().find(|key| key.kid == header.kid).ok_or(TokenError::Invalid)?;
+    let signature = Signature::from_slice(&decode(signature)?).map_err(|_| TokenError::Malformed)?;
+    key.signing_key
+        .verifying_key()
+        .verify_strict(signed.as_bytes(), &signature)
+        .map_err(|_| TokenError::Invalid)?;
+
+    let claims: SessionClaims = serde_json::from_slice(&decode(claims)?).map_err(|_| TokenError::Malformed)?;
+    if claims.iss != ISSUER {
+        return Err(TokenError::Invalid);
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+/// Returns the claims of a new session of `account` ending at `expires_at`.
+pub fn session_claims(account: &str, expires_at: DateTime<Utc>) -> SessionClaims {
+    SessionClaims {
+        iss: ISSUER.to_string(),
+        sub: account.to_string(),
+        iat: Utc::now().timestamp(),
+        exp: expires_at.timestamp(),
+        jti: Uuid::new_v4(),
+    }
+}
+
+/// Keys of the `jwt_keys` table, shared by all workers through the app
+/// data and reloaded every minute by `jobs::spawn_jwt_key_refresh`.
+///
+/// Signing and verifying only read the loaded keys, so that they can run
+/// in request guards.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use chrono::{Duration, Utc};
+/// use dothtml_backend::database::Database;
+/// use dothtml_backend::jwt::SigningKeys;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), sqlx::Error> {
+///     let keys = SigningKeys::load(Database::new().await?, 24).await?;
+///     let token = keys.sign("alice", Utc::now() + Duration::hours(8)).expect("a key is loaded");
+///     assert_eq!(keys.verify(&token).unwrap().sub, "alice");
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SigningKeys {
+    db: Database,
+    overlap_hours: u32,
+    keys: Arc<ArcSwap<Vec<JwtKey>>>,
+}
+
+impl SigningKeys {
+    /// Loads the keys still valid with an overlap of `overlap_hours`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn load(db: Database, overlap_hours: u32) -> Result<Self, sqlx::Error> {
+        let keys = db.list_jwt_keys(overlap_hours).await?;
+        Ok(SigningKeys { db, overlap_hours, keys: Arc::new(ArcSwap::from_pointee(keys)) })
+    }
+
+    /// Reloads the keys, to pick up the rotations of the leader.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur;
+    /// the previous keys are kept.
+    pub async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let keys = self.db.list_jwt_keys(self.overlap_hours).await?;
+        self.keys.store(Arc::new(keys));
+        Ok(())
+    }
+
+    /// Returns a session of `account` ending at `expires_at`, or `None` if
+    /// no key is loaded.
+    pub fn sign(&self, account: &str, expires_at: DateTime<Utc>) -> Option<String> {
+        let keys = self.keys.load();
+        let key = current_key(&keys, Utc::now())?;
+        Some(sign(key, &session_claims(account, expires_at)))
+    }
+
+    /// Checks a session against the loaded keys (see [`verify`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the token is rejected (see [`TokenError`]).
+    pub fn verify(&self, token: &str) -> Result<SessionClaims, TokenError> {
+        verify(&self.keys.load(), token)
+    }
+
+    /// Returns the JSON Web Key Set of the loaded keys, served at
+    /// `GET /.well-known/jwks.json`.
+    pub fn jwks(&self) -> Value {
+        json!({ "keys": self.keys.load().iter().map(JwtKey::jwk).collect::<Vec<_>>() })
+    }
+}
+
+/// Database operations for the keys signing sessions.
+impl Database {
+    /// Creates the 'jwt_keys' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_jwt_keys_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS jwt_keys (
+                kid TEXT PRIMARY KEY,
+                private_key BYTEA NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                retired_at TIMESTAMPTZ
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the keys still validating tokens: the keys signing or about to,
+    /// and the keys retired less than `overlap_hours` ago.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur,
+    /// or if a stored key is corrupted.
+    pub async fn list_jwt_keys(&self, overlap_hours: u32) -> Result<Vec<JwtKey>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT kid, private_key, created_at, retired_at
+            FROM jwt_keys
+            WHERE retired_at IS NULL OR retired_at > NOW() - make_interval(hours => $1)
+            ORDER BY created_at
+        "#)
+        .bind(overlap_hours as i32)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(jwt_key_from_row).collect()
+    }
+
+    /// Creates a new key when there is none or the newest one is older than
+    /// `rotation_days`, and deletes the keys retired more than
+    /// `overlap_hours` ago.
+    ///
+    /// The keys it replaces are retired once the new key signs, after
+    /// [`KEY_PUBLICATION_DELAY`]. Replicas starting together rotate one at
+    /// a time, so only one key is created.
+    ///
+    /// # Returns
+    ///
+    /// Returns the new key, `None` if the newest key is recent enough.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     if let Some(key) = db.rotate_jwt_keys(30, 24).await? {
+    ///         println!("Created JWT key {}", key.kid);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn rotate_jwt_keys(&self, rotation_days: u32, overlap_hours: u32) -> Result<Option<JwtKey>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('jwt_keys'))")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM jwt_keys WHERE retired_at < NOW() - make_interval(hours => $1)")
+            .bind(overlap_hours as i32)
+            .execute(&mut *tx)
+            .await?;
+
+        let due: bool = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(created_at) < NOW() - make_interval(days => $1), TRUE) FROM jwt_keys",
+        )
+        .bind(rotation_days as i32)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !due {
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE jwt_keys SET retired_at = NOW() + make_interval(secs => $1) WHERE retired_at IS NULL")
+            .bind(KEY_PUBLICATION_DELAY.as_secs() as f64)
+            .execute(&mut *tx)
+            .await?;
+        let seed: [u8; 32] = rand::random();
+        let row = sqlx::query(r#"
+            INSERT INTO jwt_keys (kid, private_key)
+            VALUES ($1, $2)
+            RETURNING kid, private_key, created_at, retired_at
+        "#)
+        .bind(Uuid::new_v4().simple().to_string())
+        .bind(seed.as_slice())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        jwt_key_from_row(&row).map(Some)
+    }
+}
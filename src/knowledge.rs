@@ -0,0 +1,274 @@
+//! # Knowledge Base Suggestions
+//!
+//! Suggests FAQ and knowledge base articles matching a contact form draft,
+//! through `POST /contact/suggest`, so that the website can answer common
+//! questions before they reach the inbox. Sources implement the
+//! [`KnowledgeBase`] trait and are selected at startup from the
+//! configuration.
+//!
+//! ## Sources
+//!
+//! - [`StaticKnowledgeBase`] - Articles listed in a JSON file, matched by keywords
+//! - [`ApiKnowledgeBase`] - An external search API
+//!
+//! The JSON file of the static source is an array of articles:
+//!
+//! ```json
+//! [
+//!   {
+//!     "title": "How do I reset my password?",
+//!     "url": "https://dotshell.eu/help/password",
+//!     "summary": "Use the link on the sign-in page.",
+//!     "keywords": ["password", "reset", "sign in"]
+//!   }
+//! ]
+//! ```
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+
+/// Shortest word of a draft taken into account when matching articles.
+const MIN_WORD_LENGTH: usize = 3;
+
+/// Time the external API has to answer before no suggestion is made.
+const API_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An article suggested to the sender.
+///
+/// # Fields
+///
+/// * `title` - Title of the article
+/// * `url` - Link to the article
+/// * `summary` - Short description of the article
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub summary: Option<String>,
+}
+
+/// An article of a [`StaticKnowledgeBase`].
+///
+/// # Fields
+///
+/// * `title` - Title of the article, whose words are matched against drafts
+/// * `url` - Link to the article
+/// * `summary` - Short description of the article
+/// * `keywords` - Words or phrases of drafts the article answers, weighing
+///   more than title words
+#[derive(Debug, Clone, Deserialize)]
+pub struct Article {
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+/// A source of articles answering contact form drafts.
+#[async_trait]
+pub trait KnowledgeBase: Send + Sync {
+    /// Returns up to `limit` articles matching `draft`, best first.
+    async fn suggest(&self, draft: &str, limit: usize) -> io::Result<Vec<Suggestion>>;
+}
+
+/// Splits `text` into lowercase words, ignoring punctuation.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Knowledge base matching a fixed list of articles by keywords.
+///
+/// An article scores two points per keyword found in the draft and one per
+/// word of its title; articles without points are not suggested.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::knowledge::{Article, KnowledgeBase, StaticKnowledgeBase};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let knowledge_base = StaticKnowledgeBase::new(vec![
+///         Article {
+///             title: "How do I reset my password?".to_string(),
+///             url: "https://dotshell.eu/help/password".to_string(),
+///             summary: None,
+///             keywords: vec!["sign in".to_string()],
+///         },
+///         Article {
+///             title: "Pricing".to_string(),
+///             url: "https://dotshell.eu/pricing".to_string(),
+///             summary: None,
+///             keywords: vec!["price".to_string(), "quote".to_string()],
+///         },
+///     ]);
+///
+///     let suggestions = knowledge_base.suggest("I can't sign in, my password is refused", 3).await?;
+///     assert_eq!(suggestions.len(), 1);
+///     assert_eq!(suggestions[0].url, "https://dotshell.eu/help/password");
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticKnowledgeBase {
+    articles: Vec<Article>,
+}
+
+impl StaticKnowledgeBase {
+    /// Creates a knowledge base holding `articles`.
+    pub fn new(articles: Vec<Article>) -> Self {
+        StaticKnowledgeBase { articles }
+    }
+
+    /// Reads the articles from a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file cannot be read or is not
+    /// a JSON array of articles.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read(path)?;
+        let articles = serde_json::from_slice(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid knowledge base {}: {}", path.display(), e))
+        })?;
+        Ok(StaticKnowledgeBase::new(articles))
+    }
+
+    /// Returns the number of articles.
+    pub fn len(&self) -> usize {
+        self.articles.len()
+    }
+
+    /// Returns whether the knowledge base holds no article.
+    pub fn is_empty(&self) -> bool {
+        self.articles.is_empty()
+    }
+}
+
+#[async_trait]
+impl KnowledgeBase for StaticKnowledgeBase {
+    async fn suggest(&self, draft: &str, limit: usize) -> io::Result<Vec<Suggestion>> {
+        let draft_words = words(draft);
+        // Keywords are matched as whole words, so phrases are matched on the joined words
+        let draft_text = format!(" {} ", draft_words.join(" "));
+        let draft_words: HashSet<&str> = draft_words
+            .iter()
+            .map(String::as_str)
+            .filter(|word| word.chars().count() >= MIN_WORD_LENGTH)
+            .collect();
+
+        let mut scored: Vec<(usize, &Article)> = self
+            .articles
+            .iter()
+            .map(|article| {
+                let keywords = article
+                    .keywords
+                    .iter()
+                    .map(|keyword| words(keyword).join(" "))
+                    .filter(|keyword| !keyword.is_empty() && draft_text.contains(&format!(" {} ", keyword)))
+                    .count();
+                let title_words: HashSet<String> = words(&article.title).into_iter().collect();
+                let title = title_words.iter().filter(|word| draft_words.contains(word.as_str())).count();
+                (2 * keywords + title, article)
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        // Stable, so ties keep the order of the file
+        scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, article)| Suggestion {
+                title: article.title.clone(),
+                url: article.url.clone(),
+                summary: article.summary.clone(),
+            })
+            .collect())
+    }
+}
+
+/// Knowledge base delegating to an external search API.
+///
+/// The API is called with `GET <url>?q=<draft>&limit=<limit>` and must
+/// answer a JSON array of [`Suggestion`]s.
+#[derive(Debug, Clone)]
+pub struct ApiKnowledgeBase {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl ApiKnowledgeBase {
+    /// Creates a knowledge base querying the API at `url`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(API_TIMEOUT)
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(ApiKnowledgeBase { client, url: url.to_string() })
+    }
+}
+
+#[async_trait]
+impl KnowledgeBase for ApiKnowledgeBase {
+    async fn suggest(&self, draft: &str, limit: usize) -> io::Result<Vec<Suggestion>> {
+        let mut suggestions: Vec<Suggestion> = self
+            .client
+            .get(&self.url)
+            .query(&[("q", draft), ("limit", &limit.to_string())])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?
+            .json()
+            .await
+            .map_err(io::Error::other)?;
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+}
+
+/// Builds the knowledge base selected by `KNOWLEDGE_BASE`.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when no knowledge base is configured.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `KNOWLEDGE_BASE` names an unknown source
+/// - The file of the `static` source cannot be read or parsed
+/// - `KNOWLEDGE_BASE=api` is used without `KNOWLEDGE_BASE_URL`
+pub fn from_config(config: &AppConfig) -> io::Result<Option<Arc<dyn KnowledgeBase>>> {
+    match config.knowledge_base.as_deref() {
+        None => Ok(None),
+        Some("static") => Ok(Some(Arc::new(StaticKnowledgeBase::load(&config.knowledge_base_path)?))),
+        Some("api") => {
+            let url = config.knowledge_base_url.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "KNOWLEDGE_BASE_URL must be set when KNOWLEDGE_BASE=api")
+            })?;
+            Ok(Some(Arc::new(ApiKnowledgeBase::new(url)?)))
+        }
+        Some(other) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown KNOWLEDGE_BASE: {}", other))),
+    }
+}
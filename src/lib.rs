@@ -14,7 +14,7 @@
 //! 
 //! ## Quick Start
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use dothtml_backend::database::Database;
 //! 
 //! #[tokio::main]
@@ -32,6 +32,74 @@
 //! - [`models`] - Data models and database operations
 //! - [`routes`] - HTTP route configuration
 //! - [`handlers`] - HTTP request handlers
+//! - [`config`] - Application configuration
+//! - [`jobs`] - Background jobs
+//! - [`auth`] - Authentication guards
+//! - [`storage`] - Blob storage for large payloads
+//! - [`caching`] - HTTP caching of rarely changing resources
+//! - [`query`] - Filter expressions for inbox searches
+//! - [`events`] - Message lifecycle event log
+//! - [`outbox`] - Transactional outbox for reliable dispatch
+//! - [`shared`] - State shared between replicas, such as rate limits
+//! - [`telemetry`] - Request tracing exported to OpenTelemetry
+//! - [`status`] - HTML status page
+//! - [`version`] - Build information
+//! - [`check`] - Pre-flight checks run by `dothtml-backend check`
+//! - [`client_ip`] - Client IP resolution behind trusted reverse proxies
+//! - [`concurrency`] - Concurrency limits of expensive endpoints
+//! - [`timestamp`] - RFC 3339 serialization of timestamps
+//! - [`flags`] - Feature flags toggled at runtime
+//! - [`request_log`] - Sampled request/response capture for debugging
+//! - [`reporting`] - Error reporting to Sentry
+//! - [`recovery`] - 500 responses for handler panics
+//! - [`ids`] - Typed message and company identifiers
+//! - [`backfill`] - Batched backfills of new columns
+//! - [`indexes`] - Indexes required by the queries
+//! - [`partitioning`] - Monthly partitioning of the messages table
+//! - [`archival`] - Export of old resolved messages to the blob store
+//! - [`backup`] - Backups run by `dothtml-backend backup` and `restore`
+//! - [`anonymize`] - Anonymized dataset exports
+//! - [`tokens`] - Signed links given to the senders of messages
+//! - [`ratings`] - Satisfaction ratings of resolved messages
+//! - [`knowledge`] - Knowledge base articles suggested to senders
+//! - [`moderation`] - Abuse filter screening contact form submissions
+//! - [`email_domain`] - MX checks of contact form email domains
+//! - [`spam`] - Spam scoring trained by moderator feedback
+//! - [`classification`] - Topic classification of incoming messages
+//! - [`rules`] - Routing rules applied to incoming messages
+//! - [`suppression`] - Do-not-contact list checked before replying
+//! - [`templates`] - Email templates for auto-replies, notifications and canned responses
+//! - [`mailer`] - Multipart composition of the emails sent by the server
+//! - [`email_transport`] - Delivery of emails over SMTP, SES, SendGrid or the log
+//! - [`mail_queue`] - Prioritized queue of the emails waiting to be sent
+//! - [`notifications`] - Notification preferences of the agents and their dispatcher
+//! - [`sessions`] - Magic-link login and the sessions it opens
+//! - [`jwt`] - Sessions signed as JWTs with rotating keys, published as a JWKS
+//! - [`api_tokens`] - Scoped API tokens for automation
+//! - [`auth_events`] - Audit of failed authentications and the lockout it feeds
+//! - [`webhooks`] - Signed delivery of the outbox entries to webhook endpoints
+//! - [`dead_letters`] - Webhook deliveries and emails given up, kept for a manual retry
+//! - [`metrics`] - Latency of the asynchronous pipelines, served to Prometheus
+//! - [`live`] - Live inbox updates pushed over a WebSocket
+//! - [`presence`] - Roster of the agents connected to the live inbox
+//! - [`reports`] - Reports emailed or pushed over HTTP on a schedule
+//! - [`ingest`] - Submissions of external form builders stored as messages
+//! - [`integrations`] - Polling trigger and actions for no-code automation tools
+//! - [`escalation`] - Messages escalated to a GitHub or Jira issue
+//! - [`crm`] - Senders of new messages synced to HubSpot or Pipedrive
+//! - [`scheduling`] - Booking links of the agents and meeting times proposed in replies
+//! - [`ics`] - Writer of the iCalendar files proposing meetings and listing deadlines
+//! - [`deadlines`] - Calendar feeds of the SLA due times of each agent's messages
+//! - [`acknowledgements`] - Emails confirming submissions to their senders, with the follow-up link
+//! - [`office_hours`] - Opening hours and holidays the SLA due times are counted in
+//! - [`atom`] - Atom feed of the latest messages, for feed readers
+//! - [`public_stats`] - Aggregate figures of the inbox published on the website
+//! - [`widget`] - Contact form embedded in other sites with a script tag
+//! - [`csrf`] - Double-submit tokens protecting cookie-authenticated requests
+//! - [`json_limits`] - Size, depth and string length limits of JSON request bodies
+//! - [`form_origins`] - Sites allowed to submit each form
+//! - `graphql` - GraphQL API for the backoffice (with the `graphql` feature)
+//! - `admin_ui` - Static backoffice UI served at `/app` (with the `admin-ui` feature)
 
 /// Database connection and query management
 pub mod database;
@@ -44,3 +112,209 @@ pub mod routes;
 
 /// HTTP request handlers
 pub mod handlers;
+
+/// Application configuration
+pub mod config;
+
+/// Background jobs
+pub mod jobs;
+
+/// Authentication guards
+pub mod auth;
+
+/// Blob storage for large payloads
+pub mod storage;
+
+/// HTTP caching of rarely changing resources
+pub mod caching;
+
+/// Filter expressions for inbox searches
+pub mod query;
+
+/// Message lifecycle event log
+pub mod events;
+
+/// Transactional outbox for reliable dispatch
+pub mod outbox;
+
+/// State shared between replicas, such as rate limits
+pub mod shared;
+
+/// Request tracing exported to OpenTelemetry
+pub mod telemetry;
+
+/// HTML status page
+pub mod status;
+
+/// Build information
+pub mod version;
+
+/// Pre-flight checks run by `dothtml-backend check`
+pub mod check;
+
+/// Client IP resolution behind trusted reverse proxies
+pub mod client_ip;
+
+/// Concurrency limits of expensive endpoints
+pub mod concurrency;
+
+/// RFC 3339 serialization of timestamps
+pub mod timestamp;
+
+/// Feature flags toggled at runtime
+pub mod flags;
+
+/// Sampled request/response capture for debugging
+pub mod request_log;
+
+/// Error reporting to Sentry
+pub mod reporting;
+
+/// 500 responses for handler panics
+pub mod recovery;
+
+/// Typed message and company identifiers
+pub mod ids;
+
+/// Batched backfills of new columns
+pub mod backfill;
+
+/// Indexes required by the queries
+pub mod indexes;
+
+/// Monthly partitioning of the messages table
+pub mod partitioning;
+
+/// Export of old resolved messages to the blob store
+pub mod archival;
+
+/// Backups run by `dothtml-backend backup` and `restore`
+pub mod backup;
+
+/// Anonymized dataset exports
+pub mod anonymize;
+
+/// Signed links given to the senders of messages
+pub mod tokens;
+
+/// Satisfaction ratings of resolved messages
+pub mod ratings;
+
+/// Knowledge base articles suggested to senders
+pub mod knowledge;
+
+/// Abuse filter screening contact form submissions
+pub mod moderation;
+
+/// MX checks of contact form email domains
+pub mod email_domain;
+
+/// Spam scoring trained by moderator feedback
+pub mod spam;
+
+/// Topic classification of incoming messages
+pub mod classification;
+
+/// Routing rules applied to incoming messages
+pub mod rules;
+
+/// Do-not-contact list checked before replying
+pub mod suppression;
+
+/// Email templates for auto-replies, notifications and canned responses
+pub mod templates;
+
+/// Multipart composition of the emails sent by the server
+pub mod mailer;
+
+/// Delivery of emails over SMTP, SES, SendGrid or the log
+pub mod email_transport;
+
+/// Prioritized queue of the emails waiting to be sent
+pub mod mail_queue;
+
+/// Notification preferences of the agents and their dispatcher
+pub mod notifications;
+
+/// Magic-link login and the sessions it opens
+pub mod sessions;
+
+/// Sessions signed as JWTs with rotating keys, published as a JWKS
+pub mod jwt;
+
+/// Scoped API tokens for automation
+pub mod api_tokens;
+
+/// Audit of failed authentications and the lockout it feeds
+pub mod auth_events;
+
+/// Signed delivery of the outbox entries to webhook endpoints
+pub mod webhooks;
+
+/// Webhook deliveries and emails given up, kept for a manual retry
+pub mod dead_letters;
+
+/// Latency of the asynchronous pipelines, served to Prometheus
+pub mod metrics;
+
+/// Live inbox updates pushed over a WebSocket
+pub mod live;
+
+/// Roster of the agents connected to the live inbox
+pub mod presence;
+
+/// Reports emailed or pushed over HTTP on a schedule
+pub mod reports;
+
+/// Submissions of external form builders stored as messages
+pub mod ingest;
+
+/// Polling trigger and actions for no-code automation tools
+pub mod integrations;
+
+/// Messages escalated to a GitHub or Jira issue
+pub mod escalation;
+
+/// Senders of new messages synced to HubSpot or Pipedrive
+pub mod crm;
+
+/// Booking links of the agents and meeting times proposed in replies
+pub mod scheduling;
+
+/// Writer of the iCalendar files proposing meetings and listing deadlines
+pub mod ics;
+
+/// Calendar feeds of the SLA due times of each agent's messages
+pub mod deadlines;
+
+/// Opening hours and holidays the SLA due times are counted in
+pub mod office_hours;
+
+/// Emails confirming submissions to their senders, with the follow-up link
+pub mod acknowledgements;
+
+/// Atom feed of the latest messages, for feed readers
+pub mod atom;
+
+/// Aggregate figures of the inbox published on the website
+pub mod public_stats;
+
+/// Contact form embedded in other sites with a script tag
+pub mod widget;
+
+/// Double-submit tokens protecting cookie-authenticated requests
+pub mod csrf;
+
+/// Size, depth and string length limits of JSON request bodies
+pub mod json_limits;
+
+/// Sites allowed to submit each form
+pub mod form_origins;
+
+/// GraphQL API for the backoffice
+#[cfg(feature = "graphql")]
+pub mod graphql;
+
+/// Static backoffice UI
+#[cfg(feature = "admin-ui")]
+pub mod admin_ui;
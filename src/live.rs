@@ -0,0 +1,361 @@
+//! # Live Inbox Updates
+//!
+//! WebSocket at `/inbox/ws` pushing inbox changes to the backoffice as
+//! they happen, so that agents see new, assigned and updated messages
+//! without polling.
+//!
+//! Agents connect with the credentials of the account endpoints (see
+//! `auth::Account`). Every replica follows the message event log (see the
+//! `events` module) and broadcasts each event to its connections, with the
+//! message's current assignee and tags. Lightweight signals that change no
+//! state, such as an agent typing, travel between replicas through
+//! PostgreSQL `NOTIFY` on the [`LIVE_CHANNEL`] channel. The open
+//! connections make up the presence roster (see the `presence` module).
+//!
+//! ## Server messages
+//!
+//! - `{"type": "event", "event": {...}, "assigned_to": "alice", "tags": ["sales"]}` - A message
+//!   lifecycle event, such as `created`, `assigned` or `status_changed`
+//! - `{"type": "typing", "message_id": "...", "account": "bob"}` - Another agent is writing a reply
+//! - `{"type": "opened", "message_id": "...", "account": "bob"}` - Another agent opened a message
+//! - `{"type": "ack", "ref": ..., ...}` - A command succeeded, with its result
+//! - `{"type": "error", "ref": ..., "message": "..."}` - A command failed
+//! - `{"type": "lagged", "skipped": 12}` - The connection fell behind and missed updates; the
+//!   client should reload the inbox
+//!
+//! ## Commands
+//!
+//! - `{"type": "subscribe", "assigned_to_me": true, "tags": ["sales"]}` - Only receive the events
+//!   of messages assigned to the caller and/or carrying one of the tags (default: every event)
+//! - `{"type": "mark_open", "message_id": "..."}` - Record the first opening of a message, like
+//!   `POST /inbox/{id}/open`; the ack carries the message
+//! - `{"type": "typing", "message_id": "..."}` - Tell the other agents the caller is writing a reply
+//!
+//! A command may carry a `ref`, any JSON value, echoed in its `ack` or
+//! `error` so that a client applying the command optimistically knows
+//! which change to confirm or roll back.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_ws::{Message as WsMessage, MessageStream, Session};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgListener;
+use sqlx::Row;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::events::MessageEvent;
+
+/// PostgreSQL channel carrying the signals between replicas.
+pub const LIVE_CHANNEL: &str = "inbox_live";
+
+/// Updates kept for a connection that falls behind before it lags.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Interval between two pings keeping idle connections open through proxies.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Shortest interval between two typing signals of a connection.
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+
+/// An update pushed to the connections.
+///
+/// * `Event` - A message lifecycle event, with the current assignee and tags of the message
+/// * `Typing` - An agent is writing a reply to a message
+/// * `Opened` - An agent opened a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveUpdate {
+    #[serde(skip_deserializing)]
+    Event {
+        event: MessageEvent,
+        assigned_to: Option<String>,
+        tags: Vec<String>,
+    },
+    Typing { message_id: Uuid, account: String },
+    Opened { message_id: Uuid, account: String },
+}
+
+/// What a connection subscribed to.
+///
+/// # Fields
+///
+/// * `assigned_to_me` - Only events of messages assigned to the connected agent
+/// * `tags` - Only events of messages carrying one of these tags, every message when empty
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::live::{LiveFilter, LiveUpdate};
+/// use uuid::Uuid;
+///
+/// let filter = LiveFilter { assigned_to_me: true, tags: vec![] };
+/// let typing = LiveUpdate::Typing { message_id: Uuid::nil(), account: "bob".to_string() };
+/// assert!(filter.matches(&typing, "alice"));
+/// // Agents do not hear their own signals
+/// assert!(!filter.matches(&typing, "bob"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveFilter {
+    pub assigned_to_me: bool,
+    pub tags: Vec<String>,
+}
+
+impl LiveFilter {
+    /// Returns whether `account`, connected with this filter, receives
+    /// `update`. Signals reach every other agent, whatever the filter.
+    pub fn matches(&self, update: &LiveUpdate, account: &str) -> bool {
+        match update {
+            LiveUpdate::Event { assigned_to, tags, .. } => {
+                (!self.assigned_to_me || assigned_to.as_deref() == Some(account))
+                    && (self.tags.is_empty() || tags.iter().any(|tag| self.tags.contains(tag)))
+            }
+            LiveUpdate::Typing { account: author, .. } | LiveUpdate::Opened { account: author, .. } => author != account,
+        }
+    }
+}
+
+/// A command sent by a client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LiveCommand {
+    Subscribe {
+        #[serde(default)]
+        assigned_to_me: bool,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    MarkOpen { message_id: Uuid },
+    Typing { message_id: Uuid },
+}
+
+/// Broadcasts the updates of this replica to its connections.
+#[derive(Debug, Clone)]
+pub struct LiveHub {
+    sender: broadcast::Sender<Arc<LiveUpdate>>,
+}
+
+impl LiveHub {
+    /// Creates a hub without connections.
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        LiveHub { sender }
+    }
+
+    /// Pushes an update to the connections of this replica.
+    pub fn publish(&self, update: LiveUpdate) {
+        // Sending only fails when nobody is connected
+        let _ = self.sender.send(Arc::new(update));
+    }
+
+    /// Returns the number of open connections on this replica.
+    pub fn connections(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<LiveUpdate>> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for LiveHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves a WebSocket connection until the client leaves.
+///
+/// # Arguments
+///
+/// * `session` - Session writing to the connection
+/// * `stream` - Messages read from the connection
+/// * `account` - The connected agent
+/// * `hub` - Hub broadcasting the updates of this replica
+/// * `db` - Database the commands act on
+pub async fn serve(session: Session, stream: MessageStream, account: String, hub: LiveHub, db: Database) {
+    let connection_id = Uuid::new_v4();
+    run(session, stream, connection_id, &account, hub, &db).await;
+    if let Err(e) = db.end_presence(connection_id).await {
+        eprintln!("Failed to remove the connection of {} from the presence roster: {}", account, e);
+    }
+}
+
+async fn run(mut session: Session, mut stream: MessageStream, connection_id: Uuid, account: &str, hub: LiveHub, db: &Database) {
+    let mut updates = hub.subscribe();
+    let mut filter = LiveFilter::default();
+    let mut last_typing: Option<Instant> = None;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        let sent = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(WsMessage::Text(text))) => {
+                    let reply = run_command(&text, account, &mut filter, &mut last_typing, db).await;
+                    match reply {
+                        Some(reply) => session.text(reply.to_string()).await,
+                        None => Ok(()),
+                    }
+                }
+                Some(Ok(WsMessage::Ping(bytes))) => session.pong(&bytes).await,
+                Some(Ok(WsMessage::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => Ok(()),
+                Some(Err(_)) | None => break,
+            },
+            update = updates.recv() => match update {
+                Ok(update) if filter.matches(&update, account) => {
+                    session.text(serde_json::to_string(&*update).unwrap_or_default()).await
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(skipped)) => session.text(json!({ "type": "lagged", "skipped": skipped }).to_string()).await,
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                // The first tick, right away, records the connection
+                if let Err(e) = db.record_presence(connection_id, account).await {
+                    eprintln!("Failed to record the presence of {}: {}", account, e);
+                }
+                session.ping(b"").await
+            }
+        };
+        if sent.is_err() {
+            // The client is gone
+            return;
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+/// Runs a client command.
+///
+/// # Returns
+///
+/// Returns the `ack` or `error` to send back, `None` for commands
+/// answered with nothing, such as a throttled typing signal.
+async fn run_command(
+    text: &str,
+    account: &str,
+    filter: &mut LiveFilter,
+    last_typing: &mut Option<Instant>,
+    db: &Database,
+) -> Option<Value> {
+    let reference = serde_json::from_str::<Value>(text).ok().and_then(|value| value.get("ref").cloned());
+    let command = match serde_json::from_str::<LiveCommand>(text) {
+        Ok(command) => command,
+        Err(e) => return Some(json!({ "type": "error", "ref": reference, "message": format!("Invalid command: {}", e) })),
+    };
+
+    match command {
+        LiveCommand::Subscribe { assigned_to_me, tags } => {
+            *filter = LiveFilter { assigned_to_me, tags };
+            Some(json!({ "type": "ack", "ref": reference, "assigned_to_me": filter.assigned_to_me, "tags": filter.tags }))
+        }
+        LiveCommand::MarkOpen { message_id } => match db.mark_message_opened(message_id, account).await {
+            Ok(message) => {
+                let signal = LiveUpdate::Opened { message_id, account: account.to_string() };
+                if let Err(e) = db.notify_live(&signal).await {
+                    eprintln!("Failed to signal the opening of message {}: {}", message_id, e);
+                }
+                Some(json!({ "type": "ack", "ref": reference, "message": message }))
+            }
+            Err(sqlx::Error::RowNotFound) => Some(json!({ "type": "error", "ref": reference, "message": "Message not found" })),
+            Err(_) => Some(json!({ "type": "error", "ref": reference, "message": "Failed to update message" })),
+        },
+        LiveCommand::Typing { message_id } => {
+            if last_typing.is_some_and(|last| last.elapsed() < TYPING_THROTTLE) {
+                return None;
+            }
+            *last_typing = Some(Instant::now());
+            let signal = LiveUpdate::Typing { message_id, account: account.to_string() };
+            match db.notify_live(&signal).await {
+                Ok(()) => reference.map(|reference| json!({ "type": "ack", "ref": reference })),
+                Err(_) => Some(json!({ "type": "error", "ref": reference, "message": "Failed to send the typing signal" })),
+            }
+        }
+    }
+}
+
+/// Database operations for the live updates.
+impl Database {
+    /// Returns the position of the last message event, 0 when there is none.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn last_event_id(&self) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query("SELECT COALESCE(MAX(id), 0) AS id FROM message_events")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+    /// Lists the message events recorded after `after_id`, oldest first, as
+    /// updates carrying the current assignee and tags of their message.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_live_updates_after(&self, after_id: i64, limit: i64) -> Result<Vec<(i64, LiveUpdate)>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT e.id, e.message_id, e.event_type, e.payload, e.created_at,
+                   m.assigned_to, COALESCE(m.tags, '{}') AS tags
+            FROM message_events e
+            LEFT JOIN messages m ON m.id = e.message_id
+            WHERE e.id > $1
+            ORDER BY e.id
+            LIMIT $2
+        "#)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let event = MessageEvent {
+                    id: row.get("id"),
+                    message_id: row.get("message_id"),
+                    event_type: row.get("event_type"),
+                    payload: row.get("payload"),
+                    created_at: row.get("created_at"),
+                };
+                (event.id, LiveUpdate::Event { event, assigned_to: row.get("assigned_to"), tags: row.get("tags") })
+            })
+            .collect())
+    }
+
+    /// Sends a signal to the connections of every replica.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn notify_live(&self, signal: &LiveUpdate) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(LIVE_CHANNEL)
+            .bind(serde_json::to_string(signal).unwrap_or_default())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns a listener receiving the signals sent with [`notify_live`](Database::notify_live).
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn listen_live(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(LIVE_CHANNEL).await?;
+        Ok(listener)
+    }
+}
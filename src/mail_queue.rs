@@ -0,0 +1,304 @@
+//! # Mail Queue
+//!
+//! Emails waiting for the mail dispatch worker (see
+//! `jobs::spawn_mail_dispatch`), which hands them one at a time to the
+//! mailer's transport.
+//!
+//! ## Priorities
+//!
+//! Transactional emails, such as replies to a customer, always leave
+//! before digests: a digest is only sent when no transactional email is
+//! waiting, so a large digest run cannot delay a reply by more than the
+//! email being sent. Emails of the same priority leave in the order they
+//! were queued.
+//!
+//! ## Throttling
+//!
+//! Providers reject or defer senders going over their sending rate, so the
+//! worker sends at most `MAIL_MAX_PER_MINUTE` emails per minute, or the
+//! default rate of the transport when unset. The count is kept in the
+//! shared state (see the `shared` module), so the limit holds across
+//! replicas.
+//!
+//! ## Metrics
+//!
+//! The queue counts the emails waiting, sent and failed per priority, and
+//! the time between queueing an email and the provider accepting it. They
+//! are shown on the status page.
+//!
+//! The queue lives in memory: emails still waiting when the server stops
+//! are lost. Emails the transport fails to send are kept as dead letters
+//! (see the `dead_letters` module), from which an admin can queue them
+//! again.
+//!
+//! ## Notification emails
+//!
+//! Emails following a database change, such as assignment notifications,
+//! are not queued by the code making the change: it writes an
+//! [`OutboxEmail`] to the outbox in the same transaction, and the email
+//! relay of the outbox queues it once committed (see `outbox::RelayScope`).
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::outbox::{OutboxEntry, Publisher};
+use crate::templates::RenderedEmail;
+
+/// Urgency of a queued email.
+///
+/// * `Transactional` - Sent as soon as possible, e.g. a reply to a customer
+/// * `Digest` - Sent when no transactional email is waiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailPriority {
+    Transactional,
+    Digest,
+}
+
+impl MailPriority {
+    /// Returns the name of the priority, as shown on the status page.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MailPriority::Transactional => "transactional",
+            MailPriority::Digest => "digest",
+        }
+    }
+}
+
+impl fmt::Display for MailPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An email waiting to be sent.
+///
+/// # Fields
+///
+/// * `to` - Recipient of the email
+/// * `email` - Rendered subject and bodies
+/// * `priority` - Urgency of the email
+/// * `queued_at` - When the email was queued
+#[derive(Debug, Clone)]
+pub struct QueuedEmail {
+    pub to: String,
+    pub email: RenderedEmail,
+    pub priority: MailPriority,
+    pub queued_at: Instant,
+}
+
+/// Payload of a `notification.email` outbox entry: a transactional email
+/// to queue once the transaction enqueuing it is committed.
+///
+/// # Fields
+///
+/// * `to` - Recipient of the email
+/// * `email` - Rendered subject and bodies, flattened into the payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEmail {
+    pub to: String,
+    #[serde(flatten)]
+    pub email: RenderedEmail,
+}
+
+/// Counters of one priority.
+///
+/// # Fields
+///
+/// * `waiting` - Emails in the queue
+/// * `sent` - Emails accepted by the provider since the server started
+/// * `failed` - Emails the transport failed to deliver since the server started
+/// * `average_latency` - Average time from queueing to acceptance, if an email was sent
+/// * `max_latency` - Longest time from queueing to acceptance, if an email was sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PriorityStats {
+    pub waiting: usize,
+    pub sent: u64,
+    pub failed: u64,
+    pub average_latency: Option<Duration>,
+    pub max_latency: Option<Duration>,
+}
+
+/// Snapshot of the queue metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MailQueueStats {
+    pub transactional: PriorityStats,
+    pub digest: PriorityStats,
+}
+
+/// Emails and counters of one priority.
+#[derive(Debug, Default)]
+struct Lane {
+    emails: VecDeque<QueuedEmail>,
+    sent: u64,
+    failed: u64,
+    total_latency: Duration,
+    max_latency: Duration,
+}
+
+impl Lane {
+    fn stats(&self) -> PriorityStats {
+        PriorityStats {
+            waiting: self.emails.len(),
+            sent: self.sent,
+            failed: self.failed,
+            average_latency: (self.sent > 0).then(|| self.total_latency / self.sent as u32),
+            max_latency: (self.sent > 0).then_some(self.max_latency),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Lanes {
+    transactional: Lane,
+    digest: Lane,
+}
+
+impl Lanes {
+    fn lane(&mut self, priority: MailPriority) -> &mut Lane {
+        match priority {
+            MailPriority::Transactional => &mut self.transactional,
+            MailPriority::Digest => &mut self.digest,
+        }
+    }
+}
+
+/// Priority queue of the emails waiting to be sent, shared between the
+/// handlers queueing emails and the dispatch worker.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::mail_queue::{MailPriority, MailQueue};
+/// use dothtml_backend::templates::RenderedEmail;
+///
+/// let email = RenderedEmail { subject: "Hello".into(), html: "<p>Hello</p>".into(), text: "Hello".into() };
+/// let queue = MailQueue::default();
+/// queue.push("weekly@example.com", email.clone(), MailPriority::Digest);
+/// queue.push("jane@example.com", email, MailPriority::Transactional);
+///
+/// assert_eq!(queue.pop().unwrap().to, "jane@example.com");
+/// assert_eq!(queue.pop().unwrap().to, "weekly@example.com");
+/// assert!(queue.pop().is_none());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MailQueue {
+    lanes: Arc<Mutex<Lanes>>,
+    pushed: Arc<Notify>,
+}
+
+impl MailQueue {
+    fn lanes(&self) -> MutexGuard<'_, Lanes> {
+        // Every change to the lanes is a single push, pop or counter update,
+        // so a panic while holding the lock cannot leave them inconsistent
+        self.lanes.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Queues an email to `to`.
+    pub fn push(&self, to: &str, email: RenderedEmail, priority: MailPriority) {
+        let queued = QueuedEmail { to: to.to_string(), email, priority, queued_at: Instant::now() };
+        self.lanes().lane(priority).emails.push_back(queued);
+        self.pushed.notify_one();
+    }
+
+    /// Takes the next email to send: the oldest transactional email, or the
+    /// oldest digest when no transactional email is waiting.
+    pub fn pop(&self) -> Option<QueuedEmail> {
+        let mut lanes = self.lanes();
+        lanes.transactional.emails.pop_front().or_else(|| lanes.digest.emails.pop_front())
+    }
+
+    /// Waits until an email is queued.
+    ///
+    /// The queue has a single consumer, so the email is still there when
+    /// this returns.
+    pub async fn ready(&self) {
+        loop {
+            let pushed = self.pushed.notified();
+            if !self.is_empty() {
+                return;
+            }
+            pushed.await;
+        }
+    }
+
+    /// Returns the number of emails waiting.
+    pub fn len(&self) -> usize {
+        let lanes = self.lanes();
+        lanes.transactional.emails.len() + lanes.digest.emails.len()
+    }
+
+    /// Returns whether no email is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Records the outcome of sending `email`.
+    pub fn record(&self, email: &QueuedEmail, sent: bool) {
+        let mut lanes = self.lanes();
+        let lane = lanes.lane(email.priority);
+        if sent {
+            let latency = email.queued_at.elapsed();
+            lane.sent += 1;
+            lane.total_latency += latency;
+            lane.max_latency = lane.max_latency.max(latency);
+        } else {
+            lane.failed += 1;
+        }
+    }
+
+    /// Returns the current metrics of the queue.
+    pub fn stats(&self) -> MailQueueStats {
+        let lanes = self.lanes();
+        MailQueueStats { transactional: lanes.transactional.stats(), digest: lanes.digest.stats() }
+    }
+}
+
+/// Queues the notification emails relayed from the outbox.
+///
+/// An entry that is not an [`OutboxEmail`] fails, and stays in the outbox
+/// with the error.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::Utc;
+/// use dothtml_backend::mail_queue::{MailQueue, OutboxEmail};
+/// use dothtml_backend::outbox::{OutboxEntry, Publisher};
+/// use dothtml_backend::templates::RenderedEmail;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let email = RenderedEmail { subject: "Assigned".into(), html: "<p>Assigned</p>".into(), text: "Assigned".into() };
+///     let outbox_email = OutboxEmail { to: "alice@dotshell.eu".into(), email };
+///     let mut entry = OutboxEntry {
+///         id: 1,
+///         topic: "notification.email".into(),
+///         dedup_key: "email:alice@dotshell.eu:assigned:1".into(),
+///         payload: serde_json::to_value(&outbox_email).unwrap(),
+///         created_at: Utc::now(),
+///         attempts: 0,
+///     };
+///
+///     let queue = MailQueue::default();
+///     queue.publish(&entry).await.unwrap();
+///     assert_eq!(queue.pop().unwrap().email, outbox_email.email);
+///
+///     entry.payload = serde_json::json!({ "to": "alice@dotshell.eu" });
+///     assert!(queue.publish(&entry).await.is_err());
+/// }
+/// ```
+#[async_trait]
+impl Publisher for MailQueue {
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()> {
+        let outbox_email: OutboxEmail = serde_json::from_value(entry.payload.clone())?;
+        self.push(&outbox_email.to, outbox_email.email, MailPriority::Transactional);
+        Ok(())
+    }
+}
@@ -1,11 +1,31 @@
-mod routes;
-mod handlers;
-mod database;
-mod models;
-
-use actix_web::{web, App, HttpServer};
+use actix_web::{middleware, web, App, HttpServer};
 use actix_cors::Cors;
-use database::Database;
+use std::sync::Arc;
+use std::time::Duration;
+use dothtml_backend::backfill::{self, BackfillOptions};
+use dothtml_backend::concurrency::ConcurrencyLimits;
+use dothtml_backend::config::{AppConfig, ListenAddress, LiveConfig};
+use dothtml_backend::database::Database;
+use dothtml_backend::flags::FeatureFlags;
+use dothtml_backend::form_origins::{self, FormOrigins};
+use dothtml_backend::models::DEFAULT_FORM;
+use dothtml_backend::mail_queue::MailQueue;
+use dothtml_backend::live::LiveHub;
+use dothtml_backend::metrics::PipelineMetrics;
+use dothtml_backend::reports::ReportSender;
+use dothtml_backend::indexes::IndexState;
+use dothtml_backend::jwt::SigningKeys;
+use dothtml_backend::moderation::AbuseFilterCache;
+use dothtml_backend::public_stats::PublicStatsCache;
+use dothtml_backend::notifications::NotificationDispatcher;
+use dothtml_backend::outbox::{DiscardPublisher, Publisher, RelayScope};
+use dothtml_backend::request_log::{self, RequestLog};
+use dothtml_backend::shared::RateLimiter;
+use dothtml_backend::status::Uptime;
+use dothtml_backend::templates::TemplateRenderer;
+use dothtml_backend::routes::Surface;
+use dothtml_backend::webhooks::WebhookSender;
+use dothtml_backend::{check, classification, crm, csrf, email_domain, escalation, jobs, json_limits, knowledge, mailer, outbox, recovery, reporting, shared, storage, telemetry, widget};
 
 /// Main application entry point.
 /// 
@@ -30,12 +50,111 @@ use database::Database;
 /// cargo run
 /// ```
 /// 
-/// The server will start on `http://127.0.0.1:8080`
+/// The server will start on `http://127.0.0.1:8080`, or on the address set
+/// in `BIND_ADDRESS` (`host:port` or `unix:/path/to/socket`). When
+/// `ADMIN_BIND_ADDRESS` is also set, a second listener serves the backoffice
+/// and only the website API stays on `BIND_ADDRESS`.
+///
+/// Validate the configuration and external services without starting the
+/// server (exits with status 1 if a check fails):
+/// ```bash
+/// cargo run -- check
+/// ```
+///
+/// List the backfills, or run one (see the `backfill` module):
+/// ```bash
+/// cargo run -- backfill
+/// cargo run -- backfill assigned_at
+/// ```
+///
+/// List the indexes the queries rely on, or create the missing ones (see
+/// the `indexes` module):
+/// ```bash
+/// cargo run -- indexes
+/// cargo run -- indexes --create
+/// ```
+///
+/// Convert the messages table to monthly partitions (see the
+/// `partitioning` module), during a maintenance window:
+/// ```bash
+/// cargo run -- partition-messages
+/// ```
+///
+/// List the archives exported to the blob store, or restore one (see the
+/// `archival` module):
+/// ```bash
+/// cargo run -- restore-archive
+/// cargo run -- restore-archive 123e4567-e89b-12d3-a456-426614174000
+/// ```
+///
+/// Back up the tables of the server to a single file, and restore it into
+/// a database where the server created its tables (see the `backup` module):
+/// ```bash
+/// cargo run -- backup dothtml.backup.gz
+/// cargo run -- restore dothtml.backup.gz
+/// cargo run -- restore dothtml.backup.gz --replace
+/// ```
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let config = AppConfig::from_env();
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "check" || arg == "--check") {
+        let report = check::run(&config).await;
+        print!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "backfill") {
+        return run_backfill(&config, std::env::args().nth(2).as_deref()).await;
+    }
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "partition-messages") {
+        let db = Database::new().await.map_err(std::io::Error::other)?;
+        let partitions = db.partition_messages_table().await.map_err(std::io::Error::other)?;
+        println!("Partitioned the messages table into {} monthly partitions", partitions);
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "restore-archive") {
+        return run_restore_archive(&config, std::env::args().nth(2).as_deref()).await;
+    }
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "backup" || arg == "restore") {
+        let replace = std::env::args().nth(3).is_some_and(|arg| arg == "--replace");
+        return run_backup(std::env::args().nth(1).as_deref() == Some("restore"), std::env::args().nth(2), replace).await;
+    }
+
+    if std::env::args().nth(1).is_some_and(|arg| arg == "indexes") {
+        return run_indexes(std::env::args().nth(2).is_some_and(|arg| arg == "--create")).await;
+    }
+
+    // Export request traces when a collector is configured
+    let telemetry = telemetry::init(&config)?;
+
+    // Report panics, 5xx responses and job failures when a DSN is configured
+    let reporting = reporting::init(&config)?;
+
     // Initialize database connection
-    let db = Database::new().await
+    let mut db = Database::new().await
         .expect("Failed to connect to database");
+
+    // Quarantine the messages of unknown senders when greylisting is enabled
+    db = db.with_greylisting(config.greylist_new_senders);
+
+    // Give incoming messages a category unless classification is disabled
+    if let Some(classifier) = classification::from_config(&config)? {
+        db = db.with_classifier(classifier);
+    }
+
+    // Move very long message bodies out of PostgreSQL when a blob store is configured
+    if let Some(store) = storage::from_config(&config)? {
+        db = db.with_blob_store(store, config.message_overflow_threshold_kb * 1024);
+    } else if config.archive_export_after_months.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "ARCHIVE_EXPORT_AFTER_MONTHS requires BLOB_STORE",
+        ));
+    }
     
     // Test database connectivity
     db.test_connection().await
@@ -47,27 +166,492 @@ async fn main() -> std::io::Result<()> {
             sqlx::Error::Database(ref err) if err.code().as_deref() == Some("42P07") => {
                 println!("Messages table already exists, continuing...");
             }
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            _ => return Err(std::io::Error::other(e)),
         }
     }
 
+    db.create_companies_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_resource_changes_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_message_events_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_outbox_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_feature_flags_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_backfills_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_archives_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_ratings_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_abuse_tables().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_spam_tables().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_rules_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_suppressions_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_templates_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_notification_prefs_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_jwt_keys_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_api_tokens_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_auth_events_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_account_revocations_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_webhook_tables().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_dead_letters_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_agent_presence_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_reports_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_ingest_tables().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_escalations_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_crm_syncs_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_scheduling_table().await
+        .map_err(std::io::Error::other)?;
+
+    db.create_form_origins_table().await
+        .map_err(std::io::Error::other)?;
+
+    // Bring existing tables up to date with the current schema
+    db.upgrade_messages_table().await
+        .map_err(std::io::Error::other)?;
+
+    // Attach messages created before the companies directory existed
+    db.backfill_message_companies().await
+        .map_err(std::io::Error::other)?;
+
+    // Build the indexes missing from databases created by older versions
+    jobs::spawn_index_creation(db.clone(), &config);
+
+    // Start background jobs; scheduled ones only run on the elected replica
+    let leader = jobs::spawn_leader_election(db.clone()).await;
+    jobs::spawn_archive_job(db.clone(), &config, &leader);
+    jobs::spawn_trash_purge_job(db.clone(), &config, &leader);
+    jobs::spawn_auto_release_job(db.clone(), &config, &leader);
+    jobs::spawn_outbox_cleanup_job(db.clone(), &config, &leader);
+    jobs::spawn_partition_maintenance_job(db.clone(), &config, &leader);
+    jobs::spawn_archive_export_job(db.clone(), &config, &leader);
+    jobs::spawn_jwt_key_rotation_job(db.clone(), &config, &leader);
+    jobs::spawn_auth_events_cleanup_job(db.clone(), &config, &leader);
+    if let Some(publisher) = outbox::publisher_from_config(&config, &db).await? {
+        jobs::spawn_outbox_relay_job(db.clone(), publisher, RelayScope::External);
+    }
+    let pipeline_metrics = web::Data::new(PipelineMetrics::new(
+        Duration::from_secs(config.webhook_latency_slo_seconds),
+        Duration::from_secs(config.email_latency_slo_seconds),
+    ));
+    if config.outbox_publisher.as_deref() == Some("webhooks") {
+        jobs::spawn_webhook_delivery_job(db.clone(), WebhookSender::new(pipeline_metrics.get_ref().clone())?);
+    }
+    let crm_connector = crm::from_config(&config)?;
+    if let Some(connector) = &crm_connector {
+        jobs::spawn_crm_sync_job(db.clone(), connector.clone());
+    }
+    let crm_connector = crm_connector.map(web::Data::from);
+    let live_hub = web::Data::new(LiveHub::new());
+    jobs::spawn_live_feed(db.clone(), live_hub.get_ref().clone());
+
     // Start HTTP server
-    HttpServer::new(move || {
+    let shared_state = shared::from_config(&config).await?;
+    let mail_limiter = RateLimiter::new(shared_state.clone(), "mail", Duration::from_secs(60));
+    let contact_limiter = web::Data::new(RateLimiter::new(shared_state.clone(), "contact", Duration::from_secs(60 * 60)));
+    let shared_state = web::Data::from(shared_state);
+
+    // Settings read while serving requests can be reloaded without a restart
+    let live_config = LiveConfig::new(config.clone());
+    #[cfg(unix)]
+    live_config.reload_on_sighup()?;
+
+    #[cfg(feature = "graphql")]
+    let schema = web::Data::new(dothtml_backend::graphql::build_schema(db.clone()));
+
+    #[cfg(feature = "otel")]
+    let tracing_enabled = telemetry.is_some();
+
+    // Serve the bundled backoffice UI when it is configured
+    let admin_ui_path = config.admin_ui_path.clone();
+    if let Some(dir) = &admin_ui_path {
+        if cfg!(not(feature = "admin-ui")) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "ADMIN_UI_PATH requires building with the `admin-ui` feature",
+            ));
+        }
+        if !std::path::Path::new(dir).join("index.html").is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("ADMIN_UI_PATH `{}` does not contain an index.html", dir),
+            ));
+        }
+    }
+
+    let uptime = web::Data::new(Uptime::start());
+    let concurrency_limits = web::Data::new(ConcurrencyLimits::from_config(&config));
+    let feature_flags = web::Data::new(FeatureFlags::new(db.clone()));
+    let abuse_filter = web::Data::new(AbuseFilterCache::new(db.clone()));
+    let public_stats = web::Data::new(PublicStatsCache::new(db.clone()));
+    let request_log = web::Data::new(RequestLog::new(config.debug_log_capacity));
+    let template_renderer = web::Data::new(TemplateRenderer::new());
+    let knowledge_base = knowledge::from_config(&config)?.map(web::Data::from);
+    let issue_tracker = escalation::from_config(&config)?.map(web::Data::from);
+    let mx_checker = email_domain::from_config(&config)?.map(web::Data::new);
+    let mailer = mailer::from_config(&config)?.map(web::Data::new);
+    let mail_queue = mailer.as_ref().map(|_| web::Data::new(MailQueue::default()));
+    if let (Some(mailer), Some(mail_queue)) = (&mailer, &mail_queue) {
+        jobs::spawn_mail_dns_check(mailer.get_ref().clone());
+        jobs::spawn_mail_dispatch(
+            db.clone(),
+            mailer.get_ref().clone(),
+            mail_queue.get_ref().clone(),
+            mail_limiter,
+            pipeline_metrics.get_ref().clone(),
+            &config,
+        );
+    }
+    // Queue the notification emails once the changes they follow are committed, or drop them without a mailer
+    let email_publisher: Arc<dyn Publisher> = match &mail_queue {
+        Some(mail_queue) => Arc::new(mail_queue.get_ref().clone()),
+        None => Arc::new(DiscardPublisher),
+    };
+    jobs::spawn_outbox_relay_job(db.clone(), email_publisher, RelayScope::Emails);
+    let report_sender = web::Data::new(ReportSender::new(mailer.as_ref().map(|mailer| mailer.get_ref().clone()))?);
+    jobs::spawn_report_job(db.clone(), report_sender.get_ref().clone());
+    let notifier = web::Data::new(NotificationDispatcher::new(
+        db.clone(),
+        mail_queue.as_ref().map(|mail_queue| mail_queue.get_ref().clone()),
+    ));
+    jobs::spawn_daily_digest_job(notifier.get_ref().clone(), &leader);
+
+    // Sign sessions with the current key, creating the first one on a new database
+    db.rotate_jwt_keys(config.jwt_key_rotation_days, config.jwt_key_overlap_hours).await
+        .map_err(std::io::Error::other)?;
+    let signing_keys = web::Data::new(
+        SigningKeys::load(db.clone(), config.jwt_key_overlap_hours).await.map_err(std::io::Error::other)?,
+    );
+    jobs::spawn_jwt_key_refresh(signing_keys.get_ref().clone());
+    let form_origins = web::Data::new(FormOrigins::load(db.clone()).await.map_err(std::io::Error::other)?);
+    jobs::spawn_form_origins_refresh(form_origins.get_ref().clone());
+
+    // Builds the application serving one group of routes
+    let build_app = move |surface: Surface| {
+        let cors_config = live_config.clone();
+        let cors_form_origins = form_origins.get_ref().clone();
         let cors = Cors::default()
-            .allowed_origin("https://dotshell.eu")  // Production domain
-            .allowed_origin("http://dotshell.ddns.net:4000")  // Development domain
-            .allowed_origin("http://localhost:4000")  // Local development
-            .allowed_methods(vec!["GET", "POST"])
-            .allowed_headers(vec!["Content-Type"])
+            // Checked on each request so that reloads apply to open workers
+            .allowed_origin_fn(move |origin, head| {
+                // Submissions of the contact widget, served by the API itself
+                if widget::is_same_origin(origin, head) {
+                    return true;
+                }
+                let Ok(origin) = origin.to_str() else {
+                    return false;
+                };
+                let config = cors_config.load();
+                if head.uri.path().starts_with("/contact") {
+                    // Sites of the form named by `?form=`, also checked by `POST /contact`
+                    let form = head.uri.query().and_then(form_origins::query_form);
+                    let form = form.as_deref().unwrap_or(DEFAULT_FORM);
+                    return cors_form_origins.allows(form, origin, &config.cors_allowed_origins);
+                }
+                config.cors_allowed_origins.iter().any(|allowed| origin == allowed)
+            })
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .allowed_headers(vec!["Content-Type", "Authorization", "X-CSRF-Token"])
             .max_age(3600)
             .supports_credentials();
 
-        App::new()
+        let app = App::new()
+            // Reject oversized, too deeply nested or too long JSON bodies before handlers parse them
+            .wrap(middleware::from_fn(json_limits::enforce))
+            // Answer handler panics with a 500 JSON error instead of dropping the connection
+            .wrap(middleware::from_fn(recovery::catch_panics))
+            // Report 5xx responses and give panics the request they happened in
+            .wrap(middleware::from_fn(reporting::middleware))
+            // Keep sampled requests for GET /admin/debug/requests, before compression
+            .wrap(middleware::from_fn(request_log::capture))
+            // Negotiate gzip/brotli/zstd with the client's Accept-Encoding header
+            .wrap(middleware::Condition::new(config.compression, middleware::Compress::default()))
+            // Check the CSRF token of state-changing requests authenticated by cookies
+            .wrap(middleware::from_fn(csrf::protect))
             .wrap(cors)  // Ajouter le middleware CORS
             .app_data(web::Data::new(db.clone())) // Share database instance across handlers
-            .configure(routes::config) // Configure routes from the routes module
-    })
-        .bind("0.0.0.0:8080")?  // Bind to all network interfaces
-        .run()
+            .app_data(json_limits::json_config(config.max_json_body_kb.saturating_mul(1024))) // Answer rejected JSON bodies with structured errors
+            .app_data(web::Data::new(live_config.clone())) // Share the live configuration across handlers
+            .app_data(contact_limiter.clone()) // Share the contact form rate limiter across workers
+            .app_data(shared_state.clone()) // Share the state consistent across replicas, such as used login links
+            .app_data(uptime.clone()) // Share the server start time with the status page
+            .app_data(concurrency_limits.clone()) // Share the concurrency limits across workers
+            .app_data(feature_flags.clone()) // Share the feature flag cache across workers
+            .app_data(abuse_filter.clone()) // Share the abuse pattern cache across workers
+            .app_data(public_stats.clone()) // Share the public statistics cache across workers
+            .app_data(request_log.clone()) // Share the debug request log across workers
+            .app_data(template_renderer.clone()) // Share the email template renderer across workers
+            .app_data(notifier.clone()) // Share the notification dispatcher across workers
+            .app_data(signing_keys.clone()) // Share the keys signing sessions across workers
+            .app_data(form_origins.clone()) // Share the origins allowed to submit each form across workers
+            .app_data(pipeline_metrics.clone()) // Share the pipeline latency metrics with the jobs recording them
+            .app_data(live_hub.clone()) // Share the live update hub across the WebSocket connections
+            .app_data(report_sender.clone()) // Share the report sender with the manual runs
+            .configure(|cfg| surface.configure(cfg)); // Configure routes from the routes module
+
+        // Share the knowledge base suggesting articles, when one is configured
+        let app = match &knowledge_base {
+            Some(knowledge_base) => app.app_data(knowledge_base.clone()),
+            None => app,
+        };
+
+        // Share the issue tracker messages are escalated to, when one is configured
+        let app = match &issue_tracker {
+            Some(issue_tracker) => app.app_data(issue_tracker.clone()),
+            None => app,
+        };
+
+        // Share the CRM the senders are synced to, when one is configured
+        let app = match &crm_connector {
+            Some(crm_connector) => app.app_data(crm_connector.clone()),
+            None => app,
+        };
+
+        // Share the MX checker of contact form emails, when the check is enabled
+        let app = match &mx_checker {
+            Some(mx_checker) => app.app_data(mx_checker.clone()),
+            None => app,
+        };
+
+        // Share the mailer composing emails, when a sender is configured
+        let app = match &mailer {
+            Some(mailer) => app.app_data(mailer.clone()),
+            None => app,
+        };
+
+        // Share the queue of emails waiting to be sent, when a sender is configured
+        let app = match &mail_queue {
+            Some(mail_queue) => app.app_data(mail_queue.clone()),
+            None => app,
+        };
+
+        #[cfg(feature = "graphql")]
+        let app = app.app_data(schema.clone()); // Share the GraphQL schema across requests
+
+        #[cfg(feature = "admin-ui")]
+        let app = match &admin_ui_path {
+            Some(dir) if surface.serves_backoffice() => app.configure(dothtml_backend::admin_ui::config(dir)),
+            _ => app,
+        };
+
+        // Open a span per request and return its request and trace IDs
+        #[cfg(feature = "otel")]
+        let app = app
+            .wrap(middleware::Condition::new(tracing_enabled, middleware::from_fn(telemetry::correlation_headers)))
+            .wrap(middleware::Condition::new(tracing_enabled, tracing_actix_web::TracingLogger::default()));
+
+        app
+    };
+
+    // Binds a server to a TCP address or a Unix domain socket
+    macro_rules! bind {
+        ($server:expr, $address:expr) => {{
+            let server = $server;
+            match $address {
+                ListenAddress::Tcp(address) => server.bind(address)?,
+                #[cfg(unix)]
+                ListenAddress::Unix(path) => {
+                    // A socket left behind by a previous run would make the bind fail
+                    if std::fs::symlink_metadata(path).is_ok_and(|metadata| {
+                        std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())
+                    }) {
+                        std::fs::remove_file(path)?;
+                    }
+                    server.bind_uds(path)?
+                }
+                #[cfg(not(unix))]
+                ListenAddress::Unix(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "Unix domain sockets are only supported on Unix",
+                    ));
+                }
+            }
+        }};
+    }
+
+    // Keep the backoffice off the public listener when it has its own
+    let public_surface = if config.admin_bind_address.is_some() { Surface::Website } else { Surface::All };
+    let public_app = build_app.clone();
+    let server = bind!(HttpServer::new(move || public_app(public_surface)), &config.bind_address);
+    println!("Listening on {}", config.bind_address);
+
+    let result = match &config.admin_bind_address {
+        Some(admin_address) => {
+            let admin_server = bind!(HttpServer::new(move || build_app(Surface::Backoffice)), admin_address);
+            println!("Serving the backoffice on {}", admin_address);
+            tokio::try_join!(server.run(), admin_server.run()).map(|_| ())
+        }
+        None => server.run().await,
+    };
+
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    if let Some(reporting) = reporting {
+        reporting.shutdown();
+    }
+    result
+}
+
+/// Implements `dothtml-backend backfill [name]`: lists the backfills with
+/// their progress, or runs the named one while printing its progress.
+async fn run_backfill(config: &AppConfig, name: Option<&str>) -> std::io::Result<()> {
+    let db = Database::new().await.map_err(std::io::Error::other)?;
+    db.create_backfills_table().await.map_err(std::io::Error::other)?;
+
+    let Some(name) = name else {
+        for progress in db.list_backfills().await.map_err(std::io::Error::other)? {
+            println!("{:<16} {:<12} {}", progress.name, progress.status, progress.description);
+        }
+        return Ok(());
+    };
+    let Some(backfill) = backfill::find(name) else {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Unknown backfill `{}`", name)));
+    };
+
+    let options = BackfillOptions {
+        batch_size: config.backfill_batch_size,
+        pause: Duration::from_millis(config.backfill_pause_ms),
+    };
+    let progress = db
+        .run_backfill(backfill, options, |progress| {
+            println!(
+                "{}: {} rows scanned, {}/{} updated",
+                progress.name, progress.rows_scanned, progress.rows_updated, progress.total_rows
+            );
+        })
         .await
+        .map_err(std::io::Error::other)?;
+    println!("{}: {}", progress.name, progress.status);
+    Ok(())
+}
+
+/// Implements `dothtml-backend restore-archive [id]`: lists the archives
+/// exported to the blob store, or restores the given one.
+async fn run_restore_archive(config: &AppConfig, id: Option<&str>) -> std::io::Result<()> {
+    let Some(store) = storage::from_config(config)? else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Archives are read from BLOB_STORE, which is unset"));
+    };
+    let db = Database::new().await
+        .map_err(std::io::Error::other)?
+        .with_blob_store(store, config.message_overflow_threshold_kb * 1024);
+    db.create_archives_table().await.map_err(std::io::Error::other)?;
+
+    let Some(id) = id else {
+        for archive in db.list_archives().await.map_err(std::io::Error::other)? {
+            println!(
+                "{}  {:>6} messages  {} to {}{}",
+                archive.id,
+                archive.message_count,
+                archive.first_created_at.date_naive(),
+                archive.last_created_at.date_naive(),
+                if archive.restored_at.is_some() { "  (restored)" } else { "" }
+            );
+        }
+        return Ok(());
+    };
+    let id = id.parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid archive ID `{}`", id)))?;
+
+    match db.restore_archive(id).await.map_err(std::io::Error::other)? {
+        Some(count) => println!("Restored {} messages from archive {}", count, id),
+        None => return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Unknown archive `{}`", id))),
+    }
+    Ok(())
+}
+
+/// Implements `dothtml-backend backup <file>` and `dothtml-backend restore
+/// <file> [--replace]`, printing the rows of each table.
+async fn run_backup(restore: bool, path: Option<String>, replace: bool) -> std::io::Result<()> {
+    let Some(path) = path else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing the path of the backup file"));
+    };
+    let db = Database::new().await.map_err(std::io::Error::other)?;
+
+    let counts = if restore {
+        let file = std::fs::File::open(&path)?;
+        db.restore(file, replace).await.map_err(std::io::Error::other)?
+    } else {
+        // Written next to the target, which is only replaced by a complete backup
+        let partial = format!("{}.partial", path);
+        let file = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+        match db.backup(file).await {
+            Ok(counts) => {
+                std::fs::rename(&partial, &path)?;
+                counts
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                return Err(std::io::Error::other(e));
+            }
+        }
+    };
+    for (table, rows) in counts {
+        println!("{:<20} {} rows", table, rows);
+    }
+    println!("{} {}", if restore { "Restored" } else { "Backed up to" }, path);
+    Ok(())
+}
+
+/// Implements `dothtml-backend indexes [--create]`: lists the state of the
+/// required indexes, then creates the missing ones when asked to.
+async fn run_indexes(create: bool) -> std::io::Result<()> {
+    let db = Database::new().await.map_err(std::io::Error::other)?;
+
+    for (index, state) in db.check_indexes().await.map_err(std::io::Error::other)? {
+        println!("{:<8} {:<36} {}", state, index.name, index.purpose);
+        if state != IndexState::Present && !create {
+            println!("         {}", index.create_statement());
+        }
+    }
+    if create {
+        for index in db.create_missing_indexes().await.map_err(std::io::Error::other)? {
+            println!("Created {}", index);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,208 @@
+//! # Pipeline Metrics
+//!
+//! End-to-end latency of the asynchronous pipelines, served by
+//! `GET /metrics` in the Prometheus text format so that alerts can prove
+//! the background jobs keep up:
+//!
+//! - `dothtml_webhook_delivery_seconds` - From the outbox entry being written, e.g. a message
+//!   being created, to its webhook delivery being accepted (see the `webhooks` module)
+//! - `dothtml_email_send_seconds` - From an email, e.g. a reply, being queued to the provider
+//!   accepting it, per `priority` (see the `mail_queue` module)
+//!
+//! Both are histograms, with a counter of the deliveries slower than their
+//! objective, `WEBHOOK_LATENCY_SLO_SECONDS` and `EMAIL_LATENCY_SLO_SECONDS`,
+//! next to a gauge of the objective itself. An alert on the rate of
+//! `dothtml_webhook_delivery_slo_violations_total` needs no bucket math.
+//!
+//! Metrics are counted by each replica since it started; Prometheus sums
+//! them across the replicas it scrapes.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::mail_queue::MailPriority;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 14] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0];
+
+/// A latency histogram with the [`LATENCY_BUCKETS`] buckets.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use dothtml_backend::metrics::Histogram;
+///
+/// let histogram = Histogram::default();
+/// histogram.observe(Duration::from_millis(300));
+/// histogram.observe(Duration::from_secs(45));
+///
+/// let mut text = String::new();
+/// histogram.render(&mut text, "latency_seconds", "");
+/// assert!(text.contains("latency_seconds_bucket{le=\"0.25\"} 0\n"));
+/// assert!(text.contains("latency_seconds_bucket{le=\"0.5\"} 1\n"));
+/// assert!(text.contains("latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+/// assert!(text.contains("latency_seconds_sum 45.3\n"));
+/// assert!(text.contains("latency_seconds_count 2\n"));
+/// ```
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    /// Counts one latency.
+    pub fn observe(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Writes the samples of the histogram to `out`, in the Prometheus text
+    /// format.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Text the samples are appended to
+    /// * `name` - Name of the metric
+    /// * `labels` - Labels of the samples, such as `priority="digest"`, or an empty string
+    pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let braced = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, count);
+        let _ = writeln!(out, "{}_sum{} {}", name, braced, self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{}_count{} {}", name, braced, count);
+    }
+}
+
+/// Latency of one pipeline, with its objective.
+#[derive(Debug)]
+struct Pipeline {
+    latency: Histogram,
+    violations: AtomicU64,
+    objective: Duration,
+}
+
+impl Pipeline {
+    fn new(objective: Duration) -> Self {
+        Pipeline { latency: Histogram::default(), violations: AtomicU64::new(0), objective }
+    }
+
+    fn observe(&self, latency: Duration) {
+        self.latency.observe(latency);
+        if latency > self.objective {
+            self.violations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pipelines {
+    webhook: Pipeline,
+    transactional_email: Pipeline,
+    digest_email: Pipeline,
+}
+
+/// Latency metrics of the asynchronous pipelines, shared by the jobs
+/// recording them and the `GET /metrics` handler.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use dothtml_backend::mail_queue::MailPriority;
+/// use dothtml_backend::metrics::PipelineMetrics;
+///
+/// let metrics = PipelineMetrics::new(Duration::from_secs(60), Duration::from_secs(30));
+/// metrics.observe_webhook(Duration::from_secs(2));
+/// metrics.observe_email(MailPriority::Transactional, Duration::from_secs(45));
+///
+/// let text = metrics.render();
+/// assert!(text.contains("dothtml_webhook_delivery_slo_violations_total 0\n"));
+/// assert!(text.contains("dothtml_email_send_slo_violations_total{priority=\"transactional\"} 1\n"));
+/// assert!(text.contains("dothtml_email_send_slo_seconds 30\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PipelineMetrics {
+    pipelines: Arc<Pipelines>,
+}
+
+impl PipelineMetrics {
+    /// Creates empty metrics with the latency objectives of the webhook
+    /// deliveries and the emails.
+    pub fn new(webhook_objective: Duration, email_objective: Duration) -> Self {
+        PipelineMetrics {
+            pipelines: Arc::new(Pipelines {
+                webhook: Pipeline::new(webhook_objective),
+                transactional_email: Pipeline::new(email_objective),
+                digest_email: Pipeline::new(email_objective),
+            }),
+        }
+    }
+
+    /// Records the time between an outbox entry being written and its
+    /// webhook delivery being accepted.
+    pub fn observe_webhook(&self, latency: Duration) {
+        self.pipelines.webhook.observe(latency);
+    }
+
+    /// Records the time between an email being queued and the provider
+    /// accepting it.
+    pub fn observe_email(&self, priority: MailPriority, latency: Duration) {
+        self.email(priority).observe(latency);
+    }
+
+    fn email(&self, priority: MailPriority) -> &Pipeline {
+        match priority {
+            MailPriority::Transactional => &self.pipelines.transactional_email,
+            MailPriority::Digest => &self.pipelines.digest_email,
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let webhook = &self.pipelines.webhook;
+
+        out.push_str("# HELP dothtml_webhook_delivery_seconds Time from an outbox entry being written to its webhook delivery being accepted.\n");
+        out.push_str("# TYPE dothtml_webhook_delivery_seconds histogram\n");
+        webhook.latency.render(&mut out, "dothtml_webhook_delivery_seconds", "");
+        out.push_str("# HELP dothtml_webhook_delivery_slo_violations_total Webhook deliveries slower than WEBHOOK_LATENCY_SLO_SECONDS.\n");
+        out.push_str("# TYPE dothtml_webhook_delivery_slo_violations_total counter\n");
+        let _ = writeln!(out, "dothtml_webhook_delivery_slo_violations_total {}", webhook.violations.load(Ordering::Relaxed));
+        out.push_str("# HELP dothtml_webhook_delivery_slo_seconds Latency objective of the webhook deliveries.\n");
+        out.push_str("# TYPE dothtml_webhook_delivery_slo_seconds gauge\n");
+        let _ = writeln!(out, "dothtml_webhook_delivery_slo_seconds {}", webhook.objective.as_secs_f64());
+
+        let priorities = [MailPriority::Transactional, MailPriority::Digest];
+        out.push_str("# HELP dothtml_email_send_seconds Time from an email being queued to the provider accepting it.\n");
+        out.push_str("# TYPE dothtml_email_send_seconds histogram\n");
+        for priority in priorities {
+            self.email(priority).latency.render(&mut out, "dothtml_email_send_seconds", &format!("priority=\"{}\"", priority));
+        }
+        out.push_str("# HELP dothtml_email_send_slo_violations_total Emails sent slower than EMAIL_LATENCY_SLO_SECONDS.\n");
+        out.push_str("# TYPE dothtml_email_send_slo_violations_total counter\n");
+        for priority in priorities {
+            let violations = self.email(priority).violations.load(Ordering::Relaxed);
+            let _ = writeln!(out, "dothtml_email_send_slo_violations_total{{priority=\"{}\"}} {}", priority, violations);
+        }
+        out.push_str("# HELP dothtml_email_send_slo_seconds Latency objective of the emails.\n");
+        out.push_str("# TYPE dothtml_email_send_slo_seconds gauge\n");
+        let _ = writeln!(out, "dothtml_email_send_slo_seconds {}", self.pipelines.transactional_email.objective.as_secs_f64());
+
+        out
+    }
+}
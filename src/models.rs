@@ -1,8 +1,15 @@
+use crate::acknowledgements::Acknowledgement;
 use crate::database::Database;
-use sqlx::Row;
+use crate::caching::{RESOURCE_COMPANIES, RESOURCE_TAGS};
+use crate::classification::MessageDraft;
+use crate::events::{MessageEvent, MessageEventKind};
+use crate::query::{FilterExpr, MessageSort};
+use sqlx::postgres::PgRow;
+use sqlx::{Postgres, QueryBuilder, Row};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 
 /// Represents a message in the system.
 /// 
@@ -20,25 +27,59 @@ use chrono::{DateTime, Utc};
 /// * `message` - The message content/body
 /// * `created_at` - Timestamp when the message was created
 /// * `assigned_to` - Optional field for the person assigned to handle the message
-/// * `status` - Current status of the message (e.g., "pending", "assigned", "resolved")
+/// * `assigned_at` - Timestamp of the current assignment
+/// * `status` - Current status of the message (e.g., "pending", "assigned", "resolved", "merged",
+///   "quarantine" for the first message of an unknown sender awaiting approval)
+/// * `priority` - Triage priority, one of `PRIORITIES` (default: "normal")
+/// * `merged_into` - ID of the message this one was merged into, if it was a duplicate
+/// * `resolved_at` - Timestamp when the message was resolved
+/// * `tags` - Labels attached to the message by agents
+/// * `company_id` - Company directory entry derived from `company`
+/// * `archived` - Whether the message is hidden from the default inbox listing
+/// * `deleted_at` - Timestamp when the message was moved to the trash
+/// * `opened_by` - Agent who first opened the message in the backoffice
+/// * `opened_at` - Timestamp when the message was first opened
+/// * `spam_score` - Probability from 0 to 1 that the message is spam, once the spam model is trained
+/// * `category` - Topic given by the classifier: sales, support, partnership, jobs or spam
+/// * `form` - Form the message was submitted through (default: "contact")
+/// * `body_ref` - Blob store key of the full body when it overflowed the table (not serialized)
 /// 
 /// # Examples
 /// 
-/// ```rust
+/// ```rust,no_run
 /// use dothtml_backend::models::Message;
 /// use uuid::Uuid;
 /// use chrono::Utc;
 /// 
 /// let message = Message {
 ///     id: Uuid::new_v4(),
-///     content: "Hello, world!".to_string(),
-///     sender_email: "user@example.com".to_string(),
+///     name: "John Doe".to_string(),
+///     email: "user@example.com".to_string(),
+///     country_region: "France".to_string(),
+///     phone_number: "+33612345678".to_string(),
+///     company: "ACME Corp".to_string(),
+///     message: "Hello, world!".to_string(),
 ///     created_at: Utc::now(),
 ///     assigned_to: None,
+///     assigned_at: None,
 ///     status: "pending".to_string(),
+///     priority: "normal".to_string(),
+///     merged_into: None,
+///     resolved_at: None,
+///     tags: vec![],
+///     company_id: None,
+///     archived: false,
+///     deleted_at: None,
+///     opened_by: None,
+///     opened_at: None,
+///     spam_score: None,
+///     category: None,
+///     form: "contact".to_string(),
+///     body_ref: None,
 /// };
 /// ```
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject), graphql(complex))]
 pub struct Message {
     pub id: Uuid,
 
@@ -49,9 +90,815 @@ pub struct Message {
     pub company: String,
     pub message: String,
 
+    #[serde(with = "crate::timestamp")]
     pub created_at: DateTime<Utc>,
     pub assigned_to: Option<String>,
+    #[serde(default, with = "crate::timestamp::option")]
+    pub assigned_at: Option<DateTime<Utc>>,
     pub status: String,
+    pub priority: String,
+    pub merged_into: Option<Uuid>,
+    #[serde(default, with = "crate::timestamp::option")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub company_id: Option<Uuid>,
+    pub archived: bool,
+    #[serde(default, with = "crate::timestamp::option")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub opened_by: Option<String>,
+    #[serde(default, with = "crate::timestamp::option")]
+    pub opened_at: Option<DateTime<Utc>>,
+    pub spam_score: Option<f64>,
+    pub category: Option<String>,
+    pub form: String,
+    #[serde(skip)]
+    #[cfg_attr(feature = "graphql", graphql(skip))]
+    pub body_ref: Option<String>,
+}
+
+/// Relation that can be embedded in a `MessageDetail`.
+///
+/// * `History` - Assignment history (`history`)
+/// * `Events` - Lifecycle events (`events`)
+/// * `Duplicates` - Messages merged into this one (`duplicates`)
+/// * `Sender` - Profile of the sender (`sender`)
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::models::MessageRelation;
+///
+/// let relations = MessageRelation::parse_list("history, events").unwrap();
+/// assert_eq!(relations, vec![MessageRelation::History, MessageRelation::Events]);
+/// assert!(MessageRelation::parse_list("attachments").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRelation {
+    History,
+    Events,
+    Duplicates,
+    Sender,
+}
+
+impl MessageRelation {
+    /// Names accepted by `parse_list`. `tags` is accepted too: tags are
+    /// part of every message.
+    pub const NAMES: [&'static str; 5] = ["history", "events", "duplicates", "sender", "tags"];
+
+    /// Parses a comma-separated list of relation names.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first name that is not a known relation.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, String> {
+        let mut relations = Vec::new();
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let relation = match name {
+                "history" => MessageRelation::History,
+                "events" => MessageRelation::Events,
+                "duplicates" => MessageRelation::Duplicates,
+                "sender" => MessageRelation::Sender,
+                "tags" => continue,
+                _ => return Err(name.to_string()),
+            };
+            if !relations.contains(&relation) {
+                relations.push(relation);
+            }
+        }
+        Ok(relations)
+    }
+}
+
+/// A message with the relations requested by the client.
+///
+/// Relations that were not requested are left out of the JSON rather than
+/// serialized as empty, so that clients can tell them apart.
+///
+/// # Fields
+///
+/// * `message` - The message itself, flattened into the response
+/// * `history` - Assignment history, oldest first
+/// * `events` - Lifecycle events, oldest first
+/// * `duplicates` - Messages merged into this one, oldest first
+/// * `sender` - Profile of the sender across all their messages
+#[derive(Debug, Serialize)]
+pub struct MessageDetail {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<AssignmentEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<MessageEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<Vec<Message>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<SenderProfile>,
+}
+
+/// Progress of a message as shown to its sender.
+///
+/// * `Received` - Nobody has looked at the message yet
+/// * `InProgress` - The message was opened or assigned
+/// * `Answered` - An agent replied, or the message is resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InquiryStatus {
+    Received,
+    InProgress,
+    Answered,
+}
+
+/// Answer to a status inquiry from the sender of a message.
+///
+/// # Fields
+///
+/// * `reference` - Reference of the message, as returned by `POST /contact`
+/// * `status` - Coarse progress of the message
+/// * `received_at` - Timestamp when the message was received
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusInquiry {
+    pub reference: Uuid,
+    pub status: InquiryStatus,
+    #[serde(with = "crate::timestamp")]
+    pub received_at: DateTime<Utc>,
+}
+
+/// Column list selected for every full `Message` row.
+pub(crate) const MESSAGE_COLUMNS: &str =
+    "id, name, email, country_region, phone_number, company, message, created_at, assigned_to, assigned_at, status, priority, merged_into, \
+     resolved_at, tags, company_id, archived, deleted_at, \
+     opened_by, opened_at, spam_score, category, form, body_ref";
+
+/// Maps a row selected with `MESSAGE_COLUMNS` to a `Message`.
+pub(crate) fn message_from_row(row: &PgRow) -> Message {
+    Message {
+        id: row.get("id"),
+        name: row.get("name"),
+        email: row.get("email"),
+        country_region: row.get("country_region"),
+        phone_number: row.get("phone_number"),
+        company: row.get("company"),
+        message: row.get("message"),
+        created_at: row.get("created_at"),
+        assigned_to: row.get("assigned_to"),
+        assigned_at: row.get("assigned_at"),
+        status: row.get("status"),
+        priority: row.get("priority"),
+        merged_into: row.get("merged_into"),
+        resolved_at: row.get("resolved_at"),
+        tags: row.get("tags"),
+        company_id: row.get("company_id"),
+        archived: row.get("archived"),
+        deleted_at: row.get("deleted_at"),
+        opened_by: row.get("opened_by"),
+        opened_at: row.get("opened_at"),
+        spam_score: row.get("spam_score"),
+        category: row.get("category"),
+        form: row.get("form"),
+        body_ref: row.get("body_ref"),
+    }
+}
+
+/// Reads the pagination key of an inbox row selected by `fetch_inbox_page`.
+fn inbox_keyset(row: &PgRow) -> SortKeyset {
+    (row.try_get("sort_rank").ok(), row.get("created_at"), row.get("id"))
+}
+
+/// Appends the `WHERE` clause shared by the inbox listing and its count.
+fn push_inbox_conditions(builder: &mut QueryBuilder<'_, Postgres>, options: &MessageListOptions) {
+    builder.push(" WHERE deleted_at IS NULL");
+    if !options.include_archived {
+        builder.push(" AND NOT archived");
+    }
+    if options.unopened {
+        builder.push(" AND opened_at IS NULL");
+    }
+    options.filter.push_conditions(builder);
+}
+
+/// Number of characters kept in PostgreSQL when a body overflows to the blob store.
+const OVERFLOW_PREVIEW_CHARS: usize = 500;
+
+/// Checks a delay in days or hours before it is bound as the `INTEGER`
+/// argument of `make_interval`, where a cast would wrap it into another
+/// delay, or a negative one into a date in the future.
+///
+/// # Errors
+///
+/// Returns `sqlx::Error::Configuration` naming `setting` when `value` is
+/// negative or does not fit an `INTEGER`.
+fn interval_units(setting: &str, value: i64) -> Result<i32, sqlx::Error> {
+    i32::try_from(value)
+        .ok()
+        .filter(|units| *units >= 0)
+        .ok_or_else(|| sqlx::Error::Configuration(format!("{} must be between 0 and {}, got {}", setting, i32::MAX, value).into()))
+}
+
+/// Aggregated view of everything a sender has submitted.
+///
+/// Built by `Database::sender_profile` from all the messages sharing the
+/// same email address (case-insensitive), excluding merged duplicates.
+///
+/// # Fields
+///
+/// * `email` - The email address the profile was requested for
+/// * `total_messages` - Number of messages received from this sender
+/// * `first_contact` - Creation date of the oldest message
+/// * `last_contact` - Creation date of the most recent message
+/// * `companies` - Distinct non-empty company names used by the sender
+/// * `average_response_time_seconds` - Mean time between creation and resolution,
+///   `None` until at least one message has been resolved
+/// * `tags` - Distinct tags attached to the sender's messages
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct SenderProfile {
+    pub email: String,
+    pub total_messages: i64,
+    #[serde(with = "crate::timestamp")]
+    pub first_contact: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub last_contact: DateTime<Utc>,
+    pub companies: Vec<String>,
+    pub average_response_time_seconds: Option<f64>,
+    pub tags: Vec<String>,
+}
+
+/// Result of an attempt to assign a message to an agent.
+///
+/// * `Assigned` - The message is now assigned to the agent
+/// * `CapReached` - The agent already holds `limit` assigned messages
+#[derive(Debug)]
+pub enum AssignmentOutcome {
+    Assigned(Box<Message>),
+    CapReached { limit: i64 },
+}
+
+/// Form of the messages submitted without naming one: the website's
+/// contact form.
+pub const DEFAULT_FORM: &str = "contact";
+
+/// Longest name of a form.
+pub const MAX_FORM_NAME_LENGTH: usize = 50;
+
+/// Returns whether `name` is a valid form name: 1 to
+/// `MAX_FORM_NAME_LENGTH` lowercase ASCII letters, digits, `_` or `-`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::models::is_valid_form_name;
+///
+/// assert!(is_valid_form_name("quote-request"));
+/// assert!(!is_valid_form_name("Quote Request"));
+/// ```
+pub fn is_valid_form_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_FORM_NAME_LENGTH
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+}
+
+/// A message to store, as submitted through a form.
+///
+/// # Fields
+///
+/// * `form` - Form the message was submitted through, see `is_valid_form_name`
+/// * `name` - The sender's name
+/// * `email` - The sender's email address
+/// * `country_region` - The sender's country/region
+/// * `phone_number` - The sender's phone number
+/// * `company` - The company associated with the sender
+/// * `message` - The message content/body
+#[derive(Debug, Clone, Copy)]
+pub struct NewMessage<'a> {
+    pub form: &'a str,
+    pub name: &'a str,
+    pub email: &'a str,
+    pub country_region: &'a str,
+    pub phone_number: &'a str,
+    pub company: &'a str,
+    pub message: &'a str,
+}
+
+/// Priorities a message can be given, from lowest to highest.
+pub const PRIORITIES: [&str; 4] = ["low", "normal", "high", "urgent"];
+
+/// Statuses a message can be moved to with `Database::patch_message`.
+/// Merging has its own operation.
+pub const PATCHABLE_STATUSES: [&str; 3] = ["pending", "assigned", "resolved"];
+
+/// Partial update of a message. Fields left to `None` are not changed.
+///
+/// # Fields
+///
+/// * `status` - New status, one of `PATCHABLE_STATUSES`
+/// * `priority` - New priority, one of `PRIORITIES`
+/// * `assigned_to` - New assignee, `Some(None)` to unassign the message
+/// * `tags` - New tags, replacing the current ones
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessagePatch {
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    pub assigned_to: Option<Option<String>>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Result of an attempt to merge a message into another one.
+///
+/// * `Merged` - The source now points to the target, which got its tags
+/// * `TargetNotFound` - The target does not exist or is in the trash
+/// * `TargetMerged` - The target has itself been merged into another message
+#[derive(Debug)]
+pub enum MergeOutcome {
+    Merged(Box<Message>),
+    TargetNotFound,
+    TargetMerged,
+}
+
+/// Result of an attempt to update a message.
+///
+/// * `Updated` - The changes were applied (or there was nothing to change)
+/// * `CapReached` - The new assignee already holds `limit` assigned messages
+/// * `Forbidden` - Changing `field` this way requires an admin
+/// * `Invalid` - The combination of changes is not allowed, with the reason
+#[derive(Debug)]
+pub enum PatchOutcome {
+    Updated(Box<Message>),
+    CapReached { limit: i64 },
+    Forbidden { field: &'static str },
+    Invalid(String),
+}
+
+/// An entry of a message's assignment history.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the message
+/// * `agent` - Agent the message was assigned to or taken from
+/// * `action` - One of "assigned", "claimed", "released" or "auto_released"
+/// * `created_at` - Timestamp of the change
+#[derive(Debug, Serialize)]
+pub struct AssignmentEvent {
+    pub message_id: Uuid,
+    pub agent: String,
+    pub action: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Builds an assignment history entry from a row of `assignment_history`.
+fn assignment_event_from_row(row: &PgRow) -> AssignmentEvent {
+    AssignmentEvent {
+        message_id: row.get("message_id"),
+        agent: row.get("agent"),
+        action: row.get("action"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Options for `Database::list_messages`.
+///
+/// # Fields
+///
+/// * `include_archived` - Also return archived messages
+/// * `unopened` - Only return messages nobody has opened yet
+/// * `filter` - Filter expression the messages must match
+/// * `limit` - Maximum number of messages per page
+/// * `cursor` - Position to continue from, taken from a previous page
+/// * `sort` - Order of the listing (default: newest first)
+#[derive(Debug, Clone)]
+pub struct MessageListOptions {
+    pub include_archived: bool,
+    pub unopened: bool,
+    pub filter: FilterExpr,
+    pub limit: i64,
+    pub cursor: Option<PageCursor>,
+    pub sort: MessageSort,
+}
+
+impl Default for MessageListOptions {
+    fn default() -> Self {
+        MessageListOptions {
+            include_archived: false,
+            unopened: false,
+            filter: FilterExpr::default(),
+            limit: DEFAULT_PAGE_SIZE,
+            cursor: None,
+            sort: MessageSort::default(),
+        }
+    }
+}
+
+/// Subset of the `Message` fields returned by a listing (sparse fieldset).
+///
+/// Only the selected columns are read from PostgreSQL, plus `id` and
+/// `created_at` which pagination needs, and only the selected fields are
+/// serialized.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::models::MessageFields;
+///
+/// let fields = MessageFields::parse("id,name, status").unwrap();
+/// assert_eq!(fields.names(), ["id", "name", "status"]);
+/// assert_eq!(fields.columns(), "id, created_at, name, status");
+/// assert!(MessageFields::parse("body_ref").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageFields {
+    names: Vec<&'static str>,
+}
+
+impl MessageFields {
+    /// Fields of a serialized `Message`, which are all a client can select.
+    pub const ALL: [&'static str; 23] = [
+        "id", "name", "email", "country_region", "phone_number", "company", "message", "created_at",
+        "assigned_to", "assigned_at", "status", "priority", "merged_into", "resolved_at", "tags",
+        "company_id", "archived", "deleted_at", "opened_by", "opened_at", "spam_score",
+        "category", "form",
+    ];
+
+    /// Parses a comma-separated list of field names, keeping their order.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first name that is not a field of `Message`, or an empty
+    /// string if the list is empty.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut names = Vec::new();
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match Self::ALL.iter().find(|field| **field == name) {
+                Some(field) if !names.contains(field) => names.push(*field),
+                Some(_) => {}
+                None => return Err(name.to_string()),
+            }
+        }
+        if names.is_empty() {
+            return Err(String::new());
+        }
+        Ok(MessageFields { names })
+    }
+
+    /// Returns the selected field names.
+    pub fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    /// Returns the column list to select: the pagination key, then the
+    /// selected fields.
+    pub fn columns(&self) -> String {
+        let mut columns = vec!["id", "created_at"];
+        columns.extend(self.names.iter().filter(|name| !matches!(**name, "id" | "created_at")));
+        columns.join(", ")
+    }
+
+    /// Builds the JSON object of a row selected with `columns`.
+    fn to_json(&self, row: &PgRow) -> Map<String, Value> {
+        self.names
+            .iter()
+            .map(|name| {
+                let value = match *name {
+                    "id" => json!(row.get::<Uuid, _>(*name)),
+                    "merged_into" | "company_id" => json!(row.get::<Option<Uuid>, _>(*name)),
+                    "created_at" => json!(crate::timestamp::format(&row.get(*name))),
+                    "assigned_at" | "resolved_at" | "deleted_at" | "opened_at" => {
+                        json!(row.get::<Option<DateTime<Utc>>, _>(*name).as_ref().map(crate::timestamp::format))
+                    }
+                    "assigned_to" | "opened_by" | "category" => json!(row.get::<Option<String>, _>(*name)),
+                    "tags" => json!(row.get::<Vec<String>, _>(*name)),
+                    "archived" => json!(row.get::<bool, _>(*name)),
+                    "spam_score" => json!(row.get::<Option<f64>, _>(*name)),
+                    _ => json!(row.get::<String, _>(*name)),
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+}
+
+/// A message restricted to a `MessageFields` selection, serialized as a
+/// JSON object holding only the selected fields.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct SparseMessage {
+    fields: Map<String, Value>,
+    #[serde(skip)]
+    key: SortKeyset,
+}
+
+/// Position of an item in a listing: its sort rank, if the listing is
+/// ranked, then its creation date and ID.
+pub type SortKeyset = (Option<i32>, DateTime<Utc>, Uuid);
+
+/// Number of items per page when the client does not ask for a size.
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// Largest page size a client can ask for.
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// A position in a listing ordered by creation date, newest first, or by a
+/// rank then creation date (see `query::MessageSort`).
+///
+/// Cursors point between two items: paging forward returns the items
+/// after `(rank, created_at, id)` in listing order, paging backward the
+/// ones before. They are handed to clients as opaque strings.
+///
+/// # Fields
+///
+/// * `rank` - Sort rank of the item at the page boundary, for ranked listings
+/// * `created_at` - Creation date of the item at the page boundary
+/// * `id` - ID of that item, breaking ties between equal dates
+/// * `backward` - Whether the cursor leads to the previous page
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::Utc;
+/// use dothtml_backend::models::PageCursor;
+/// use uuid::Uuid;
+///
+/// let cursor = PageCursor { rank: Some(3), created_at: Utc::now(), id: Uuid::new_v4(), backward: false };
+/// let decoded = PageCursor::decode(&cursor.encode()).unwrap();
+/// assert_eq!(decoded.id, cursor.id);
+/// assert_eq!(decoded.rank, Some(3));
+/// assert!(PageCursor::decode("garbage").is_none());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageCursor {
+    pub rank: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+    pub backward: bool,
+}
+
+impl PageCursor {
+    /// Encodes the cursor as the string sent to clients.
+    pub fn encode(&self) -> String {
+        let direction = if self.backward { 'p' } else { 'n' };
+        let rank = self.rank.map(|rank| format!("{}:", rank)).unwrap_or_default();
+        format!("{}{}{}_{}", direction, rank, self.created_at.timestamp_micros(), self.id)
+    }
+
+    /// Decodes a cursor produced by `encode`, returning `None` if it is malformed.
+    pub fn decode(value: &str) -> Option<Self> {
+        let backward = match value.chars().next()? {
+            'n' => false,
+            'p' => true,
+            _ => return None,
+        };
+        let (position, id) = value[1..].split_once('_')?;
+        let (rank, micros) = match position.split_once(':') {
+            Some((rank, micros)) => (Some(rank.parse().ok()?), micros),
+            None => (None, position),
+        };
+
+        Some(PageCursor {
+            rank,
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: id.parse().ok()?,
+            backward,
+        })
+    }
+}
+
+/// Pagination details of a `Paginated` response.
+///
+/// # Fields
+///
+/// * `next_cursor` - Cursor of the following page, if there is one
+/// * `prev_cursor` - Cursor of the preceding page, if there is one
+/// * `total_estimate` - Approximate number of items across all pages,
+///   `None` for a page past the end of a listing paged by offset
+#[derive(Debug, Serialize)]
+pub struct PageInfo {
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+    pub total_estimate: Option<i64>,
+}
+
+/// Page of a secondary listing asked for with `?limit=` and `?cursor=`.
+///
+/// The inbox pages with keyset cursors (see [`PageCursor`]). The other
+/// listings, ordered by various columns, page by offset: their cursors are
+/// `o` then the offset, and an item may show up twice when the listing
+/// changes between two pages.
+///
+/// # Fields
+///
+/// * `limit` - Maximum number of items per page
+/// * `offset` - Number of items before the page
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::models::{PageRequest, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+///
+/// let page = PageRequest::parse(None, None).unwrap();
+/// assert_eq!((page.limit, page.offset), (DEFAULT_PAGE_SIZE, 0));
+/// let page = PageRequest::parse(Some(1000), Some("o40")).unwrap();
+/// assert_eq!((page.limit, page.offset), (MAX_PAGE_SIZE, 40));
+/// assert!(PageRequest::parse(None, Some("n1705051800000000_x")).is_none());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest { limit: DEFAULT_PAGE_SIZE, offset: 0 }
+    }
+}
+
+impl PageRequest {
+    /// Reads the `limit` (default [`DEFAULT_PAGE_SIZE`], at most
+    /// [`MAX_PAGE_SIZE`]) and `cursor` query parameters, returning `None`
+    /// if the cursor is malformed.
+    pub fn parse(limit: Option<i64>, cursor: Option<&str>) -> Option<Self> {
+        let offset = match cursor {
+            Some(cursor) => cursor.strip_prefix('o')?.parse::<i64>().ok().filter(|offset| *offset >= 0)?,
+            None => 0,
+        };
+        Some(PageRequest { limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE), offset })
+    }
+
+    /// Returns the `LIMIT` of the query fetching the page: one more item
+    /// than the page holds, telling whether another page follows.
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Returns the number of items of the listing, given the `COUNT(*) OVER
+    /// ()` of the first row of the page if it has one.
+    ///
+    /// A page past the end has no row to count with: the listing may hold
+    /// any number of items up to the offset, so the total is unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::models::PageRequest;
+    ///
+    /// assert_eq!(PageRequest { limit: 20, offset: 40 }.total_estimate(Some(45)), Some(45));
+    /// assert_eq!(PageRequest { limit: 20, offset: 0 }.total_estimate(None), Some(0));
+    /// // Cursor past the end
+    /// assert_eq!(PageRequest { limit: 20, offset: 400 }.total_estimate(None), None);
+    /// ```
+    pub fn total_estimate(&self, counted: Option<i64>) -> Option<i64> {
+        match counted {
+            Some(total) => Some(total),
+            None if self.offset > 0 => None,
+            None => Some(0),
+        }
+    }
+}
+
+/// One page of a list response.
+///
+/// Every list endpoint whose items accumulate returns this envelope so that
+/// the backoffice can drive all listings with the same component:
+///
+/// ```json
+/// {
+///   "data": [...],
+///   "page": { "next_cursor": "n1705051800000000_...", "prev_cursor": null, "total_estimate": 120 }
+/// }
+/// ```
+///
+/// Listings of settings made by hand (webhooks, rules, abuse patterns,
+/// templates, flags, form origins and sources, backfills, tags) are not
+/// paginated and stay plain arrays.
+#[derive(Debug, Serialize)]
+pub struct Paginated<T> {
+    pub data: Vec<T>,
+    pub page: PageInfo,
+}
+
+impl<T> Paginated<T> {
+    /// Builds a page from rows fetched with a keyset query.
+    ///
+    /// `rows` must hold up to `limit + 1` items ordered away from `cursor`
+    /// (newest first when paging forward, oldest first when paging
+    /// backward); the extra row only tells whether another page exists.
+    /// `key` returns the `(rank, created_at, id)` the listing is ordered by.
+    pub fn from_keyset(
+        mut rows: Vec<T>,
+        limit: i64,
+        cursor: Option<&PageCursor>,
+        total_estimate: i64,
+        key: impl Fn(&T) -> SortKeyset,
+    ) -> Self {
+        let backward = cursor.is_some_and(|cursor| cursor.backward);
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit.max(0) as usize);
+        if backward {
+            rows.reverse();
+        }
+
+        let (has_next, has_prev) = if backward {
+            (true, has_more)
+        } else {
+            (has_more, cursor.is_some())
+        };
+        let boundary = |item: Option<&T>, backward| {
+            item.map(|item| {
+                let (rank, created_at, id) = key(item);
+                PageCursor { rank, created_at, id, backward }.encode()
+            })
+        };
+
+        Paginated {
+            page: PageInfo {
+                next_cursor: if has_next { boundary(rows.last(), false) } else { None },
+                prev_cursor: if has_prev { boundary(rows.first(), true) } else { None },
+                total_estimate: Some(total_estimate),
+            },
+            data: rows,
+        }
+    }
+
+    /// Builds a page from rows fetched with `LIMIT page.fetch_limit() OFFSET
+    /// page.offset`, given the number of items of the whole listing if it
+    /// is known (see [`PageRequest::total_estimate`]).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::models::{PageRequest, Paginated};
+    ///
+    /// let page = Paginated::from_offset(vec![1, 2, 3], &PageRequest { limit: 2, offset: 4 }, Some(9));
+    /// assert_eq!(page.data, vec![1, 2]);
+    /// assert_eq!(page.page.next_cursor.as_deref(), Some("o6"));
+    /// assert_eq!(page.page.prev_cursor.as_deref(), Some("o2"));
+    ///
+    /// // Cursor past the end
+    /// let request = PageRequest { limit: 2, offset: 40 };
+    /// let page = Paginated::<i32>::from_offset(vec![], &request, request.total_estimate(None));
+    /// assert!(page.data.is_empty());
+    /// assert_eq!(page.page.next_cursor, None);
+    /// assert_eq!(page.page.total_estimate, None);
+    /// ```
+    pub fn from_offset(mut rows: Vec<T>, page: &PageRequest, total_estimate: Option<i64>) -> Self {
+        let has_next = rows.len() as i64 > page.limit;
+        rows.truncate(page.limit.max(0) as usize);
+        Paginated {
+            page: PageInfo {
+                next_cursor: has_next.then(|| format!("o{}", page.offset + page.limit)),
+                prev_cursor: (page.offset > 0).then(|| format!("o{}", (page.offset - page.limit).max(0))),
+                total_estimate,
+            },
+            data: rows,
+        }
+    }
+
+    /// Builds a page from rows fetched for `page` with `COUNT(*) OVER () AS
+    /// total`, converting them with `item`.
+    pub(crate) fn from_rows(rows: &[PgRow], page: &PageRequest, item: impl Fn(&PgRow) -> T) -> Self {
+        let total = page.total_estimate(rows.first().map(|row| row.get("total")));
+        Self::from_offset(rows.iter().map(item).collect(), page, total)
+    }
+
+    /// Converts the items of the page, keeping its pagination details.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Paginated<U> {
+        Paginated { data: self.data.into_iter().map(f).collect(), page: self.page }
+    }
+}
+
+/// Inbox-wide counters for the backoffice dashboard.
+///
+/// # Fields
+///
+/// * `total` - Number of messages, excluding trashed ones
+/// * `quarantined` - Number of messages of unknown senders awaiting approval
+/// * `pending` - Number of pending messages
+/// * `assigned` - Number of assigned messages
+/// * `resolved` - Number of resolved messages
+/// * `never_opened` - Number of open messages nobody has looked at yet
+/// * `average_time_to_first_open_seconds` - Mean delay between reception and first open
+#[derive(Debug, Serialize)]
+pub struct InboxStats {
+    pub total: i64,
+    pub quarantined: i64,
+    pub pending: i64,
+    pub assigned: i64,
+    pub resolved: i64,
+    pub never_opened: i64,
+    pub average_time_to_first_open_seconds: Option<f64>,
+}
+
+/// Messages received and resolved on one calendar day.
+///
+/// # Fields
+///
+/// * `date` - Day in the time zone the statistics were computed for
+/// * `received` - Number of messages created that day, excluding trashed ones
+/// * `resolved` - Number of messages resolved that day, excluding trashed ones
+#[derive(Debug, Serialize)]
+pub struct DailyCount {
+    pub date: NaiveDate,
+    pub received: i64,
+    pub resolved: i64,
 }
 
 #[derive(Debug, Serialize)]
@@ -61,6 +908,62 @@ pub struct PendingMessage {
     pub name: String,
     pub email: String,
     pub message: String,
+    #[serde(with = "crate::timestamp::option")]
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+/// A company referenced by incoming messages.
+///
+/// Companies are derived from the free-text `company` field of messages:
+/// spellings that normalize to the same value (see `normalize_company_name`)
+/// share one company, and agents can merge the remaining duplicates by hand.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier for the company
+/// * `name` - Display name, taken from the first message that mentioned it
+/// * `created_at` - Timestamp when the company was first seen
+/// * `message_count` - Number of messages linked to the company
+#[derive(Debug, Serialize)]
+pub struct Company {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    pub message_count: i64,
+}
+
+/// Normalizes a free-text company name for matching.
+///
+/// The name is lowercased, punctuation is dropped, whitespace is collapsed
+/// and common legal suffixes ("Inc", "Ltd", "SAS", ...) are removed, so that
+/// "ACME Corp." and "acme" resolve to the same company.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::models::normalize_company_name;
+///
+/// assert_eq!(normalize_company_name("  ACME   Corp. "), "acme");
+/// assert_eq!(normalize_company_name("Dupont & Fils SAS"), "dupont fils");
+/// assert_eq!(normalize_company_name("Inc."), "inc");
+/// ```
+pub fn normalize_company_name(name: &str) -> String {
+    const LEGAL_SUFFIXES: [&str; 12] = [
+        "inc", "incorporated", "corp", "corporation", "co", "ltd", "llc", "gmbh", "sa", "sas", "sarl", "plc",
+    ];
+
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c.to_lowercase().next().unwrap_or(c) } else { ' ' })
+        .collect();
+    let mut words: Vec<&str> = cleaned.split_whitespace().collect();
+
+    while words.len() > 1 && LEGAL_SUFFIXES.contains(words.last().unwrap()) {
+        words.pop();
+    }
+
+    words.join(" ")
 }
 
 /// Database operations for the Message model.
@@ -89,7 +992,7 @@ impl Database {
     /// 
     /// # Examples
     /// 
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     /// 
     /// #[tokio::main]
@@ -147,49 +1050,166 @@ impl Database {
     /// 
     /// # Examples
     /// 
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     /// 
     /// #[tokio::main]
     /// async fn main() -> Result<(), sqlx::Error> {
     ///     let db = Database::new().await?;
     ///     let message = db.insert_message(
-    ///         "Hello, world!",
-    ///         "user@example.com"
+    ///         "John Doe",
+    ///         "user@example.com",
+    ///         "France",
+    ///         "+33612345678",
+    ///         "ACME Corp",
+    ///         "Hello, world!"
     ///     ).await?;
     ///     println!("Created message with ID: {}", message.id);
     ///     Ok(())
     /// }
     /// ```
+    #[tracing::instrument(skip_all)]
     pub async fn insert_message(
         &self, name: &str, email: &str, country_region: &str, phone_number: &str, company: &str, message: &str
     ) -> Result<Message, sqlx::Error> {
-        let row = sqlx::query(r#"
-            INSERT INTO messages (name, email, country_region, phone_number, company, message)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id, name, email, country_region, phone_number, company, message, created_at, assigned_to, status
-        "#)
-        .bind(name)
-        .bind(email)
-        .bind(country_region)
-        .bind(phone_number)
-        .bind(company)
-        .bind(message)
-        .fetch_one(&self.pool)
-        .await?;
-        
-        Ok(Message {
-            id: row.get("id"),
-            name: row.get("name"),
-            email: row.get("email"),
-            country_region: row.get("country_region"),
-            phone_number: row.get("phone_number"),
-            company: row.get("company"),
-            created_at: row.get("created_at"),
-            assigned_to: row.get("assigned_to"),
-            status: row.get("status"),
-            message: row.get("message")
-        })
+        self.insert_form_message(&NewMessage {
+            form: DEFAULT_FORM,
+            name,
+            email,
+            country_region,
+            phone_number,
+            company,
+            message,
+        }).await
+    }
+
+    /// Inserts a new message submitted through a form.
+    ///
+    /// The message is scored for spam, classified (see the `classification`
+    /// module) and routed by the rules (see the `rules` module) before it is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - The blob store fails to store an overflowing body
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::NewMessage;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let message = db.insert_form_message(&NewMessage {
+    ///         form: "quote-request",
+    ///         name: "John Doe",
+    ///         email: "user@example.com",
+    ///         country_region: "France",
+    ///         phone_number: "+33612345678",
+    ///         company: "ACME Corp",
+    ///         message: "Could you send us a quote?",
+    ///     }).await?;
+    ///     println!("Created message with ID: {}", message.id);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn insert_form_message(&self, new: &NewMessage<'_>) -> Result<Message, sqlx::Error> {
+        self.insert_new_message(new, None).await
+    }
+
+    /// Inserts a new message submitted through a form, like
+    /// [`Database::insert_form_message`], and queues the acknowledgement
+    /// email to its sender in the same transaction (see the
+    /// `acknowledgements` module).
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - The blob store fails to store an overflowing body
+    pub async fn insert_acknowledged_message(
+        &self, new: &NewMessage<'_>, acknowledgement: &Acknowledgement<'_>
+    ) -> Result<Message, sqlx::Error> {
+        self.insert_new_message(new, Some(acknowledgement)).await
+    }
+
+    #[tracing::instrument(skip_all, fields(form = new.form))]
+    async fn insert_new_message(
+        &self, new: &NewMessage<'_>, acknowledgement: Option<&Acknowledgement<'_>>
+    ) -> Result<Message, sqlx::Error> {
+        let NewMessage { form, name, email, country_region, phone_number, company, message } = *new;
+        let company_id = self.resolve_company(company).await?;
+        let spam_score = self.spam_score(name, email, company, message).await?;
+        let category = self.classify(&MessageDraft { name, email, company, message, spam_score }).await;
+
+        // Very long bodies go to the blob store, only a preview stays in the table
+        let (stored_body, body_ref) = match &self.blob_store {
+            Some(store) if message.len() > self.overflow_threshold => {
+                let key = format!("messages/{}", Uuid::new_v4());
+                store.put(&key, message.as_bytes().to_vec()).await.map_err(sqlx::Error::Io)?;
+                (message.chars().take(OVERFLOW_PREVIEW_CHARS).collect::<String>(), Some(key))
+            }
+            _ => (message.to_string(), None),
+        };
+
+        let result = async {
+            let mut tx = self.pool.begin().await?;
+
+            // With greylisting, senders without any message let into the inbox are quarantined
+            let row = sqlx::query(&format!(r#"
+                INSERT INTO messages (name, email, country_region, phone_number, company, message, company_id, body_ref, spam_score, category, form, status)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $10, $11, $12, CASE
+                    WHEN $9 AND NOT EXISTS (
+                        SELECT 1 FROM messages
+                        WHERE lower(email) = lower($2) AND status <> 'quarantine' AND deleted_at IS NULL
+                    ) THEN 'quarantine'
+                    ELSE 'pending'
+                END)
+                RETURNING {MESSAGE_COLUMNS}
+            "#))
+            .bind(name)
+            .bind(email)
+            .bind(country_region)
+            .bind(phone_number)
+            .bind(company)
+            .bind(&stored_body)
+            .bind(company_id)
+            .bind(&body_ref)
+            .bind(self.greylisting)
+            .bind(spam_score)
+            .bind(category.map(|category| category.as_str()))
+            .bind(form)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            // With the full body, which the rules match keywords against
+            let mut created = message_from_row(&row);
+            created.message = message.to_string();
+
+            Self::record_event(&mut tx, created.id, MessageEventKind::Created, json!({
+                "email": email,
+                "company": company,
+            })).await?;
+            if company_id.is_some() {
+                Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+            }
+            Self::apply_rules(&mut tx, &mut created).await?;
+            if let Some(acknowledgement) = acknowledgement {
+                Self::enqueue_acknowledgement(&mut tx, &created, acknowledgement).await?;
+            }
+
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(created)
+        }.await;
+
+        if result.is_err() {
+            self.delete_blobs(body_ref.as_slice()).await;
+        }
+        result
     }
     
     /// Retrieves 20 pending messages from the database.
@@ -211,7 +1231,7 @@ impl Database {
     /// 
     /// # Examples
     /// 
-    /// ```rust
+    /// ```rust,no_run
     /// use dothtml_backend::database::Database;
     /// 
     /// #[tokio::main]
@@ -227,9 +1247,9 @@ impl Database {
     /// ```
     pub async fn list_pending_messages(&self) -> Result<Vec<PendingMessage>, sqlx::Error> {
         let mut rows = sqlx::query(r#"
-            SELECT id, name, email, message
+            SELECT id, name, email, message, opened_at
             FROM messages
-            WHERE status = 'pending'
+            WHERE status = 'pending' AND deleted_at IS NULL
             ORDER BY created_at DESC
             LIMIT 20
         "#)
@@ -238,7 +1258,7 @@ impl Database {
 
         // Random shuffle of results
         use rand::seq::SliceRandom;
-        let mut rng = rand::thread_rng();
+        let mut rng = rand::rng();
         rows.shuffle(&mut rng);
 
         let messages = rows.into_iter().map(|row| PendingMessage {
@@ -246,33 +1266,2314 @@ impl Database {
             name: row.get("name"),
             email: row.get("email"),
             message: row.get("message"),
+            opened_at: row.get("opened_at"),
         }).collect();
         
         Ok(messages)
     }
 
-    pub async fn get_message_by_id(&self, id: Uuid) -> Result<Message, sqlx::Error> {
-        let row = sqlx::query(r#"
-            SELECT id, name, email, country_region, phone_number, company, message, created_at, assigned_to, status
-            FROM messages
-            WHERE id = $1
-        "#)
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+    /// Retrieves one page of the inbox listing, newest first.
+    ///
+    /// Archived messages are left out unless `options.include_archived`
+    /// is set, and `options.filter` narrows the listing down further (see
+    /// the `query` module). `options.sort` sets the order, newest first by
+    /// default. Pages are delimited by keyset cursors on `(created_at, id)`,
+    /// preceded by the sort rank for priority and status orders, so messages
+    /// arriving while the client pages through the inbox do not shift the
+    /// following pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Listing options, including the page size and cursor
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the requested page on success,
+    /// or a `sqlx::Error` on failure.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Query execution fails
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::MessageListOptions;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let page = db.list_messages(&MessageListOptions::default()).await?;
+    ///     println!("{} of about {} messages", page.data.len(), page.page.total_estimate.unwrap_or_default());
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all, fields(filter = options.filter.terms().len(), limit = options.limit))]
+    pub async fn list_messages(&self, options: &MessageListOptions) -> Result<Paginated<Message>, sqlx::Error> {
+        let (rows, total) = self.fetch_inbox_page(options, MESSAGE_COLUMNS).await?;
 
-        Ok(Message {
-            id: row.get("id"),
-            name: row.get("name"),
-            email: row.get("email"),
-            country_region: row.get("country_region"),
-            phone_number: row.get("phone_number"),
-            company: row.get("company"),
-            message: row.get("message"),
-            created_at: row.get("created_at"),
-            assigned_to: row.get("assigned_to"),
-            status: row.get("status"),
-        })
+        let messages = rows.iter().map(|row| (message_from_row(row), inbox_keyset(row))).collect();
+        let page = Paginated::from_keyset(messages, options.limit, options.cursor.as_ref(), total, |(_, key)| *key);
+        Ok(page.map(|(message, _)| message))
     }
 
+    /// Retrieves one page of the inbox listing like `list_messages`, with
+    /// only some fields of each message.
+    ///
+    /// Only the columns backing the selected fields (and the pagination
+    /// key) are read, which keeps large listings and exports light.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Listing options, including the page size and cursor
+    /// * `fields` - Fields to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::{MessageFields, MessageListOptions};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let fields = MessageFields::parse("id,name,status").unwrap();
+    ///     let page = db.list_message_fields(&MessageListOptions::default(), &fields).await?;
+    ///     println!("{}", serde_json::to_string(&page).unwrap());
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all, fields(filter = options.filter.terms().len(), limit = options.limit))]
+    pub async fn list_message_fields(
+        &self,
+        options: &MessageListOptions,
+        fields: &MessageFields,
+    ) -> Result<Paginated<SparseMessage>, sqlx::Error> {
+        let (rows, total) = self.fetch_inbox_page(options, &fields.columns()).await?;
+
+        let messages = rows
+            .iter()
+            .map(|row| SparseMessage { fields: fields.to_json(row), key: inbox_keyset(row) })
+            .collect();
+        Ok(Paginated::from_keyset(messages, options.limit, options.cursor.as_ref(), total, |message: &SparseMessage| {
+            message.key
+        }))
+    }
+
+    /// Runs the keyset query of an inbox page, selecting `columns`.
+    ///
+    /// Returns up to `options.limit + 1` rows ordered away from the cursor,
+    /// as `Paginated::from_keyset` expects, and the total count.
+    async fn fetch_inbox_page(&self, options: &MessageListOptions, columns: &str) -> Result<(Vec<PgRow>, i64), sqlx::Error> {
+        let cursor = options.cursor.as_ref();
+        let rank = options.sort.rank_sql();
+
+        let mut query = match &rank {
+            Some(rank) => QueryBuilder::new(format!("SELECT {columns}, {rank} AS sort_rank FROM messages")),
+            None => QueryBuilder::new(format!("SELECT {columns} FROM messages")),
+        };
+        push_inbox_conditions(&mut query, options);
+
+        // Paging backward walks the listing in reverse, from the cursor
+        let descending = options.sort.descending != cursor.is_some_and(|cursor| cursor.backward);
+        let order = if descending { "DESC" } else { "ASC" };
+        if let Some(cursor) = cursor {
+            query.push(" AND (");
+            if let Some(rank) = &rank {
+                query.push(format!("{rank}, "));
+            }
+            query.push(if descending { "created_at, id) < (" } else { "created_at, id) > (" });
+            if rank.is_some() {
+                query.push_bind(cursor.rank.unwrap_or_default()).push(", ");
+            }
+            query.push_bind(cursor.created_at).push(", ").push_bind(cursor.id).push(")");
+        }
+        query.push(" ORDER BY ");
+        if let Some(rank) = &rank {
+            query.push(format!("{rank} {order}, "));
+        }
+        query
+            .push(format!("created_at {order}, id {order} LIMIT "))
+            .push_bind(options.limit + 1);
+        let rows = query.build().fetch_all(&self.pool).await?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) AS total FROM messages");
+        push_inbox_conditions(&mut count, options);
+        let total: i64 = count.build().fetch_one(&self.pool).await?.get("total");
+
+        Ok((rows, total))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_message_by_id(&self, id: Uuid) -> Result<Message, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE id = $1
+        "#))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let mut message = message_from_row(&row);
+        self.load_overflowed_body(&mut message).await?;
+        Ok(message)
+    }
+
+    /// Checks whether a message exists, trashed or not.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     println!("Exists: {}", db.message_exists(id).await?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn message_exists(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM messages WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Returns the progress of a message for its sender.
+    ///
+    /// A message merged into another one reports the progress of the
+    /// message it was merged into.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message, its reference for the sender
+    /// * `email` - Email the sender gives, compared case-insensitively
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` when the message doesn't exist, is in the trash or
+    /// was sent from another email, without telling these cases apart.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     if let Some(inquiry) = db.status_inquiry(id, "john@example.com").await? {
+    ///         println!("{:?}", inquiry.status);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn status_inquiry(&self, id: Uuid, email: &str) -> Result<Option<StatusInquiry>, sqlx::Error> {
+        let row = sqlx::query(r#"
+            WITH RECURSIVE chain AS (
+                SELECT id, merged_into, status, opened_at, created_at, 0 AS depth
+                FROM messages
+                WHERE id = $1 AND deleted_at IS NULL AND lower(email) = lower(trim($2))
+                UNION ALL
+                SELECT m.id, m.merged_into, m.status, m.opened_at, m.created_at, c.depth + 1
+                FROM messages m
+                JOIN chain c ON m.id = c.merged_into
+                WHERE c.depth < 16
+            )
+            SELECT
+                (SELECT created_at FROM chain WHERE depth = 0) AS received_at,
+                last.status,
+                last.opened_at IS NOT NULL AS opened,
+                EXISTS (
+                    SELECT 1 FROM message_events
+                    WHERE message_id IN (SELECT id FROM chain) AND event_type = 'replied'
+                ) AS replied
+            FROM (SELECT status, opened_at FROM chain ORDER BY depth DESC LIMIT 1) AS last
+        "#)
+        .bind(id)
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            let status: String = row.get("status");
+            let status = if row.get("replied") || status == "resolved" {
+                InquiryStatus::Answered
+            } else if status == "quarantine" || (status == "pending" && !row.get::<bool, _>("opened")) {
+                InquiryStatus::Received
+            } else {
+                InquiryStatus::InProgress
+            };
+            StatusInquiry { reference: id, status, received_at: row.get("received_at") }
+        }))
+    }
+
+    /// Retrieves a message with the requested relations.
+    ///
+    /// The message is loaded first, then each relation with one query, all
+    /// of them concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `relations` - Relations to embed
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::MessageRelation;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let detail = db.get_message_detail(id, &[MessageRelation::History]).await?;
+    ///     println!("{} assignments", detail.history.unwrap_or_default().len());
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip(self))]
+    pub async fn get_message_detail(&self, id: Uuid, relations: &[MessageRelation]) -> Result<MessageDetail, sqlx::Error> {
+        let message = self.get_message_by_id(id).await?;
+        let wants = |relation| relations.contains(&relation);
+
+        let (history, events, duplicates, sender) = tokio::try_join!(
+            async { if wants(MessageRelation::History) { self.list_assignment_history(id).await.map(Some) } else { Ok(None) } },
+            async { if wants(MessageRelation::Events) { self.list_message_events(id).await.map(Some) } else { Ok(None) } },
+            async { if wants(MessageRelation::Duplicates) { self.list_merged_messages(&[id]).await.map(Some) } else { Ok(None) } },
+            async { if wants(MessageRelation::Sender) { self.sender_profile(&message.email).await.map(Some) } else { Ok(None) } },
+        )?;
+
+        Ok(MessageDetail { message, history, events, duplicates, sender })
+    }
+
+    /// Retrieves several messages at once, in no particular order.
+    ///
+    /// IDs that do not match any message, or match a message in the trash,
+    /// are skipped, so the result may be shorter than `ids`. Used to batch
+    /// lookups, e.g. by the GraphQL API.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let ids = [Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap()];
+    ///     let messages = db.get_messages_by_ids(&ids).await?;
+    ///     println!("Found {} messages", messages.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn get_messages_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Message>, sqlx::Error> {
+        let rows = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE id = ANY($1) AND deleted_at IS NULL
+        "#))
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+        for message in &mut messages {
+            self.load_overflowed_body(message).await?;
+        }
+        Ok(messages)
+    }
+
+    /// Retrieves the duplicates merged into any of the given messages,
+    /// oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - IDs of the messages heading the threads
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let ids = [Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap()];
+    ///     let duplicates = db.list_merged_messages(&ids).await?;
+    ///     println!("{} duplicates", duplicates.len());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_merged_messages(&self, ids: &[Uuid]) -> Result<Vec<Message>, sqlx::Error> {
+        let rows = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE merged_into = ANY($1) AND deleted_at IS NULL
+            ORDER BY created_at
+        "#))
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    /// Replaces the preview of an overflowed message with its full body.
+    async fn load_overflowed_body(&self, message: &mut Message) -> Result<(), sqlx::Error> {
+        if let (Some(store), Some(key)) = (&self.blob_store, &message.body_ref) {
+            let body = store.get(key).await.map_err(sqlx::Error::Io)?;
+            message.message = String::from_utf8_lossy(&body).into_owned();
+        }
+        Ok(())
+    }
+
+    /// Applies additive schema changes to the 'messages' table.
+    ///
+    /// `create_messages_table` only describes the original schema, so columns
+    /// and indexes added afterwards are created here with `IF NOT EXISTS`
+    /// guards. It is safe to run on every startup.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for altering the table
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.upgrade_messages_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn upgrade_messages_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            ALTER TABLE messages
+                ADD COLUMN IF NOT EXISTS merged_into UUID REFERENCES messages(id),
+                ADD COLUMN IF NOT EXISTS resolved_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}',
+                ADD COLUMN IF NOT EXISTS company_id UUID REFERENCES companies(id) ON DELETE SET NULL,
+                ADD COLUMN IF NOT EXISTS archived BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS unarchived_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS assigned_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS opened_by TEXT,
+                ADD COLUMN IF NOT EXISTS opened_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS last_activity_at TIMESTAMPTZ,
+                ADD COLUMN IF NOT EXISTS body_ref TEXT,
+                ADD COLUMN IF NOT EXISTS spam_score DOUBLE PRECISION,
+                ADD COLUMN IF NOT EXISTS category TEXT,
+                ADD COLUMN IF NOT EXISTS form TEXT NOT NULL DEFAULT 'contact',
+                ADD COLUMN IF NOT EXISTS priority TEXT NOT NULL DEFAULT 'normal';
+
+            CREATE TABLE IF NOT EXISTS assignment_history (
+                id BIGSERIAL PRIMARY KEY,
+                message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+                agent TEXT NOT NULL,
+                action TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            CREATE INDEX IF NOT EXISTS assignment_history_message_id_idx ON assignment_history (message_id);
+
+            CREATE INDEX IF NOT EXISTS messages_lower_email_idx ON messages (lower(email));
+            CREATE INDEX IF NOT EXISTS messages_company_id_idx ON messages (company_id);
+            CREATE INDEX IF NOT EXISTS messages_pending_created_at_idx ON messages (created_at) WHERE status = 'pending';
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Merges a duplicate message into another one.
+    ///
+    /// The source message is marked as `merged` and points to the target
+    /// through `merged_into`, and its tags are added to the target's.
+    /// Messages previously merged into the source are re-pointed to the
+    /// target so that merge chains stay one level deep.
+    ///
+    /// Both messages are locked while the target is checked, so that two
+    /// merges of the same messages in opposite directions cannot make a
+    /// cycle: the second one finds its target merged.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_id` - ID of the duplicate message
+    /// * `target_id` - ID of the message that is kept
+    ///
+    /// # Returns
+    ///
+    /// Returns the `MergeOutcome` on success, with the updated source
+    /// `Message` when it was merged.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The source message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::MergeOutcome;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let source = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let target = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174001").unwrap();
+    ///     if let MergeOutcome::Merged(merged) = db.merge_message(source, target).await? {
+    ///         println!("Merged into {:?}", merged.merged_into);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn merge_message(&self, source_id: Uuid, target_id: Uuid) -> Result<MergeOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock both messages in a fixed order, so that concurrent merges cannot deadlock
+        let rows = sqlx::query(r#"
+            SELECT id, status, tags, merged_into, deleted_at IS NOT NULL AS deleted
+            FROM messages
+            WHERE id = ANY($1)
+            ORDER BY id
+            FOR UPDATE
+        "#)
+        .bind([source_id, target_id])
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let find = |id: Uuid| rows.iter().find(|row| row.get::<Uuid, _>("id") == id);
+        let source = find(source_id).ok_or(sqlx::Error::RowNotFound)?;
+        let Some(target) = find(target_id).filter(|target| !target.get::<bool, _>("deleted")) else {
+            return Ok(MergeOutcome::TargetNotFound);
+        };
+        if target.get::<Option<Uuid>, _>("merged_into").is_some() {
+            return Ok(MergeOutcome::TargetMerged);
+        }
+        let previous: String = source.get("status");
+        let tags: Vec<String> = source.get("tags");
+
+        sqlx::query("UPDATE messages SET merged_into = $2 WHERE merged_into = $1")
+            .bind(source_id)
+            .bind(target_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET merged_into = $2, status = 'merged'
+            WHERE id = $1
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(source_id)
+        .bind(target_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !tags.is_empty() {
+            sqlx::query(r#"
+                UPDATE messages
+                SET tags = tags || ARRAY(SELECT DISTINCT tag FROM unnest($2::TEXT[]) AS tag WHERE tag <> ALL(tags))
+                WHERE id = $1
+            "#)
+            .bind(target_id)
+            .bind(&tags)
+            .execute(&mut *tx)
+            .await?;
+            Self::touch_resource(&mut *tx, RESOURCE_TAGS).await?;
+        }
+
+        Self::record_event(&mut tx, source_id, MessageEventKind::StatusChanged, json!({
+            "from": previous,
+            "to": "merged",
+            "merged_into": target_id,
+        })).await?;
+
+        tx.commit().await?;
+
+        Ok(MergeOutcome::Merged(Box::new(message_from_row(&row))))
+    }
+
+    /// Adds information sent by the sender of a message to its thread.
+    ///
+    /// The information is stored as a new message from the same sender,
+    /// merged into the head of the thread (the message itself, or the one it
+    /// was merged into), so the backoffice shows it with the duplicates.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message the sender follows up on
+    /// * `text` - Information added by the sender
+    ///
+    /// # Returns
+    ///
+    /// Returns the new `Message` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message or the head of its thread does not exist or is in the
+    ///   trash (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let followup = db.add_followup(id, "My order number is 4521").await?;
+    ///     println!("Added to the thread of {:?}", followup.merged_into);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn add_followup(&self, id: Uuid, text: &str) -> Result<Message, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO messages (name, email, country_region, phone_number, company, message, company_id, status, merged_into)
+            SELECT m.name, m.email, m.country_region, m.phone_number, m.company, $2, m.company_id, 'merged', head.id
+            FROM messages m
+            JOIN messages head ON head.id = COALESCE(m.merged_into, m.id)
+            WHERE m.id = $1 AND m.deleted_at IS NULL AND head.deleted_at IS NULL
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(text)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let followup = message_from_row(&row);
+        Self::record_event(&mut tx, followup.id, MessageEventKind::Created, json!({
+            "email": followup.email,
+            "company": followup.company,
+            "followup_of": followup.merged_into,
+        })).await?;
+
+        tx.commit().await?;
+
+        Ok(followup)
+    }
+
+    /// Retrieves a page of the other messages sent from the same email address.
+    ///
+    /// Email addresses are compared case-insensitively, which is backed by
+    /// the `messages_lower_email_idx` index. Results are ordered from the
+    /// most recent to the oldest and never include the reference message.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the reference message
+    /// * `same_company` - Only keep messages that also share the reference company
+    /// * `page` - Page of the listing to return
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the page of related messages on
+    /// success, or a `sqlx::Error` on failure.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The reference message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::PageRequest;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let related = db.list_related_messages(id, false, &PageRequest::default()).await?;
+    ///     println!("{} other messages from this sender", related.page.total_estimate.unwrap_or_default());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_related_messages(
+        &self, id: Uuid, same_company: bool, page: &PageRequest
+    ) -> Result<Paginated<Message>, sqlx::Error> {
+        let reference = self.get_message_by_id(id).await?;
+
+        let rows = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}, COUNT(*) OVER () AS total
+            FROM messages
+            WHERE lower(email) = lower($1)
+              AND id <> $2
+              AND deleted_at IS NULL
+              AND (NOT $3 OR lower(company) = lower($4))
+            ORDER BY created_at DESC, id
+            LIMIT $5 OFFSET $6
+        "#))
+        .bind(&reference.email)
+        .bind(id)
+        .bind(same_company)
+        .bind(&reference.company)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, message_from_row))
+    }
+
+    /// Builds the aggregated profile of a sender.
+    ///
+    /// All messages sent from `email` (compared case-insensitively) are
+    /// aggregated, except duplicates that were merged into another message.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - Email address of the sender
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `SenderProfile` on success,
+    /// or a `sqlx::Error` on failure.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - No message was received from this address (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let profile = db.sender_profile("john@example.com").await?;
+    ///     println!("{} messages since {}", profile.total_messages, profile.first_contact);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all)]
+    pub async fn sender_profile(&self, email: &str) -> Result<SenderProfile, sqlx::Error> {
+        let profile = self.sender_profiles(&[email.to_string()]).await?.into_iter().next();
+        let profile = profile.ok_or(sqlx::Error::RowNotFound)?;
+        Ok(SenderProfile { email: email.to_string(), ..profile })
+    }
+
+    /// Builds the profiles of several senders in one query, in no
+    /// particular order.
+    ///
+    /// Addresses are compared case-insensitively and the profiles carry
+    /// them in lowercase. Addresses without any message are skipped, so the
+    /// result may be shorter than `emails`. Used to batch lookups, e.g. by
+    /// the GraphQL API.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let emails = ["john@example.com".to_string(), "jane@example.com".to_string()];
+    ///     for profile in db.sender_profiles(&emails).await? {
+    ///         println!("{}: {} messages", profile.email, profile.total_messages);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all, fields(count = emails.len()))]
+    pub async fn sender_profiles(&self, emails: &[String]) -> Result<Vec<SenderProfile>, sqlx::Error> {
+        let emails: Vec<String> = emails.iter().map(|email| email.to_lowercase()).collect();
+        let rows = sqlx::query(r#"
+            WITH sent AS (
+                SELECT lower(email) AS email, created_at, company, resolved_at, tags
+                FROM messages
+                WHERE lower(email) = ANY($1) AND merged_into IS NULL AND deleted_at IS NULL
+            )
+            SELECT
+                sender.email,
+                COUNT(*) AS total_messages,
+                MIN(created_at) AS first_contact,
+                MAX(created_at) AS last_contact,
+                COALESCE(array_agg(DISTINCT company) FILTER (WHERE company <> ''), '{}') AS companies,
+                EXTRACT(EPOCH FROM AVG(resolved_at - created_at))::FLOAT8 AS average_response_time_seconds,
+                COALESCE((
+                    SELECT array_agg(DISTINCT tag ORDER BY tag)
+                    FROM sent, unnest(sent.tags) AS tag
+                    WHERE sent.email = sender.email
+                ), '{}') AS tags
+            FROM sent AS sender
+            GROUP BY sender.email
+        "#)
+        .bind(&emails)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| SenderProfile {
+            email: row.get("email"),
+            total_messages: row.get("total_messages"),
+            first_contact: row.get("first_contact"),
+            last_contact: row.get("last_contact"),
+            companies: row.get("companies"),
+            average_response_time_seconds: row.get("average_response_time_seconds"),
+            tags: row.get("tags"),
+        }).collect())
+    }
+
+    /// Lists the distinct tags used by messages that are not in the trash,
+    /// sorted alphabetically.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let tags = db.list_tags().await?;
+    ///     println!("Tags: {}", tags.join(", "));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_tags(&self) -> Result<Vec<String>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT DISTINCT tag
+            FROM messages, unnest(tags) AS tag
+            WHERE deleted_at IS NULL
+            ORDER BY tag
+        "#)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| row.get("tag")).collect())
+    }
+
+    /// Archives messages that have been resolved for more than `after_days` days.
+    ///
+    /// Messages without a resolution timestamp fall back to their creation
+    /// date. Messages that were unarchived by hand get another `after_days`
+    /// days before being archived again.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_days` - Number of days after resolution before archiving
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of archived messages.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - `after_days` is negative or too large (`sqlx::Error::Configuration`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let archived = db.archive_resolved_messages(30).await?;
+    ///     println!("Archived {} messages", archived);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn archive_resolved_messages(&self, after_days: i64) -> Result<u64, sqlx::Error> {
+        let after_days = interval_units("ARCHIVE_RESOLVED_AFTER_DAYS", after_days)?;
+        let result = sqlx::query(r#"
+            UPDATE messages
+            SET archived = TRUE
+            WHERE status = 'resolved'
+              AND NOT archived
+              AND COALESCE(resolved_at, created_at) < NOW() - make_interval(days => $1)
+              AND (unarchived_at IS NULL OR unarchived_at < NOW() - make_interval(days => $1))
+        "#)
+        .bind(after_days)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Brings an archived message back into the default inbox listing.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    ///
+    /// # Returns
+    ///
+    /// Returns the updated `Message` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let message = db.unarchive_message(id).await?;
+    ///     assert!(!message.archived);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn unarchive_message(&self, id: Uuid) -> Result<Message, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET archived = FALSE, unarchived_at = NOW()
+            WHERE id = $1
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(message_from_row(&row))
+    }
+
+    /// Moves a message to the trash.
+    ///
+    /// Trashed messages disappear from every listing but are kept until
+    /// they are purged, either by hand or by the trash retention job.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist or is already trashed (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     db.trash_message(id).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn trash_message(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("UPDATE messages SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        Self::record_event(&mut tx, id, MessageEventKind::Deleted, json!({ "permanent": false })).await?;
+        Self::touch_resource(&mut *tx, RESOURCE_TAGS).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Retrieves a page of the messages currently in the trash, most
+    /// recently deleted first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::PageRequest;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let trash = db.list_trashed_messages(&PageRequest::default()).await?;
+    ///     println!("{} messages in the trash", trash.page.total_estimate.unwrap_or_default());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_trashed_messages(&self, page: &PageRequest) -> Result<Paginated<Message>, sqlx::Error> {
+        let rows = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}, COUNT(*) OVER () AS total
+            FROM messages
+            WHERE deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, id
+            LIMIT $1 OFFSET $2
+        "#))
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, message_from_row))
+    }
+
+    /// Permanently removes a trashed message.
+    ///
+    /// Only messages already in the trash can be purged. Duplicates that
+    /// were merged into the purged message lose their link to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the trashed message
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message is not in the trash (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     db.purge_message(id).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn purge_message(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE messages SET merged_into = NULL WHERE merged_into = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        let row = sqlx::query("DELETE FROM messages WHERE id = $1 AND deleted_at IS NOT NULL RETURNING body_ref")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        Self::record_event(&mut tx, id, MessageEventKind::Deleted, json!({ "permanent": true })).await?;
+        Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        tx.commit().await?;
+
+        let body_ref: Option<String> = row.get("body_ref");
+        self.delete_blobs(body_ref.as_slice()).await;
+        Ok(())
+    }
+
+    /// Permanently removes messages that have been in the trash for more than `after_days` days.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_days` - Number of days a message stays in the trash
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of purged messages.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - `after_days` is negative or too large (`sqlx::Error::Configuration`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let purged = db.purge_old_trash(30).await?;
+    ///     println!("Purged {} messages", purged);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn purge_old_trash(&self, after_days: i64) -> Result<u64, sqlx::Error> {
+        let after_days = interval_units("TRASH_RETENTION_DAYS", after_days)?;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            UPDATE messages
+            SET merged_into = NULL
+            WHERE merged_into IN (
+                SELECT id FROM messages
+                WHERE deleted_at < NOW() - make_interval(days => $1)
+            )
+        "#)
+        .bind(after_days)
+        .execute(&mut *tx)
+        .await?;
+
+        let rows = sqlx::query(r#"
+            DELETE FROM messages
+            WHERE deleted_at < NOW() - make_interval(days => $1)
+            RETURNING id, body_ref
+        "#)
+        .bind(after_days)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for row in &rows {
+            Self::record_event(&mut tx, row.get("id"), MessageEventKind::Deleted, json!({ "permanent": true })).await?;
+        }
+        if !rows.is_empty() {
+            Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        }
+        tx.commit().await?;
+
+        let body_refs: Vec<String> = rows.iter().filter_map(|row| row.get("body_ref")).collect();
+        self.delete_blobs(&body_refs).await;
+        Ok(rows.len() as u64)
+    }
+
+    /// Removes overflowed bodies from the blob store.
+    ///
+    /// Failures are only logged: a leftover blob is harmless, while failing
+    /// the surrounding operation after the rows are gone would not help.
+    pub(crate) async fn delete_blobs(&self, keys: &[String]) {
+        let Some(store) = &self.blob_store else {
+            return;
+        };
+        for key in keys {
+            if let Err(e) = store.delete(key).await {
+                eprintln!("Failed to delete blob {}: {}", key, e);
+            }
+        }
+    }
+
+    /// Assigns the oldest unassigned pending message to an agent.
+    ///
+    /// The message is selected with `FOR UPDATE SKIP LOCKED`, so concurrent
+    /// callers never claim the same message: each one gets the next free one.
+    ///
+    /// # Arguments
+    ///
+    /// * `agent` - Identifier of the agent claiming the message
+    /// * `cap` - Maximum number of assigned messages the agent may hold
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(Some(outcome))` with the claimed message or the reached
+    /// cap, or `Ok(None)` when no pending message is left.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::AssignmentOutcome;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     match db.claim_next_message("alice", Some(10)).await? {
+    ///         Some(AssignmentOutcome::Assigned(message)) => println!("Claimed {}", message.id),
+    ///         Some(AssignmentOutcome::CapReached { limit }) => println!("Already holding {} messages", limit),
+    ///         None => println!("Inbox zero!"),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip(self, cap))]
+    pub async fn claim_next_message(&self, agent: &str, cap: Option<i64>) -> Result<Option<AssignmentOutcome>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(limit) = cap {
+            if Self::lock_and_count_assignments(&mut tx, agent, None).await? >= limit {
+                return Ok(Some(AssignmentOutcome::CapReached { limit }));
+            }
+        }
+
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET status = 'assigned', assigned_to = $1, assigned_at = NOW()
+            WHERE id = (
+                SELECT id
+                FROM messages
+                WHERE status = 'pending'
+                  AND assigned_to IS NULL
+                  AND deleted_at IS NULL
+                  AND NOT archived
+                ORDER BY created_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(agent)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let message = message_from_row(&row);
+        Self::record_assignment_event(&mut tx, message.id, agent, "claimed").await?;
+        Self::record_event(&mut tx, message.id, MessageEventKind::Assigned, json!({
+            "agent": agent,
+            "claimed": true,
+        })).await?;
+
+        tx.commit().await?;
+        Ok(Some(AssignmentOutcome::Assigned(Box::new(message))))
+    }
+
+    /// Assigns a message to an agent.
+    ///
+    /// Reassigning a message that is already assigned to someone else is
+    /// allowed. The cap only counts the agent's other assigned messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `agent` - Identifier of the agent
+    /// * `cap` - Maximum number of assigned messages the agent may hold
+    ///
+    /// # Returns
+    ///
+    /// Returns the `AssignmentOutcome` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist, is trashed or is no longer open
+    ///   (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let outcome = db.assign_message(id, "alice", None).await?;
+    ///     println!("{:?}", outcome);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip(self, cap))]
+    pub async fn assign_message(&self, id: Uuid, agent: &str, cap: Option<i64>) -> Result<AssignmentOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(limit) = cap {
+            if Self::lock_and_count_assignments(&mut tx, agent, Some(id)).await? >= limit {
+                return Ok(AssignmentOutcome::CapReached { limit });
+            }
+        }
+
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET status = 'assigned', assigned_to = $2, assigned_at = NOW()
+            WHERE id = $1
+              AND status IN ('pending', 'assigned')
+              AND deleted_at IS NULL
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(agent)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Self::record_assignment_event(&mut tx, id, agent, "assigned").await?;
+        Self::record_event(&mut tx, id, MessageEventKind::Assigned, json!({
+            "agent": agent,
+            "claimed": false,
+        })).await?;
+
+        tx.commit().await?;
+        Ok(AssignmentOutcome::Assigned(Box::new(message_from_row(&row))))
+    }
+
+    /// Releases a message back to the pending queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    ///
+    /// # Returns
+    ///
+    /// Returns the updated `Message` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist or is not assigned (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let message = db.release_message(id).await?;
+    ///     assert_eq!(message.status, "pending");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn release_message(&self, id: Uuid) -> Result<Message, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let previous = sqlx::query("SELECT assigned_to FROM messages WHERE id = $1 AND status = 'assigned' FOR UPDATE")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let agent: Option<String> = previous.get("assigned_to");
+
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET status = 'pending', assigned_to = NULL, assigned_at = NULL
+            WHERE id = $1
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(agent) = &agent {
+            Self::record_assignment_event(&mut tx, id, agent, "released").await?;
+        }
+        Self::record_event(&mut tx, id, MessageEventKind::StatusChanged, json!({
+            "from": "assigned",
+            "to": "pending",
+            "agent": agent,
+        })).await?;
+
+        tx.commit().await?;
+        Ok(message_from_row(&row))
+    }
+
+    /// Lets a quarantined message into the inbox, along with the other
+    /// quarantined messages of its sender, who is known from then on.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the quarantined message
+    ///
+    /// # Returns
+    ///
+    /// Returns the approved message, now pending, and the number of other
+    /// messages of the sender approved with it.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist or is not quarantined (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let (message, others) = db.approve_message(id).await?;
+    ///     assert_eq!(message.status, "pending");
+    ///     println!("{} other messages approved", others);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn approve_message(&self, id: Uuid) -> Result<(Message, u64), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let email: String = sqlx::query_scalar(
+            "SELECT email FROM messages WHERE id = $1 AND status = 'quarantine' AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let approved: Vec<Uuid> = sqlx::query_scalar(r#"
+            UPDATE messages
+            SET status = 'pending'
+            WHERE lower(email) = lower($1) AND status = 'quarantine' AND deleted_at IS NULL
+            RETURNING id
+        "#)
+        .bind(&email)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for message_id in &approved {
+            Self::record_event(&mut tx, *message_id, MessageEventKind::StatusChanged, json!({
+                "from": "quarantine",
+                "to": "pending",
+                "approved_with": (*message_id != id).then_some(id),
+            })).await?;
+        }
+
+        let row = sqlx::query(&format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE id = $1"))
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok((message_from_row(&row), approved.len() as u64 - 1))
+    }
+
+    /// Applies a partial update to a message in a single `UPDATE`.
+    ///
+    /// Status and assignee are kept consistent with each other: assigning a
+    /// pending message makes it `assigned`, unassigning an assigned one
+    /// makes it `pending`, and `assigned_at`/`resolved_at` follow the
+    /// changes. Assignment history, lifecycle events and the tags cache are
+    /// updated like with the dedicated endpoints. Every patch counts as
+    /// activity for `release_stale_assignments`.
+    ///
+    /// Reopening a resolved message is reserved to admins.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `patch` - Fields to change
+    /// * `cap` - Maximum number of assigned messages the new assignee may hold
+    /// * `is_admin` - Whether the caller is an admin
+    ///
+    /// # Returns
+    ///
+    /// Returns the `PatchOutcome` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist or is trashed (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::MessagePatch;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let patch = MessagePatch {
+    ///         status: Some("resolved".to_string()),
+    ///         tags: Some(vec!["billing".to_string()]),
+    ///         ..MessagePatch::default()
+    ///     };
+    ///     let outcome = db.patch_message(id, &patch, None, false).await?;
+    ///     println!("{:?}", outcome);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip(self, patch, cap))]
+    pub async fn patch_message(
+        &self,
+        id: Uuid,
+        patch: &MessagePatch,
+        cap: Option<i64>,
+        is_admin: bool,
+    ) -> Result<PatchOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let current = sqlx::query("SELECT status, assigned_to FROM messages WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let status: String = current.get("status");
+        let assignee: Option<String> = current.get("assigned_to");
+
+        if status == "merged" {
+            return Ok(PatchOutcome::Invalid("Merged messages cannot be edited".to_string()));
+        }
+        if status == "quarantine" && (patch.status.is_some() || patch.assigned_to.is_some()) {
+            return Ok(PatchOutcome::Invalid("Quarantined messages must be approved first".to_string()));
+        }
+
+        let new_assignee = patch.assigned_to.clone().unwrap_or_else(|| assignee.clone());
+        let new_status = match (&patch.status, &patch.assigned_to) {
+            (Some(status), _) => status.clone(),
+            (None, Some(Some(_))) if status == "pending" => "assigned".to_string(),
+            (None, Some(None)) if status == "assigned" => "pending".to_string(),
+            (None, _) => status.clone(),
+        };
+
+        match (new_status.as_str(), &new_assignee) {
+            ("assigned", None) => {
+                return Ok(PatchOutcome::Invalid("An assigned message needs an assignee".to_string()));
+            }
+            ("pending", Some(_)) if patch.assigned_to.as_ref().is_some_and(Option::is_some) => {
+                return Ok(PatchOutcome::Invalid("A pending message cannot have an assignee".to_string()));
+            }
+            _ => {}
+        }
+        // Back in the queue, the message belongs to nobody
+        let new_assignee = if new_status == "pending" { None } else { new_assignee };
+
+        if status == "resolved" && new_status != "resolved" && !is_admin {
+            return Ok(PatchOutcome::Forbidden { field: "status" });
+        }
+
+        let assignee_changed = new_assignee != assignee;
+        if let (Some(limit), Some(agent), true) = (cap, &new_assignee, assignee_changed && new_status == "assigned") {
+            if Self::lock_and_count_assignments(&mut tx, agent, Some(id)).await? >= limit {
+                return Ok(PatchOutcome::CapReached { limit });
+            }
+        }
+
+        let mut query = QueryBuilder::<Postgres>::new("UPDATE messages SET ");
+        let mut changes = query.separated(", ");
+        // Keeps the assignment from being released as stale, even when nothing else changes
+        changes.push("last_activity_at = NOW()");
+        if new_status != status {
+            changes.push("status = ").push_bind_unseparated(&new_status);
+            if new_status == "resolved" {
+                changes.push("resolved_at = NOW()");
+            } else if status == "resolved" {
+                changes.push("resolved_at = NULL");
+            }
+        }
+        if assignee_changed {
+            changes.push("assigned_to = ").push_bind_unseparated(&new_assignee);
+            changes.push(if new_assignee.is_some() { "assigned_at = NOW()" } else { "assigned_at = NULL" });
+        }
+        if let Some(priority) = &patch.priority {
+            changes.push("priority = ").push_bind_unseparated(priority);
+        }
+        if let Some(tags) = &patch.tags {
+            changes.push("tags = ").push_bind_unseparated(tags);
+        }
+        query.push(" WHERE id = ").push_bind(id);
+        query.push(format!(" RETURNING {MESSAGE_COLUMNS}"));
+
+        let row = query.build().fetch_one(&mut *tx).await?;
+
+        if assignee_changed {
+            if let Some(agent) = &assignee {
+                Self::record_assignment_event(&mut tx, id, agent, "released").await?;
+            }
+            if let Some(agent) = &new_assignee {
+                Self::record_assignment_event(&mut tx, id, agent, "assigned").await?;
+                Self::record_event(&mut tx, id, MessageEventKind::Assigned, json!({
+                    "agent": agent,
+                    "claimed": false,
+                })).await?;
+            }
+        }
+        if new_status != status && new_status != "assigned" {
+            Self::record_event(&mut tx, id, MessageEventKind::StatusChanged, json!({
+                "from": status,
+                "to": new_status,
+                "agent": new_assignee.as_ref().or(assignee.as_ref()),
+            })).await?;
+        }
+        if patch.tags.is_some() {
+            Self::touch_resource(&mut *tx, RESOURCE_TAGS).await?;
+        }
+
+        tx.commit().await?;
+        Ok(PatchOutcome::Updated(Box::new(message_from_row(&row))))
+    }
+
+    /// Returns assignments without activity for more than `after_hours` hours to the pending queue.
+    ///
+    /// The last activity is the latest of the assignment, an opening of the
+    /// message by its assignee and a change through `patch_message`. Each
+    /// release is recorded as `auto_released` in the assignment history,
+    /// and its `status_changed` event notifies the previous assignee (see
+    /// the `notifications` module). Deleted and merged messages keep their
+    /// assignment: they are no longer in anyone's queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_hours` - Number of hours an assignment may stay without activity
+    ///
+    /// # Returns
+    ///
+    /// Returns the released message IDs with the agent they were taken from.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - `after_hours` is negative or too large (`sqlx::Error::Configuration`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for (id, agent) in db.release_stale_assignments(48).await? {
+    ///         println!("Released {} from {}", id, agent);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn release_stale_assignments(&self, after_hours: i64) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+        let hours = interval_units("AUTO_RELEASE_AFTER_HOURS", after_hours)?;
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(r#"
+            WITH stale AS (
+                SELECT id, assigned_to
+                FROM messages
+                WHERE status = 'assigned'
+                  AND deleted_at IS NULL
+                  AND merged_into IS NULL
+                  AND GREATEST(assigned_at, last_activity_at) < NOW() - make_interval(hours => $1)
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE messages m
+            SET status = 'pending', assigned_to = NULL, assigned_at = NULL
+            FROM stale
+            WHERE m.id = stale.id
+            RETURNING m.id, stale.assigned_to
+        "#)
+        .bind(hours)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut released = Vec::with_capacity(rows.len());
+        for row in rows {
+            let id: Uuid = row.get("id");
+            let agent: Option<String> = row.get("assigned_to");
+            Self::record_event(&mut tx, id, MessageEventKind::StatusChanged, json!({
+                "from": "assigned",
+                "to": "pending",
+                "agent": agent,
+                "auto_released": true,
+                "after_hours": after_hours,
+            })).await?;
+            if let Some(agent) = agent {
+                Self::record_assignment_event(&mut tx, id, &agent, "auto_released").await?;
+                released.push((id, agent));
+            }
+        }
+
+        tx.commit().await?;
+        Ok(released)
+    }
+
+    /// Retrieves the assignment history of a message, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     for event in db.list_assignment_history(id).await? {
+    ///         println!("{} {} {}", event.created_at, event.action, event.agent);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_assignment_history(&self, id: Uuid) -> Result<Vec<AssignmentEvent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT message_id, agent, action, created_at
+            FROM assignment_history
+            WHERE message_id = $1
+            ORDER BY created_at ASC, id ASC
+        "#)
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(assignment_event_from_row).collect())
+    }
+
+    /// Retrieves a page of the assignment history of a message, oldest
+    /// first, for `GET /inbox/{id}/assignments`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `page` - Page of the history to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_assignment_history_page(
+        &self, id: Uuid, page: &PageRequest
+    ) -> Result<Paginated<AssignmentEvent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT message_id, agent, action, created_at, COUNT(*) OVER () AS total
+            FROM assignment_history
+            WHERE message_id = $1
+            ORDER BY created_at ASC, id ASC
+            LIMIT $2 OFFSET $3
+        "#)
+        .bind(id)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, assignment_event_from_row))
+    }
+
+    /// Appends an entry to the assignment history within a transaction.
+    pub(crate) async fn record_assignment_event(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        message_id: Uuid,
+        agent: &str,
+        action: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO assignment_history (message_id, agent, action) VALUES ($1, $2, $3)")
+            .bind(message_id)
+            .bind(agent)
+            .bind(action)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Counts the messages assigned to an agent, holding a per-agent lock
+    /// until the end of the transaction so that concurrent assignments
+    /// cannot both pass the cap check.
+    async fn lock_and_count_assignments(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        agent: &str,
+        excluding: Option<Uuid>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('assignments:' || $1))")
+            .bind(agent)
+            .execute(&mut **tx)
+            .await?;
+
+        let row = sqlx::query(r#"
+            SELECT COUNT(*) AS count
+            FROM messages
+            WHERE status = 'assigned'
+              AND assigned_to = $1
+              AND deleted_at IS NULL
+              AND id IS DISTINCT FROM $2
+        "#)
+        .bind(agent)
+        .bind(excluding)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Records that an agent opened a message.
+    ///
+    /// Only the first opening is kept: later calls leave `opened_by` and
+    /// `opened_at` untouched and simply return the message. Every opening by
+    /// the assignee counts as activity for `release_stale_assignments`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `agent` - Identifier of the agent opening the message
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Message` on success.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let message = db.mark_message_opened(id, "alice").await?;
+    ///     println!("First opened by {:?}", message.opened_by);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn mark_message_opened(&self, id: Uuid, agent: &str) -> Result<Message, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE messages
+            SET opened_by = COALESCE(opened_by, $2),
+                opened_at = COALESCE(opened_at, NOW()),
+                last_activity_at = CASE WHEN assigned_to = $2 THEN NOW() ELSE last_activity_at END
+            WHERE id = $1
+            RETURNING {MESSAGE_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(agent)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(message_from_row(&row))
+    }
+
+    /// Computes the inbox-wide counters shown on the backoffice dashboard.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let stats = db.inbox_stats().await?;
+    ///     println!("{} pending, {} never opened", stats.pending, stats.never_opened);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all)]
+    pub async fn inbox_stats(&self) -> Result<InboxStats, sqlx::Error> {
+        let row = sqlx::query(r#"
+            SELECT
+                COUNT(*) AS total,
+                COUNT(*) FILTER (WHERE status = 'quarantine') AS quarantined,
+                COUNT(*) FILTER (WHERE status = 'pending') AS pending,
+                COUNT(*) FILTER (WHERE status = 'assigned') AS assigned,
+                COUNT(*) FILTER (WHERE status = 'resolved') AS resolved,
+                COUNT(*) FILTER (WHERE opened_at IS NULL AND status IN ('pending', 'assigned')) AS never_opened,
+                EXTRACT(EPOCH FROM AVG(opened_at - created_at))::FLOAT8 AS average_time_to_first_open_seconds
+            FROM messages
+            WHERE deleted_at IS NULL
+        "#)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(InboxStats {
+            total: row.get("total"),
+            quarantined: row.get("quarantined"),
+            pending: row.get("pending"),
+            assigned: row.get("assigned"),
+            resolved: row.get("resolved"),
+            never_opened: row.get("never_opened"),
+            average_time_to_first_open_seconds: row.get("average_time_to_first_open_seconds"),
+        })
+    }
+
+    /// Counts the messages received and resolved on each of the last `days`
+    /// days, today included.
+    ///
+    /// Days run from midnight to midnight in `timezone`, so that they match
+    /// the working days of the team rather than UTC days. Days without any
+    /// message are included with zero counts.
+    ///
+    /// # Arguments
+    ///
+    /// * `timezone` - IANA time zone name, such as `Europe/Paris` or `UTC`
+    /// * `days` - Number of days to return
+    ///
+    /// # Returns
+    ///
+    /// Returns one entry per day, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if `timezone` is not a time zone known
+    /// to PostgreSQL (error code `22023`) or if database connection issues
+    /// occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for day in db.daily_counts("Europe/Paris", 7).await? {
+    ///         println!("{}: {} received, {} resolved", day.date, day.received, day.resolved);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all)]
+    pub async fn daily_counts(&self, timezone: &str, days: i32) -> Result<Vec<DailyCount>, sqlx::Error> {
+        // Local midnights are converted back to instants so that the
+        // comparisons can use the indexes on created_at and resolved_at
+        let rows = sqlx::query(r#"
+            WITH days AS (
+                SELECT
+                    day::date AS date,
+                    day::timestamp AT TIME ZONE $1 AS starts_at,
+                    (day + INTERVAL '1 day')::timestamp AT TIME ZONE $1 AS ends_at
+                FROM generate_series(
+                    (NOW() AT TIME ZONE $1)::date - ($2 - 1),
+                    (NOW() AT TIME ZONE $1)::date,
+                    INTERVAL '1 day'
+                ) AS day
+            )
+            SELECT
+                days.date,
+                (SELECT COUNT(*) FROM messages
+                 WHERE deleted_at IS NULL AND created_at >= days.starts_at AND created_at < days.ends_at) AS received,
+                (SELECT COUNT(*) FROM messages
+                 WHERE deleted_at IS NULL AND resolved_at >= days.starts_at AND resolved_at < days.ends_at) AS resolved
+            FROM days
+            ORDER BY days.date
+        "#)
+        .bind(timezone)
+        .bind(days)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| DailyCount {
+                date: row.get("date"),
+                received: row.get("received"),
+                resolved: row.get("resolved"),
+            })
+            .collect())
+    }
+}
+
+/// Database operations for the Company model.
+///
+/// Companies are resolved through the `company_aliases` table, which maps
+/// every normalized spelling seen so far to a company. Merging two companies
+/// moves both their messages and their aliases.
+impl Database {
+    /// Creates the 'companies' and 'company_aliases' tables if they don't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_companies_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_companies_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS companies (
+                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+                name TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS company_aliases (
+                normalized_name TEXT PRIMARY KEY,
+                company_id UUID NOT NULL REFERENCES companies(id) ON DELETE CASCADE
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the company matching a free-text name, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Company name as typed by the sender
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` when the name is blank, or the company ID otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = db.resolve_company("ACME Corp.").await?;
+    ///     println!("Company: {:?}", id);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn resolve_company(&self, name: &str) -> Result<Option<Uuid>, sqlx::Error> {
+        let normalized = normalize_company_name(name);
+        if normalized.is_empty() {
+            return Ok(None);
+        }
+
+        let existing = sqlx::query("SELECT company_id FROM company_aliases WHERE normalized_name = $1")
+            .bind(&normalized)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some(row) = existing {
+            return Ok(Some(row.get("company_id")));
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let company_id: Uuid = sqlx::query("INSERT INTO companies (name) VALUES ($1) RETURNING id")
+            .bind(name.trim())
+            .fetch_one(&mut *tx)
+            .await?
+            .get("id");
+
+        // Another request may have created the alias concurrently: keep theirs.
+        let alias = sqlx::query(r#"
+            INSERT INTO company_aliases (normalized_name, company_id)
+            VALUES ($1, $2)
+            ON CONFLICT (normalized_name) DO NOTHING
+        "#)
+        .bind(&normalized)
+        .bind(company_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if alias.rows_affected() == 0 {
+            tx.rollback().await?;
+            let row = sqlx::query("SELECT company_id FROM company_aliases WHERE normalized_name = $1")
+                .bind(&normalized)
+                .fetch_one(&self.pool)
+                .await?;
+            return Ok(Some(row.get("company_id")));
+        }
+
+        Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        tx.commit().await?;
+        Ok(Some(company_id))
+    }
+
+    /// Links messages that have a company name but no company yet.
+    ///
+    /// Run at startup so that messages created before the companies
+    /// directory existed are attached to their company.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of messages that were linked.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let linked = db.backfill_message_companies().await?;
+    ///     println!("Linked {} messages", linked);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn backfill_message_companies(&self) -> Result<u64, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT DISTINCT company
+            FROM messages
+            WHERE company_id IS NULL AND btrim(company) <> ''
+        "#)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut linked = 0;
+        for row in rows {
+            let company: String = row.get("company");
+            if let Some(company_id) = self.resolve_company(&company).await? {
+                linked += sqlx::query("UPDATE messages SET company_id = $1 WHERE company_id IS NULL AND company = $2")
+                    .bind(company_id)
+                    .bind(&company)
+                    .execute(&self.pool)
+                    .await?
+                    .rows_affected();
+            }
+        }
+
+        if linked > 0 {
+            Self::touch_resource(&self.pool, RESOURCE_COMPANIES).await?;
+        }
+        Ok(linked)
+    }
+
+    /// Lists a page of the companies with their message count, most active
+    /// first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::PageRequest;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for company in db.list_companies(&PageRequest::default()).await?.data {
+    ///         println!("{}: {} messages", company.name, company.message_count);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_companies(&self, page: &PageRequest) -> Result<Paginated<Company>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT c.id, c.name, c.created_at, COUNT(m.id) AS message_count, COUNT(*) OVER () AS total
+            FROM companies c
+            LEFT JOIN messages m ON m.company_id = c.id
+            GROUP BY c.id
+            ORDER BY message_count DESC, c.name, c.id
+            LIMIT $1 OFFSET $2
+        "#)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, |row| Company {
+            id: row.get("id"),
+            name: row.get("name"),
+            created_at: row.get("created_at"),
+            message_count: row.get("message_count"),
+        }))
+    }
+
+    /// Retrieves a page of the messages linked to a company, newest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `company_id` - ID of the company
+    /// * `page` - Page of the listing to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The company does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::models::PageRequest;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let messages = db.list_company_messages(id, &PageRequest::default()).await?;
+    ///     println!("{} messages", messages.page.total_estimate.unwrap_or_default());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_company_messages(
+        &self, company_id: Uuid, page: &PageRequest
+    ) -> Result<Paginated<Message>, sqlx::Error> {
+        sqlx::query("SELECT 1 FROM companies WHERE id = $1")
+            .bind(company_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let rows = sqlx::query(&format!(r#"
+            SELECT {MESSAGE_COLUMNS}, COUNT(*) OVER () AS total
+            FROM messages
+            WHERE company_id = $1 AND deleted_at IS NULL
+            ORDER BY created_at DESC, id
+            LIMIT $2 OFFSET $3
+        "#))
+        .bind(company_id)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, message_from_row))
+    }
+
+    /// Merges a duplicate company into another one.
+    ///
+    /// Messages and name aliases of the source company are moved to the
+    /// target, then the source company is deleted. Future messages using any
+    /// of the source spellings resolve to the target.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_id` - ID of the duplicate company
+    /// * `target_id` - ID of the company that is kept
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Either company does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let source = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let target = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174001").unwrap();
+    ///     db.merge_companies(source, target).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn merge_companies(&self, source_id: Uuid, target_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let found = sqlx::query("SELECT id FROM companies WHERE id = ANY($1) FOR UPDATE")
+            .bind([source_id, target_id])
+            .fetch_all(&mut *tx)
+            .await?;
+        if found.len() != 2 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        sqlx::query("UPDATE messages SET company_id = $2 WHERE company_id = $1")
+            .bind(source_id)
+            .bind(target_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE company_aliases SET company_id = $2 WHERE company_id = $1")
+            .bind(source_id)
+            .bind(target_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM companies WHERE id = $1")
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await?;
+
+        Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+        tx.commit().await?;
+        Ok(())
+    }
 }
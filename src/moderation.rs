@@ -0,0 +1,591 @@
+//! # Abuse Filter
+//!
+//! Screens contact form submissions against a list of abusive words and
+//! phrases kept in the `abuse_patterns` table and managed through the admin
+//! API (`/admin/abuse/patterns`). Each pattern either flags the submission,
+//! which is stored with the `abuse` tag, or rejects it.
+//!
+//! Patterns are whole words or phrases where `*` stands for any letters,
+//! e.g. `idiot*` or `go to hell`. Submissions and patterns are compared
+//! after lowercasing and undoing common leetspeak substitutions (`1d10t`,
+//! `$cam`), see [`normalize`].
+//!
+//! Every match is kept in the `abuse_matches` table with the patterns that
+//! matched, for moderators to review through `/admin/abuse/matches`;
+//! rejected submissions are kept there too, since they are not stored as
+//! messages.
+//!
+//! The patterns are cached by [`AbuseFilterCache`] and reloaded every
+//! [`CACHE_TTL`], like feature flags.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::caching::RESOURCE_TAGS;
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+
+/// How long the patterns read from the database are used before reloading them.
+pub const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Longest accepted pattern.
+pub const MAX_PATTERN_LENGTH: usize = 100;
+
+/// Tag added to flagged messages.
+pub const ABUSE_TAG: &str = "abuse";
+
+/// What happens to a submission matching a pattern.
+///
+/// * `Flag` - The message is stored with the [`ABUSE_TAG`] tag
+/// * `Reject` - The message is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbuseAction {
+    Flag,
+    Reject,
+}
+
+impl AbuseAction {
+    /// Returns the name stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AbuseAction::Flag => "flag",
+            AbuseAction::Reject => "reject",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        if value == "reject" {
+            AbuseAction::Reject
+        } else {
+            AbuseAction::Flag
+        }
+    }
+}
+
+/// A pattern of the abuse filter.
+///
+/// # Fields
+///
+/// * `id` - Identifier of the pattern
+/// * `pattern` - Normalized pattern (see [`normalize_pattern`])
+/// * `action` - What happens to matching submissions
+/// * `created_at` - Timestamp of the last change
+#[derive(Debug, Clone, Serialize)]
+pub struct AbusePattern {
+    pub id: i64,
+    pub pattern: String,
+    pub action: AbuseAction,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A submission that matched the abuse filter.
+///
+/// # Fields
+///
+/// * `id` - Identifier of the match
+/// * `message_id` - The flagged message, `None` for a rejected submission
+/// * `email` - Email of the sender
+/// * `message` - Body of a rejected submission, `None` for a flagged one
+///   (the message holds it)
+/// * `action` - What happened to the submission
+/// * `patterns` - Patterns that matched
+/// * `created_at` - Timestamp of the submission
+/// * `reviewed_at` - Timestamp when a moderator reviewed the match
+#[derive(Debug, Clone, Serialize)]
+pub struct AbuseMatch {
+    pub id: i64,
+    pub message_id: Option<Uuid>,
+    pub email: String,
+    pub message: Option<String>,
+    pub action: AbuseAction,
+    pub patterns: Vec<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+/// Outcome of screening a submission.
+///
+/// # Fields
+///
+/// * `action` - The strongest action of the matched patterns
+/// * `patterns` - Patterns that matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbuseVerdict {
+    pub action: AbuseAction,
+    pub patterns: Vec<String>,
+}
+
+/// Replaces the digits and symbols commonly used in place of letters.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+/// Splits `text` into the lowercase words compared with patterns, with
+/// leetspeak substitutions undone.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::moderation::normalize;
+///
+/// assert_eq!(normalize("You 1d10t, it's a $CAM!"), vec!["you", "idiot", "it", "s", "a", "scam"]);
+/// ```
+pub fn normalize(text: &str) -> Vec<String> {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(unleet)
+        .collect::<String>()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the canonical form of a pattern: normalized words, keeping `*`,
+/// separated by single spaces.
+///
+/// # Returns
+///
+/// Returns `None` if the pattern has no letter or is longer than
+/// [`MAX_PATTERN_LENGTH`].
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::moderation::normalize_pattern;
+///
+/// assert_eq!(normalize_pattern("  Go to   HELL "), Some("go to hell".to_string()));
+/// assert_eq!(normalize_pattern("1d10t*"), Some("idiot*".to_string()));
+/// assert_eq!(normalize_pattern("***"), None);
+/// ```
+pub fn normalize_pattern(pattern: &str) -> Option<String> {
+    if pattern.chars().count() > MAX_PATTERN_LENGTH {
+        return None;
+    }
+    let words: Vec<String> = pattern
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .flat_map(char::to_lowercase)
+                .map(|c| if c == '*' { c } else { unleet(c) })
+                .filter(|c| *c == '*' || c.is_alphanumeric())
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    let canonical = words.join(" ");
+    canonical.chars().any(char::is_alphanumeric).then_some(canonical)
+}
+
+/// Returns whether `word` matches `pattern`, where `*` stands for any
+/// sequence of characters.
+fn matches_word(pattern: &[char], word: &[char]) -> bool {
+    let (mut p, mut w) = (0, 0);
+    // Position after the last `*` and the word position it was tried at
+    let mut backtrack = None;
+    while w < word.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, w));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == word[w] {
+            p += 1;
+            w += 1;
+        } else if let Some((star, start)) = backtrack {
+            p = star;
+            w = start + 1;
+            backtrack = Some((star, start + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Compiled list of patterns screening submissions.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::moderation::{AbuseAction, AbuseFilter};
+///
+/// let filter = AbuseFilter::new([("idiot*", AbuseAction::Flag), ("go to hell", AbuseAction::Reject)]);
+///
+/// let verdict = filter.check("You are 1D10TS").unwrap();
+/// assert_eq!(verdict.action, AbuseAction::Flag);
+/// assert_eq!(verdict.patterns, vec!["idiot*"]);
+///
+/// assert_eq!(filter.check("Idiots, go to h3ll").unwrap().action, AbuseAction::Reject);
+/// assert_eq!(filter.check("Where do I go to see the pricing?"), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AbuseFilter {
+    patterns: Vec<(String, Vec<Vec<char>>, AbuseAction)>,
+}
+
+impl AbuseFilter {
+    /// Compiles `(pattern, action)` pairs, ignoring invalid patterns.
+    pub fn new<'a>(patterns: impl IntoIterator<Item = (&'a str, AbuseAction)>) -> Self {
+        AbuseFilter {
+            patterns: patterns
+                .into_iter()
+                .filter_map(|(pattern, action)| {
+                    let canonical = normalize_pattern(pattern)?;
+                    let words = canonical.split(' ').map(|word| word.chars().collect()).collect();
+                    Some((canonical, words, action))
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns whether the filter has no pattern.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Screens `text`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` when no pattern matches, or the matched patterns and
+    /// the strongest of their actions.
+    pub fn check(&self, text: &str) -> Option<AbuseVerdict> {
+        if self.patterns.is_empty() {
+            return None;
+        }
+        let words: Vec<Vec<char>> = normalize(text).iter().map(|word| word.chars().collect()).collect();
+
+        let mut verdict: Option<AbuseVerdict> = None;
+        for (pattern, pattern_words, action) in &self.patterns {
+            let found = words.windows(pattern_words.len()).any(|window| {
+                window.iter().zip(pattern_words).all(|(word, pattern)| matches_word(pattern, word))
+            });
+            if found {
+                let verdict = verdict.get_or_insert_with(|| AbuseVerdict { action: *action, patterns: Vec::new() });
+                verdict.action = verdict.action.max(*action);
+                verdict.patterns.push(pattern.clone());
+            }
+        }
+        verdict
+    }
+}
+
+fn pattern_from_row(row: &sqlx::postgres::PgRow) -> AbusePattern {
+    AbusePattern {
+        id: row.get("id"),
+        pattern: row.get("pattern"),
+        action: AbuseAction::from_db(row.get("action")),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Database operations for the abuse filter.
+impl Database {
+    /// Creates the 'abuse_patterns' and 'abuse_matches' tables if they
+    /// don't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_abuse_tables().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_abuse_tables(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS abuse_patterns (
+                id BIGSERIAL PRIMARY KEY,
+                pattern TEXT NOT NULL UNIQUE,
+                action TEXT NOT NULL CHECK (action IN ('flag', 'reject')),
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS abuse_matches (
+                id BIGSERIAL PRIMARY KEY,
+                message_id UUID,
+                email TEXT NOT NULL,
+                message TEXT,
+                action TEXT NOT NULL,
+                patterns TEXT[] NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                reviewed_at TIMESTAMPTZ
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the patterns of the abuse filter, alphabetically.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_abuse_patterns(&self) -> Result<Vec<AbusePattern>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, pattern, action, created_at FROM abuse_patterns ORDER BY pattern")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(pattern_from_row).collect())
+    }
+
+    /// Adds a pattern to the abuse filter, or changes its action if it is
+    /// already listed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - Canonical pattern (see [`normalize_pattern`])
+    /// * `action` - What happens to matching submissions
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::moderation::{normalize_pattern, AbuseAction};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let pattern = normalize_pattern("idiot*").unwrap();
+    ///     db.set_abuse_pattern(&pattern, AbuseAction::Flag).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn set_abuse_pattern(&self, pattern: &str, action: AbuseAction) -> Result<AbusePattern, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO abuse_patterns (pattern, action)
+            VALUES ($1, $2)
+            ON CONFLICT (pattern) DO UPDATE SET action = EXCLUDED.action, created_at = NOW()
+            RETURNING id, pattern, action, created_at
+        "#)
+        .bind(pattern)
+        .bind(action.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(pattern_from_row(&row))
+    }
+
+    /// Removes a pattern from the abuse filter.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the pattern was removed, `false` if there was none.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_abuse_pattern(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM abuse_patterns WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Keeps a submission that matched the abuse filter for review, and
+    /// tags the message when it was flagged.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_id` - The flagged message, `None` for a rejected submission
+    /// * `email` - Email of the sender
+    /// * `message` - Body of a rejected submission
+    /// * `verdict` - Outcome of the filter
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn record_abuse_match(
+        &self, message_id: Option<Uuid>, email: &str, message: Option<&str>, verdict: &AbuseVerdict
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            INSERT INTO abuse_matches (message_id, email, message, action, patterns)
+            VALUES ($1, $2, $3, $4, $5)
+        "#)
+        .bind(message_id)
+        .bind(email)
+        .bind(message)
+        .bind(verdict.action.as_str())
+        .bind(&verdict.patterns)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(id) = message_id {
+            sqlx::query("UPDATE messages SET tags = array_append(tags, $2) WHERE id = $1 AND NOT $2 = ANY(tags)")
+                .bind(id)
+                .bind(ABUSE_TAG)
+                .execute(&mut *tx)
+                .await?;
+            Self::touch_resource(&mut *tx, RESOURCE_TAGS).await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Lists a page of the submissions that matched the abuse filter, most
+    /// recent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `unreviewed` - Only list the matches no moderator reviewed yet
+    /// * `page` - Page of the listing to return
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_abuse_matches(
+        &self, unreviewed: bool, page: &PageRequest
+    ) -> Result<Paginated<AbuseMatch>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, message_id, email, message, action, patterns, created_at, reviewed_at,
+                COUNT(*) OVER () AS total
+            FROM abuse_matches
+            WHERE NOT $1 OR reviewed_at IS NULL
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2 OFFSET $3
+        "#)
+        .bind(unreviewed)
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, |row| AbuseMatch {
+            id: row.get("id"),
+            message_id: row.get("message_id"),
+            email: row.get("email"),
+            message: row.get("message"),
+            action: AbuseAction::from_db(row.get("action")),
+            patterns: row.get("patterns"),
+            created_at: row.get("created_at"),
+            reviewed_at: row.get("reviewed_at"),
+        }))
+    }
+
+    /// Marks a match as reviewed by a moderator.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the match exists.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn review_abuse_match(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE abuse_matches SET reviewed_at = COALESCE(reviewed_at, NOW()) WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Cached abuse filter, shared by all workers through the app data.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dothtml_backend::database::Database;
+/// use dothtml_backend::moderation::AbuseFilterCache;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), sqlx::Error> {
+///     let filters = AbuseFilterCache::new(Database::new().await?);
+///     if let Some(verdict) = filters.filter().await.check("You 1d10t") {
+///         println!("Matched {:?}", verdict.patterns);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct AbuseFilterCache {
+    db: Database,
+    cache: RwLock<Option<(Instant, Arc<AbuseFilter>)>>,
+}
+
+impl AbuseFilterCache {
+    /// Creates an empty cache, filled on the first lookup.
+    pub fn new(db: Database) -> Self {
+        AbuseFilterCache { db, cache: RwLock::new(None) }
+    }
+
+    /// Drops the cached filter, so that the next lookup reads the table.
+    pub async fn invalidate(&self) {
+        *self.cache.write().await = None;
+    }
+
+    /// Returns the filter, reloading the patterns when they are stale.
+    ///
+    /// When the patterns cannot be reloaded, the previous ones are kept, or
+    /// nothing is filtered if none were ever loaded.
+    pub async fn filter(&self) -> Arc<AbuseFilter> {
+        if let Some((loaded_at, filter)) = &*self.cache.read().await {
+            if loaded_at.elapsed() < CACHE_TTL {
+                return filter.clone();
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another lookup may have reloaded the patterns while this one waited
+        if let Some((loaded_at, filter)) = &*cache {
+            if loaded_at.elapsed() < CACHE_TTL {
+                return filter.clone();
+            }
+        }
+
+        let filter = match self.db.list_abuse_patterns().await {
+            Ok(patterns) => Arc::new(AbuseFilter::new(
+                patterns.iter().map(|pattern| (pattern.pattern.as_str(), pattern.action)),
+            )),
+            Err(e) => {
+                eprintln!("Failed to load abuse patterns: {}", e);
+                cache.as_ref().map(|(_, filter)| filter.clone()).unwrap_or_default()
+            }
+        };
+        *cache = Some((Instant::now(), filter.clone()));
+        filter
+    }
+}
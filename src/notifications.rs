@@ -0,0 +1,521 @@
+//! # Notification Preferences
+//!
+//! What each backoffice agent wants to be told about, and the dispatcher
+//! telling them. Agents manage their own preferences with
+//! `GET /me/preferences` and `PUT /me/preferences` (see `auth::Account`);
+//! an agent who never saved any gets no notification.
+//!
+//! ## Notifications
+//!
+//! - `email_new_message` - An email for every new message let into the inbox
+//! - `email_assignment` - An email when a message is assigned to the agent
+//! - `daily_digest` - A daily email summarizing the inbox and the agent's assignments
+//! - `slack_dm` - A Slack direct message for new messages and assignments
+//!
+//! New messages and assignments are notified when their lifecycle event is
+//! recorded (see the `events` module), in the transaction making the
+//! change, so every path notifies alike: the contact form, the
+//! integrations, the approval of quarantined messages, `POST
+//! /inbox/{id}/assign`, `PATCH /inbox/{id}` and the assignment rules.
+//! Messages an agent claims are not notified to them. The agent an
+//! assignment is taken from by the auto-release job (see
+//! `Database::release_stale_assignments`) is told like for an assignment.
+//!
+//! Emails go to the agent's `email`. New messages, assignments and
+//! revocations are written to the outbox as `notification.email` entries,
+//! which the email relay puts on the mail queue (see the `mail_queue`
+//! module); digests are queued directly, as digests. Without `MAIL_FROM`,
+//! no email is sent. Slack messages are published as
+//! `notification.slack_dm` outbox entries, for the Slack bridge to deliver
+//! to `slack_user_id`.
+
+use chrono::{DateTime, Utc};
+use maud::html;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, PgExecutor, Row};
+use validator::Validate;
+
+use crate::database::Database;
+use crate::events::{MessageEvent, MessageEventKind};
+use crate::mail_queue::{MailPriority, MailQueue, OutboxEmail};
+use crate::models::{message_from_row, Message, MESSAGE_COLUMNS};
+use crate::sessions;
+use crate::templates::RenderedEmail;
+
+/// Topic of the outbox entries asking the Slack bridge for a direct message.
+pub const SLACK_DM_TOPIC: &str = "notification.slack_dm";
+
+/// Topic of the outbox entries carrying an email for the mail queue.
+pub const EMAIL_TOPIC: &str = "notification.email";
+
+/// Columns of the `notification_prefs` table, in the order read by [`prefs_from_row`].
+const PREFS_COLUMNS: &str =
+    "account, email, email_new_message, email_assignment, daily_digest, slack_dm, slack_user_id, updated_at";
+
+/// Notification preferences of an agent.
+///
+/// # Fields
+///
+/// * `account` - Name of the agent, as used in assignments
+/// * `email` - Address notification emails are sent to
+/// * `email_new_message` - Email the agent about every new message
+/// * `email_assignment` - Email the agent when a message is assigned to them
+/// * `daily_digest` - Email the agent a daily digest
+/// * `slack_dm` - Send new messages and assignments as Slack direct messages
+/// * `slack_user_id` - Slack member ID receiving the direct messages
+/// * `updated_at` - When the preferences were last saved, `None` if never
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NotificationPrefs {
+    pub account: String,
+    pub email: Option<String>,
+    pub email_new_message: bool,
+    pub email_assignment: bool,
+    pub daily_digest: bool,
+    pub slack_dm: bool,
+    pub slack_user_id: Option<String>,
+    #[serde(with = "crate::timestamp::option")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl NotificationPrefs {
+    /// Returns the preferences of an agent who never saved any: every
+    /// notification is off.
+    pub fn defaults(account: &str) -> Self {
+        NotificationPrefs {
+            account: account.to_string(),
+            email: None,
+            email_new_message: false,
+            email_assignment: false,
+            daily_digest: false,
+            slack_dm: false,
+            slack_user_id: None,
+            updated_at: None,
+        }
+    }
+}
+
+/// Body of `PUT /me/preferences`, replacing every preference.
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationPrefsUpdate {
+    #[validate(email(message = "Email must be a valid address"))]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_new_message: bool,
+    #[serde(default)]
+    pub email_assignment: bool,
+    #[serde(default)]
+    pub daily_digest: bool,
+    #[serde(default)]
+    pub slack_dm: bool,
+    #[validate(length(min = 1, max = 50, message = "Slack user ID must be between 1 and 50 characters"))]
+    pub slack_user_id: Option<String>,
+}
+
+impl NotificationPrefsUpdate {
+    /// Checks that the enabled notifications have somewhere to go.
+    ///
+    /// # Errors
+    ///
+    /// Returns an explanation when an email notification is enabled without
+    /// an address, or Slack messages without a Slack user ID.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::notifications::NotificationPrefsUpdate;
+    ///
+    /// let mut update = NotificationPrefsUpdate {
+    ///     email: None,
+    ///     email_new_message: false,
+    ///     email_assignment: false,
+    ///     daily_digest: true,
+    ///     slack_dm: false,
+    ///     slack_user_id: None,
+    /// };
+    /// assert!(update.check_destinations().is_err());
+    ///
+    /// update.email = Some("alice@dotshell.eu".to_string());
+    /// assert!(update.check_destinations().is_ok());
+    /// ```
+    pub fn check_destinations(&self) -> Result<(), String> {
+        if (self.email_new_message || self.email_assignment || self.daily_digest) && self.email.is_none() {
+            return Err("Email notifications need an email address".to_string());
+        }
+        if self.slack_dm && self.slack_user_id.is_none() {
+            return Err("Slack messages need a Slack user ID".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Notification a message lifecycle event calls for.
+///
+/// * `NewMessage` - To the agents subscribed to new messages
+/// * `Assigned` - To the agent the message was assigned to
+/// * `Released` - To the agent the auto-release job took the message from
+enum EventNotification<'a> {
+    NewMessage,
+    Assigned(&'a str),
+    Released(&'a str),
+}
+
+/// Builds preferences from a row selected with [`PREFS_COLUMNS`].
+fn prefs_from_row(row: &PgRow) -> NotificationPrefs {
+    NotificationPrefs {
+        account: row.get("account"),
+        email: row.get("email"),
+        email_new_message: row.get("email_new_message"),
+        email_assignment: row.get("email_assignment"),
+        daily_digest: row.get("daily_digest"),
+        slack_dm: row.get("slack_dm"),
+        slack_user_id: row.get("slack_user_id"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Database operations for notification preferences.
+impl Database {
+    /// Creates the 'notification_prefs' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_notification_prefs_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS notification_prefs (
+                account TEXT PRIMARY KEY,
+                email TEXT,
+                email_new_message BOOLEAN NOT NULL DEFAULT FALSE,
+                email_assignment BOOLEAN NOT NULL DEFAULT FALSE,
+                daily_digest BOOLEAN NOT NULL DEFAULT FALSE,
+                slack_dm BOOLEAN NOT NULL DEFAULT FALSE,
+                slack_user_id TEXT,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the notification preferences of an agent, the defaults if
+    /// they never saved any.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let prefs = db.get_notification_prefs("alice").await?;
+    ///     println!("Daily digest: {}", prefs.daily_digest);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn get_notification_prefs(&self, account: &str) -> Result<NotificationPrefs, sqlx::Error> {
+        Self::fetch_notification_prefs(&self.pool, account).await
+    }
+
+    /// Returns the notification preferences of an agent with any executor,
+    /// the defaults if they never saved any.
+    async fn fetch_notification_prefs<'e, E: PgExecutor<'e>>(
+        executor: E, account: &str
+    ) -> Result<NotificationPrefs, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {PREFS_COLUMNS} FROM notification_prefs WHERE account = $1"))
+            .bind(account)
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(row.as_ref().map(prefs_from_row).unwrap_or_else(|| NotificationPrefs::defaults(account)))
+    }
+
+    /// Replaces the notification preferences of an agent.
+    ///
+    /// # Returns
+    ///
+    /// Returns the saved preferences.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn set_notification_prefs(
+        &self, account: &str, prefs: &NotificationPrefsUpdate
+    ) -> Result<NotificationPrefs, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO notification_prefs
+                (account, email, email_new_message, email_assignment, daily_digest, slack_dm, slack_user_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (account) DO UPDATE SET
+                email = EXCLUDED.email,
+                email_new_message = EXCLUDED.email_new_message,
+                email_assignment = EXCLUDED.email_assignment,
+                daily_digest = EXCLUDED.daily_digest,
+                slack_dm = EXCLUDED.slack_dm,
+                slack_user_id = EXCLUDED.slack_user_id,
+                updated_at = NOW()
+            RETURNING {PREFS_COLUMNS}
+        "#))
+        .bind(account)
+        .bind(&prefs.email)
+        .bind(prefs.email_new_message)
+        .bind(prefs.email_assignment)
+        .bind(prefs.daily_digest)
+        .bind(prefs.slack_dm)
+        .bind(&prefs.slack_user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(prefs_from_row(&row))
+    }
+
+    /// Lists the preferences of the agents told about new messages, by
+    /// email or Slack.
+    async fn new_message_subscribers(conn: &mut PgConnection) -> Result<Vec<NotificationPrefs>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {PREFS_COLUMNS} FROM notification_prefs WHERE email_new_message OR slack_dm ORDER BY account"
+        ))
+        .fetch_all(conn)
+        .await?;
+
+        Ok(rows.iter().map(prefs_from_row).collect())
+    }
+
+    /// Lists the preferences of the agents receiving the daily digest.
+    async fn digest_subscribers(&self) -> Result<Vec<NotificationPrefs>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {PREFS_COLUMNS} FROM notification_prefs WHERE daily_digest ORDER BY account"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(prefs_from_row).collect())
+    }
+
+    /// Counts the messages currently assigned to an agent.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn count_assigned_messages(&self, agent: &str) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query(r#"
+            SELECT COUNT(*) AS count
+            FROM messages
+            WHERE status = 'assigned' AND assigned_to = $1 AND deleted_at IS NULL
+        "#)
+        .bind(agent)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Asks the email relay of the outbox to queue an email.
+    pub(crate) async fn enqueue_email<'e, E: PgExecutor<'e>>(
+        executor: E, to: &str, dedup_key: &str, email: RenderedEmail
+    ) -> Result<(), sqlx::Error> {
+        let outbox_email = OutboxEmail { to: to.to_string(), email };
+        Self::enqueue_outbox(
+            executor,
+            EMAIL_TOPIC,
+            &format!("email:{}:{}", to, dedup_key),
+            &serde_json::to_value(&outbox_email).unwrap_or_default(),
+        ).await
+    }
+
+    /// Asks the Slack bridge to send a direct message.
+    async fn enqueue_slack_dm<'e, E: PgExecutor<'e>>(
+        executor: E, prefs: &NotificationPrefs, dedup_key: &str, text: &str
+    ) -> Result<(), sqlx::Error> {
+        let Some(slack_user_id) = &prefs.slack_user_id else {
+            return Ok(());
+        };
+        Self::enqueue_outbox(
+            executor,
+            SLACK_DM_TOPIC,
+            &format!("slack_dm:{}:{}", prefs.account, dedup_key),
+            &json!({ "account": prefs.account, "slack_user_id": slack_user_id, "text": text }),
+        ).await
+    }
+
+    /// Enqueues the notifications a lifecycle event calls for, in the
+    /// transaction recording it:
+    ///
+    /// - `created`: the agents subscribed to new messages, unless the
+    ///   message is quarantined, a follow-up or restored from an archive
+    /// - `status_changed` from `quarantine` to `pending`: the same agents,
+    ///   the approved message being new to them
+    /// - `assigned`: the agent the message was assigned to, unless they
+    ///   claimed it themselves
+    /// - `status_changed` by the auto-release job: the agent the message was
+    ///   taken from, under the same preferences as assignments
+    pub(crate) async fn enqueue_notifications(
+        conn: &mut PgConnection,
+        kind: MessageEventKind,
+        event: &MessageEvent,
+    ) -> Result<(), sqlx::Error> {
+        let payload = &event.payload;
+        let agent = payload["agent"].as_str();
+        let notification = match kind {
+            MessageEventKind::Created if payload.get("archive").is_none() && payload.get("followup_of").is_none() => {
+                EventNotification::NewMessage
+            }
+            MessageEventKind::StatusChanged if payload["from"] == "quarantine" && payload["to"] == "pending" => {
+                EventNotification::NewMessage
+            }
+            MessageEventKind::StatusChanged if payload["auto_released"] == true => match agent {
+                Some(agent) => EventNotification::Released(agent),
+                None => return Ok(()),
+            },
+            MessageEventKind::Assigned if payload["claimed"] != true => match agent {
+                Some(agent) => EventNotification::Assigned(agent),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        let row = sqlx::query(&format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE id = $1"))
+            .bind(event.message_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let Some(message) = row.as_ref().map(message_from_row) else {
+            return Ok(());
+        };
+        let dedup_key = format!("event:{}", event.id);
+
+        let (agent, text) = match notification {
+            EventNotification::NewMessage => {
+                return Self::enqueue_new_message_notifications(conn, &message, &dedup_key).await;
+            }
+            EventNotification::Assigned(agent) => {
+                (agent, format!("Message from {} ({}) assigned to you", message.name, message.email))
+            }
+            EventNotification::Released(agent) => (agent, format!(
+                "Message from {} ({}) returned to the queue after {} hours without activity",
+                message.name, message.email, payload["after_hours"]
+            )),
+        };
+        let prefs = Self::fetch_notification_prefs(&mut *conn, agent).await?;
+        if let (true, Some(email)) = (prefs.email_assignment, &prefs.email) {
+            Self::enqueue_email(&mut *conn, email, &dedup_key, message_email(&text, &message)).await?;
+        }
+        if prefs.slack_dm {
+            Self::enqueue_slack_dm(&mut *conn, &prefs, &dedup_key, &text).await?;
+        }
+        Ok(())
+    }
+
+    /// Enqueues the notifications of the agents subscribed to new messages.
+    async fn enqueue_new_message_notifications(
+        conn: &mut PgConnection,
+        message: &Message,
+        dedup_key: &str,
+    ) -> Result<(), sqlx::Error> {
+        if message.status == "quarantine" {
+            return Ok(());
+        }
+        let text = format!("New message from {} ({}) at {}", message.name, message.email, message.company);
+        for prefs in Self::new_message_subscribers(&mut *conn).await? {
+            if let (true, Some(email)) = (prefs.email_new_message, &prefs.email) {
+                Self::enqueue_email(&mut *conn, email, dedup_key, message_email(&text, message)).await?;
+            }
+            if prefs.slack_dm {
+                Self::enqueue_slack_dm(&mut *conn, &prefs, dedup_key, &text).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends the notifications that do not follow a message lifecycle event:
+/// revocations and daily digests. The others are enqueued with the event.
+#[derive(Clone)]
+pub struct NotificationDispatcher {
+    db: Database,
+    queue: Option<MailQueue>,
+}
+
+impl NotificationDispatcher {
+    /// Creates a dispatcher reading preferences from `db` and queueing
+    /// digests on `queue`, `None` when the mailer is disabled. Other
+    /// emails reach the queue through the outbox.
+    pub fn new(db: Database, queue: Option<MailQueue>) -> Self {
+        NotificationDispatcher { db, queue }
+    }
+
+    /// Tells an agent that an admin ended all their sessions at
+    /// `revoked_before`. Sent to the agent's saved address whatever their
+    /// notification choices, as it is about the security of their account.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn sessions_revoked(&self, account: &str, revoked_before: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        let prefs = self.db.get_notification_prefs(account).await?;
+        if let Some(email) = &prefs.email {
+            let dedup_key = format!("revoked:{}", revoked_before.timestamp_micros());
+            Database::enqueue_email(&self.db.pool, email, &dedup_key, sessions::revoked_email()).await?;
+        }
+        Ok(())
+    }
+
+    /// Queues the daily digest of every subscribed agent.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of digests queued.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn daily_digest(&self) -> Result<usize, sqlx::Error> {
+        let subscribers = self.db.digest_subscribers().await?;
+        if subscribers.is_empty() || self.queue.is_none() {
+            return Ok(0);
+        }
+
+        let stats = self.db.inbox_stats().await?;
+        let mut queued = 0;
+        for prefs in subscribers {
+            let Some(email) = &prefs.email else {
+                continue;
+            };
+            let assigned = self.db.count_assigned_messages(&prefs.account).await?;
+            let text = format!(
+                "{} messages are waiting for an agent, {} of them never opened.\n\
+                 {} messages are assigned to you.\n\
+                 {} messages of unknown senders are waiting for approval.",
+                stats.pending, stats.never_opened, assigned, stats.quarantined
+            );
+            let html = html! { @for line in text.lines() { p { (line) } } }.into_string();
+            let digest = RenderedEmail { subject: "Your daily inbox digest".to_string(), html, text };
+            if let Some(queue) = &self.queue {
+                queue.push(email, digest, MailPriority::Digest);
+            }
+            queued += 1;
+        }
+        Ok(queued)
+    }
+}
+
+/// Builds the email about one message.
+fn message_email(summary: &str, message: &Message) -> RenderedEmail {
+    let text = format!("{}.\n\n{}\n\nReference: {}", summary, message.message, message.id);
+    let html = html! {
+        p { (summary) "." }
+        blockquote { @for line in message.message.lines() { (line) br; } }
+        p { "Reference: " (message.id) }
+    };
+    RenderedEmail { subject: summary.to_string(), html: html.into_string(), text }
+}
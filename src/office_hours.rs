@@ -0,0 +1,194 @@
+//! # Office Hours
+//!
+//! Opening hours of the team, so that SLA due times only count the time
+//! it is at work: a message received on Friday evening with 4 hours to be
+//! answered is due on Monday morning rather than during the weekend (see
+//! the `deadlines` module). Senders writing while the office is closed are
+//! told when it opens again (see the `acknowledgements` module).
+//!
+//! Office hours are set with `OFFICE_HOURS`, as comma-separated days or
+//! ranges of days followed by the opening and closing times, in the IANA
+//! time zone `OFFICE_TIMEZONE`:
+//!
+//! - `Mon-Fri 09:00-18:00` - Weekdays from 9:00 to 18:00
+//! - `Mon-Thu 08:30-17:00, Fri 08:30-12:00` - Shorter Fridays
+//! - `Mon-Fri 09:00-12:00, Mon-Fri 13:00-17:00` - Closed for lunch
+//!
+//! The dates listed in `OFFICE_HOLIDAYS` (`2026-12-25,2027-01-01`) are
+//! closed all day. Opening hours cannot span midnight: split them over two
+//! days instead.
+//!
+//! Without `OFFICE_HOURS`, SLA hours run around the clock.
+//!
+//! The hours are those of the whole deployment: messages carry no tenant,
+//! so there are no per-tenant office hours.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+/// Days searched for an opening before giving up, so that a calendar
+/// closed for good cannot loop forever.
+const MAX_DAYS_SEARCHED: u32 = 3660;
+
+/// Opening hours of the team, in its time zone.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{TimeZone, Utc};
+/// use dothtml_backend::office_hours::OfficeHours;
+///
+/// let hours = OfficeHours::from_settings("Mon-Fri 09:00-18:00", "UTC", "2026-10-19").unwrap();
+///
+/// // Received on Friday at 17:00: one hour on Friday, three on Tuesday after the holiday
+/// let friday = Utc.with_ymd_and_hms(2026, 10, 16, 17, 0, 0).unwrap();
+/// assert_eq!(hours.add_hours(friday, 4), Utc.with_ymd_and_hms(2026, 10, 20, 12, 0, 0).unwrap());
+///
+/// // Received during the weekend: counted from the next opening
+/// let saturday = Utc.with_ymd_and_hms(2026, 10, 24, 10, 0, 0).unwrap();
+/// assert_eq!(hours.add_hours(saturday, 4), Utc.with_ymd_and_hms(2026, 10, 26, 13, 0, 0).unwrap());
+///
+/// assert!(OfficeHours::from_settings("Mon-Fri 18:00-09:00", "UTC", "").is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct OfficeHours {
+    timezone: Tz,
+    /// Opening periods of each day of the week, from Monday, in order
+    days: [Vec<(NaiveTime, NaiveTime)>; 7],
+    holidays: BTreeSet<NaiveDate>,
+}
+
+impl OfficeHours {
+    /// Builds the office hours from the values of `OFFICE_HOURS`,
+    /// `OFFICE_TIMEZONE` and `OFFICE_HOLIDAYS`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first invalid value.
+    pub fn from_settings(hours: &str, timezone: &str, holidays: &str) -> Result<Self, String> {
+        let timezone = Tz::from_str(timezone.trim()).map_err(|_| format!("unknown time zone `{}`", timezone.trim()))?;
+
+        let mut days: [Vec<(NaiveTime, NaiveTime)>; 7] = Default::default();
+        for period in hours.split(',').map(str::trim).filter(|period| !period.is_empty()) {
+            let (weekdays, times) = period
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("`{}` is not written `Mon-Fri 09:00-18:00`", period))?;
+            let (opens, closes) = times
+                .trim()
+                .split_once('-')
+                .ok_or_else(|| format!("`{}` is not written `Mon-Fri 09:00-18:00`", period))?;
+            let time = |value: &str| {
+                NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| format!("invalid time `{}`", value.trim()))
+            };
+            let (opens, closes) = (time(opens)?, time(closes)?);
+            if opens >= closes {
+                return Err(format!("`{}` closes before it opens", period));
+            }
+
+            let weekday = |value: &str| {
+                Weekday::from_str(value.trim())
+                    .map(|day| day.num_days_from_monday() as usize)
+                    .map_err(|_| format!("invalid day `{}`", value.trim()))
+            };
+            let (first, last) = match weekdays.split_once('-') {
+                Some((first, last)) => (weekday(first)?, weekday(last)?),
+                None => (weekday(weekdays)?, weekday(weekdays)?),
+            };
+            if first > last {
+                return Err(format!("`{}` is not a range from Monday to Sunday", weekdays));
+            }
+            for day in &mut days[first..=last] {
+                day.push((opens, closes));
+            }
+        }
+        if days.iter().all(Vec::is_empty) {
+            return Err("no opening hours".to_string());
+        }
+        for periods in &mut days {
+            periods.sort();
+            if periods.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+                return Err("opening hours of the same day overlap".to_string());
+            }
+        }
+
+        let holidays = holidays
+            .split(',')
+            .map(str::trim)
+            .filter(|date| !date.is_empty())
+            .map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("invalid holiday `{}`", date)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(OfficeHours { timezone, days, holidays })
+    }
+
+    /// Returns whether the office is open at `at`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use chrono::{TimeZone, Utc};
+    /// use dothtml_backend::office_hours::OfficeHours;
+    ///
+    /// let hours = OfficeHours::from_settings("Mon-Fri 09:00-18:00", "Europe/Paris", "").unwrap();
+    /// let friday_evening = Utc.with_ymd_and_hms(2026, 10, 16, 17, 0, 0).unwrap();
+    /// assert!(!hours.is_open(friday_evening));
+    /// assert_eq!(hours.next_opening(friday_evening), Utc.with_ymd_and_hms(2026, 10, 19, 7, 0, 0).unwrap());
+    /// assert_eq!(hours.format_local(hours.next_opening(friday_evening)), "Monday, October 19 at 09:00 CEST");
+    /// ```
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        self.next_opening(at) == at
+    }
+
+    /// Returns the next time the office is open from `at`, `at` itself if
+    /// it is open then.
+    pub fn next_opening(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        self.add_hours(at, 0)
+    }
+
+    /// Formats `at` in the office time zone, for senders.
+    pub fn format_local(&self, at: DateTime<Utc>) -> String {
+        at.with_timezone(&self.timezone).format("%A, %B %-d at %H:%M %Z").to_string()
+    }
+
+    /// Returns the time `hours` office hours after `start`, counting from
+    /// the next opening when `start` is outside office hours.
+    pub fn add_hours(&self, start: DateTime<Utc>, hours: u32) -> DateTime<Utc> {
+        let mut remaining = Duration::hours(hours.into());
+        let mut day = start.with_timezone(&self.timezone).date_naive();
+
+        for _ in 0..MAX_DAYS_SEARCHED {
+            if !self.holidays.contains(&day) {
+                for &(opens, closes) in &self.days[day.weekday().num_days_from_monday() as usize] {
+                    let (opens, closes) = (self.instant(day, opens).max(start), self.instant(day, closes));
+                    if opens >= closes {
+                        continue;
+                    }
+                    if remaining <= closes - opens {
+                        return opens + remaining;
+                    }
+                    remaining -= closes - opens;
+                }
+            }
+            let Some(next) = day.succ_opt() else {
+                break;
+            };
+            day = next;
+        }
+        start + Duration::hours(hours.into())
+    }
+
+    /// Returns the instant `time` falls at on `day`, in the office time
+    /// zone. A time skipped by a daylight saving change is taken an hour
+    /// later.
+    fn instant(&self, day: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+        let local = day.and_time(time);
+        self.timezone
+            .from_local_datetime(&local)
+            .earliest()
+            .or_else(|| self.timezone.from_local_datetime(&(local + Duration::hours(1))).earliest())
+            .map_or_else(|| Utc.from_utc_datetime(&local), |instant| instant.with_timezone(&Utc))
+    }
+}
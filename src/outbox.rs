@@ -0,0 +1,520 @@
+//! # Transactional Outbox
+//!
+//! Reliable delivery of side effects (webhooks, emails, event streams) that
+//! must happen after a message change is committed.
+//!
+//! Instead of calling external systems directly, database methods write an
+//! entry to the `outbox` table in the same transaction as the change. A
+//! relay job then claims a batch of pending entries for a few minutes (see
+//! [`CLAIM_LEASE_SECS`]), hands them to a [`Publisher`] outside of any
+//! transaction and marks them as published. If the process crashes between
+//! publishing and marking, the entry is published again once its claim
+//! expires: delivery is at-least-once, and each entry carries a
+//! `dedup_key` that consumers use to drop duplicates.
+//!
+//! An entry still failing after [`MAX_PUBLISH_ATTEMPTS`] attempts is given
+//! up and becomes a dead letter (see the `dead_letters` module).
+//!
+//! Every message lifecycle event (see the `events` module) is enqueued on
+//! the `message.<event_type>` topic with the dedup key
+//! `message_event:<event id>`.
+//!
+//! ## Publishers
+//!
+//! - [`LogPublisher`] - Writes entries to the server log, for development
+//! - `NatsPublisher` - Publishes to NATS JetStream subjects (requires the `nats` feature)
+//! - `KafkaPublisher` - Produces to Kafka topics (requires the `kafka` feature)
+//! - [`WebhookPublisher`](crate::webhooks::WebhookPublisher) - Posts entries to the endpoints
+//!   registered with `/admin/webhooks`, signed with their secrets (see the `webhooks` module)
+//!
+//! The broker publishers send each entry to `<EVENT_TOPIC_PREFIX>.<topic>`,
+//! e.g. `dothtml.message.created`, with the JSON payload as body.
+//!
+//! ## Notification emails
+//!
+//! Entries on the `notification.email` topic (see the `notifications`
+//! module) never reach the publisher. A second relay hands them to the mail
+//! queue instead (see [`RelayScope`]), so that an email is only lost if the
+//! server stops while it waits in the queue, not between the commit and
+//! the queueing. Without a mailer, that relay drops them.
+
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{PgExecutor, Row};
+
+use crate::config::AppConfig;
+use crate::database::Database;
+use crate::dead_letters::{DeadLetterKind, NewDeadLetter};
+use crate::notifications::EMAIL_TOPIC;
+use crate::webhooks::WebhookPublisher;
+
+/// Attempts made to publish an entry before giving it up.
+pub const MAX_PUBLISH_ATTEMPTS: i32 = 10;
+
+/// Time a relay has to publish the entries it claimed before other relays
+/// may claim them again, in seconds.
+pub const CLAIM_LEASE_SECS: f64 = 5.0 * 60.0;
+
+/// Longest delay between two delivery attempts of a failing entry, in seconds.
+const MAX_RETRY_DELAY_SECS: f64 = 60.0 * 60.0;
+
+/// An entry waiting in, or relayed from, the outbox.
+///
+/// # Fields
+///
+/// * `id` - Position of the entry in the outbox
+/// * `topic` - Destination of the entry, e.g. `message.created`
+/// * `dedup_key` - Unique key identifying the entry across redeliveries
+/// * `payload` - Content to publish
+/// * `created_at` - Timestamp when the entry was enqueued
+/// * `attempts` - Number of delivery attempts made before this one
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub topic: String,
+    pub dedup_key: String,
+    pub payload: Value,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+}
+
+/// Entries a relay hands to its publisher.
+///
+/// * `External` - Every entry but the notification emails, for the publisher of `OUTBOX_PUBLISHER`
+/// * `Emails` - The notification emails only, for the mail queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayScope {
+    External,
+    Emails,
+}
+
+impl RelayScope {
+    /// Returns where the entries go, as recorded on dead letters.
+    pub fn destination(&self) -> &'static str {
+        match self {
+            RelayScope::External => "outbox_publisher",
+            RelayScope::Emails => "mail_queue",
+        }
+    }
+}
+
+/// A destination for outbox entries.
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Publishes one entry. Returning an error schedules a retry.
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()>;
+}
+
+/// Publisher writing every entry to the server log.
+#[derive(Debug, Clone, Default)]
+pub struct LogPublisher;
+
+#[async_trait]
+impl Publisher for LogPublisher {
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()> {
+        println!("[outbox] {} {} {}", entry.topic, entry.dedup_key, entry.payload);
+        Ok(())
+    }
+}
+
+/// Publisher dropping every entry: the email relay uses it when the mailer
+/// is disabled, so that notification emails do not pile up in the outbox.
+#[derive(Debug, Clone, Default)]
+pub struct DiscardPublisher;
+
+#[async_trait]
+impl Publisher for DiscardPublisher {
+    async fn publish(&self, _entry: &OutboxEntry) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Publisher sending entries to NATS JetStream.
+///
+/// Every publish waits for the stream acknowledgement, and the entry's
+/// dedup key is sent as `Nats-Msg-Id` so that JetStream drops redeliveries
+/// within the stream's duplicate window. A stream must capture the
+/// `<prefix>.>` subjects, otherwise publishing fails and is retried.
+#[cfg(feature = "nats")]
+pub struct NatsPublisher {
+    jetstream: async_nats::jetstream::Context,
+    prefix: String,
+}
+
+#[cfg(feature = "nats")]
+impl NatsPublisher {
+    /// Connects to the NATS server at `url`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the server cannot be reached.
+    pub async fn connect(url: &str, prefix: &str) -> io::Result<Self> {
+        let client = async_nats::connect(url).await.map_err(io::Error::other)?;
+        Ok(NatsPublisher {
+            jetstream: async_nats::jetstream::new(client),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "nats")]
+#[async_trait]
+impl Publisher for NatsPublisher {
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()> {
+        let publish = async_nats::jetstream::context::Publish::build()
+            .payload(serde_json::to_vec(&entry.payload)?.into())
+            .message_id(&entry.dedup_key);
+
+        self.jetstream
+            .send_publish(format!("{}.{}", self.prefix, entry.topic), publish)
+            .await
+            .map_err(io::Error::other)?
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Publisher producing entries to Kafka.
+///
+/// Records are keyed by message ID so that the events of one message stay
+/// ordered within a partition, and carry the dedup key in a `dedup_key`
+/// header. The producer is idempotent, which removes duplicates caused by
+/// its own retries; redeliveries from the outbox must be dropped by
+/// consumers using the header.
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    producer: rdkafka::producer::FutureProducer,
+    prefix: String,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    /// Creates a producer for the comma-separated `brokers` list.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the producer configuration is invalid.
+    pub fn new(brokers: &str, prefix: &str) -> io::Result<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .create()
+            .map_err(io::Error::other)?;
+        Ok(KafkaPublisher { producer, prefix: prefix.to_string() })
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl Publisher for KafkaPublisher {
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()> {
+        use rdkafka::message::{Header, OwnedHeaders};
+        use rdkafka::producer::FutureRecord;
+
+        let topic = format!("{}.{}", self.prefix, entry.topic);
+        let key = entry.payload.get("message_id").and_then(Value::as_str).unwrap_or(&entry.dedup_key);
+        let payload = serde_json::to_vec(&entry.payload)?;
+        let headers = OwnedHeaders::new().insert(Header { key: "dedup_key", value: Some(&entry.dedup_key) });
+
+        let record = FutureRecord::to(&topic).key(key).payload(&payload).headers(headers);
+        self.producer
+            .send(record, std::time::Duration::from_secs(30))
+            .await
+            .map_err(|(e, _)| io::Error::other(e))?;
+        Ok(())
+    }
+}
+
+/// Builds the publisher selected by `OUTBOX_PUBLISHER`.
+///
+/// # Arguments
+///
+/// * `config` - Application configuration
+/// * `db` - Database storing the webhook deliveries, for the `webhooks` publisher
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when no publisher is configured. Entries then stay in
+/// the outbox until one is.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `OUTBOX_PUBLISHER` names an unknown publisher
+/// - `nats` or `kafka` is selected without the matching feature or broker URL
+/// - The broker cannot be reached
+pub async fn publisher_from_config(config: &AppConfig, db: &Database) -> io::Result<Option<Arc<dyn Publisher>>> {
+    match config.outbox_publisher.as_deref() {
+        None => Ok(None),
+        Some("log") => Ok(Some(Arc::new(LogPublisher))),
+        Some("webhooks") => Ok(Some(Arc::new(WebhookPublisher::new(db.clone())))),
+        #[cfg(feature = "nats")]
+        Some("nats") => {
+            let publisher = NatsPublisher::connect(broker_url(config, "nats")?, &config.event_topic_prefix).await?;
+            Ok(Some(Arc::new(publisher)))
+        }
+        #[cfg(not(feature = "nats"))]
+        Some("nats") => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "OUTBOX_PUBLISHER=nats requires building with the `nats` feature",
+        )),
+        #[cfg(feature = "kafka")]
+        Some("kafka") => {
+            let publisher = KafkaPublisher::new(broker_url(config, "kafka")?, &config.event_topic_prefix)?;
+            Ok(Some(Arc::new(publisher)))
+        }
+        #[cfg(not(feature = "kafka"))]
+        Some("kafka") => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "OUTBOX_PUBLISHER=kafka requires building with the `kafka` feature",
+        )),
+        Some(other) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown OUTBOX_PUBLISHER: {}", other))),
+    }
+}
+
+/// Returns `EVENT_BROKER_URL`, which the broker publishers require.
+#[cfg(any(feature = "nats", feature = "kafka"))]
+fn broker_url<'a>(config: &'a AppConfig, publisher: &str) -> io::Result<&'a str> {
+    config.event_broker_url.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("EVENT_BROKER_URL must be set when OUTBOX_PUBLISHER={}", publisher),
+        )
+    })
+}
+
+/// Database operations for the outbox.
+impl Database {
+    /// Creates the 'outbox' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_outbox_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_outbox_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS outbox (
+                id BIGSERIAL PRIMARY KEY,
+                topic TEXT NOT NULL,
+                dedup_key TEXT NOT NULL UNIQUE,
+                payload JSONB NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                last_error TEXT,
+                published_at TIMESTAMPTZ
+            );
+
+            ALTER TABLE outbox ADD COLUMN IF NOT EXISTS locked_until TIMESTAMPTZ;
+            ALTER TABLE outbox ADD COLUMN IF NOT EXISTS failed_at TIMESTAMPTZ;
+
+            CREATE INDEX IF NOT EXISTS outbox_pending_idx
+                ON outbox (next_attempt_at, id)
+                WHERE published_at IS NULL;
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Adds an entry to the outbox.
+    ///
+    /// Takes any executor so that it runs in the transaction whose commit
+    /// the entry depends on. Enqueuing a `dedup_key` that is already in the
+    /// outbox does nothing.
+    pub(crate) async fn enqueue_outbox<'e, E: PgExecutor<'e>>(
+        executor: E,
+        topic: &str,
+        dedup_key: &str,
+        payload: &Value,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO outbox (topic, dedup_key, payload)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (dedup_key) DO NOTHING
+        "#)
+        .bind(topic)
+        .bind(dedup_key)
+        .bind(payload)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Publishes up to `batch_size` pending entries of `scope`, oldest first.
+    ///
+    /// Entries are claimed for [`CLAIM_LEASE_SECS`] in a first short
+    /// transaction, so that several relays can run concurrently without
+    /// publishing the same entry twice, then published without holding any
+    /// lock or connection, and marked in a second short transaction. A
+    /// failed entry is retried later with an exponential backoff capped at
+    /// one hour, and given up after [`MAX_PUBLISH_ATTEMPTS`] attempts,
+    /// becoming a dead letter. The entries after it are still published, so
+    /// consumers should order events by their own IDs rather than by arrival.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of entries that were published.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    /// Publisher failures are recorded on the entry instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::outbox::{LogPublisher, RelayScope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let published = db.relay_outbox(&LogPublisher, RelayScope::External, 100).await?;
+    ///     println!("Published {} entries", published);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn relay_outbox(
+        &self,
+        publisher: &dyn Publisher,
+        scope: RelayScope,
+        batch_size: i64,
+    ) -> Result<usize, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            UPDATE outbox
+            SET locked_until = NOW() + make_interval(secs => $4)
+            WHERE id IN (
+                SELECT id
+                FROM outbox
+                WHERE published_at IS NULL
+                  AND failed_at IS NULL
+                  AND next_attempt_at <= NOW()
+                  AND (locked_until IS NULL OR locked_until <= NOW())
+                  AND (topic = $2) = $3
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, topic, dedup_key, payload, created_at, attempts
+        "#)
+        .bind(batch_size)
+        .bind(EMAIL_TOPIC)
+        .bind(scope == RelayScope::Emails)
+        .bind(CLAIM_LEASE_SECS)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries: Vec<OutboxEntry> = rows
+            .iter()
+            .map(|row| OutboxEntry {
+                id: row.get("id"),
+                topic: row.get("topic"),
+                dedup_key: row.get("dedup_key"),
+                payload: row.get("payload"),
+                created_at: row.get("created_at"),
+                attempts: row.get("attempts"),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.id);
+
+        let mut outcomes = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            outcomes.push(publisher.publish(entry).await);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut published = 0;
+        for (entry, outcome) in entries.iter().zip(outcomes) {
+            match outcome {
+                Ok(()) => {
+                    sqlx::query(r#"
+                        UPDATE outbox
+                        SET published_at = NOW(), attempts = attempts + 1, last_error = NULL, locked_until = NULL
+                        WHERE id = $1
+                    "#)
+                    .bind(entry.id)
+                    .execute(&mut *tx)
+                    .await?;
+                    published += 1;
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    let row = sqlx::query(r#"
+                        UPDATE outbox
+                        SET attempts = attempts + 1,
+                            last_error = $2,
+                            locked_until = NULL,
+                            next_attempt_at = NOW() + make_interval(secs => LEAST(power(2, attempts), $3)),
+                            failed_at = CASE WHEN attempts + 1 >= $4 THEN NOW() END
+                        WHERE id = $1
+                        RETURNING attempts, failed_at IS NOT NULL AS given_up
+                    "#)
+                    .bind(entry.id)
+                    .bind(&error)
+                    .bind(MAX_RETRY_DELAY_SECS)
+                    .bind(MAX_PUBLISH_ATTEMPTS)
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+                    if row.get::<bool, _>("given_up") {
+                        Self::insert_dead_letter(&mut *tx, &NewDeadLetter {
+                            kind: DeadLetterKind::Outbox,
+                            source_id: Some(entry.id),
+                            topic: &entry.topic,
+                            destination: scope.destination(),
+                            payload: &entry.payload,
+                            error: &error,
+                            attempts: row.get("attempts"),
+                        })
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(published)
+    }
+
+    /// Deletes entries published more than `after_days` days ago.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of deleted entries.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn purge_published_outbox(&self, after_days: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM outbox WHERE published_at < NOW() - make_interval(days => $1)")
+            .bind(after_days as i32)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
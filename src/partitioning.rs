@@ -0,0 +1,361 @@
+//! # Messages Partitioning
+//!
+//! Optional monthly partitioning of the `messages` table, for deployments
+//! that keep years of messages. Each month of `created_at` (in UTC) goes to
+//! its own partition, named `messages_yYYYYmMM`, and rows outside the
+//! prepared months go to `messages_default`. Queries keep using `messages`
+//! unchanged.
+//!
+//! A table is converted once, during a maintenance window since the table
+//! is locked while its rows are copied:
+//!
+//! ```bash
+//! dothtml-backend partition-messages
+//! ```
+//!
+//! Afterwards, a daily job on the leader creates the partition of the next
+//! month ahead of time, and with `MESSAGE_RETENTION_MONTHS` drops the
+//! partitions of the months older than the retention, instead of deleting
+//! the rows one by one. Dropped messages get a `deleted` event like purged
+//! ones.
+//!
+//! PostgreSQL cannot enforce a foreign key to a partitioned table whose
+//! primary key includes the partition key, so the conversion replaces the
+//! foreign keys pointing at `messages`: a trigger removes the assignment
+//! history of deleted messages, and the queries already clear `merged_into`
+//! before deleting a message.
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Utc};
+use serde_json::json;
+use sqlx::{PgConnection, Row};
+
+use crate::caching::RESOURCE_COMPANIES;
+use crate::database::Database;
+use crate::events::MessageEventKind;
+use crate::indexes::REQUIRED_INDEXES;
+
+/// Name of the partition receiving the rows outside the prepared months.
+const DEFAULT_PARTITION: &str = "messages_default";
+
+/// Transaction setting telling the assignment history trigger that rows are
+/// moved between partitions, not deleted.
+const MOVING_ROWS_SETTING: &str = "dothtml.moving_partition_rows";
+
+/// Returns the name of the partition holding the month starting on `month`.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::NaiveDate;
+/// use dothtml_backend::partitioning::partition_name;
+///
+/// assert_eq!(partition_name(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()), "messages_y2024m03");
+/// ```
+pub fn partition_name(month: NaiveDate) -> String {
+    format!("messages_y{:04}m{:02}", month.year(), month.month())
+}
+
+/// Returns the month of a partition name, the reverse of [`partition_name`].
+fn partition_month(name: &str) -> Option<NaiveDate> {
+    let rest = name.strip_prefix("messages_y")?;
+    let (year, month) = rest.split_once('m')?;
+    NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)
+}
+
+/// Returns the first day of the month of `date`.
+fn month_start(date: DateTime<Utc>) -> NaiveDate {
+    date.date_naive().with_day(1).unwrap_or_default()
+}
+
+/// Returns midnight UTC at the start of `month`.
+fn month_bound(month: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&month.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+/// Database operations for the partitioning of the messages table.
+impl Database {
+    /// Checks whether the `messages` table is partitioned.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn messages_partitioned(&self) -> Result<bool, sqlx::Error> {
+        self.is_partitioned("messages").await
+    }
+
+    /// Checks whether a table is partitioned.
+    pub(crate) async fn is_partitioned(&self, table: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM pg_partitioned_table p
+                JOIN pg_class c ON c.oid = p.partrelid
+                WHERE c.relname = $1 AND pg_table_is_visible(c.oid)
+            )
+        "#)
+        .bind(table)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Converts the `messages` table to a table partitioned by month.
+    ///
+    /// Creates one partition per month from the oldest message to the next
+    /// month, copies the rows, then replaces the old table, its indexes and
+    /// the foreign keys pointing at it. Everything happens in one
+    /// transaction holding an exclusive lock on the table, so the server
+    /// waits for the conversion to finish.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of monthly partitions created.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The table is already partitioned (`sqlx::Error::Protocol`)
+    /// - Database connection issues occur, in which case nothing changes
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let partitions = db.partition_messages_table().await?;
+    ///     println!("Created {} partitions", partitions);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn partition_messages_table(&self) -> Result<usize, sqlx::Error> {
+        if self.messages_partitioned().await? {
+            return Err(sqlx::Error::Protocol("The messages table is already partitioned".to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::raw_sql(r#"
+            LOCK TABLE messages IN ACCESS EXCLUSIVE MODE;
+            ALTER TABLE assignment_history DROP CONSTRAINT IF EXISTS assignment_history_message_id_fkey;
+            ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_merged_into_fkey;
+
+            CREATE TABLE messages_partitioned (
+                LIKE messages INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+                PRIMARY KEY (id, created_at)
+            ) PARTITION BY RANGE (created_at);
+        "#)
+        .execute(&mut *tx)
+        .await?;
+
+        let oldest: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT MIN(created_at) FROM messages")
+            .fetch_one(&mut *tx)
+            .await?;
+        let last = month_start(Utc::now()) + Months::new(1);
+        let mut month = oldest.map(month_start).unwrap_or(last).min(last);
+        let mut partitions = 0;
+        while month <= last {
+            Self::create_partition(&mut tx, "messages_partitioned", month).await?;
+            month = month + Months::new(1);
+            partitions += 1;
+        }
+
+        sqlx::raw_sql(&format!(r#"
+            CREATE TABLE {DEFAULT_PARTITION} PARTITION OF messages_partitioned DEFAULT;
+
+            INSERT INTO messages_partitioned SELECT * FROM messages;
+            DROP TABLE messages;
+            ALTER TABLE messages_partitioned RENAME TO messages;
+            ALTER TABLE messages RENAME CONSTRAINT messages_partitioned_pkey TO messages_pkey;
+            ALTER TABLE messages
+                ADD CONSTRAINT messages_company_id_fkey
+                FOREIGN KEY (company_id) REFERENCES companies(id) ON DELETE SET NULL;
+
+            CREATE OR REPLACE FUNCTION delete_message_assignment_history() RETURNS trigger
+            LANGUAGE plpgsql AS $$
+            BEGIN
+                IF current_setting('{MOVING_ROWS_SETTING}', true) IS DISTINCT FROM 'on' THEN
+                    DELETE FROM assignment_history WHERE message_id = OLD.id;
+                END IF;
+                RETURN OLD;
+            END
+            $$;
+            CREATE TRIGGER messages_delete_assignment_history
+                AFTER DELETE ON messages
+                FOR EACH ROW EXECUTE FUNCTION delete_message_assignment_history();
+        "#))
+        .execute(&mut *tx)
+        .await?;
+
+        // Indexes on a partitioned table cannot be built concurrently, and the table is locked anyway
+        for index in REQUIRED_INDEXES.iter().filter(|index| index.table == "messages") {
+            sqlx::query(&index.statement(false))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(partitions)
+    }
+
+    /// Creates the partitions of the current month and of the next
+    /// `months_ahead` months that do not exist yet.
+    ///
+    /// Rows of a new month already stored in the default partition are
+    /// moved to the new partition.
+    ///
+    /// # Returns
+    ///
+    /// Returns the names of the created partitions.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn create_message_partitions(&self, months_ahead: u32) -> Result<Vec<String>, sqlx::Error> {
+        let existing = self.list_message_partitions().await?;
+        let current = month_start(Utc::now());
+
+        let mut created = Vec::new();
+        for offset in 0..=months_ahead {
+            let month = current + Months::new(offset);
+            let name = partition_name(month);
+            if existing.contains(&month) {
+                continue;
+            }
+
+            let mut tx = self.pool.begin().await?;
+            // A partition cannot be added while the default one holds rows of its range
+            let stray: bool = sqlx::query_scalar(&format!(
+                "SELECT EXISTS (SELECT 1 FROM {DEFAULT_PARTITION} WHERE created_at >= $1 AND created_at < $2)"
+            ))
+            .bind(month_bound(month))
+            .bind(month_bound(month + Months::new(1)))
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if stray {
+                sqlx::query(&format!("SET LOCAL {MOVING_ROWS_SETTING} = 'on'")).execute(&mut *tx).await?;
+                sqlx::query(&format!("ALTER TABLE messages DETACH PARTITION {DEFAULT_PARTITION}"))
+                    .execute(&mut *tx)
+                    .await?;
+                Self::create_partition(&mut tx, "messages", month).await?;
+                sqlx::query(&format!(r#"
+                    WITH moved AS (
+                        DELETE FROM {DEFAULT_PARTITION}
+                        WHERE created_at >= $1 AND created_at < $2
+                        RETURNING *
+                    )
+                    INSERT INTO {name} SELECT * FROM moved
+                "#))
+                .bind(month_bound(month))
+                .bind(month_bound(month + Months::new(1)))
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query(&format!("ALTER TABLE messages ATTACH PARTITION {DEFAULT_PARTITION} DEFAULT"))
+                    .execute(&mut *tx)
+                    .await?;
+            } else {
+                Self::create_partition(&mut tx, "messages", month).await?;
+            }
+
+            tx.commit().await?;
+            created.push(name);
+        }
+        Ok(created)
+    }
+
+    /// Drops the partitions of the months that ended more than
+    /// `retention_months` months before the current one.
+    ///
+    /// The messages are removed like purged ones: duplicates merged into
+    /// them are detached, their assignment history and overflowed bodies
+    /// are deleted, and a permanent `deleted` event is recorded for each.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of messages removed.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let removed = db.drop_expired_message_partitions(24).await?;
+    ///     println!("Removed {} messages", removed);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn drop_expired_message_partitions(&self, retention_months: u32) -> Result<u64, sqlx::Error> {
+        let cutoff = month_start(Utc::now()) - Months::new(retention_months);
+
+        let mut removed = 0;
+        for month in self.list_message_partitions().await? {
+            if month >= cutoff {
+                continue;
+            }
+            let name = partition_name(month);
+
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(&format!("UPDATE messages SET merged_into = NULL WHERE merged_into IN (SELECT id FROM {name})"))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&format!("DELETE FROM assignment_history WHERE message_id IN (SELECT id FROM {name})"))
+                .execute(&mut *tx)
+                .await?;
+
+            let rows = sqlx::query(&format!("SELECT id, body_ref FROM {name}")).fetch_all(&mut *tx).await?;
+            for row in &rows {
+                Self::record_event(&mut tx, row.get("id"), MessageEventKind::Deleted, json!({ "permanent": true })).await?;
+            }
+
+            sqlx::query(&format!("ALTER TABLE messages DETACH PARTITION {name}")).execute(&mut *tx).await?;
+            sqlx::query(&format!("DROP TABLE {name}")).execute(&mut *tx).await?;
+            if !rows.is_empty() {
+                Self::touch_resource(&mut *tx, RESOURCE_COMPANIES).await?;
+            }
+            tx.commit().await?;
+
+            let body_refs: Vec<String> = rows.iter().filter_map(|row| row.get("body_ref")).collect();
+            self.delete_blobs(&body_refs).await;
+            removed += rows.len() as u64;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the months of the existing monthly partitions, oldest first.
+    async fn list_message_partitions(&self) -> Result<Vec<NaiveDate>, sqlx::Error> {
+        let names: Vec<String> = sqlx::query_scalar(r#"
+            SELECT c.relname
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = 'messages'::regclass
+        "#)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut months: Vec<NaiveDate> = names.iter().filter_map(|name| partition_month(name)).collect();
+        months.sort();
+        Ok(months)
+    }
+
+    /// Creates the partition of `parent` holding the month starting on `month`.
+    async fn create_partition(conn: &mut PgConnection, parent: &str, month: NaiveDate) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "CREATE TABLE {} PARTITION OF {} FOR VALUES FROM ('{}') TO ('{}')",
+            partition_name(month),
+            parent,
+            month_bound(month).to_rfc3339(),
+            month_bound(month + Months::new(1)).to_rfc3339(),
+        ))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
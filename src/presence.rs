@@ -0,0 +1,205 @@
+//! # Presence Roster
+//!
+//! Agents currently connected to the live inbox (see the `live` module),
+//! listed by `GET /inbox/agents/online` so that the backoffice can show who
+//! is around.
+//!
+//! Each WebSocket connection is recorded in the `agent_presence` table when
+//! it opens, refreshed on every heartbeat and removed when it closes, so
+//! the roster is shared by the replicas. An agent is online while one of
+//! their connections was seen within the last [`PRESENCE_TTL_SECS`]
+//! seconds, which also covers the connections of a replica that stopped
+//! without closing them.
+//!
+//! With the [`ONLINE_ASSIGNMENT_FLAG`] feature flag enabled, the routing
+//! rules (see the `rules` module) only assign messages to online agents: an
+//! offline assignee is skipped for the next matching rule setting one, and
+//! the message stays pending when none is online.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+
+/// Seconds after its last heartbeat a connection stops counting, three
+/// times the heartbeat interval of the live inbox.
+pub const PRESENCE_TTL_SECS: f64 = 90.0;
+
+/// Feature flag making the routing rules only assign to online agents.
+pub const ONLINE_ASSIGNMENT_FLAG: &str = "online_assignment";
+
+/// An agent connected to the live inbox.
+///
+/// # Fields
+///
+/// * `account` - Name of the agent
+/// * `connections` - Number of open connections, e.g. browser tabs
+/// * `online_since` - When the oldest open connection was opened
+/// * `last_seen_at` - Last heartbeat of any of the connections
+#[derive(Debug, Clone, Serialize)]
+pub struct OnlineAgent {
+    pub account: String,
+    pub connections: i64,
+    #[serde(with = "crate::timestamp")]
+    pub online_since: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// Database operations for the presence roster.
+impl Database {
+    /// Creates the 'agent_presence' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_agent_presence_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS agent_presence (
+                connection_id UUID PRIMARY KEY,
+                account TEXT NOT NULL,
+                connected_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE INDEX IF NOT EXISTS agent_presence_account_idx ON agent_presence (account, last_seen_at);
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a connection as opened or still alive.
+    ///
+    /// Opening a connection also removes the connections left behind by
+    /// stopped replicas.
+    ///
+    /// # Arguments
+    ///
+    /// * `connection_id` - Identifier of the connection
+    /// * `account` - The connected agent
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn record_presence(&self, connection_id: Uuid, account: &str) -> Result<(), sqlx::Error> {
+        let result = sqlx::query("UPDATE agent_presence SET last_seen_at = NOW() WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            return Ok(());
+        }
+
+        sqlx::query("DELETE FROM agent_presence WHERE last_seen_at < NOW() - make_interval(secs => $1)")
+            .bind(PRESENCE_TTL_SECS)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("INSERT INTO agent_presence (connection_id, account) VALUES ($1, $2)")
+            .bind(connection_id)
+            .bind(account)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes a closed connection from the roster.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn end_presence(&self, connection_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM agent_presence WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Lists the online agents, by name.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     for agent in db.list_online_agents().await? {
+    ///         println!("{} is online in {} tabs", agent.account, agent.connections);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_online_agents(&self) -> Result<Vec<OnlineAgent>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT account, COUNT(*) AS connections, MIN(connected_at) AS online_since, MAX(last_seen_at) AS last_seen_at
+            FROM agent_presence
+            WHERE last_seen_at >= NOW() - make_interval(secs => $1)
+            GROUP BY account
+            ORDER BY account
+        "#)
+        .bind(PRESENCE_TTL_SECS)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| OnlineAgent {
+                account: row.get("account"),
+                connections: row.get("connections"),
+                online_since: row.get("online_since"),
+                last_seen_at: row.get("last_seen_at"),
+            })
+            .collect())
+    }
+
+    /// Picks the assignee of a message among `candidates`, in order: the
+    /// first one, or the first online one with the [`ONLINE_ASSIGNMENT_FLAG`]
+    /// flag enabled.
+    ///
+    /// Reads the flag from the table rather than the cache of the
+    /// `FeatureFlags`, as it runs in the transaction storing the message.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` when there is no candidate to assign.
+    pub(crate) async fn pick_assignee(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, candidates: &[String]
+    ) -> Result<Option<String>, sqlx::Error> {
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let row = sqlx::query(r#"
+            SELECT candidate
+            FROM unnest($1::text[]) WITH ORDINALITY AS c (candidate, position)
+            WHERE NOT COALESCE((SELECT enabled FROM feature_flags WHERE name = $2 AND tenant = ''), FALSE)
+               OR EXISTS (
+                   SELECT 1 FROM agent_presence p
+                   WHERE p.account = c.candidate AND p.last_seen_at >= NOW() - make_interval(secs => $3)
+               )
+            ORDER BY position
+            LIMIT 1
+        "#)
+        .bind(candidates)
+        .bind(ONLINE_ASSIGNMENT_FLAG)
+        .bind(PRESENCE_TTL_SECS)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        Ok(row.map(|row| row.get("candidate")))
+    }
+}
@@ -0,0 +1,201 @@
+//! # Public Statistics
+//!
+//! A few aggregate figures about the inbox that the website may show on a
+//! transparency page, such as "we received 1,240 messages this year and
+//! answered them in 6 hours on average", served by `GET /public/stats`.
+//!
+//! The endpoint is off unless a deployment opts in with `PUBLIC_STATS`.
+//! Only the curated set of [`PublicStats`] is exposed: counts of the
+//! current year and response times rounded to a tenth of an hour, left
+//! out while fewer than [`MIN_ANSWERED`] messages were answered, so that
+//! no figure points at a single conversation. Messages in the trash,
+//! quarantined, merged into another or classified as spam do not count.
+//!
+//! The figures are computed at most once every
+//! `PUBLIC_STATS_MAX_AGE_SECONDS` per replica (see [`PublicStatsCache`]),
+//! and served with the same `max-age` so that browsers and CDNs keep them.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::Serialize;
+use sqlx::Row;
+use tokio::sync::RwLock;
+
+use crate::database::Database;
+
+/// Answered messages below which response times are not published.
+pub const MIN_ANSWERED: i64 = 10;
+
+/// Aggregate figures of the current year, safe to publish.
+///
+/// A message counts as answered at its first reply, or when it was
+/// resolved if nobody replied through the backoffice.
+///
+/// # Fields
+///
+/// * `year` - Calendar year of the figures, in UTC
+/// * `received` - Messages received this year
+/// * `answered` - Messages received this year that were answered
+/// * `average_response_hours` - Average time to answer, `None` below [`MIN_ANSWERED`]
+/// * `median_response_hours` - Median time to answer, `None` below [`MIN_ANSWERED`]
+/// * `computed_at` - When the figures were computed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublicStats {
+    pub year: i32,
+    pub received: i64,
+    pub answered: i64,
+    pub average_response_hours: Option<f64>,
+    pub median_response_hours: Option<f64>,
+    #[serde(with = "crate::timestamp")]
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Converts a duration in seconds to hours rounded to one decimal.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::public_stats::rounded_hours;
+///
+/// assert_eq!(rounded_hours(6.0 * 3600.0 + 200.0), 6.1);
+/// assert_eq!(rounded_hours(90.0), 0.0);
+/// ```
+pub fn rounded_hours(seconds: f64) -> f64 {
+    (seconds / 360.0).round() / 10.0
+}
+
+/// Database operations for the public statistics.
+impl Database {
+    /// Computes the public statistics of the current year.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let stats = db.public_stats().await?;
+    ///     println!("{} messages received in {}", stats.received, stats.year);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[tracing::instrument(skip_all)]
+    pub async fn public_stats(&self) -> Result<PublicStats, sqlx::Error> {
+        let now = Utc::now();
+        let year_start = Utc.with_ymd_and_hms(now.year(), 1, 1, 0, 0, 0).unwrap();
+        let row = sqlx::query(r#"
+            WITH received AS (
+                SELECT
+                    m.created_at,
+                    LEAST(
+                        (SELECT MIN(e.created_at) FROM message_events e
+                         WHERE e.message_id = m.id AND e.event_type = 'replied'),
+                        m.resolved_at
+                    ) AS answered_at
+                FROM messages m
+                WHERE m.created_at >= $1 AND m.deleted_at IS NULL AND m.merged_into IS NULL
+                  AND m.status <> 'quarantine' AND m.category IS DISTINCT FROM 'spam'
+            )
+            SELECT
+                COUNT(*) AS received,
+                COUNT(answered_at) AS answered,
+                EXTRACT(EPOCH FROM AVG(answered_at - created_at))::FLOAT8 AS average_seconds,
+                EXTRACT(EPOCH FROM percentile_cont(0.5) WITHIN GROUP (ORDER BY answered_at - created_at))::FLOAT8
+                    AS median_seconds
+            FROM received
+        "#)
+        .bind(year_start)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let answered: i64 = row.get("answered");
+        let hours = |column: &str| {
+            row.get::<Option<f64>, _>(column)
+                .filter(|_| answered >= MIN_ANSWERED)
+                .map(rounded_hours)
+        };
+        Ok(PublicStats {
+            year: now.year(),
+            received: row.get("received"),
+            answered,
+            average_response_hours: hours("average_seconds"),
+            median_response_hours: hours("median_seconds"),
+            computed_at: now,
+        })
+    }
+}
+
+/// Public statistics kept in memory between two computations.
+///
+/// Shared by the workers of a replica through `web::Data`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use dothtml_backend::database::Database;
+/// use dothtml_backend::public_stats::PublicStatsCache;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), sqlx::Error> {
+///     let cache = PublicStatsCache::new(Database::new().await?);
+///     let stats = cache.stats(Duration::from_secs(3600)).await?;
+///     println!("{} messages answered", stats.answered);
+///     Ok(())
+/// }
+/// ```
+pub struct PublicStatsCache {
+    db: Database,
+    cache: RwLock<Option<(Instant, Arc<PublicStats>)>>,
+}
+
+impl PublicStatsCache {
+    /// Creates an empty cache, filled on the first lookup.
+    pub fn new(db: Database) -> Self {
+        PublicStatsCache { db, cache: RwLock::new(None) }
+    }
+
+    /// Returns the statistics, computing them again when they are older
+    /// than `max_age`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the database error when the statistics cannot be computed
+    /// and none were computed before; otherwise the previous ones are kept.
+    pub async fn stats(&self, max_age: Duration) -> Result<Arc<PublicStats>, sqlx::Error> {
+        if let Some((computed_at, stats)) = &*self.cache.read().await {
+            if computed_at.elapsed() < max_age {
+                return Ok(stats.clone());
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another request may have computed the statistics while this one waited
+        if let Some((computed_at, stats)) = &*cache {
+            if computed_at.elapsed() < max_age {
+                return Ok(stats.clone());
+            }
+        }
+
+        let stats = match self.db.public_stats().await {
+            Ok(stats) => Arc::new(stats),
+            Err(e) => match cache.as_ref() {
+                Some((_, stats)) => {
+                    eprintln!("Failed to compute the public statistics: {}", e);
+                    stats.clone()
+                }
+                None => return Err(e),
+            },
+        };
+        *cache = Some((Instant::now(), stats.clone()));
+        Ok(stats)
+    }
+}
@@ -0,0 +1,376 @@
+//! # Inbox Filter Expressions
+//!
+//! Parses the search syntax accepted by `GET /inbox?q=` and compiles it to
+//! parameterized SQL conditions for `Database::list_messages`.
+//!
+//! An expression is a whitespace-separated list of terms, all of which must
+//! match:
+//!
+//! - `status:pending` - Messages with the given status
+//! - `tag:sales` - Messages carrying the tag
+//! - `country:France` - Messages from the country/region (case-insensitive)
+//! - `category:sales` - Messages the classifier gave the category, `category:none` for
+//!   messages without one
+//! - `form:contact` - Messages submitted through the form
+//! - `company:"ACME Corp"` - Messages linked to the company, using the same
+//!   name normalization as the companies directory
+//! - `email:jane@example.com` - Messages from the sender (case-insensitive)
+//! - `assigned:alice` - Messages assigned to the agent, `assigned:none` for
+//!   unassigned ones
+//! - `after:2024-01-01` - Messages received on or after the day (UTC)
+//! - `before:2024-02-01` - Messages received before the day (UTC)
+//! - Any other word searches the sender name, email and message body
+//!
+//! Prefix a term with `-` to exclude its matches, and wrap values containing
+//! spaces in double quotes.
+//!
+//! ## Sorting
+//!
+//! `GET /inbox?sort=` picks the order of the listing with a [`MessageSort`]:
+//! `created_at`, `priority` or `status`, prefixed with `-` for descending
+//! order. Priorities rank from `low` to `urgent` and statuses follow the
+//! workflow (`quarantine`, `pending`, `assigned`, `resolved`, `merged`).
+//! Messages with the same rank are ordered by creation date in the same
+//! direction, so that keyset pagination keeps working.
+
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::models::{normalize_company_name, PRIORITIES};
+
+/// A parsed filter expression.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::query::FilterExpr;
+///
+/// let filter = FilterExpr::parse("status:pending tag:sales -country:US after:2024-01-01").unwrap();
+/// assert_eq!(filter.terms().len(), 4);
+/// assert!(filter.terms()[2].negated);
+///
+/// let error = FilterExpr::parse("status:pending colour:red").unwrap_err();
+/// assert_eq!(error.to_string(), "Unknown filter `colour` at column 16");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterExpr {
+    terms: Vec<Term>,
+}
+
+/// A single term of a filter expression.
+///
+/// # Fields
+///
+/// * `negated` - Whether matching messages are excluded instead of kept
+/// * `condition` - What the term matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub negated: bool,
+    pub condition: Condition,
+}
+
+/// What a filter term matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Status(String),
+    Tag(String),
+    Country(String),
+    Category(Option<String>),
+    Form(String),
+    Company(String),
+    Email(String),
+    AssignedTo(Option<String>),
+    After(NaiveDate),
+    Before(NaiveDate),
+    Text(String),
+}
+
+/// Error returned for malformed filter expressions.
+///
+/// # Fields
+///
+/// * `column` - 1-based position of the offending term in the expression
+/// * `message` - Human-readable description of the problem
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FilterExpr {
+    /// Parses a filter expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ParseError` if:
+    /// - A quoted value is not terminated
+    /// - A term uses an unknown filter name or has an empty value
+    /// - A date is not in the `YYYY-MM-DD` format
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let mut terms = Vec::new();
+        let mut chars = input.char_indices().peekable();
+
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            // Read up to the next whitespace that is not inside quotes
+            let mut end = input.len();
+            let mut in_quotes = false;
+            while let Some(&(index, c)) = chars.peek() {
+                if c.is_whitespace() && !in_quotes {
+                    end = index;
+                    break;
+                }
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                chars.next();
+            }
+
+            let column = input[..start].chars().count() + 1;
+            if in_quotes {
+                return Err(ParseError { column, message: "Unterminated quote".to_string() });
+            }
+            terms.push(Term::parse(&input[start..end], column)?);
+        }
+
+        Ok(FilterExpr { terms })
+    }
+
+    /// Returns the terms of the expression, in the order they were written.
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// Returns whether the expression has no terms and matches every message.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Appends the expression to a `WHERE` clause, as one `AND` condition
+    /// per term with every value passed as a bind parameter.
+    pub fn push_conditions(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        for term in &self.terms {
+            // NULL columns never match, so negated terms keep them
+            builder.push(if term.negated { " AND NOT COALESCE(" } else { " AND COALESCE(" });
+            term.condition.push_sql(builder);
+            builder.push(", FALSE)");
+        }
+    }
+}
+
+impl Term {
+    fn parse(raw: &str, column: usize) -> Result<Self, ParseError> {
+        let error = |message: String| ParseError { column, message };
+
+        let (negated, raw) = match raw.strip_prefix('-') {
+            Some(rest) if !rest.is_empty() => (true, rest),
+            _ => (false, raw),
+        };
+
+        let (key, value) = match raw.split_once(':') {
+            Some((key, value)) if !key.contains('"') => (Some(key), value),
+            _ => (None, raw),
+        };
+        let value = unquote(value);
+        if value.is_empty() {
+            return Err(error(match key {
+                Some(key) => format!("Missing value for `{}`", key),
+                None => "Empty search term".to_string(),
+            }));
+        }
+
+        let parse_date = |value: &str| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|_| error(format!("Invalid date `{}`, expected YYYY-MM-DD", value)))
+        };
+
+        let condition = match key.map(str::to_lowercase).as_deref() {
+            None => Condition::Text(value.to_string()),
+            Some("status") => Condition::Status(value.to_lowercase()),
+            Some("tag") => Condition::Tag(value.to_string()),
+            Some("country") => Condition::Country(value.to_string()),
+            Some("category") if value.eq_ignore_ascii_case("none") => Condition::Category(None),
+            Some("category") => Condition::Category(Some(value.to_lowercase())),
+            Some("form") => Condition::Form(value.to_lowercase()),
+            Some("company") => Condition::Company(value.to_string()),
+            Some("email") => Condition::Email(value.to_string()),
+            Some("assigned") if value.eq_ignore_ascii_case("none") => Condition::AssignedTo(None),
+            Some("assigned") => Condition::AssignedTo(Some(value.to_string())),
+            Some("after") => Condition::After(parse_date(value)?),
+            Some("before") => Condition::Before(parse_date(value)?),
+            Some(_) => return Err(error(format!("Unknown filter `{}`", key.unwrap_or_default()))),
+        };
+
+        Ok(Term { negated, condition })
+    }
+}
+
+impl Condition {
+    fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Condition::Status(status) => {
+                builder.push("status = ").push_bind(status.clone());
+            }
+            Condition::Tag(tag) => {
+                builder.push_bind(tag.clone()).push(" = ANY(tags)");
+            }
+            Condition::Country(country) => {
+                builder.push("lower(country_region) = lower(").push_bind(country.clone()).push(")");
+            }
+            Condition::Category(Some(category)) => {
+                builder.push("category = ").push_bind(category.clone());
+            }
+            Condition::Category(None) => {
+                builder.push("category IS NULL");
+            }
+            Condition::Form(form) => {
+                builder.push("form = ").push_bind(form.clone());
+            }
+            Condition::Company(company) => {
+                builder
+                    .push("company_id IN (SELECT company_id FROM company_aliases WHERE normalized_name = ")
+                    .push_bind(normalize_company_name(company))
+                    .push(")");
+            }
+            Condition::Email(email) => {
+                builder.push("lower(email) = lower(").push_bind(email.clone()).push(")");
+            }
+            Condition::AssignedTo(Some(agent)) => {
+                builder.push("assigned_to = ").push_bind(agent.clone());
+            }
+            Condition::AssignedTo(None) => {
+                builder.push("assigned_to IS NULL");
+            }
+            Condition::After(date) => {
+                builder.push("created_at >= ").push_bind(start_of_day(*date));
+            }
+            Condition::Before(date) => {
+                builder.push("created_at < ").push_bind(start_of_day(*date));
+            }
+            Condition::Text(text) => {
+                builder
+                    .push("strpos(lower(name || ' ' || email || ' ' || message), lower(")
+                    .push_bind(text.clone())
+                    .push(")) > 0");
+            }
+        }
+    }
+}
+
+/// Order of the inbox listing.
+///
+/// # Fields
+///
+/// * `key` - Column the messages are ranked by
+/// * `descending` - Whether the listing goes from the highest value to the lowest
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::query::MessageSort;
+///
+/// let sort: MessageSort = "-priority".parse().unwrap();
+/// assert!(sort.descending);
+/// assert_eq!(sort.to_string(), "-priority");
+/// assert_eq!(MessageSort::default().to_string(), "-created_at");
+/// assert!("name".parse::<MessageSort>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+/// Column the inbox listing is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    CreatedAt,
+    Priority,
+    Status,
+}
+
+/// Statuses in workflow order, as ranked by `sort=status`.
+const STATUS_ORDER: [&str; 5] = ["quarantine", "pending", "assigned", "resolved", "merged"];
+
+impl MessageSort {
+    /// Values accepted by `sort=`.
+    pub const ALLOWED: [&'static str; 6] = ["created_at", "-created_at", "priority", "-priority", "status", "-status"];
+
+    /// SQL expression ranking a message for this sort, or `None` when the
+    /// creation date alone orders the listing.
+    ///
+    /// Values outside the known list rank first (0), so that no message is
+    /// dropped from the keyset comparison.
+    pub fn rank_sql(&self) -> Option<String> {
+        let values: &[&str] = match self.key {
+            SortKey::CreatedAt => return None,
+            SortKey::Priority => &PRIORITIES,
+            SortKey::Status => &STATUS_ORDER,
+        };
+        let column = if self.key == SortKey::Priority { "priority" } else { "status" };
+        let array = values.iter().map(|value| format!("'{}'", value)).collect::<Vec<_>>().join(", ");
+        Some(format!("COALESCE(array_position(ARRAY[{array}], {column}), 0)"))
+    }
+}
+
+impl Default for MessageSort {
+    /// Newest messages first.
+    fn default() -> Self {
+        MessageSort { key: SortKey::CreatedAt, descending: true }
+    }
+}
+
+impl std::str::FromStr for MessageSort {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (descending, name) = match value.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, value),
+        };
+        let key = match name {
+            "created_at" => SortKey::CreatedAt,
+            "priority" => SortKey::Priority,
+            "status" => SortKey::Status,
+            _ => return Err(format!("Invalid sort `{}`, expected one of: {}", value, Self::ALLOWED.join(", "))),
+        };
+        Ok(MessageSort { key, descending })
+    }
+}
+
+impl fmt::Display for MessageSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.key {
+            SortKey::CreatedAt => "created_at",
+            SortKey::Priority => "priority",
+            SortKey::Status => "status",
+        };
+        write!(f, "{}{}", if self.descending { "-" } else { "" }, name)
+    }
+}
+
+/// Strips the double quotes around a value, if any.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
@@ -0,0 +1,264 @@
+//! # Satisfaction Ratings
+//!
+//! Asks the senders of resolved messages to rate the answer they got, from
+//! 1 to 5 with an optional comment.
+//!
+//! When `SATISFACTION_SURVEY=true` (and `SENDER_TOKEN_SECRET` is set),
+//! resolving a message enqueues a `message.rating_requested` entry in the
+//! outbox (see the `outbox` module) with the sender's email and a rating
+//! token (see the `tokens` module).
+//!
+//! With `RATING_PAGE_URL`, the server emails the sender one link per score
+//! to that page, e.g. `https://dotshell.eu/rate?token=<token>&score=4`, in
+//! the same transaction (see [`rating_email`]); senders on the
+//! do-not-contact list (see the `suppression` module) get none. Without it,
+//! the rating request is only an event, for a service mailing senders to
+//! consume. Either way, the page posts the score to
+//! `POST /contact/rating/{token}`. A message has a single rating: rating
+//! again replaces it, so a one-click score can be completed with a comment.
+//!
+//! Ratings are kept in the `ratings` table and summed up by
+//! `GET /admin/stats/satisfaction`.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use maud::html;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::models::Message;
+use crate::templates::RenderedEmail;
+
+/// Lowest score of a rating.
+pub const MIN_SCORE: i16 = 1;
+
+/// Highest score of a rating.
+pub const MAX_SCORE: i16 = 5;
+
+/// Outbox topic of rating requests.
+pub const RATING_REQUESTED_TOPIC: &str = "message.rating_requested";
+
+/// Returns the link of `RATING_PAGE_URL` giving `score` with `token`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::ratings::rating_link;
+///
+/// assert_eq!(rating_link("https://dotshell.eu/rate", "abc", 5), "https://dotshell.eu/rate?token=abc&score=5");
+/// assert_eq!(rating_link("https://dotshell.eu/?page=rate", "abc", 1), "https://dotshell.eu/?page=rate&token=abc&score=1");
+/// ```
+pub fn rating_link(url: &str, token: &str, score: i16) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}&score={}", url, separator, token, score)
+}
+
+/// Builds the email asking the sender of `message` to rate the answer,
+/// with one link per score to `page_url`.
+pub fn rating_email(message: &Message, page_url: &str, token: &str, expires_at: DateTime<Utc>) -> RenderedEmail {
+    let scores = MIN_SCORE..=MAX_SCORE;
+    let until = expires_at.format("%B %-d, %Y");
+    let text = format!(
+        "Hello {},\n\nYour message was answered. How satisfied are you with the answer, from {} (not at all) to {} (very)?\n\n{}\n\nThe links work until {}.",
+        message.name,
+        MIN_SCORE,
+        MAX_SCORE,
+        scores.clone().map(|score| format!("{}: {}", score, rating_link(page_url, token, score))).collect::<Vec<_>>().join("\n"),
+        until
+    );
+    let html = html! {
+        p { "Hello " (message.name) "," }
+        p { "Your message was answered. How satisfied are you with the answer, from " (MIN_SCORE) " (not at all) to " (MAX_SCORE) " (very)?" }
+        p { @for score in scores { a href=(rating_link(page_url, token, score)) { (score) } " " } }
+        p { "The links work until " (until) "." }
+    };
+    RenderedEmail { subject: "How was our answer?".to_string(), html: html.into_string(), text }
+}
+
+/// Summary of the ratings given over a period.
+///
+/// # Fields
+///
+/// * `ratings` - Number of rated messages
+/// * `average` - Mean score, `None` without ratings
+/// * `scores` - Number of ratings per score, from 1 to 5
+/// * `comments` - Number of ratings with a comment
+#[derive(Debug, Serialize)]
+pub struct SatisfactionStats {
+    pub ratings: i64,
+    pub average: Option<f64>,
+    pub scores: BTreeMap<i16, i64>,
+    pub comments: i64,
+}
+
+/// Database operations for satisfaction ratings.
+impl Database {
+    /// Creates the 'ratings' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_ratings_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_ratings_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(r#"
+            CREATE TABLE IF NOT EXISTS ratings (
+                message_id UUID PRIMARY KEY,
+                score SMALLINT NOT NULL CHECK (score BETWEEN {MIN_SCORE} AND {MAX_SCORE}),
+                comment TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Enqueues the request for the sender of a resolved message to rate
+    /// the answer, unless it was already requested or the message rated.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The resolved message
+    /// * `token` - Rating token for the message
+    /// * `expires_at` - Expiration time of the token
+    /// * `page_url` - `RATING_PAGE_URL`, to also email the rating links to
+    ///   the sender unless they are on the do-not-contact list
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn request_rating(
+        &self,
+        message: &Message,
+        token: &str,
+        expires_at: DateTime<Utc>,
+        page_url: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let rated: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM ratings WHERE message_id = $1)")
+            .bind(message.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !rated {
+            Self::enqueue_outbox(
+                &mut *tx,
+                RATING_REQUESTED_TOPIC,
+                &format!("rating_request:{}", message.id),
+                &json!({
+                    "message_id": message.id,
+                    "name": message.name,
+                    "email": message.email,
+                    "token": token,
+                    "expires_at": crate::timestamp::format(&expires_at),
+                }),
+            ).await?;
+
+            if let Some(page_url) = page_url {
+                if !Self::is_suppressed(&mut *tx, &message.email).await? {
+                    let email = rating_email(message, page_url, token, expires_at);
+                    Self::enqueue_email(&mut *tx, &message.email, &format!("rating:{}", message.id), email).await?;
+                }
+            }
+        }
+
+        tx.commit().await
+    }
+
+    /// Stores the rating of a message, replacing any previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_id` - ID of the rated message
+    /// * `score` - Score from [`MIN_SCORE`] to [`MAX_SCORE`]
+    /// * `comment` - Optional comment of the sender
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The score is out of range (check constraint violation)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     db.save_rating(id, 5, Some("Quick and helpful")).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn save_rating(&self, message_id: Uuid, score: i16, comment: Option<&str>) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO ratings (message_id, score, comment)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (message_id) DO UPDATE
+            SET score = EXCLUDED.score, comment = EXCLUDED.comment, updated_at = NOW()
+        "#)
+        .bind(message_id)
+        .bind(score)
+        .bind(comment)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sums up the ratings given in the last `days` days, or ever.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn satisfaction_stats(&self, days: Option<i32>) -> Result<SatisfactionStats, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT score, COUNT(*) AS ratings, COUNT(comment) FILTER (WHERE comment <> '') AS comments
+            FROM ratings
+            WHERE $1::int IS NULL OR updated_at >= NOW() - make_interval(days => $1)
+            GROUP BY score
+        "#)
+        .bind(days)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut scores: BTreeMap<i16, i64> = (MIN_SCORE..=MAX_SCORE).map(|score| (score, 0)).collect();
+        let mut comments = 0;
+        for row in &rows {
+            scores.insert(row.get("score"), row.get("ratings"));
+            comments += row.get::<i64, _>("comments");
+        }
+        let ratings: i64 = scores.values().sum();
+        let total: i64 = scores.iter().map(|(score, count)| i64::from(*score) * count).sum();
+
+        Ok(SatisfactionStats {
+            ratings,
+            average: (ratings > 0).then(|| total as f64 / ratings as f64),
+            scores,
+            comments,
+        })
+    }
+}
@@ -0,0 +1,144 @@
+//! # Panic Recovery
+//!
+//! Turns a panic in a handler into a regular 500 Internal Server Error
+//! response instead of a dropped connection, so that the frontend always
+//! receives the standard JSON error envelope:
+//!
+//! ```json
+//! {
+//!   "status": "error",
+//!   "message": "Internal server error",
+//!   "request_id": "0b6e3c1e-5f2a-4d8e-9c71-2f0a6b4d9e13"
+//! }
+//! ```
+//!
+//! The request ID is also sent in the `X-Request-Id` header and printed
+//! with the request in the server output, next to the panic message, so a
+//! report from a user can be matched with the panic. With the `otel`
+//! feature it is the ID of the request span.
+//!
+//! Recovered panics are counted (see [`panic_count`], shown on the status
+//! page) and reported by the panic hook of the `reporting` module, which
+//! skips the 500 response that follows.
+
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::{HeaderName, HeaderValue}, StatusCode},
+    middleware::Next,
+    HttpResponse, ResponseError,
+};
+#[cfg(feature = "otel")]
+use actix_web::HttpMessage;
+use uuid::Uuid;
+
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of handler panics recovered since the server started.
+pub fn panic_count() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+/// Error returned in place of the response of a handler that panicked.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::ResponseError;
+/// use dothtml_backend::recovery::RecoveredPanic;
+///
+/// let error = RecoveredPanic { request_id: "0b6e3c1e".to_string() };
+/// let res = error.error_response();
+/// assert_eq!(res.status(), 500);
+/// assert_eq!(res.headers().get("x-request-id").unwrap(), "0b6e3c1e");
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecoveredPanic {
+    pub request_id: String,
+}
+
+impl fmt::Display for RecoveredPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request {} panicked", self.request_id)
+    }
+}
+
+impl ResponseError for RecoveredPanic {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": "Internal server error",
+            "request_id": self.request_id
+        }));
+        if let Ok(value) = HeaderValue::from_str(&self.request_id) {
+            res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+        }
+        res
+    }
+}
+
+/// Middleware answering 500 Internal Server Error when the rest of the
+/// chain panics.
+///
+/// The 500 response is returned as a [`RecoveredPanic`] error, which the
+/// outer middleware (CORS headers, error reporting) process like any other
+/// error. Register it innermost.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{middleware, web, App, HttpResponse};
+/// use dothtml_backend::recovery;
+///
+/// let app = App::new()
+///     .wrap(middleware::from_fn(recovery::catch_panics))
+///     .route("/", web::get().to(|| async { HttpResponse::Ok().finish() }));
+/// ```
+pub async fn catch_panics<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let request_id = span_request_id(&req);
+    let (method, path) = (req.method().clone(), req.path().to_string());
+
+    let mut call = pin!(next.call(req));
+    let result = poll_fn(|cx| match panic::catch_unwind(AssertUnwindSafe(|| call.as_mut().poll(cx))) {
+        Ok(Poll::Pending) => Poll::Pending,
+        Ok(Poll::Ready(res)) => Poll::Ready(Ok(res)),
+        Err(payload) => Poll::Ready(Err(payload)),
+    })
+    .await;
+
+    match result {
+        Ok(res) => res,
+        Err(_) => {
+            PANICS.fetch_add(1, Ordering::Relaxed);
+            let request_id = request_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+            eprintln!("Request {} ({} {}) panicked", request_id, method, path);
+            Err(RecoveredPanic { request_id }.into())
+        }
+    }
+}
+
+/// Returns the ID of the request span.
+#[cfg(feature = "otel")]
+fn span_request_id(req: &ServiceRequest) -> Option<String> {
+    req.extensions().get::<tracing_actix_web::RequestId>().map(ToString::to_string)
+}
+
+/// Requests have no ID without the `otel` feature.
+#[cfg(not(feature = "otel"))]
+fn span_request_id(_req: &ServiceRequest) -> Option<String> {
+    None
+}
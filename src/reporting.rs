@@ -0,0 +1,313 @@
+//! # Error Reporting
+//!
+//! Sends unexpected errors to an error tracker so that they are noticed
+//! without watching the server output: panics, 5xx responses and
+//! background job failures. Reports are tagged with the release and commit
+//! of the running build, and those raised while serving a request carry
+//! its method, path and route.
+//!
+//! Reports go to the [`ErrorReporter`] installed at startup. The bundled
+//! one sends them to Sentry, enabled with the `sentry` cargo feature and
+//! `SENTRY_DSN`. Without a reporter, errors are only printed as before.
+//!
+//! 503 Service Unavailable responses are not reported: the server answers
+//! them on purpose when it sheds load or when the database is down, which
+//! the status page and the health checks already show.
+
+use std::fmt;
+use std::io;
+use std::panic;
+use std::sync::OnceLock;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    middleware::Next,
+};
+
+use crate::config::AppConfig;
+use crate::recovery::RecoveredPanic;
+
+/// Where a reported error comes from.
+///
+/// * `Panic` - A panic, in a handler or anywhere else
+/// * `Response` - A request answered with a 5xx status
+/// * `Job` - A failed run of the named background job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSource {
+    Panic,
+    Response,
+    Job(&'static str),
+}
+
+impl fmt::Display for ErrorSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorSource::Panic => f.write_str("panic"),
+            ErrorSource::Response => f.write_str("response"),
+            ErrorSource::Job(_) => f.write_str("job"),
+        }
+    }
+}
+
+/// The request being served when an error happened.
+///
+/// # Fields
+///
+/// * `method` - HTTP method
+/// * `path` - Request path
+/// * `route` - Matched route pattern, e.g. `/inbox/{id}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    pub method: String,
+    pub path: String,
+    pub route: Option<String>,
+}
+
+impl RequestContext {
+    fn from_request(req: &ServiceRequest) -> Self {
+        RequestContext {
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            route: req.match_pattern(),
+        }
+    }
+}
+
+/// An error to report.
+///
+/// # Fields
+///
+/// * `source` - Where the error comes from
+/// * `message` - Description of the error
+/// * `status` - Status code of the response, for `ErrorSource::Response`
+/// * `request` - The request being served, if any
+#[derive(Debug, Clone)]
+pub struct ErrorReport {
+    pub source: ErrorSource,
+    pub message: String,
+    pub status: Option<u16>,
+    pub request: Option<RequestContext>,
+}
+
+/// Destination of error reports.
+///
+/// Reports are sent from the code that hit the error, including panic
+/// hooks, so implementations must not block: queue the report and send it
+/// in the background.
+pub trait ErrorReporter: Send + Sync {
+    /// Sends a report.
+    fn report(&self, report: &ErrorReport);
+
+    /// Waits for the queued reports to be sent, before the process exits.
+    fn flush(&self) {}
+}
+
+static REPORTER: OnceLock<Box<dyn ErrorReporter>> = OnceLock::new();
+
+tokio::task_local! {
+    /// Request served by the current task, set by [`middleware`].
+    static CURRENT_REQUEST: RequestContext;
+}
+
+/// Handle on the installed reporter, to send pending reports on shutdown.
+pub struct Reporting;
+
+impl Reporting {
+    /// Sends the reports still queued.
+    pub fn shutdown(self) {
+        if let Some(reporter) = REPORTER.get() {
+            reporter.flush();
+        }
+    }
+}
+
+/// Starts reporting errors to Sentry when `SENTRY_DSN` is set.
+///
+/// Must be called once, before the HTTP server and the background jobs
+/// start.
+///
+/// # Returns
+///
+/// Returns the reporting handle, or `None` when error reporting is disabled.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `SENTRY_DSN` is set without the `sentry` feature
+/// - `SENTRY_DSN` is not a valid DSN
+/// - A reporter is already installed
+pub fn init(config: &AppConfig) -> io::Result<Option<Reporting>> {
+    match config.sentry_dsn.as_deref() {
+        None => Ok(None),
+        #[cfg(feature = "sentry")]
+        Some(dsn) => {
+            let reporter = SentryReporter::new(dsn, config.sentry_environment.clone())?;
+            install(Box::new(reporter))?;
+            println!("Reporting errors to Sentry");
+            Ok(Some(Reporting))
+        }
+        #[cfg(not(feature = "sentry"))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SENTRY_DSN requires building with the `sentry` feature",
+        )),
+    }
+}
+
+/// Installs `reporter` as the destination of every report, and a panic
+/// hook reporting panics before running the previous hook.
+///
+/// # Errors
+///
+/// This function returns an error if a reporter is already installed.
+pub fn install(reporter: Box<dyn ErrorReporter>) -> io::Result<()> {
+    REPORTER
+        .set(reporter)
+        .map_err(|_| io::Error::new(io::ErrorKind::AlreadyExists, "An error reporter is already installed"))?;
+
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let message = match info.location() {
+            Some(location) => format!("{} at {}:{}", payload, location.file(), location.line()),
+            None => payload.to_string(),
+        };
+        report(ErrorReport { source: ErrorSource::Panic, message, status: None, request: current_request() });
+        previous(info);
+    }));
+    Ok(())
+}
+
+/// Sends a report to the installed reporter, if any.
+pub fn report(report: ErrorReport) {
+    if let Some(reporter) = REPORTER.get() {
+        reporter.report(&report);
+    }
+}
+
+/// Prints and reports the failure of a background job run.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::reporting;
+///
+/// reporting::job_failed("trash_purge", "Failed to purge the trash: connection refused");
+/// ```
+pub fn job_failed(job: &'static str, message: impl fmt::Display) {
+    let message = message.to_string();
+    eprintln!("{}", message);
+    report(ErrorReport { source: ErrorSource::Job(job), message, status: None, request: None });
+}
+
+/// Returns the request served by the current task, when called from a
+/// handler wrapped by [`middleware`].
+pub fn current_request() -> Option<RequestContext> {
+    CURRENT_REQUEST.try_with(Clone::clone).ok()
+}
+
+/// Middleware reporting 5xx responses and errors, and making the request
+/// available to the panic hook and to [`current_request`].
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{middleware, App};
+/// use dothtml_backend::reporting;
+///
+/// let app = App::new().wrap(middleware::from_fn(reporting::middleware));
+/// ```
+pub async fn middleware<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    if REPORTER.get().is_none() {
+        return next.call(req).await;
+    }
+
+    let context = RequestContext::from_request(&req);
+    let res = CURRENT_REQUEST.scope(context.clone(), next.call(req)).await;
+
+    // The panic hook already reported the panic behind a recovered response
+    let recovered = matches!(&res, Err(e) if e.as_error::<RecoveredPanic>().is_some());
+    let (status, message) = match &res {
+        Ok(res) => match res.response().error() {
+            Some(e) => (res.status(), e.to_string()),
+            None => (res.status(), format!("{} {} answered {}", context.method, context.path, res.status())),
+        },
+        Err(e) => (e.as_response_error().status_code(), e.to_string()),
+    };
+    if status.is_server_error() && status != StatusCode::SERVICE_UNAVAILABLE && !recovered {
+        report(ErrorReport {
+            source: ErrorSource::Response,
+            message,
+            status: Some(status.as_u16()),
+            request: Some(context),
+        });
+    }
+    res
+}
+
+/// Reporter sending events to Sentry.
+#[cfg(feature = "sentry")]
+struct SentryReporter {
+    client: sentry::ClientInitGuard,
+}
+
+#[cfg(feature = "sentry")]
+impl SentryReporter {
+    fn new(dsn: &str, environment: Option<String>) -> io::Result<Self> {
+        let dsn = dsn.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid SENTRY_DSN: {}", e)))?;
+        let client = sentry::init(sentry::ClientOptions {
+            dsn: Some(dsn),
+            release: Some(format!("dothtml-backend@{}", crate::version::VERSION).into()),
+            environment: environment.map(Into::into),
+            ..Default::default()
+        });
+        Ok(SentryReporter { client })
+    }
+}
+
+#[cfg(feature = "sentry")]
+impl ErrorReporter for SentryReporter {
+    fn report(&self, report: &ErrorReport) {
+        use sentry::protocol::{Event, Level};
+
+        let mut event = Event {
+            level: if report.source == ErrorSource::Panic { Level::Fatal } else { Level::Error },
+            message: Some(report.message.clone()),
+            ..Default::default()
+        };
+        event.tags.insert("source".to_string(), report.source.to_string());
+        event.tags.insert("commit".to_string(), crate::version::BuildInfo::current().short_commit().to_string());
+        if let ErrorSource::Job(job) = report.source {
+            event.tags.insert("job".to_string(), job.to_string());
+            event.transaction = Some(job.to_string());
+        }
+        if let Some(status) = report.status {
+            event.tags.insert("status".to_string(), status.to_string());
+        }
+        if let Some(request) = &report.request {
+            let route = request.route.as_deref().unwrap_or(&request.path);
+            event.transaction = Some(format!("{} {}", request.method, route));
+            event.tags.insert("route".to_string(), route.to_string());
+            event.request = Some(sentry::protocol::Request { method: Some(request.method.clone()), ..Default::default() });
+            event.extra.insert("path".to_string(), request.path.clone().into());
+        }
+        if report.source == ErrorSource::Panic {
+            event.stacktrace = sentry::integrations::backtrace::current_stacktrace();
+        }
+        sentry::capture_event(event);
+    }
+
+    fn flush(&self) {
+        self.client.flush(Some(std::time::Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,978 @@
+//! # Scheduled Reports
+//!
+//! Reports defined by admins through `/admin/reports` and delivered on a
+//! schedule, so that stakeholders get the numbers without opening the
+//! backoffice:
+//!
+//! - `daily_counts` - Messages received and resolved per day, as served by `GET /stats`
+//! - `countries` - Messages received and resolved per country/region of the sender
+//!
+//! A report covers the last `days` days, counted in its IANA `timezone`, up
+//! to the day it runs. Its `schedule` is a cron expression evaluated in the
+//! same time zone, with five fields (`minute hour day-of-month month
+//! day-of-week`) or six with the seconds first. Days of the week are
+//! numbered as in crontab, from 0 for Sunday (7 is Sunday too) to 6 for
+//! Saturday, or named (`Mon`).
+//!
+//! - `0 8 * * Mon` with `days: 7` - The stats of the past week, every Monday at 8:00
+//! - `0 6 1 * *` with `days: 31` - The country breakdown of the past month, on the 1st at 6:00
+//!
+//! ## Destinations
+//!
+//! A report is delivered to every destination it has (see
+//! [`ReportDestination`]):
+//!
+//! - `recipients` - Emailed as a CSV file, when a mail transport is configured
+//! - `push` - Posted to an HTTP endpoint as CSV (`text/csv`) or JSON (`application/json`), with
+//!   an optional `Authorization` header, so that reports can land in a spreadsheet through a
+//!   Google Apps Script web app or in a BI tool's webhook
+//!
+//! The JSON body carries the report with its rows:
+//!
+//! ```json
+//! {
+//!   "report": "weekly-stats",
+//!   "kind": "daily_counts",
+//!   "timezone": "Europe/Paris",
+//!   "days": 7,
+//!   "generated_at": "2026-10-19T06:00:00Z",
+//!   "rows": [{ "date": "2026-10-13", "received": 12, "resolved": 9 }]
+//! }
+//! ```
+//!
+//! The authorization value of a push is never returned by the API; leave it
+//! out of an update to keep it, or set it to an empty string to remove it.
+//!
+//! The report job runs the due reports every minute, on every replica, a
+//! report being locked while it runs. `POST /admin/reports/{id}/run` sends
+//! a report right away without moving its schedule. A run failing for any
+//! destination records the error in `last_error`; the other destinations
+//! still receive the report.
+
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use maud::html;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::mailer::{FileAttachment, Mailer};
+use crate::models::{DailyCount, PageRequest, Paginated};
+use crate::templates::RenderedEmail;
+
+/// Most recipients of a report.
+const MAX_RECIPIENTS: usize = 20;
+
+/// Longest period a report covers, in days.
+const MAX_DAYS: i32 = 366;
+
+/// Time a push endpoint may take to accept a report.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a report counts.
+///
+/// * `DailyCounts` - Messages received and resolved per day
+/// * `Countries` - Messages received and resolved per country/region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    DailyCounts,
+    Countries,
+}
+
+impl ReportKind {
+    /// Returns the name stored in the `kind` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportKind::DailyCounts => "daily_counts",
+            ReportKind::Countries => "countries",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        if value == "countries" {
+            ReportKind::Countries
+        } else {
+            ReportKind::DailyCounts
+        }
+    }
+}
+
+/// Body format of a push.
+///
+/// * `Csv` - The CSV file emailed to the recipients
+/// * `Json` - The report with its rows (see the module documentation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushFormat {
+    Csv,
+    Json,
+}
+
+impl PushFormat {
+    /// Returns the name stored in the `push_format` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PushFormat::Csv => "csv",
+            PushFormat::Json => "json",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        if value == "json" {
+            PushFormat::Json
+        } else {
+            PushFormat::Csv
+        }
+    }
+}
+
+/// HTTP endpoint a report is posted to.
+///
+/// # Fields
+///
+/// * `url` - Address the report is posted to
+/// * `format` - `csv` or `json` (default: `csv`)
+/// * `authorization` - Value of the `Authorization` header, such as `Bearer <token>`; never returned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportPush {
+    pub url: String,
+    #[serde(default = "default_push_format")]
+    pub format: PushFormat,
+    #[serde(default, skip_serializing)]
+    pub authorization: Option<String>,
+}
+
+fn default_push_format() -> PushFormat {
+    PushFormat::Csv
+}
+
+/// A scheduled report.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the report
+/// * `name` - Unique name of the report, used in the file name
+/// * `kind` - What the report counts
+/// * `schedule` - Cron expression of the runs
+/// * `timezone` - IANA time zone of the schedule and of the days counted
+/// * `days` - Number of days covered, up to the day of the run
+/// * `recipients` - Addresses the report is emailed to
+/// * `push` - HTTP endpoint the report is posted to, if any
+/// * `enabled` - Whether the report runs on its schedule
+/// * `next_run_at` - Next scheduled run, if enabled
+/// * `last_run_at` - Last run, scheduled or manual
+/// * `last_error` - Error of the last run, if it failed
+/// * `created_at` - Timestamp when the report was created
+/// * `updated_at` - Timestamp of the last change of the report
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub id: i64,
+    pub name: String,
+    pub kind: ReportKind,
+    pub schedule: String,
+    pub timezone: String,
+    pub days: i32,
+    pub recipients: Vec<String>,
+    pub push: Option<ReportPush>,
+    pub enabled: bool,
+    #[serde(with = "crate::timestamp::option")]
+    pub next_run_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Column list selected for every `Report` row.
+const REPORT_COLUMNS: &str = "id, name, kind, schedule, timezone, days, recipients, push_url, push_format, push_authorization, \
+     enabled, next_run_at, last_run_at, last_error, created_at, updated_at";
+
+fn report_from_row(row: &PgRow) -> Report {
+    let push_url: Option<String> = row.get("push_url");
+    Report {
+        id: row.get("id"),
+        name: row.get("name"),
+        kind: ReportKind::from_db(row.get("kind")),
+        schedule: row.get("schedule"),
+        timezone: row.get("timezone"),
+        days: row.get("days"),
+        recipients: row.get("recipients"),
+        push: push_url.map(|url| ReportPush {
+            url,
+            format: PushFormat::from_db(row.get::<Option<&str>, _>("push_format").unwrap_or_default()),
+            authorization: row.get("push_authorization"),
+        }),
+        enabled: row.get("enabled"),
+        next_run_at: row.get("next_run_at"),
+        last_run_at: row.get("last_run_at"),
+        last_error: row.get("last_error"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+impl Report {
+    /// Returns the run following `after`, `None` when the schedule has no
+    /// further run or cannot be parsed.
+    pub fn next_run_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let schedule = parse_schedule(&self.schedule).ok()?;
+        let timezone = Tz::from_str(&self.timezone).ok()?;
+        next_run(&schedule, timezone, after)
+    }
+}
+
+/// Settings of a report, as created or replaced by an admin.
+///
+/// # Fields
+///
+/// * `name` - Unique name of the report
+/// * `kind` - `daily_counts` or `countries`
+/// * `schedule` - Cron expression of the runs
+/// * `timezone` - IANA time zone (default: `UTC`)
+/// * `days` - Number of days covered (default: 7)
+/// * `recipients` - Addresses the report is emailed to (default: none)
+/// * `push` - HTTP endpoint the report is posted to (default: none)
+/// * `enabled` - Whether the report runs on its schedule (default: true)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportDefinition {
+    pub name: String,
+    pub kind: ReportKind,
+    pub schedule: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default = "default_days")]
+    pub days: i32,
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub push: Option<ReportPush>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_days() -> i32 {
+    7
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ReportDefinition {
+    /// Checks the settings and returns them normalized, with the next run
+    /// after `now` when the report is enabled.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first problem found.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use chrono::{TimeZone, Utc};
+    /// use dothtml_backend::reports::ReportDefinition;
+    ///
+    /// let definition: ReportDefinition = serde_json::from_value(serde_json::json!({
+    ///     "name": "weekly-stats",
+    ///     "kind": "daily_counts",
+    ///     "schedule": "0 8 * * Mon",
+    ///     "timezone": "Europe/Paris",
+    ///     "recipients": ["ops@dotshell.eu"]
+    /// })).unwrap();
+    /// // Friday 17 October 2026; the next Monday at 8:00 in Paris is 6:00 UTC
+    /// let now = Utc.with_ymd_and_hms(2026, 10, 17, 9, 0, 0).unwrap();
+    /// let (_, next_run_at) = definition.check(now).unwrap();
+    /// assert_eq!(next_run_at, Some(Utc.with_ymd_and_hms(2026, 10, 19, 6, 0, 0).unwrap()));
+    ///
+    /// let definition: ReportDefinition = serde_json::from_value(serde_json::json!({
+    ///     "name": "weekly-stats",
+    ///     "kind": "countries",
+    ///     "schedule": "every monday",
+    ///     "recipients": ["ops@dotshell.eu"]
+    /// })).unwrap();
+    /// assert!(definition.check(now).is_err());
+    ///
+    /// // A report needs somewhere to go
+    /// let definition: ReportDefinition = serde_json::from_value(serde_json::json!({
+    ///     "name": "weekly-stats",
+    ///     "kind": "countries",
+    ///     "schedule": "0 8 * * Mon"
+    /// })).unwrap();
+    /// assert!(definition.check(now).is_err());
+    /// ```
+    pub fn check(mut self, now: DateTime<Utc>) -> Result<(Self, Option<DateTime<Utc>>), String> {
+        self.name = self.name.trim().to_string();
+        if !(1..=100).contains(&self.name.chars().count()) {
+            return Err("Name must be between 1 and 100 characters".to_string());
+        }
+        if !(1..=MAX_DAYS).contains(&self.days) {
+            return Err(format!("Days must be between 1 and {}", MAX_DAYS));
+        }
+
+        self.timezone = self.timezone.trim().to_string();
+        let timezone = Tz::from_str(&self.timezone).map_err(|_| format!("Unknown time zone: {}", self.timezone))?;
+        self.schedule = self.schedule.split_whitespace().collect::<Vec<_>>().join(" ");
+        let schedule = parse_schedule(&self.schedule)?;
+        let next_run_at = next_run(&schedule, timezone, now);
+        if next_run_at.is_none() {
+            return Err("The schedule has no future run".to_string());
+        }
+
+        let mut recipients: Vec<String> = Vec::new();
+        for recipient in self.recipients.iter().map(|recipient| recipient.trim()) {
+            if recipient.parse::<lettre::Address>().is_err() {
+                return Err(format!("Invalid recipient: {}", recipient));
+            }
+            if !recipients.iter().any(|existing| existing.eq_ignore_ascii_case(recipient)) {
+                recipients.push(recipient.to_string());
+            }
+        }
+        if recipients.len() > MAX_RECIPIENTS {
+            return Err(format!("A report can have at most {} recipients", MAX_RECIPIENTS));
+        }
+        self.recipients = recipients;
+
+        if let Some(push) = &mut self.push {
+            push.url = push.url.trim().to_string();
+            match reqwest::Url::parse(&push.url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {}
+                _ => return Err("Push URL must be an absolute http or https address".to_string()),
+            }
+            if push.url.len() > 2000 {
+                return Err("Push URL must be at most 2000 characters".to_string());
+            }
+            push.authorization = push.authorization.take().map(|authorization| authorization.trim().to_string());
+            if push.authorization.as_ref().is_some_and(|authorization| authorization.contains(['\r', '\n'])) {
+                return Err("Push authorization must fit on one line".to_string());
+            }
+        }
+        if self.recipients.is_empty() && self.push.is_none() {
+            return Err("A report needs recipients or a push endpoint".to_string());
+        }
+
+        let next_run_at = next_run_at.filter(|_| self.enabled);
+        Ok((self, next_run_at))
+    }
+}
+
+/// Parses a cron expression of five fields, or six with the seconds first.
+///
+/// # Errors
+///
+/// This function returns a description of the syntax error.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::reports::parse_schedule;
+///
+/// assert!(parse_schedule("0 8 * * Mon").is_ok());
+/// assert!(parse_schedule("30 0 6 1 * *").is_ok());
+/// assert!(parse_schedule("0 25 * * *").is_err());
+///
+/// // Days of the week are numbered from 0 for Sunday, as in crontab
+/// let runs = |expression| {
+///     let start = chrono::DateTime::UNIX_EPOCH;
+///     parse_schedule(expression).unwrap().after(&start).take(14).collect::<Vec<_>>()
+/// };
+/// assert_eq!(runs("0 8 * * 1"), runs("0 8 * * Mon"));
+/// assert_eq!(runs("0 8 * * 1-5"), runs("0 8 * * Mon-Fri"));
+/// assert_eq!(runs("0 8 * * 0,7"), runs("0 8 * * Sun"));
+/// assert_eq!(runs("0 8 * * */2"), runs("0 8 * * Sun,Tue,Thu,Sat"));
+/// assert_eq!(runs("0 8 * * 0-6"), runs("0 8 * * *"));
+/// assert_eq!(runs("0 8 * * 1-7"), runs("0 8 * * *"));
+/// assert_eq!(runs("0 8 * * 0-7"), runs("0 8 * * *"));
+/// assert_eq!(runs("0 8 * * 5-7"), runs("0 8 * * Fri,Sat,Sun"));
+/// assert_eq!(runs("0 8 * * 2-7/2"), runs("0 8 * * Tue,Thu,Sat"));
+/// assert!(parse_schedule("0 8 * * 8").is_err());
+/// ```
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    let mut fields: Vec<String> = expression.split_whitespace().map(str::to_string).collect();
+    let seconds = match fields.len() {
+        5 => "0 ",
+        6 => "",
+        _ => return Err("Schedule must be a cron expression of 5 fields: minute hour day-of-month month day-of-week".to_string()),
+    };
+    if let Some(days) = fields.last_mut() {
+        *days = name_days_of_week(days)?;
+    }
+    Schedule::from_str(&format!("{}{}", seconds, fields.join(" "))).map_err(|e| format!("Invalid schedule: {}", e))
+}
+
+/// Replaces the crontab day numbers of a day-of-week field by day names,
+/// since the `cron` crate numbers the days from 1 for Sunday.
+fn name_days_of_week(field: &str) -> Result<String, String> {
+    const DAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+    let name = |day: &str| match day.parse::<usize>() {
+        Ok(number) => DAYS
+            .get(number)
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("Invalid schedule: day of week {} is not between 0 and 7", number)),
+        Err(_) => Ok(day.to_string()),
+    };
+
+    field
+        .split(',')
+        .map(|item| {
+            // The step after a slash is a count, not a day
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (item, None),
+            };
+            // Sunday ends the week as 7 but starts it for the crate, so a
+            // range up to 7 is listed day by day instead
+            if let Some((first, "7")) = range.split_once('-') {
+                if let Ok(first) = first.parse::<usize>() {
+                    let step = match step {
+                        Some(step) => step
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|step| *step > 0)
+                            .ok_or_else(|| format!("Invalid schedule: invalid step `{}`", step))?,
+                        None => 1,
+                    };
+                    let days = (first..=7).step_by(step).map(|day| name(&day.to_string()));
+                    return days.collect::<Result<Vec<_>, _>>().map(|days| days.join(","));
+                }
+            }
+            let range = range.split('-').map(name).collect::<Result<Vec<_>, _>>()?.join("-");
+            Ok(match step {
+                Some(step) => format!("{}/{}", range, step),
+                None => range,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map(|items| items.join(","))
+}
+
+/// Returns the first run of `schedule`, evaluated in `timezone`, after `after`.
+pub fn next_run(schedule: &Schedule, timezone: Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule.after(&after.with_timezone(&timezone)).next().map(|run| run.with_timezone(&Utc))
+}
+
+/// Messages received and resolved from one country/region.
+///
+/// # Fields
+///
+/// * `country_region` - Country/region given by the senders
+/// * `received` - Number of messages received in the period, excluding trashed ones
+/// * `resolved` - Number of those messages resolved since
+#[derive(Debug, Serialize)]
+pub struct CountryCount {
+    pub country_region: String,
+    pub received: i64,
+    pub resolved: i64,
+}
+
+/// Rows of a built report.
+#[derive(Debug)]
+pub enum ReportRows {
+    DailyCounts(Vec<DailyCount>),
+    Countries(Vec<CountryCount>),
+}
+
+/// A built report, ready to be delivered.
+///
+/// # Fields
+///
+/// * `rows` - Rows of the report
+/// * `generated_at` - When the report was built
+#[derive(Debug)]
+pub struct ReportOutput {
+    pub rows: ReportRows,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl ReportOutput {
+    /// Returns the CSV file of the report, named after the report and the
+    /// day it was built.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the error met.
+    pub fn csv_file(&self, report: &Report) -> Result<FileAttachment, String> {
+        let data = match &self.rows {
+            ReportRows::DailyCounts(rows) => to_csv(rows)?,
+            ReportRows::Countries(rows) => to_csv(rows)?,
+        };
+        let day = match Tz::from_str(&report.timezone) {
+            Ok(timezone) => self.generated_at.with_timezone(&timezone).date_naive(),
+            Err(_) => self.generated_at.date_naive(),
+        };
+        Ok(FileAttachment {
+            filename: format!("{}-{}.csv", report.name, day),
+            content_type: "text/csv; charset=utf-8".to_string(),
+            data,
+        })
+    }
+
+    /// Returns the report with its rows, as pushed in the JSON format.
+    pub fn json(&self, report: &Report) -> Value {
+        let rows = match &self.rows {
+            ReportRows::DailyCounts(rows) => serde_json::to_value(rows),
+            ReportRows::Countries(rows) => serde_json::to_value(rows),
+        };
+        json!({
+            "report": report.name,
+            "kind": report.kind,
+            "timezone": report.timezone,
+            "days": report.days,
+            "generated_at": crate::timestamp::format(&self.generated_at),
+            "rows": rows.unwrap_or_default(),
+        })
+    }
+}
+
+/// Writes rows as a CSV file with a header line.
+fn to_csv<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(|e| format!("Failed to write the CSV file: {}", e))?;
+    }
+    writer.into_inner().map_err(|e| format!("Failed to write the CSV file: {}", e))
+}
+
+/// Builds the email carrying a report.
+fn report_email(report: &Report) -> RenderedEmail {
+    let what = match report.kind {
+        ReportKind::DailyCounts => "messages received and resolved per day",
+        ReportKind::Countries => "messages received and resolved per country",
+    };
+    let text = format!(
+        "The attached file lists the {} over the last {} days ({}).\n\nThis report is scheduled by an administrator \
+         of the backoffice; ask them to stop it if you no longer need it.",
+        what, report.days, report.timezone
+    );
+    let html = html! {
+        p { "The attached file lists the " (what) " over the last " (report.days) " days (" (report.timezone) ")." }
+        p { "This report is scheduled by an administrator of the backoffice; ask them to stop it if you no longer need it." }
+    };
+    RenderedEmail { subject: format!("Report: {}", report.name), html: html.into_string(), text }
+}
+
+/// A place reports are delivered to.
+#[async_trait]
+pub trait ReportDestination: Send + Sync {
+    /// Delivers a built report.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the error met.
+    async fn deliver(&self, report: &Report, output: &ReportOutput) -> Result<(), String>;
+}
+
+/// Emails reports as CSV files.
+pub struct EmailDestination<'a> {
+    mailer: Option<&'a Mailer>,
+    recipients: &'a [String],
+}
+
+#[async_trait]
+impl ReportDestination for EmailDestination<'_> {
+    async fn deliver(&self, report: &Report, output: &ReportOutput) -> Result<(), String> {
+        let Some(mailer) = self.mailer else {
+            return Err("Cannot email the report: the mailer is disabled".to_string());
+        };
+        let file = output.csv_file(report)?;
+        let email = report_email(report);
+        let mut errors = Vec::new();
+        for recipient in self.recipients {
+            if let Err(e) = mailer.send_with_attachments(recipient, &email, vec![file.clone()]).await {
+                errors.push(format!("{}: {}", recipient, e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to email the report to {}", errors.join("; ")))
+        }
+    }
+}
+
+/// Posts reports to an HTTP endpoint.
+pub struct HttpPushDestination<'a> {
+    client: &'a reqwest::Client,
+    push: &'a ReportPush,
+}
+
+#[async_trait]
+impl ReportDestination for HttpPushDestination<'_> {
+    async fn deliver(&self, report: &Report, output: &ReportOutput) -> Result<(), String> {
+        let request = self.client.post(&self.push.url).header("x-dothtml-report", &report.name);
+        let request = match self.push.format {
+            PushFormat::Csv => {
+                let file = output.csv_file(report)?;
+                request.header("content-type", file.content_type).body(file.data)
+            }
+            PushFormat::Json => request.json(&output.json(report)),
+        };
+        let request = match &self.push.authorization {
+            Some(authorization) => request.header("authorization", authorization),
+            None => request,
+        };
+
+        let response = request.send().await.map_err(|e| format!("Failed to push the report: {}", e))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("The push endpoint answered {}", status))
+        }
+    }
+}
+
+/// Delivers reports to their destinations.
+#[derive(Clone)]
+pub struct ReportSender {
+    mailer: Option<Mailer>,
+    client: reqwest::Client,
+}
+
+impl ReportSender {
+    /// Creates a sender emailing reports with `mailer`, when a sender is
+    /// configured, and giving push endpoints thirty seconds to answer.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(mailer: Option<Mailer>) -> io::Result<Self> {
+        let client = reqwest::Client::builder().timeout(PUSH_TIMEOUT).build().map_err(io::Error::other)?;
+        Ok(ReportSender { mailer, client })
+    }
+
+    /// Returns the destinations of a report.
+    pub fn destinations<'a>(&'a self, report: &'a Report) -> Vec<Box<dyn ReportDestination + 'a>> {
+        let mut destinations: Vec<Box<dyn ReportDestination + 'a>> = Vec::new();
+        if !report.recipients.is_empty() {
+            destinations.push(Box::new(EmailDestination { mailer: self.mailer.as_ref(), recipients: &report.recipients }));
+        }
+        if let Some(push) = &report.push {
+            destinations.push(Box::new(HttpPushDestination { client: &self.client, push }));
+        }
+        destinations
+    }
+}
+
+/// Database operations for the scheduled reports.
+impl Database {
+    /// Creates the 'reports' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_reports_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS reports (
+                id BIGSERIAL PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                kind TEXT NOT NULL,
+                schedule TEXT NOT NULL,
+                timezone TEXT NOT NULL,
+                days INTEGER NOT NULL,
+                recipients TEXT[] NOT NULL,
+                push_url TEXT,
+                push_format TEXT,
+                push_authorization TEXT,
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                next_run_at TIMESTAMPTZ,
+                last_run_at TIMESTAMPTZ,
+                last_error TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE INDEX IF NOT EXISTS reports_next_run_at_idx ON reports (next_run_at) WHERE enabled;
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists a page of the reports, by name.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_reports(&self, page: &PageRequest) -> Result<Paginated<Report>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {REPORT_COLUMNS}, COUNT(*) OVER () AS total FROM reports ORDER BY name LIMIT $1 OFFSET $2"
+        ))
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, report_from_row))
+    }
+
+    /// Creates a report checked with [`ReportDefinition::check`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Another report has this name (unique violation)
+    /// - Database connection issues occur
+    pub async fn create_report(&self, report: &ReportDefinition, next_run_at: Option<DateTime<Utc>>) -> Result<Report, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO reports (name, kind, schedule, timezone, days, recipients, enabled, next_run_at, push_url, push_format, push_authorization)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NULLIF($11, ''))
+            RETURNING {REPORT_COLUMNS}
+        "#))
+        .bind(&report.name)
+        .bind(report.kind.as_str())
+        .bind(&report.schedule)
+        .bind(&report.timezone)
+        .bind(report.days)
+        .bind(&report.recipients)
+        .bind(report.enabled)
+        .bind(next_run_at)
+        .bind(report.push.as_ref().map(|push| &push.url))
+        .bind(report.push.as_ref().map(|push| push.format.as_str()))
+        .bind(report.push.as_ref().and_then(|push| push.authorization.as_ref()))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(report_from_row(&row))
+    }
+
+    /// Replaces the settings of a report, checked with
+    /// [`ReportDefinition::check`], and reschedules it. A push without an
+    /// authorization keeps the current one.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The report does not exist (`sqlx::Error::RowNotFound`)
+    /// - Another report has this name (unique violation)
+    /// - Database connection issues occur
+    pub async fn update_report(
+        &self, id: i64, report: &ReportDefinition, next_run_at: Option<DateTime<Utc>>
+    ) -> Result<Report, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE reports
+            SET name = $2, kind = $3, schedule = $4, timezone = $5, days = $6, recipients = $7, enabled = $8,
+                next_run_at = $9, push_url = $10, push_format = $11,
+                push_authorization = CASE WHEN $10::text IS NULL THEN NULL ELSE NULLIF(COALESCE($12, push_authorization), '') END,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING {REPORT_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(&report.name)
+        .bind(report.kind.as_str())
+        .bind(&report.schedule)
+        .bind(&report.timezone)
+        .bind(report.days)
+        .bind(&report.recipients)
+        .bind(report.enabled)
+        .bind(next_run_at)
+        .bind(report.push.as_ref().map(|push| &push.url))
+        .bind(report.push.as_ref().map(|push| push.format.as_str()))
+        .bind(report.push.as_ref().and_then(|push| push.authorization.as_ref()))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(report_from_row(&row))
+    }
+
+    /// Deletes a report.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the report was deleted, `false` if it did not exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_report(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM reports WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Counts the messages received per country/region of the sender over
+    /// the last `days` days in `timezone`, the most frequent first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if `timezone` is not a time zone known
+    /// to PostgreSQL or if database connection issues occur.
+    pub async fn country_counts(&self, timezone: &str, days: i32) -> Result<Vec<CountryCount>, sqlx::Error> {
+        let rows = sqlx::query(r#"
+            SELECT
+                country_region,
+                COUNT(*) AS received,
+                COUNT(*) FILTER (WHERE resolved_at IS NOT NULL) AS resolved
+            FROM messages
+            WHERE deleted_at IS NULL
+              AND created_at >= ((NOW() AT TIME ZONE $1)::date - ($2 - 1))::timestamp AT TIME ZONE $1
+            GROUP BY country_region
+            ORDER BY received DESC, country_region
+        "#)
+        .bind(timezone)
+        .bind(days)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| CountryCount {
+                country_region: row.get("country_region"),
+                received: row.get("received"),
+                resolved: row.get("resolved"),
+            })
+            .collect())
+    }
+
+    /// Builds a report from the current data.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn build_report(&self, report: &Report) -> Result<ReportOutput, sqlx::Error> {
+        let rows = match report.kind {
+            ReportKind::DailyCounts => ReportRows::DailyCounts(self.daily_counts(&report.timezone, report.days).await?),
+            ReportKind::Countries => ReportRows::Countries(self.country_counts(&report.timezone, report.days).await?),
+        };
+        Ok(ReportOutput { rows, generated_at: Utc::now() })
+    }
+
+    /// Builds a report and delivers it to its destinations, then records
+    /// the outcome of the run.
+    ///
+    /// # Returns
+    ///
+    /// Returns the report with the outcome of the run.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the outcome cannot be recorded.
+    pub async fn send_report(&self, report: &Report, sender: &ReportSender) -> Result<Report, sqlx::Error> {
+        let mut errors = Vec::new();
+        match self.build_report(report).await {
+            Ok(output) => {
+                for destination in sender.destinations(report) {
+                    if let Err(e) = destination.deliver(report, &output).await {
+                        errors.push(e);
+                    }
+                }
+            }
+            Err(e) => errors.push(format!("Failed to build the report: {}", e)),
+        }
+        let error = (!errors.is_empty()).then(|| errors.join("; "));
+        if let Some(error) = &error {
+            eprintln!("Report {} failed: {}", report.name, error);
+        }
+
+        let row = sqlx::query(&format!(r#"
+            UPDATE reports SET last_run_at = NOW(), last_error = $2
+            WHERE id = $1
+            RETURNING {REPORT_COLUMNS}
+        "#))
+        .bind(report.id)
+        .bind(error)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // Deleted while it ran
+        Ok(row.as_ref().map(report_from_row).unwrap_or_else(|| report.clone()))
+    }
+
+    /// Fetches a report.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The report does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    pub async fn get_report(&self, id: i64) -> Result<Report, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {REPORT_COLUMNS} FROM reports WHERE id = $1"))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(report_from_row(&row))
+    }
+
+    /// Runs the report due the earliest, if any: moves it to its next run,
+    /// then sends it.
+    ///
+    /// # Returns
+    ///
+    /// Returns the report run, `None` when no report is due.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::mailer::Mailer;
+    /// use dothtml_backend::reports::ReportSender;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let db = Database::new().await?;
+    ///     let mailer = Mailer::new("dotshell <reports@dotshell.eu>".parse().unwrap());
+    ///     let sender = ReportSender::new(Some(mailer))?;
+    ///     while let Some(report) = db.run_due_report(&sender).await? {
+    ///         println!("Ran {}: {:?}", report.name, report.last_error);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn run_due_report(&self, sender: &ReportSender) -> Result<Option<Report>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(&format!(r#"
+            SELECT {REPORT_COLUMNS}
+            FROM reports
+            WHERE enabled AND next_run_at <= NOW()
+            ORDER BY next_run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        "#))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(report) = row.as_ref().map(report_from_row) else {
+            return Ok(None);
+        };
+
+        // Runs missed while the server was down are not caught up
+        sqlx::query("UPDATE reports SET next_run_at = $2 WHERE id = $1")
+            .bind(report.id)
+            .bind(report.next_run_after(Utc::now()))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.send_report(&report, sender).await.map(Some)
+    }
+}
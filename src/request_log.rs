@@ -0,0 +1,329 @@
+//! # Debug Request Log
+//!
+//! Keeps the last requests and responses, bodies included, in memory so
+//! that "the frontend sent X but got Y" reports can be checked against what
+//! the server actually saw. The log is read with `GET /admin/debug/requests`.
+//!
+//! Nothing is captured by default. A request is captured when its path
+//! starts with one of the `DEBUG_LOG_ENDPOINTS` prefixes, or at random with
+//! the probability `DEBUG_LOG_SAMPLE_RATE`. Both settings are read on each
+//! request, so capturing can be switched on with a configuration reload.
+//! The log holds the last `DEBUG_LOG_CAPACITY` captured requests.
+//!
+//! Entries are sanitized before being stored: credentials headers
+//! (`Authorization`, `Cookie`, `X-CSRF-Token`, ...), path segments matched
+//! by a route parameter such as the `{token}` of
+//! `/contact/followup/{token}`, and the values of query parameters and
+//! JSON or form fields, whose name mentions a password, token or secret are
+//! replaced by `[redacted]`. Bodies over [`MAX_CAPTURED_BODY`] bytes, or
+//! streamed without a known size, are not captured.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::{
+    body::{self, BodySize, EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap},
+    middleware::Next,
+    web,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::config::LiveConfig;
+
+/// Largest request or response body captured, in bytes.
+pub const MAX_CAPTURED_BODY: usize = 16 * 1024;
+
+/// Path of the endpoint reading the log, never captured itself.
+const LOG_ENDPOINT: &str = "/admin/debug/requests";
+
+/// Placeholder stored instead of sensitive values.
+const REDACTED: &str = "[redacted]";
+
+/// Headers carrying credentials, stored as `[redacted]`.
+const SENSITIVE_HEADERS: [&str; 6] =
+    ["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key", "x-csrf-token"];
+
+/// Why a request was captured.
+///
+/// * `Endpoint` - Its path matches a `DEBUG_LOG_ENDPOINTS` prefix
+/// * `Sampled` - It was picked by `DEBUG_LOG_SAMPLE_RATE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureReason {
+    Endpoint,
+    Sampled,
+}
+
+/// A captured request and its response.
+///
+/// # Fields
+///
+/// * `id` - Sequence number of the entry, increasing with each capture
+/// * `received_at` - Timestamp when the request arrived
+/// * `reason` - Why the request was captured
+/// * `method` - HTTP method
+/// * `path` - Request path
+/// * `query` - Sanitized query string, if any
+/// * `request_headers` - Sanitized request headers
+/// * `request_body` - Sanitized request body: a JSON value, text, or a
+///   size summary for binary data; `None` when empty or not captured
+/// * `status` - Response status code
+/// * `response_headers` - Sanitized response headers
+/// * `response_body` - Sanitized response body, like `request_body`
+/// * `duration_ms` - Time spent handling the request, in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedExchange {
+    pub id: u64,
+    #[serde(with = "crate::timestamp")]
+    pub received_at: DateTime<Utc>,
+    pub reason: CaptureReason,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub request_headers: Map<String, Value>,
+    pub request_body: Option<Value>,
+    pub status: u16,
+    pub response_headers: Map<String, Value>,
+    pub response_body: Option<Value>,
+    pub duration_ms: f64,
+}
+
+/// Ring buffer of the last captured requests, shared by all workers.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::request_log::RequestLog;
+///
+/// let log = RequestLog::new(100);
+/// assert!(log.entries().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct RequestLog {
+    capacity: usize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<CapturedExchange>>,
+}
+
+impl RequestLog {
+    /// Creates an empty log keeping the last `capacity` requests.
+    pub fn new(capacity: usize) -> Self {
+        RequestLog { capacity, next_id: AtomicU64::new(1), entries: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    /// Returns the captured requests, newest first.
+    pub fn entries(&self) -> Vec<CapturedExchange> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev().cloned().collect()
+    }
+
+    /// Adds an entry, dropping the oldest one when the log is full.
+    fn push(&self, mut entry: CapturedExchange) {
+        if self.capacity == 0 {
+            return;
+        }
+        entry.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// Middleware capturing requests into the [`RequestLog`] of the app data,
+/// according to the debug logging settings of the live configuration.
+///
+/// Wrap it inside the compression middleware so that the captured response
+/// bodies are readable.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::{middleware, web, App};
+/// use dothtml_backend::request_log::{self, RequestLog};
+///
+/// let app = App::new()
+///     .wrap(middleware::from_fn(request_log::capture))
+///     .app_data(web::Data::new(RequestLog::new(100)));
+/// ```
+pub async fn capture<B>(mut req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error>
+where
+    B: MessageBody + 'static,
+{
+    let reason = match req.app_data::<web::Data<LiveConfig>>() {
+        Some(config) if req.path() != LOG_ENDPOINT => {
+            let config = config.load();
+            if config.debug_log_endpoints.iter().any(|prefix| req.path().starts_with(prefix.as_str())) {
+                Some(CaptureReason::Endpoint)
+            } else if config.debug_log_sample_rate > 0.0 && rand::random::<f64>() < config.debug_log_sample_rate {
+                Some(CaptureReason::Sampled)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let (Some(reason), Some(log)) = (reason, req.app_data::<web::Data<RequestLog>>().cloned()) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let started = Instant::now();
+    let received_at = Utc::now();
+    let method = req.method().to_string();
+    let query = Some(req.query_string()).filter(|query| !query.is_empty()).map(sanitize_form);
+    let request_headers = sanitize_headers(req.headers());
+
+    // Read the body so that it can be logged, then hand it back to the handler
+    let request_body = match content_length(req.headers()) {
+        Some(length) if length <= MAX_CAPTURED_BODY => {
+            let bytes = req.extract::<web::Bytes>().await?;
+            req.set_payload(Payload::from(bytes.clone()));
+            sanitize_body(req.headers(), &bytes)
+        }
+        _ => None,
+    };
+
+    let res = next.call(req).await?;
+    // The route is only known once the request went through the router
+    let path = redact_path(res.request().path(), res.request().match_pattern().as_deref());
+    let status = res.status().as_u16();
+    let response_headers = sanitize_headers(res.headers());
+
+    let (res, response_body) = match res.response().body().size() {
+        BodySize::Sized(size) if size as usize <= MAX_CAPTURED_BODY => {
+            let (http_req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let bytes = body::to_bytes(body).await.map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+            let captured = sanitize_body(res.headers(), &bytes);
+            let res = ServiceResponse::new(http_req, res.set_body(bytes).map_into_boxed_body());
+            (res.map_into_right_body(), captured)
+        }
+        _ => (res.map_into_left_body(), None),
+    };
+
+    log.push(CapturedExchange {
+        id: 0,
+        received_at,
+        reason,
+        method,
+        path,
+        query,
+        request_headers,
+        request_body,
+        status,
+        response_headers,
+        response_body,
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+    });
+    Ok(res)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers.get(header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// Returns whether a query parameter or JSON field holds a secret.
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    ["password", "token", "secret", "authorization"].iter().any(|word| name.contains(word))
+}
+
+/// Redacts the segments of `path` matched by a sensitive parameter of the
+/// route `pattern`, if the request was routed.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::request_log::redact_path;
+///
+/// assert_eq!(redact_path("/contact/rating/abc", Some("/contact/rating/{token}")), "/contact/rating/[redacted]");
+/// assert_eq!(redact_path("/inbox/123/assign", Some("/inbox/{id}/assign")), "/inbox/123/assign");
+/// assert_eq!(redact_path("/unknown/abc", None), "/unknown/abc");
+/// ```
+pub fn redact_path(path: &str, pattern: Option<&str>) -> String {
+    let Some(pattern) = pattern else {
+        return path.to_string();
+    };
+    let parameters: Vec<&str> = pattern.split('/').collect();
+    path.split('/')
+        .enumerate()
+        .map(|(i, segment)| {
+            let parameter = parameters
+                .get(i)
+                .and_then(|part| part.strip_prefix('{'))
+                .and_then(|part| part.strip_suffix('}'))
+                .map(|part| part.split(':').next().unwrap_or(part));
+            if parameter.is_some_and(is_sensitive) { REDACTED } else { segment }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn sanitize_headers(headers: &HeaderMap) -> Map<String, Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), Value::String(value))
+        })
+        .collect()
+}
+
+/// Redacts the sensitive values of a query string or form body.
+fn sanitize_form(form: &str) -> String {
+    form.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_sensitive(name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Redacts the sensitive fields of a JSON value, at any depth.
+fn sanitize_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                if is_sensitive(name) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    sanitize_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sanitize_json),
+        _ => {}
+    }
+}
+
+/// Converts a body to the value stored in the log, according to its content type.
+fn sanitize_body(headers: &HeaderMap, bytes: &[u8]) -> Option<Value> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+
+    if content_type.contains("json") {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(bytes) {
+            sanitize_json(&mut value);
+            return Some(value);
+        }
+    }
+    Some(Value::String(match std::str::from_utf8(bytes) {
+        Ok(text) if content_type.starts_with("application/x-www-form-urlencoded") => sanitize_form(text),
+        Ok(text) => text.to_string(),
+        Err(_) => format!("[{} bytes of binary data]", bytes.len()),
+    }))
+}
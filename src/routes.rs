@@ -2,18 +2,195 @@
 //! 
 //! This module defines the HTTP routes for the application, organizing
 //! them into logical groups for the website API and backoffice API.
+//!
+//! When `ADMIN_BIND_ADDRESS` is set, the website API is served on
+//! `BIND_ADDRESS` and every other group on `ADMIN_BIND_ADDRESS` (see
+//! [`Surface`]), so that the backoffice can be kept off the public network.
+//!
+//! Listings whose items accumulate (the inbox, the trash, related and
+//! company messages, companies, assignments, events, security events, abuse
+//! matches, the do-not-contact list, API tokens, dead letters and reports)
+//! answer one page at a time, as `{ "data": [...], "page": {...} }`, and
+//! take `?limit=` and `?cursor=` (see [`crate::models::Paginated`]).
 //! 
 //! ## Route Groups
 //! 
 //! ### Website API
-//! - `POST /contact` - Handle contact form submissions
+//! - `POST /contact` - Handle contact form submissions (403 when the request comes from a site not
+//!   allowed to submit the form; `?form=` names the form for CORS)
+//! - `POST /contact/suggest` - Knowledge base articles matching a draft message, to show before it is sent
+//! - `GET /contact/status/{id}` - Coarse status of a submission for its sender, given the reference
+//!   returned by `POST /contact` and `?email=`
+//! - `POST /contact/followup/{token}` - Add information to a submission, with the follow-up token
+//!   returned by `POST /contact`
+//! - `POST /contact/rating/{token}` - Rate the answer to a resolved submission, with the rating token
+//!   sent to the sender
+//! - `GET /public/stats` - Messages received this year and response times, for a transparency page
+//!   (requires `PUBLIC_STATS`)
+//! - `GET /widget/{form}` - Contact form page submitting to `POST /contact` as `form`, to frame in
+//!   another site (see [`crate::widget`])
+//! - `GET /widget/{form}/embed.js` - Script inserting the contact widget of `form` where its tag is
+//! - `POST /webhooks/email/{provider}` - Bounce and complaint notifications of the email provider,
+//!   adding the addresses to the do-not-contact list (requires `EMAIL_WEBHOOK_SECRET`)
+//! - `POST /webhooks/issues/{provider}` - Callbacks of the issue tracker (`github` or `jira`), resolving
+//!   the escalated message when its issue is closed (requires `ISSUE_TRACKER_WEBHOOK_SECRET`)
+//! - `POST /ingest/{provider}` - Submissions of a form built with Typeform or Tally, stored as messages
+//!   (signed with the secret set up in `PUT /admin/ingest/{provider}`)
 //! 
 //! ### Backoffice API
-//! - `GET /inbox` - Retrieve all messages
+//! - `GET /inbox` - Retrieve a page of messages (`?include_archived=true` to include archived ones,
+//!   `?unopened=true` to only list messages nobody has opened, `?q=` to search with a filter
+//!   expression such as `status:pending tag:sales -country:US`, `?limit=&cursor=` to page through,
+//!   `?fields=id,name,status` to only return some fields, `?sort=-priority` to order by
+//!   `created_at`, `priority` or `status`)
+//! - `GET /inbox/{id}` - Retrieve a message (`?include=history,events,duplicates,sender` to embed
+//!   related data)
+//! - `GET /inbox/{id}/related` - List other messages from the same sender
+//! - `POST /inbox/claim-next` - Assign the oldest pending message to the caller
+//! - `GET /inbox/ws` - WebSocket pushing message events to the calling agent as they happen, with
+//!   typing and opening signals of the other agents (see [`crate::live`])
+//! - `GET /inbox/agents/online` - Agents connected to `/inbox/ws`, with their number of connections
+//! - `GET /inbox/feed.atom` - Atom feed of the latest messages, for a feed reader (admin token, or an
+//!   API token with the `feed:read` scope, also accepted as `?token=`)
 //! - `POST /inbox/{id}/assign` - Assign a message to a user
 //! - `POST /inbox/{id}/release` - Release a message from assignment
-//! - `POST /inbox/{id}/reply` - Reply to a message
-//! - `DELETE /inbox/{id}` - Delete a message
+//! - `POST /inbox/{id}/approve` - Let a quarantined message of a new sender into the inbox, with the
+//!   sender's other quarantined messages
+//! - `POST /inbox/{id}/spam` - Report a message as spam, training the spam scorer
+//! - `POST /inbox/{id}/ham` - Report a message as legitimate, training the spam scorer
+//! - `GET /inbox/{id}/assignments` - Assignment history of a message
+//! - `GET /inbox/{id}/events` - Lifecycle events of a message
+//! - `POST /inbox/{id}/open` - Record the first opening of a message
+//! - `POST /inbox/{id}/reply` - Reply to a message (422 when the sender is on the do-not-contact list)
+//! - `POST /inbox/{id}/merge` - Merge a duplicate message into another one
+//! - `POST /inbox/{id}/unarchive` - Bring an archived message back into the inbox
+//! - `POST /inbox/{id}/escalate` - Open an issue in the GitHub repository or Jira project of `ISSUE_TRACKER`
+//!   holding the message, and record its key
+//! - `GET /inbox/{id}/escalation` - Issue a message was escalated to, and whether it is closed
+//! - `GET /inbox/{id}/crm` - Status of the sync of a message's sender to the CRM of `CRM_PROVIDER`
+//! - `POST /inbox/{id}/crm/sync` - Sync a message's sender to the CRM again
+//! - `POST /inbox/{id}/draft` - Render a canned response for a message, with its assignee's booking link
+//! - `POST /inbox/{id}/propose-times` - ICS file proposing meeting times to the sender, to attach to the reply
+//! - `PATCH /inbox/{id}` - Update the status, priority, assignee or tags of a message
+//!   (reopening a resolved message is admin-only)
+//! - `DELETE /inbox/{id}` - Move a message to the trash
+//! - `GET /inbox/trash` - List messages in the trash
+//! - `DELETE /inbox/trash/{id}` - Permanently remove a trashed message (admin-only)
+//! - `GET /senders/{email}` - Aggregated profile of a sender
+//! - `GET /stats` - Inbox-wide statistics and daily counts (`?tz=Europe/Paris` to count days in a
+//!   time zone, `?days=` for the length of the series)
+//! - `GET /tags` - List tags used by messages (supports conditional requests)
+//! - `GET /companies` - List companies derived from messages (supports conditional requests)
+//! - `GET /companies/{id}/messages` - List messages from a company
+//! - `POST /companies/{id}/merge` - Merge a duplicate company into another one
+//! - `POST /graphql` - GraphQL API for the backoffice (with the `graphql` feature)
+//! - `GET /me` - Account of the calling agent, named by the user name of Basic credentials carrying the
+//!   admin token: role, number of assigned messages and notification preferences
+//! - `GET /me/preferences` - Notification preferences of the calling agent
+//! - `PUT /me/preferences` - Replace the notification preferences of the calling agent
+//! - `GET /me/security/events` - Failed authentications made with the calling agent's name (`?limit=`)
+//! - `GET /me/scheduling` - Booking link and usual meeting length of the calling agent
+//! - `PUT /me/scheduling` - Replace the booking link and usual meeting length of the calling agent
+//! - `DELETE /me/scheduling` - Remove the booking link of the calling agent
+//! - `POST /me/deadlines/feed` - URL of the calling agent's deadline feed, to subscribe to in a calendar app
+//!   (requires `SESSION_SECRET`)
+//! - `GET /me/deadlines.ics` - Calendar of an agent's assigned messages at their SLA due times, authenticated
+//!   by the `?token=` of the feed URL
+//! - `POST /auth/magic-link` - Email a single-use login link to an agent (requires `MAGIC_LINK_LOGIN`)
+//! - `POST /auth/magic-link/{token}` - Open a session with the token of a login link
+//! - `GET /auth/csrf-token` - Token to send as `X-CSRF-Token` with state-changing requests authenticated
+//!   by cookies, also set as the `__Host-csrf` cookie (see [`crate::csrf`])
+//!
+//! `GET /inbox`, `GET /senders/{email}`, `GET /stats`, `GET /companies` and
+//! `GET /admin/export/anonymized` answer 503 Service Unavailable when too
+//! many of them are already running (see [`crate::concurrency`]).
+//! 
+//! ### Operations
+//! - `GET /version` - Version, git commit, build time and enabled features of the running build
+//! - `GET /.well-known/jwks.json` - Public keys checking the sessions opened with login links
+//! 
+//! ### Admin API
+//! - `GET /metrics` - Latency histograms and SLO violation counters of the webhook deliveries and emails,
+//!   in the Prometheus text format (admin-only)
+//! - `GET /status` - HTML status page with uptime, database health and pending count (admin-only)
+//! - `POST /admin/config/reload` - Reload the configuration (admin-only)
+//! - `GET /admin/debug/requests` - Requests and responses kept by the debug request log (admin-only)
+//! - `GET /admin/flags` - List feature flag values (admin-only)
+//! - `PUT /admin/flags/{name}` - Set a feature flag globally or for a tenant (admin-only)
+//! - `DELETE /admin/flags/{name}` - Remove a feature flag value (`?tenant=` for a tenant override, admin-only)
+//! - `GET /admin/origins` - Origins allowed to submit each form (admin-only)
+//! - `PUT /admin/origins/{form}` - Only accept the submissions of a form from the given sites, replacing
+//!   `CORS_ALLOWED_ORIGINS` for it (admin-only, see [`crate::form_origins`])
+//! - `DELETE /admin/origins/{form}` - Accept the submissions of a form from `CORS_ALLOWED_ORIGINS` again (admin-only)
+//! - `GET /admin/backfills` - Progress of the backfills run with `dothtml-backend backfill` (admin-only)
+//! - `GET /admin/stats/satisfaction` - Summary of the satisfaction ratings (`?days=` to only count recent
+//!   ones, admin-only)
+//! - `GET /admin/abuse/patterns` - List the patterns of the abuse filter (admin-only)
+//! - `POST /admin/abuse/patterns` - Add a pattern to the abuse filter, or change its action (admin-only)
+//! - `DELETE /admin/abuse/patterns/{id}` - Remove a pattern from the abuse filter (admin-only)
+//! - `GET /admin/abuse/matches` - Submissions that matched the abuse filter (`?unreviewed=true` to hide
+//!   reviewed ones, `?limit=`, admin-only)
+//! - `POST /admin/abuse/matches/{id}/review` - Mark a match as reviewed (admin-only)
+//! - `GET /admin/rules` - List the routing rules applied to new messages (admin-only)
+//! - `POST /admin/rules` - Create a routing rule (admin-only)
+//! - `PUT /admin/rules/{id}` - Replace a routing rule (admin-only)
+//! - `DELETE /admin/rules/{id}` - Delete a routing rule (admin-only)
+//! - `GET /admin/suppressions` - List the do-not-contact list (admin-only)
+//! - `POST /admin/suppressions` - Add an address to the do-not-contact list (admin-only)
+//! - `DELETE /admin/suppressions/{email}` - Remove an address from the do-not-contact list (admin-only)
+//! - `GET /admin/templates` - List the email templates (admin-only, supports conditional requests)
+//! - `POST /admin/templates` - Create an email template (admin-only)
+//! - `PUT /admin/templates/{id}` - Replace an email template (admin-only)
+//! - `DELETE /admin/templates/{id}` - Delete an email template (admin-only)
+//! - `POST /admin/templates/{id}/preview` - Render an email template against sample data, returning
+//!   its subject, HTML and text parts (admin-only)
+//! - `GET /admin/export/anonymized` - Messages with pseudonyms instead of personal data, as NDJSON
+//!   (requires `ANONYMIZATION_KEY`, admin-only)
+//! - `GET /admin/tokens` - List the scoped API tokens, with when they were last used (admin-only)
+//! - `POST /admin/tokens` - Mint a scoped API token for automation (admin-only)
+//! - `DELETE /admin/tokens/{id}` - Revoke a scoped API token (admin-only)
+//! - `POST /admin/accounts/{account}/revoke-all` - End every session and login link of an agent, and
+//!   email them about it (admin-only)
+//! - `GET /admin/webhooks` - List the webhook endpoints receiving the outbox entries (admin-only)
+//! - `POST /admin/webhooks` - Register a webhook endpoint, with the events and payload fields it receives
+//!   and a template reshaping its body, returning its signing secret (admin-only)
+//! - `PUT /admin/webhooks/{id}` - Replace the address, filters and template of a webhook endpoint (admin-only)
+//! - `DELETE /admin/webhooks/{id}` - Remove a webhook endpoint (admin-only)
+//! - `POST /admin/webhooks/{id}/rotate-secret` - Give a webhook endpoint a new signing secret, the
+//!   previous one signing along for `WEBHOOK_SECRET_OVERLAP_HOURS` (admin-only)
+//! - `GET /admin/dead-letters` - Webhook deliveries, emails and outbox entries given up, with their error
+//!   (`?kind=webhook`, `?kind=email` or `?kind=outbox`, `?include_retried=true`, `?limit=`, admin-only)
+//! - `POST /admin/dead-letters/{id}/retry` - Send a dead letter again (admin-only)
+//! - `GET /admin/reports` - List the scheduled reports, with the outcome of their last run (admin-only)
+//! - `POST /admin/reports` - Schedule a report, such as the weekly stats, emailed as CSV and/or pushed to
+//!   an HTTP endpoint as CSV or JSON (admin-only)
+//! - `PUT /admin/reports/{id}` - Replace the settings of a report and reschedule it (admin-only)
+//! - `DELETE /admin/reports/{id}` - Delete a report (admin-only)
+//! - `POST /admin/reports/{id}/run` - Send a report right away (admin-only)
+//! - `GET /admin/ingest` - List the form providers set up to post submissions (admin-only)
+//! - `PUT /admin/ingest/{provider}` - Set a form provider up, with its signing secret, form and field
+//!   mapping (admin-only)
+//! - `DELETE /admin/ingest/{provider}` - Stop accepting the submissions of a form provider (admin-only)
+//!
+//! `GET /metrics`, `GET /status`, `GET /admin/stats/satisfaction`, `/admin/suppressions` and
+//! `GET /admin/export/anonymized` also accept the API tokens having their
+//! scope (see [`crate::api_tokens`]).
+//! 
+//! ### Integration API
+//! - `GET /integrations/me` - Who the credentials belong to, for connection tests
+//! - `GET /integrations/messages` - Polling trigger: messages received (or `?event=assigned`, `replied`,
+//!   `status_changed`) since the cursor of the previous poll (`?since=`, `?limit=`)
+//! - `GET /integrations/messages/{id}` - Look a message up
+//! - `POST /integrations/messages` - Create a message
+//! - `POST /integrations/messages/{id}/update` - Change the status, priority, assignee or tags of a message
+//!
+//! They take an API token with the `integrations:read` (`GET`) or
+//! `integrations:write` (`POST`) scope, or the admin token (see
+//! [`crate::integrations`]).
+//! 
+//! ### Admin UI
+//! - `GET /app/...` - Backoffice single-page UI (with the `admin-ui` feature and `ADMIN_UI_PATH`,
+//!   configured in `main.rs` by the `admin_ui` module)
 //! 
 //! ## Usage
 //! 
@@ -21,13 +198,20 @@
 //! 
 //! ```rust
 //! use actix_web::{App, web};
-//! use dothtml_backend::routes;
+//! use dothtml_backend::routes::{self, Surface};
 //! 
 //! let app = App::new()
 //!     .configure(routes::config);
+//!
+//! // Only the routes of the public listener
+//! let public_app = App::new()
+//!     .configure(|cfg| Surface::Website.configure(cfg));
 //! ```
 
-use actix_web::web;
+use actix_web::{middleware, web, Route};
+
+use crate::api_tokens::{self, TokenScope};
+use crate::concurrency::{self, Scope};
 
 pub use crate::handlers::*;
 
@@ -58,7 +242,7 @@ pub use crate::handlers::*;
 /// 
 /// Using this configuration in an Actix Web application:
 /// 
-/// ```rust
+/// ```rust,no_run
 /// use actix_web::{App, HttpServer, web};
 /// use dothtml_backend::routes;
 /// 
@@ -74,17 +258,190 @@ pub use crate::handlers::*;
 /// }
 /// ```
 pub fn config(cfg: &mut web::ServiceConfig) {
+    Surface::All.configure(cfg);
+}
+
+/// Group of routes served by one listener.
+///
+/// * `All` - Every route, when the server has a single listener
+/// * `Website` - The website API, for the public listener
+/// * `Backoffice` - The backoffice, operations and admin APIs, for the internal listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    All,
+    Website,
+    Backoffice,
+}
+
+impl Surface {
+    /// Returns whether the backoffice routes (and the admin UI) are served.
+    pub fn serves_backoffice(self) -> bool {
+        self != Surface::Website
+    }
+
+    /// Registers the routes of this surface.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - Mutable reference to the service configuration
+    pub fn configure(self, cfg: &mut web::ServiceConfig) {
+        if self != Surface::Backoffice {
+            website(cfg);
+        }
+        if self.serves_backoffice() {
+            backoffice(cfg);
+        }
+    }
+}
+
+/// Registers the routes used by the public website.
+fn website(cfg: &mut web::ServiceConfig) {
     cfg
         // ========================= Website API ========================= //
         .route("/contact", web::post().to(contact))
-        
+        .route("/contact/suggest", web::post().to(suggest))
+        .route("/contact/status/{id}", web::get().to(contact_status))
+        .route("/contact/followup/{token}", web::post().to(followup))
+        .route("/contact/rating/{token}", web::post().to(rate))
+        .route("/public/stats", web::get().to(public_stats))
+        .route("/widget/{form}", web::get().to(widget))
+        .route("/widget/{form}/embed.js", web::get().to(widget_script))
+        // Reached by the email provider, the issue tracker and the form builders, from outside like the website
+        .route("/webhooks/email/{provider}", web::post().to(email_webhook))
+        .route("/webhooks/issues/{provider}", web::post().to(issue_webhook))
+        .route("/ingest/{provider}", web::post().to(ingest));
+}
+
+/// Caps the concurrent executions of `route` with the limit of `scope`.
+fn limited(scope: Scope, route: Route) -> Route {
+    route.wrap(middleware::from_fn(move |req, next| concurrency::limit(scope, req, next)))
+}
+
+/// Lets the API tokens with `scope` call `route`.
+fn scoped(scope: TokenScope, route: Route) -> Route {
+    route.wrap(middleware::from_fn(move |req, next| api_tokens::authorize(scope, req, next)))
+}
+
+/// Registers the backoffice, operations and admin routes.
+fn backoffice(cfg: &mut web::ServiceConfig) {
+    cfg
         // ======================== Backoffice API ======================= //
+        .route("/inbox", limited(Scope::Search, web::get().to(inbox)))
         .route("/inbox/pending", web::get().to(pending))
+        .route("/inbox/trash", web::get().to(trash))
+        .route("/inbox/trash/{id}", web::delete().to(purge))
+        .route("/inbox/claim-next", web::post().to(claim_next))
+        .route("/inbox/ws", web::get().to(inbox_ws))
+        .route("/inbox/agents/online", web::get().to(online_agents))
+        .route("/inbox/feed.atom", scoped(TokenScope::FeedRead, web::get().to(inbox_feed)))
         .route("/inbox/{id}", web::get().to(get_message_by_id))
+        .route("/inbox/{id}/related", web::get().to(related))
 
         .route("/inbox/{id}/assign", web::post().to(assign))
         .route("/inbox/{id}/release", web::post().to(release))
+        .route("/inbox/{id}/approve", web::post().to(approve))
+        .route("/inbox/{id}/spam", web::post().to(spam))
+        .route("/inbox/{id}/ham", web::post().to(ham))
+        .route("/inbox/{id}/assignments", web::get().to(assignment_history))
+        .route("/inbox/{id}/events", web::get().to(message_events))
+        .route("/inbox/{id}/open", web::post().to(open))
         .route("/inbox/{id}/reply", web::post().to(reply))
+        .route("/inbox/{id}/merge", web::post().to(merge))
+        .route("/inbox/{id}/unarchive", web::post().to(unarchive))
+        .route("/inbox/{id}/escalate", web::post().to(escalate))
+        .route("/inbox/{id}/escalation", web::get().to(message_escalation))
+        .route("/inbox/{id}/crm", web::get().to(crm_sync))
+        .route("/inbox/{id}/crm/sync", web::post().to(resync_crm))
+        .route("/inbox/{id}/draft", web::post().to(draft_reply))
+        .route("/inbox/{id}/propose-times", web::post().to(propose_times))
+
+        .route("/inbox/{id}", web::patch().to(patch_message))
+        .route("/inbox/{id}", web::delete().to(delete))
+
+        .route("/senders/{email}", limited(Scope::Reports, web::get().to(sender_profile)))
+        .route("/stats", limited(Scope::Reports, web::get().to(stats)))
+        .route("/tags", web::get().to(tags))
+
+        .route("/companies", limited(Scope::Reports, web::get().to(companies)))
+        .route("/companies/{id}/messages", web::get().to(company_messages))
+        .route("/companies/{id}/merge", web::post().to(merge_company))
+
+        .route("/me", web::get().to(me))
+        .route("/me/preferences", web::get().to(get_preferences))
+        .route("/me/preferences", web::put().to(set_preferences))
+        .route("/me/security/events", web::get().to(security_events))
+        .route("/me/scheduling", web::get().to(get_scheduling))
+        .route("/me/scheduling", web::put().to(set_scheduling))
+        .route("/me/scheduling", web::delete().to(delete_scheduling))
+        .route("/me/deadlines/feed", web::post().to(deadline_feed_url))
+        .route("/me/deadlines.ics", web::get().to(deadlines_feed))
+        .route("/auth/magic-link", web::post().to(request_magic_link))
+        .route("/auth/magic-link/{token}", web::post().to(open_session))
+        .route("/auth/csrf-token", web::get().to(csrf_token))
+
+        // ========================== Admin API ========================== //
+        .route("/version", web::get().to(version))
+        .route("/.well-known/jwks.json", web::get().to(jwks))
+        .route("/metrics", scoped(TokenScope::MetricsRead, web::get().to(metrics)))
+        .route("/status", scoped(TokenScope::StatsRead, web::get().to(status_page)))
+        .route("/admin/config/reload", web::post().to(reload_config))
+        .route("/admin/debug/requests", web::get().to(debug_requests))
+        .route("/admin/flags", web::get().to(list_flags))
+        .route("/admin/flags/{name}", web::put().to(set_flag))
+        .route("/admin/flags/{name}", web::delete().to(delete_flag))
+        .route("/admin/origins", web::get().to(list_origins))
+        .route("/admin/origins/{form}", web::put().to(set_origins))
+        .route("/admin/origins/{form}", web::delete().to(delete_origins))
+        .route("/admin/backfills", web::get().to(list_backfills))
+        .route("/admin/stats/satisfaction", scoped(TokenScope::StatsRead, web::get().to(satisfaction_stats)))
+        .route("/admin/abuse/patterns", web::get().to(list_abuse_patterns))
+        .route("/admin/abuse/patterns", web::post().to(set_abuse_pattern))
+        .route("/admin/abuse/patterns/{id}", web::delete().to(delete_abuse_pattern))
+        .route("/admin/abuse/matches", web::get().to(list_abuse_matches))
+        .route("/admin/abuse/matches/{id}/review", web::post().to(review_abuse_match))
+        .route("/admin/rules", web::get().to(list_rules))
+        .route("/admin/rules", web::post().to(create_rule))
+        .route("/admin/rules/{id}", web::put().to(update_rule))
+        .route("/admin/rules/{id}", web::delete().to(delete_rule))
+        .route("/admin/suppressions", scoped(TokenScope::SuppressionsRead, web::get().to(list_suppressions)))
+        .route("/admin/suppressions", scoped(TokenScope::SuppressionsWrite, web::post().to(add_suppression)))
+        .route("/admin/suppressions/{email}", scoped(TokenScope::SuppressionsWrite, web::delete().to(remove_suppression)))
+        .route("/admin/templates", web::get().to(list_templates))
+        .route("/admin/templates", web::post().to(create_template))
+        .route("/admin/templates/{id}", web::put().to(update_template))
+        .route("/admin/templates/{id}", web::delete().to(delete_template))
+        .route("/admin/templates/{id}/preview", web::post().to(preview_template))
+        .route("/admin/export/anonymized", scoped(TokenScope::ExportRead, limited(Scope::Export, web::get().to(anonymized_export))))
+        .route("/admin/tokens", web::get().to(list_api_tokens))
+        .route("/admin/tokens", web::post().to(create_api_token))
+        .route("/admin/tokens/{id}", web::delete().to(revoke_api_token))
+        .route("/admin/accounts/{account}/revoke-all", web::post().to(revoke_all_sessions))
+        .route("/admin/webhooks", web::get().to(list_webhooks))
+        .route("/admin/webhooks", web::post().to(create_webhook))
+        .route("/admin/webhooks/{id}", web::put().to(update_webhook))
+        .route("/admin/webhooks/{id}", web::delete().to(delete_webhook))
+        .route("/admin/webhooks/{id}/rotate-secret", web::post().to(rotate_webhook_secret))
+        .route("/admin/dead-letters", web::get().to(list_dead_letters))
+        .route("/admin/dead-letters/{id}/retry", web::post().to(retry_dead_letter))
+        .route("/admin/reports", web::get().to(list_reports))
+        .route("/admin/reports", web::post().to(create_report))
+        .route("/admin/reports/{id}", web::put().to(update_report))
+        .route("/admin/reports/{id}", web::delete().to(delete_report))
+        .route("/admin/reports/{id}/run", web::post().to(run_report))
+        .route("/admin/ingest", web::get().to(list_form_sources))
+        .route("/admin/ingest/{provider}", web::put().to(set_form_source))
+        .route("/admin/ingest/{provider}", web::delete().to(delete_form_source))
+
+        // ======================= Integration API ======================= //
+        .route("/integrations/me", scoped(TokenScope::IntegrationsRead, web::get().to(integration_me)))
+        .route("/integrations/messages", scoped(TokenScope::IntegrationsRead, web::get().to(integration_messages)))
+        .route("/integrations/messages", scoped(TokenScope::IntegrationsWrite, web::post().to(integration_create_message)))
+        .route("/integrations/messages/{id}", scoped(TokenScope::IntegrationsRead, web::get().to(integration_message)))
+        .route(
+            "/integrations/messages/{id}/update",
+            scoped(TokenScope::IntegrationsWrite, web::post().to(integration_update_message)),
+        );
 
-        .route("/inbox/{id}", web::delete().to(delete));
+    #[cfg(feature = "graphql")]
+    cfg.route("/graphql", web::post().to(graphql));
 }
@@ -0,0 +1,459 @@
+//! # Routing Rules
+//!
+//! Rules managed by admins through `/admin/rules`, evaluated on every new
+//! message, so that the team can route messages ("messages containing
+//! 'invoice' go to accounting") without code changes.
+//!
+//! A rule has conditions, all of which must match, each listing values
+//! any of which matches:
+//!
+//! - `forms` - Form the message was submitted through (`contact` by default)
+//! - `categories` - Category given by the classifier (see the `classification` module)
+//! - `countries` - Country/region of the sender (case-insensitive)
+//! - `keywords` - Words or phrases of the company or message (case-insensitive)
+//!
+//! and actions:
+//!
+//! - `assign` - Assign the message to an agent, unless it is quarantined
+//! - `tags` - Add tags to the message
+//! - `priority` - Set the priority of the message
+//! - `notify` - Publish a `message.rule_matched` entry in the outbox for the
+//!   listed recipients, for the notification service to deliver
+//!
+//! Rules are evaluated in the order of their `position`, then creation. Tags
+//! and notifications of every matching rule apply; for the assignee and the
+//! priority, the first matching rule setting them wins; with the
+//! `online_assignment` feature flag, the first one setting an online agent
+//! (see the `presence` module). A matching rule with `stop` ends the
+//! evaluation. Rules run in the transaction storing the
+//! message, so a message is never stored unrouted.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::Row;
+
+use crate::caching::RESOURCE_TAGS;
+use crate::classification::Category;
+use crate::database::Database;
+use crate::events::MessageEventKind;
+use crate::models::Message;
+
+/// Outbox topic of the notifications of matching rules.
+pub const RULE_MATCHED_TOPIC: &str = "message.rule_matched";
+
+/// Conditions of a rule. Empty lists match every message.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::classification::Category;
+/// use dothtml_backend::rules::RuleConditions;
+///
+/// let conditions = RuleConditions {
+///     categories: vec![Category::Support],
+///     keywords: vec!["invoice".to_string(), "credit note".to_string()],
+///     ..RuleConditions::default()
+/// };
+/// assert!(conditions.matches("contact", Some("support"), "France", "ACME", "Where is my Credit Note?"));
+/// assert!(!conditions.matches("contact", Some("sales"), "France", "ACME", "Where is my invoice?"));
+/// assert!(!conditions.matches("contact", Some("support"), "France", "ACME", "Invoices are late"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleConditions {
+    pub forms: Vec<String>,
+    pub categories: Vec<Category>,
+    pub countries: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+impl RuleConditions {
+    /// Returns whether a message with these attributes matches every
+    /// condition.
+    pub fn matches(&self, form: &str, category: Option<&str>, country_region: &str, company: &str, message: &str) -> bool {
+        if !self.forms.is_empty() && !self.forms.iter().any(|candidate| candidate == form) {
+            return false;
+        }
+        if !self.categories.is_empty()
+            && !self.categories.iter().any(|candidate| Some(candidate.as_str()) == category)
+        {
+            return false;
+        }
+        if !self.countries.is_empty()
+            && !self.countries.iter().any(|candidate| candidate.eq_ignore_ascii_case(country_region.trim()))
+        {
+            return false;
+        }
+        if !self.keywords.is_empty() {
+            // Keywords are matched as whole words, so phrases are matched on the joined words
+            let text = format!(" {} {} ", words(company).join(" "), words(message).join(" "));
+            let found = self
+                .keywords
+                .iter()
+                .map(|keyword| words(keyword).join(" "))
+                .any(|keyword| !keyword.is_empty() && text.contains(&format!(" {} ", keyword)));
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Actions of a rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleActions {
+    pub assign: Option<String>,
+    pub tags: Vec<String>,
+    pub priority: Option<String>,
+    pub notify: Vec<String>,
+}
+
+impl RuleActions {
+    /// Returns whether the rule would do nothing.
+    pub fn is_empty(&self) -> bool {
+        self.assign.is_none() && self.tags.is_empty() && self.priority.is_none() && self.notify.is_empty()
+    }
+}
+
+/// A routing rule.
+///
+/// # Fields
+///
+/// * `id` - Identifier of the rule
+/// * `name` - Description of the rule for admins
+/// * `position` - Evaluation order, lowest first
+/// * `enabled` - Whether the rule is evaluated
+/// * `stop` - Whether no rule is evaluated after this one when it matches
+/// * `conditions` - What messages the rule applies to
+/// * `actions` - What the rule does to them
+/// * `created_at` - Timestamp when the rule was created
+/// * `updated_at` - Timestamp of the last change of the rule
+#[derive(Debug, Clone, Serialize)]
+pub struct Rule {
+    pub id: i64,
+    pub name: String,
+    pub position: i32,
+    pub enabled: bool,
+    pub stop: bool,
+    pub conditions: RuleConditions,
+    pub actions: RuleActions,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Content of a rule, as created or replaced by an admin.
+///
+/// # Fields
+///
+/// * `name` - Description of the rule for admins
+/// * `position` - Evaluation order, lowest first (default: 0)
+/// * `enabled` - Whether the rule is evaluated (default: true)
+/// * `stop` - Whether no rule is evaluated after this one when it matches (default: false)
+/// * `conditions` - What messages the rule applies to
+/// * `actions` - What the rule does to them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub position: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub stop: bool,
+    #[serde(default)]
+    pub conditions: RuleConditions,
+    pub actions: RuleActions,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Splits `text` into lowercase words, ignoring punctuation.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Column list selected for every `Rule` row.
+const RULE_COLUMNS: &str = "id, name, position, enabled, stop, conditions, actions, created_at, updated_at";
+
+fn rule_from_row(row: &PgRow) -> Rule {
+    Rule {
+        id: row.get("id"),
+        name: row.get("name"),
+        position: row.get("position"),
+        enabled: row.get("enabled"),
+        stop: row.get("stop"),
+        // Written by this module, an unreadable value can only come from a manual edit
+        conditions: row.try_get::<Json<RuleConditions>, _>("conditions").map(|json| json.0).unwrap_or_default(),
+        actions: row.try_get::<Json<RuleActions>, _>("actions").map(|json| json.0).unwrap_or_default(),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Database operations for routing rules.
+impl Database {
+    /// Creates the 'rules' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_rules_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_rules_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS rules (
+                id BIGSERIAL PRIMARY KEY,
+                name TEXT NOT NULL,
+                position INTEGER NOT NULL DEFAULT 0,
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                stop BOOLEAN NOT NULL DEFAULT FALSE,
+                conditions JSONB NOT NULL DEFAULT '{}',
+                actions JSONB NOT NULL DEFAULT '{}',
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the rules in evaluation order, disabled ones included.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_rules(&self) -> Result<Vec<Rule>, sqlx::Error> {
+        let rows = sqlx::query(&format!("SELECT {RULE_COLUMNS} FROM rules ORDER BY position, id"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(rule_from_row).collect())
+    }
+
+    /// Creates a rule.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::rules::{RuleActions, RuleConditions, RuleDefinition};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let rule = db.create_rule(&RuleDefinition {
+    ///         name: "Invoices go to accounting".to_string(),
+    ///         position: 0,
+    ///         enabled: true,
+    ///         stop: false,
+    ///         conditions: RuleConditions { keywords: vec!["invoice".to_string()], ..RuleConditions::default() },
+    ///         actions: RuleActions { assign: Some("accounting".to_string()), ..RuleActions::default() },
+    ///     }).await?;
+    ///     println!("Created rule {}", rule.id);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_rule(&self, rule: &RuleDefinition) -> Result<Rule, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO rules (name, position, enabled, stop, conditions, actions)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING {RULE_COLUMNS}
+        "#))
+        .bind(&rule.name)
+        .bind(rule.position)
+        .bind(rule.enabled)
+        .bind(rule.stop)
+        .bind(Json(&rule.conditions))
+        .bind(Json(&rule.actions))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rule_from_row(&row))
+    }
+
+    /// Replaces a rule.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The rule does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    pub async fn update_rule(&self, id: i64, rule: &RuleDefinition) -> Result<Rule, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE rules
+            SET name = $2, position = $3, enabled = $4, stop = $5, conditions = $6, actions = $7, updated_at = NOW()
+            WHERE id = $1
+            RETURNING {RULE_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(&rule.name)
+        .bind(rule.position)
+        .bind(rule.enabled)
+        .bind(rule.stop)
+        .bind(Json(&rule.conditions))
+        .bind(Json(&rule.actions))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rule_from_row(&row))
+    }
+
+    /// Deletes a rule.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the rule existed.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_rule(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM rules WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Evaluates the enabled rules on a message being stored and applies
+    /// the actions of the matching ones, in the transaction storing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - Transaction storing the message
+    /// * `message` - The stored message, with its full body; updated with
+    ///   the actions applied
+    ///
+    /// # Returns
+    ///
+    /// Returns the IDs of the matching rules.
+    pub(crate) async fn apply_rules(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, message: &mut Message
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let rows = sqlx::query(&format!("SELECT {RULE_COLUMNS} FROM rules WHERE enabled ORDER BY position, id"))
+            .fetch_all(&mut **tx)
+            .await?;
+
+        let mut matched = Vec::new();
+        let mut assignees: Vec<String> = Vec::new();
+        let mut priority: Option<String> = None;
+        let mut tags: Vec<String> = Vec::new();
+        for rule in rows.iter().map(rule_from_row) {
+            if !rule.conditions.matches(
+                &message.form,
+                message.category.as_deref(),
+                &message.country_region,
+                &message.company,
+                &message.message,
+            ) {
+                continue;
+            }
+            matched.push(rule.id);
+
+            if let Some(agent) = rule.actions.assign {
+                assignees.push(agent);
+            }
+            priority = priority.or(rule.actions.priority);
+            for tag in rule.actions.tags {
+                if !tags.contains(&tag) && !message.tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            if !rule.actions.notify.is_empty() {
+                Self::enqueue_outbox(
+                    &mut **tx,
+                    RULE_MATCHED_TOPIC,
+                    &format!("rule_matched:{}:{}", rule.id, message.id),
+                    &json!({
+                        "rule_id": rule.id,
+                        "rule": rule.name,
+                        "recipients": rule.actions.notify,
+                        "message_id": message.id,
+                        "name": message.name,
+                        "email": message.email,
+                        "company": message.company,
+                        "category": message.category,
+                    }),
+                ).await?;
+            }
+            if rule.stop {
+                break;
+            }
+        }
+
+        // Quarantined messages wait for approval before being assigned
+        let assign = if message.status == "pending" { Self::pick_assignee(tx, &assignees).await? } else { None };
+        if assign.is_none() && priority.is_none() && tags.is_empty() {
+            return Ok(matched);
+        }
+
+        let row = sqlx::query(r#"
+            UPDATE messages
+            SET tags = tags || $2::text[],
+                priority = COALESCE($3, priority),
+                status = CASE WHEN $4::text IS NULL THEN status ELSE 'assigned' END,
+                assigned_to = COALESCE($4, assigned_to),
+                assigned_at = CASE WHEN $4::text IS NULL THEN assigned_at ELSE NOW() END
+            WHERE id = $1
+            RETURNING tags, priority, status, assigned_to, assigned_at
+        "#)
+        .bind(message.id)
+        .bind(&tags)
+        .bind(&priority)
+        .bind(&assign)
+        .fetch_one(&mut **tx)
+        .await?;
+        message.tags = row.get("tags");
+        message.priority = row.get("priority");
+        message.status = row.get("status");
+        message.assigned_to = row.get("assigned_to");
+        message.assigned_at = row.get("assigned_at");
+
+        if let Some(agent) = &assign {
+            Self::record_assignment_event(tx, message.id, agent, "assigned").await?;
+            Self::record_event(tx, message.id, MessageEventKind::Assigned, json!({
+                "agent": agent,
+                "claimed": false,
+                "rules": matched,
+            })).await?;
+        }
+        if !tags.is_empty() {
+            Self::touch_resource(&mut **tx, RESOURCE_TAGS).await?;
+        }
+
+        Ok(matched)
+    }
+}
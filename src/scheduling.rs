@@ -0,0 +1,324 @@
+//! # Scheduling
+//!
+//! Meetings offered to senders in replies. Each agent saves the link of
+//! their booking page (Calendly, Cal.com...) and the usual length of their
+//! meetings with `PUT /me/scheduling`:
+//!
+//! - Canned responses drafted for a message with `POST /inbox/{id}/draft`
+//!   get the booking link of the message's assignee as `booking_link`
+//!   (see the `templates` module), so that one "book a call here" template
+//!   serves every agent.
+//! - `POST /inbox/{id}/propose-times` turns a few time slots picked by the
+//!   agent into an ICS file, to attach to the reply: the sender's calendar
+//!   shows each slot as a tentative event they can accept.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::ics::{self, Calendar};
+
+/// Length of the meetings of agents who did not set one, in minutes.
+pub const DEFAULT_MEETING_MINUTES: i32 = 30;
+
+/// Maximum number of time slots proposed at once.
+pub const MAX_PROPOSED_TIMES: usize = 10;
+
+/// Scheduling settings of an agent.
+///
+/// # Fields
+///
+/// * `account` - Name of the agent, as used in assignments
+/// * `booking_url` - Link of the agent's booking page
+/// * `meeting_minutes` - Usual length of the agent's meetings
+/// * `updated_at` - When the settings were last saved
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchedulingSettings {
+    pub account: String,
+    pub booking_url: String,
+    pub meeting_minutes: i32,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn default_meeting_minutes() -> i32 {
+    DEFAULT_MEETING_MINUTES
+}
+
+/// Body of `PUT /me/scheduling`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulingUpdate {
+    pub booking_url: String,
+    #[serde(default = "default_meeting_minutes")]
+    pub meeting_minutes: i32,
+}
+
+impl SchedulingUpdate {
+    /// Normalizes the settings and checks that they are usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an explanation when the booking link is not an absolute
+    /// http(s) URL, or the meetings do not last between 5 minutes and 8 hours.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::scheduling::SchedulingUpdate;
+    ///
+    /// let update: SchedulingUpdate = serde_json::from_value(serde_json::json!({
+    ///     "booking_url": " https://cal.com/alice "
+    /// })).unwrap();
+    /// let update = update.check().unwrap();
+    /// assert_eq!(update.booking_url, "https://cal.com/alice");
+    /// assert_eq!(update.meeting_minutes, 30);
+    ///
+    /// let update: SchedulingUpdate = serde_json::from_value(serde_json::json!({
+    ///     "booking_url": "cal.com/alice"
+    /// })).unwrap();
+    /// assert!(update.check().is_err());
+    /// ```
+    pub fn check(mut self) -> Result<Self, String> {
+        self.booking_url = self.booking_url.trim().to_string();
+        match reqwest::Url::parse(&self.booking_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {}
+            _ => return Err("Booking URL must be an absolute http or https address".to_string()),
+        }
+        if self.booking_url.len() > 2000 {
+            return Err("Booking URL must be at most 2000 characters".to_string());
+        }
+        if !(5..=480).contains(&self.meeting_minutes) {
+            return Err("Meetings must last between 5 and 480 minutes".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Body of `POST /inbox/{id}/propose-times`.
+///
+/// # Fields
+///
+/// * `times` - Start of each proposed slot
+/// * `duration_minutes` - Length of the meeting (default: the agent's usual length)
+/// * `title` - Title of the events (default: "Call with <agent>")
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeetingProposal {
+    pub times: Vec<DateTime<Utc>>,
+    pub duration_minutes: Option<i32>,
+    pub title: Option<String>,
+}
+
+impl MeetingProposal {
+    /// Checks the proposal against the current time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an explanation when no slot, or more than
+    /// [`MAX_PROPOSED_TIMES`], are proposed, a slot is in the past, the
+    /// length is out of bounds, or the title is too long.
+    pub fn check(mut self, now: DateTime<Utc>) -> Result<Self, String> {
+        if self.times.is_empty() || self.times.len() > MAX_PROPOSED_TIMES {
+            return Err(format!("Between 1 and {} times must be proposed", MAX_PROPOSED_TIMES));
+        }
+        if self.times.iter().any(|time| *time <= now) {
+            return Err("Proposed times must be in the future".to_string());
+        }
+        self.times.sort();
+        self.times.dedup();
+        if self.duration_minutes.is_some_and(|minutes| !(5..=480).contains(&minutes)) {
+            return Err("Meetings must last between 5 and 480 minutes".to_string());
+        }
+        self.title = self.title.map(|title| title.trim().to_string()).filter(|title| !title.is_empty());
+        if self.title.as_ref().is_some_and(|title| title.chars().count() > 200) {
+            return Err("Title must be at most 200 characters".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// A meeting proposed to a sender, as written in an ICS file.
+///
+/// # Fields
+///
+/// * `title` - Title of the events
+/// * `description` - Description of the events
+/// * `attendee_name` - Name of the sender
+/// * `attendee_email` - Email of the sender
+/// * `duration_minutes` - Length of each event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meeting {
+    pub title: String,
+    pub description: String,
+    pub attendee_name: String,
+    pub attendee_email: String,
+    pub duration_minutes: i32,
+}
+
+impl Meeting {
+    /// Writes an ICS calendar holding one tentative event per slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_id` - ID of the message the meeting is about, making the
+    ///   events' UIDs stable when the same slots are proposed again
+    /// * `times` - Start of each slot
+    /// * `stamp` - When the calendar is written
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use chrono::{TimeZone, Utc};
+    /// use uuid::Uuid;
+    /// use dothtml_backend::scheduling::Meeting;
+    ///
+    /// let meeting = Meeting {
+    ///     title: "Call with alice".to_string(),
+    ///     description: "About your quote, sites; apps".to_string(),
+    ///     attendee_name: "Jane Doe".to_string(),
+    ///     attendee_email: "jane@example.com".to_string(),
+    ///     duration_minutes: 30,
+    /// };
+    /// let start = Utc.with_ymd_and_hms(2026, 10, 20, 14, 0, 0).unwrap();
+    /// let ics = meeting.to_ics(Uuid::nil(), &[start], start);
+    /// assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    /// assert!(ics.contains("DTSTART:20261020T140000Z\r\nDTEND:20261020T143000Z\r\n"));
+    /// assert!(ics.contains("DESCRIPTION:About your quote\\, sites\\; apps\r\n"));
+    /// assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    /// ```
+    pub fn to_ics(&self, message_id: Uuid, times: &[DateTime<Utc>], stamp: DateTime<Utc>) -> String {
+        let attendee = format!("ATTENDEE;CN={};ROLE=REQ-PARTICIPANT", ics::quoted(&self.attendee_name));
+        let mut calendar = Calendar::new("PUBLISH");
+        for time in times {
+            let end = *time + chrono::Duration::minutes(i64::from(self.duration_minutes));
+            calendar.event(&[
+                ("UID", format!("{}-{}@dothtml-backend", message_id, time.timestamp())),
+                ("DTSTAMP", ics::date_time(stamp)),
+                ("DTSTART", ics::date_time(*time)),
+                ("DTEND", ics::date_time(end)),
+                ("SUMMARY", ics::text(&self.title)),
+                ("DESCRIPTION", ics::text(&self.description)),
+                (attendee.as_str(), format!("mailto:{}", self.attendee_email)),
+                ("STATUS", "TENTATIVE".to_string()),
+            ]);
+        }
+        calendar.finish()
+    }
+}
+
+/// Builds settings from a row of the `scheduling_links` table.
+fn settings_from_row(row: &PgRow) -> SchedulingSettings {
+    SchedulingSettings {
+        account: row.get("account"),
+        booking_url: row.get("booking_url"),
+        meeting_minutes: row.get("meeting_minutes"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Database operations for scheduling settings.
+impl Database {
+    /// Creates the 'scheduling_links' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_scheduling_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS scheduling_links (
+                account TEXT PRIMARY KEY,
+                booking_url TEXT NOT NULL,
+                meeting_minutes INTEGER NOT NULL DEFAULT 30,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the scheduling settings of an agent, `None` if they never
+    /// saved any.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     if let Some(settings) = db.get_scheduling_settings("alice").await? {
+    ///         println!("Book alice at {}", settings.booking_url);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn get_scheduling_settings(&self, account: &str) -> Result<Option<SchedulingSettings>, sqlx::Error> {
+        let row = sqlx::query("SELECT account, booking_url, meeting_minutes, updated_at FROM scheduling_links WHERE account = $1")
+            .bind(account)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(settings_from_row))
+    }
+
+    /// Replaces the scheduling settings of an agent.
+    ///
+    /// # Returns
+    ///
+    /// Returns the saved settings.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn set_scheduling_settings(
+        &self, account: &str, settings: &SchedulingUpdate
+    ) -> Result<SchedulingSettings, sqlx::Error> {
+        let row = sqlx::query(r#"
+            INSERT INTO scheduling_links (account, booking_url, meeting_minutes)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (account) DO UPDATE SET
+                booking_url = EXCLUDED.booking_url,
+                meeting_minutes = EXCLUDED.meeting_minutes,
+                updated_at = NOW()
+            RETURNING account, booking_url, meeting_minutes, updated_at
+        "#)
+        .bind(account)
+        .bind(&settings.booking_url)
+        .bind(settings.meeting_minutes)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(settings_from_row(&row))
+    }
+
+    /// Deletes the scheduling settings of an agent.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the agent had none.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_scheduling_settings(&self, account: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM scheduling_links WHERE account = $1")
+            .bind(account)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,300 @@
+//! # Magic Links and Sessions
+//!
+//! Password-less fallback login for agents who cannot use the admin token
+//! on the machine they are working from. Enabled with `MAGIC_LINK_LOGIN`:
+//!
+//! 1. `POST /auth/magic-link` with the agent's name emails them a link to
+//!    `MAGIC_LINK_URL?token=<magic link token>`. Only agents who saved an
+//!    email address in their notification preferences (see the
+//!    `notifications` module) get one.
+//! 2. The backoffice page at `MAGIC_LINK_URL` posts the token to
+//!    `POST /auth/magic-link/{token}`, which answers with a session token.
+//!    Opening the link does not consume it, so mail scanners following the
+//!    links of incoming emails cannot log in in the agent's place.
+//! 3. The session token is sent as `Authorization: Bearer <session token>`
+//!    to the endpoints guarded by `auth::Account`, such as `/me`.
+//!
+//! Magic links are signed with `SESSION_SECRET` and name the agent, so
+//! nothing is stored but the links already used: a link works once, for
+//! `MAGIC_LINK_TTL_MINUTES`. Sessions are JWTs lasting `SESSION_TTL_HOURS`,
+//! signed with the rotating keys of the `jwt` module so that other services
+//! can check them.
+//!
+//! ## Revocation
+//!
+//! `POST /admin/accounts/{account}/revoke-all` ends every session of an
+//! agent, and every login link sent to them, by storing the time of the
+//! revocation in the `account_revocations` table: sessions issued and
+//! links sent before it are refused from then on, as are the deadline feed
+//! URLs (see the `deadlines` module). Other services checking
+//! sessions with the published keys do not see revocations and should
+//! keep their sessions short.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use maud::html;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::templates::RenderedEmail;
+use crate::tokens::TokenError;
+
+/// Length of the fixed part of a decoded token: issue and expiration times,
+/// nonce, signature.
+const HEADER_BYTES: usize = 8 + 8 + 16 + 32;
+
+/// Longest agent name a token carries, in bytes.
+const MAX_ACCOUNT_BYTES: usize = 400;
+
+/// What an account token allows its holder to do.
+///
+/// * `MagicLink` - Open a session once (`POST /auth/magic-link/{token}`)
+/// * `CalendarFeed` - Read the agent's deadline feed (`GET /me/deadlines.ics`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountTokenPurpose {
+    MagicLink,
+    CalendarFeed,
+}
+
+impl AccountTokenPurpose {
+    /// Returns the name signed with the token.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTokenPurpose::MagicLink => "magic_link",
+            AccountTokenPurpose::CalendarFeed => "calendar_feed",
+        }
+    }
+}
+
+/// Content of a verified account token.
+///
+/// # Fields
+///
+/// * `account` - Name of the agent the token was issued to
+/// * `nonce` - Random value identifying the token, to make magic links single-use
+/// * `issued_at` - When the token was issued, compared with revocations
+/// * `expires_at` - Expiration time of the token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountClaims {
+    pub account: String,
+    pub nonce: Uuid,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Issues and verifies the magic links of agents.
+///
+/// A token is the URL-safe base64 encoding of the issue and expiration
+/// times, a random nonce, an HMAC-SHA256 of them, the purpose and the
+/// agent's name, then the name itself. The issue time is signed rather than
+/// derived from the lifetime, which the configuration may change since.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{Duration, Utc};
+/// use dothtml_backend::sessions::{AccountTokenPurpose, AccountTokens};
+/// use dothtml_backend::tokens::TokenError;
+///
+/// let tokens = AccountTokens::new("secret");
+/// let token = tokens.issue(AccountTokenPurpose::MagicLink, "alice", Utc::now() + Duration::minutes(15));
+///
+/// let claims = tokens.verify(AccountTokenPurpose::MagicLink, &token).unwrap();
+/// assert_eq!(claims.account, "alice");
+/// assert!(claims.issued_at <= Utc::now());
+/// let used = tokens.issue(AccountTokenPurpose::MagicLink, "alice", Utc::now() - Duration::minutes(1));
+/// assert_eq!(tokens.verify(AccountTokenPurpose::MagicLink, &used), Err(TokenError::Expired));
+/// assert_eq!(AccountTokens::new("other").verify(AccountTokenPurpose::MagicLink, &token), Err(TokenError::Invalid));
+/// ```
+#[derive(Clone)]
+pub struct AccountTokens {
+    mac: Hmac<Sha256>,
+}
+
+impl AccountTokens {
+    /// Creates an issuer signing with `secret`.
+    pub fn new(secret: &str) -> Self {
+        AccountTokens {
+            mac: Hmac::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// Returns a token allowing `purpose` as `account` until `expires_at`.
+    pub fn issue(&self, purpose: AccountTokenPurpose, account: &str, expires_at: DateTime<Utc>) -> String {
+        let mut times = [0u8; 16];
+        times[..8].copy_from_slice(&Utc::now().timestamp().to_be_bytes());
+        times[8..].copy_from_slice(&expires_at.timestamp().to_be_bytes());
+        let nonce = Uuid::new_v4();
+        let signature = self.sign(purpose, account, &times, nonce.as_bytes()).finalize().into_bytes();
+
+        let mut token = Vec::with_capacity(HEADER_BYTES + account.len());
+        token.extend_from_slice(&times);
+        token.extend_from_slice(nonce.as_bytes());
+        token.extend_from_slice(&signature);
+        token.extend_from_slice(account.as_bytes());
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Checks that `token` was issued for `purpose` and has not expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the token is rejected (see [`TokenError`]).
+    pub fn verify(&self, purpose: AccountTokenPurpose, token: &str) -> Result<AccountClaims, TokenError> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| TokenError::Malformed)?;
+        if bytes.len() <= HEADER_BYTES || bytes.len() > HEADER_BYTES + MAX_ACCOUNT_BYTES {
+            return Err(TokenError::Malformed);
+        }
+        let (times, rest) = bytes.split_at(16);
+        let (nonce, rest) = rest.split_at(16);
+        let (signature, account) = rest.split_at(32);
+        let account = std::str::from_utf8(account).map_err(|_| TokenError::Malformed)?;
+
+        self.sign(purpose, account, times, nonce)
+            .verify_slice(signature)
+            .map_err(|_| TokenError::Invalid)?;
+
+        let time = |bytes: &[u8]| {
+            let seconds = i64::from_be_bytes(bytes.try_into().map_err(|_| TokenError::Malformed)?);
+            Utc.timestamp_opt(seconds, 0).single().ok_or(TokenError::Malformed)
+        };
+        let issued_at = time(&times[..8])?;
+        let expires_at = time(&times[8..])?;
+        if expires_at <= Utc::now() {
+            return Err(TokenError::Expired);
+        }
+        Ok(AccountClaims {
+            account: account.to_string(),
+            nonce: Uuid::from_slice(nonce).map_err(|_| TokenError::Malformed)?,
+            issued_at,
+            expires_at,
+        })
+    }
+
+    fn sign(&self, purpose: AccountTokenPurpose, account: &str, times: &[u8], nonce: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(purpose.as_str().as_bytes());
+        mac.update(b":");
+        mac.update(times);
+        mac.update(nonce);
+        mac.update(account.as_bytes());
+        mac
+    }
+}
+
+/// Returns the login link of `MAGIC_LINK_URL` carrying `token`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::sessions::login_link;
+///
+/// assert_eq!(login_link("https://dotshell.eu/app/login", "abc"), "https://dotshell.eu/app/login?token=abc");
+/// assert_eq!(login_link("https://dotshell.eu/app?page=login", "abc"), "https://dotshell.eu/app?page=login&token=abc");
+/// ```
+pub fn login_link(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", url, separator, token)
+}
+
+/// Builds the email carrying a login link valid for `ttl_minutes`.
+pub fn login_email(link: &str, ttl_minutes: u32) -> RenderedEmail {
+    let text = format!(
+        "Open this link to log in to the backoffice:\n\n{}\n\nIt works once, in the next {} minutes. \
+         If you did not ask for it, you can ignore this email.",
+        link, ttl_minutes
+    );
+    let html = html! {
+        p { "Open this link to log in to the backoffice:" }
+        p { a href=(link) { "Log in" } }
+        p { "It works once, in the next " (ttl_minutes) " minutes. If you did not ask for it, you can ignore this email." }
+    };
+    RenderedEmail { subject: "Your login link".to_string(), html: html.into_string(), text }
+}
+
+/// Builds the email telling an agent that all their sessions were ended.
+pub fn revoked_email() -> RenderedEmail {
+    let text = "An administrator ended all your backoffice sessions and login links. \
+                Request a new login link to log in again. If you did not expect this, \
+                contact your administrator."
+        .to_string();
+    let html = html! {
+        p { "An administrator ended all your backoffice sessions and login links." }
+        p { "Request a new login link to log in again. If you did not expect this, contact your administrator." }
+    };
+    RenderedEmail { subject: "Your sessions were ended".to_string(), html: html.into_string(), text }
+}
+
+/// Database operations for the revocation of sessions.
+impl Database {
+    /// Creates the 'account_revocations' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_account_revocations_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS account_revocations (
+                account TEXT PRIMARY KEY,
+                revoked_before TIMESTAMPTZ NOT NULL
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ends every session and login link issued to `account` until now.
+    ///
+    /// # Returns
+    ///
+    /// Returns the time of the revocation.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let revoked_before = db.revoke_account_sessions("alice").await?;
+    ///     println!("Sessions of alice issued before {} are refused", revoked_before);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn revoke_account_sessions(&self, account: &str) -> Result<DateTime<Utc>, sqlx::Error> {
+        sqlx::query_scalar(r#"
+            INSERT INTO account_revocations (account, revoked_before)
+            VALUES ($1, NOW())
+            ON CONFLICT (account) DO UPDATE SET revoked_before = EXCLUDED.revoked_before
+            RETURNING revoked_before
+        "#)
+        .bind(account)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Returns the time before which the sessions and login links of
+    /// `account` are refused, `None` if they were never revoked.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn sessions_revoked_before(&self, account: &str) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar("SELECT revoked_before FROM account_revocations WHERE account = $1")
+            .bind(account)
+            .fetch_optional(&self.pool)
+            .await
+    }
+}
@@ -0,0 +1,326 @@
+//! # Shared State
+//!
+//! Short-lived state that must be consistent across replicas, such as rate
+//! limit counters. Backends implement the [`SharedState`] trait and are
+//! selected at startup from the configuration.
+//!
+//! A single server can keep this state in memory, but once several
+//! replicas run behind a load balancer each of them would only see its own
+//! share of the traffic. Setting `REDIS_URL` moves the state to Redis so
+//! that every replica sees the same counters.
+//!
+//! ## Backends
+//!
+//! - [`InMemoryState`] - In-process map, for single-instance deployments
+//! - `RedisState` - Redis server shared by all replicas (requires the `redis` feature)
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::config::AppConfig;
+
+/// Number of in-memory entries above which expired entries are swept.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// A counter in a fixed time window.
+///
+/// # Fields
+///
+/// * `count` - Value of the counter after the increment
+/// * `resets_in` - Time left before the counter expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCounter {
+    pub count: u64,
+    pub resets_in: Duration,
+}
+
+/// A key/value store for short-lived state shared between replicas.
+///
+/// Every key expires after the time-to-live given when it was created.
+#[async_trait]
+pub trait SharedState: Send + Sync {
+    /// Increments the counter under `key`. A missing or expired counter
+    /// starts again from zero and expires after `ttl`.
+    async fn increment(&self, key: &str, ttl: Duration) -> io::Result<WindowCounter>;
+
+    /// Stores `value` under `key` for `ttl`, replacing any previous value.
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> io::Result<()>;
+
+    /// Returns the value stored under `key`, if it has not expired.
+    async fn get(&self, key: &str) -> io::Result<Option<String>>;
+
+    /// Removes `key`. Removing a missing key succeeds.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+#[derive(Debug)]
+enum Value {
+    Counter(u64),
+    Text(String),
+}
+
+/// Shared state kept in the memory of the current process.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use dothtml_backend::shared::{InMemoryState, SharedState};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let state = InMemoryState::default();
+///     state.increment("hits", Duration::from_secs(60)).await?;
+///     let counter = state.increment("hits", Duration::from_secs(60)).await?;
+///     assert_eq!(counter.count, 2);
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct InMemoryState {
+    entries: Mutex<HashMap<String, (Value, Instant)>>,
+}
+
+impl InMemoryState {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Value, Instant)>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn sweep(entries: &mut HashMap<String, (Value, Instant)>, now: Instant) {
+        if entries.len() > SWEEP_THRESHOLD {
+            entries.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+    }
+}
+
+#[async_trait]
+impl SharedState for InMemoryState {
+    async fn increment(&self, key: &str, ttl: Duration) -> io::Result<WindowCounter> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        Self::sweep(&mut entries, now);
+
+        let (count, expires_at) = match entries.get_mut(key) {
+            Some((Value::Counter(count), expires_at)) if *expires_at > now => {
+                *count += 1;
+                (*count, *expires_at)
+            }
+            _ => {
+                entries.insert(key.to_string(), (Value::Counter(1), now + ttl));
+                (1, now + ttl)
+            }
+        };
+
+        Ok(WindowCounter { count, resets_in: expires_at - now })
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> io::Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        Self::sweep(&mut entries, now);
+        entries.insert(key.to_string(), (Value::Text(value.to_string()), now + ttl));
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<String>> {
+        let now = Instant::now();
+        Ok(match self.entries().get(key) {
+            Some((Value::Text(value), expires_at)) if *expires_at > now => Some(value.clone()),
+            Some((Value::Counter(count), expires_at)) if *expires_at > now => Some(count.to_string()),
+            _ => None,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.entries().remove(key);
+        Ok(())
+    }
+}
+
+/// Shared state kept in Redis.
+///
+/// Keys are prefixed with `dothtml:` so that the Redis database can be
+/// shared with other applications. Requires Redis 7 or later.
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct RedisState {
+    connection: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl RedisState {
+    /// Connects to the Redis server at `url`, e.g. `redis://localhost:6379`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the URL is invalid or the server
+    /// cannot be reached.
+    pub async fn connect(url: &str) -> io::Result<Self> {
+        let client = redis::Client::open(url).map_err(io::Error::other)?;
+        let connection = redis::aio::ConnectionManager::new(client).await.map_err(io::Error::other)?;
+        Ok(RedisState { connection })
+    }
+
+    fn key(key: &str) -> String {
+        format!("dothtml:{}", key)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl SharedState for RedisState {
+    async fn increment(&self, key: &str, ttl: Duration) -> io::Result<WindowCounter> {
+        let key = Self::key(key);
+        let ttl_ms = ttl.as_millis() as i64;
+        let mut connection = self.connection.clone();
+
+        // NX only sets the expiry when the INCR created the key
+        let (count, remaining_ms): (u64, i64) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .cmd("PEXPIRE").arg(&key).arg(ttl_ms).arg("NX").ignore()
+            .pttl(&key)
+            .query_async(&mut connection)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(WindowCounter { count, resets_in: Duration::from_millis(remaining_ms.max(0) as u64) })
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> io::Result<()> {
+        let mut connection = self.connection.clone();
+        redis::cmd("SET")
+            .arg(Self::key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(io::Error::other)
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<String>> {
+        let mut connection = self.connection.clone();
+        redis::cmd("GET")
+            .arg(Self::key(key))
+            .query_async(&mut connection)
+            .await
+            .map_err(io::Error::other)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let mut connection = self.connection.clone();
+        redis::cmd("DEL")
+            .arg(Self::key(key))
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+/// Builds the shared state backend selected by `REDIS_URL`.
+///
+/// Falls back to [`InMemoryState`] when `REDIS_URL` is unset.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `REDIS_URL` is set without the `redis` feature
+/// - The Redis server cannot be reached
+pub async fn from_config(config: &AppConfig) -> io::Result<Arc<dyn SharedState>> {
+    match config.redis_url.as_deref() {
+        None => Ok(Arc::new(InMemoryState::default())),
+        #[cfg(feature = "redis")]
+        Some(url) => Ok(Arc::new(RedisState::connect(url).await?)),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "REDIS_URL requires building with the `redis` feature",
+        )),
+    }
+}
+
+/// Fixed-window rate limiter on top of a [`SharedState`].
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use dothtml_backend::shared::{InMemoryState, RateLimiter};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let limiter = RateLimiter::new(Arc::new(InMemoryState::default()), "contact", Duration::from_secs(3600));
+///     assert!(limiter.check("203.0.113.7", Some(2)).await?.is_none());
+///     assert!(limiter.check("203.0.113.7", Some(2)).await?.is_none());
+///     assert!(limiter.check("203.0.113.7", Some(2)).await?.is_some());
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    state: Arc<dyn SharedState>,
+    scope: String,
+    window: Duration,
+}
+
+impl RateLimiter {
+    /// Creates a limiter counting the requests of each client of `scope`
+    /// per `window`.
+    pub fn new(state: Arc<dyn SharedState>, scope: &str, window: Duration) -> Self {
+        RateLimiter { state, scope: scope.to_string(), window }
+    }
+
+    /// Returns a limiter counting the requests of another scope, with the
+    /// same state and window.
+    pub fn for_scope(&self, scope: &str) -> Self {
+        RateLimiter { state: self.state.clone(), scope: scope.to_string(), window: self.window }
+    }
+
+    /// Counts a request from `client`, allowing `limit` requests per window.
+    /// A `None` limit allows everything.
+    ///
+    /// The limit is passed on each call so that it follows configuration
+    /// reloads.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` when the request is allowed, or the time left before
+    /// the client may retry when it is over the limit.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the shared state is unreachable.
+    pub async fn check(&self, client: &str, limit: Option<u64>) -> io::Result<Option<Duration>> {
+        let Some(limit) = limit else {
+            return Ok(None);
+        };
+
+        let counter = self.state.increment(&format!("ratelimit:{}:{}", self.scope, client), self.window).await?;
+        Ok((counter.count > limit).then_some(counter.resets_in))
+    }
+
+    /// Counts a request from the client at `ip`, as resolved by the
+    /// `client_ip` module, like [`RateLimiter::check`].
+    ///
+    /// A client whose address is unknown is not limited, as for the
+    /// authentication lockout: counting all of them together would let one
+    /// of them lock the others out.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the shared state is unreachable.
+    pub async fn check_client(&self, ip: Option<IpAddr>, limit: Option<u64>) -> io::Result<Option<Duration>> {
+        match ip {
+            Some(ip) => self.check(&ip.to_string(), limit).await,
+            None => Ok(None),
+        }
+    }
+}
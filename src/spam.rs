@@ -0,0 +1,385 @@
+//! # Spam Scoring
+//!
+//! Scores incoming messages with a naive Bayes token model trained by the
+//! moderators: `POST /inbox/{id}/spam` and `POST /inbox/{id}/ham` record
+//! their verdict on a message in the `spam_training` table and count the
+//! message's tokens as spam or legitimate in `spam_tokens`. Every new
+//! message is then given a `spam_score`, the probability from 0 to 1 that
+//! it is spam, which improves as verdicts accumulate.
+//!
+//! Tokens are the lowercase words of the sender's name, company and
+//! message, plus the domain of the sender's email as `from:<domain>`. A
+//! message counts once per token however often the token appears.
+//!
+//! A verdict can be changed: the tokens counted for the previous verdict
+//! are moved to the new one, so the model does not keep mistakes. No score
+//! is given until both spam and legitimate messages were reported.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::models::Message;
+
+/// Shortest word counted as a token.
+const MIN_TOKEN_LENGTH: usize = 3;
+
+/// Longest word counted as a token, longer ones are mostly noise.
+const MAX_TOKEN_LENGTH: usize = 32;
+
+/// Most tokens taken from a single message.
+const MAX_TOKENS: usize = 1000;
+
+/// Number of tokens, the furthest from neutral, combined into a score.
+const INTERESTING_TOKENS: usize = 15;
+
+/// Weight of the neutral 0.5 prior against the observations of a token,
+/// so that a token seen once does not decide a score alone.
+const PRIOR_STRENGTH: f64 = 1.0;
+
+/// Bounds of the probability of a single token.
+const MIN_TOKEN_PROBABILITY: f64 = 0.01;
+const MAX_TOKEN_PROBABILITY: f64 = 0.99;
+
+/// Verdict of a moderator on a message.
+///
+/// * `Spam` - Unsolicited or abusive message
+/// * `Ham` - Legitimate message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpamVerdict {
+    Spam,
+    Ham,
+}
+
+impl SpamVerdict {
+    /// Returns the name stored in `spam_training`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpamVerdict::Spam => "spam",
+            SpamVerdict::Ham => "ham",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "spam" => Some(SpamVerdict::Spam),
+            "ham" => Some(SpamVerdict::Ham),
+            _ => None,
+        }
+    }
+
+    /// Returns the changes of the spam and ham counts of a token when a
+    /// message with the verdict is counted (`sign` 1) or uncounted (-1).
+    fn deltas(&self, sign: i64) -> (i64, i64) {
+        match self {
+            SpamVerdict::Spam => (sign, 0),
+            SpamVerdict::Ham => (0, sign),
+        }
+    }
+}
+
+/// Outcome of a moderator verdict.
+///
+/// # Fields
+///
+/// * `message_id` - ID of the reported message
+/// * `verdict` - The verdict recorded
+/// * `previous` - The verdict it replaced, if the message was reported before
+/// * `tokens` - Number of tokens of the message counted
+#[derive(Debug, Serialize)]
+pub struct SpamFeedback {
+    pub message_id: Uuid,
+    pub verdict: SpamVerdict,
+    pub previous: Option<SpamVerdict>,
+    pub tokens: usize,
+}
+
+/// Splits the text of a message into the tokens of the model.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::spam::tokenize;
+///
+/// let tokens = tokenize("John", "john@Example.com", "ACME", "Cheap pills, CHEAP!");
+/// assert_eq!(tokens, ["acme", "cheap", "from:example.com", "john", "pills"]);
+/// ```
+pub fn tokenize(name: &str, email: &str, company: &str, message: &str) -> Vec<String> {
+    let mut tokens: BTreeSet<String> = [name, company, message]
+        .into_iter()
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| (MIN_TOKEN_LENGTH..=MAX_TOKEN_LENGTH).contains(&word.chars().count()))
+        .map(str::to_lowercase)
+        .collect();
+    if let Some((_, domain)) = email.rsplit_once('@') {
+        tokens.insert(format!("from:{}", domain.trim().to_lowercase()));
+    }
+    tokens.into_iter().take(MAX_TOKENS).collect()
+}
+
+/// Returns the tokens of a stored message.
+fn message_tokens(message: &Message) -> Vec<String> {
+    tokenize(&message.name, &message.email, &message.company, &message.message)
+}
+
+/// Token counts of the model, as far as a message needs them.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::spam::SpamModel;
+///
+/// let mut model = SpamModel::new(10, 10);
+/// model.add_token("viagra", 9, 0);
+/// model.add_token("invoice", 0, 8);
+///
+/// let tokens = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+/// assert!(model.score(&tokens(&["viagra", "hello"])).unwrap() > 0.9);
+/// assert!(model.score(&tokens(&["invoice", "hello"])).unwrap() < 0.1);
+/// assert_eq!(model.score(&tokens(&["hello"])), Some(0.5));
+/// assert_eq!(SpamModel::new(10, 0).score(&tokens(&["viagra"])), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpamModel {
+    spam_messages: i64,
+    ham_messages: i64,
+    tokens: HashMap<String, (i64, i64)>,
+}
+
+impl SpamModel {
+    /// Creates a model trained on `spam_messages` spam and `ham_messages`
+    /// legitimate messages, without any token yet.
+    pub fn new(spam_messages: i64, ham_messages: i64) -> Self {
+        SpamModel { spam_messages, ham_messages, tokens: HashMap::new() }
+    }
+
+    /// Sets the number of spam and legitimate messages holding `token`.
+    pub fn add_token(&mut self, token: &str, spam: i64, ham: i64) {
+        self.tokens.insert(token.to_string(), (spam, ham));
+    }
+
+    /// Returns the probability, from 0 to 1, that a message with `tokens`
+    /// is spam. Unknown tokens are ignored; a message without known tokens
+    /// scores 0.5.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` until the model was trained with both spam and
+    /// legitimate messages.
+    pub fn score(&self, tokens: &[String]) -> Option<f64> {
+        if self.spam_messages <= 0 || self.ham_messages <= 0 {
+            return None;
+        }
+
+        let mut probabilities: Vec<f64> = tokens
+            .iter()
+            .filter_map(|token| self.tokens.get(token))
+            .filter(|(spam, ham)| spam + ham > 0)
+            .map(|&(spam, ham)| {
+                let spam_frequency = spam as f64 / self.spam_messages as f64;
+                let ham_frequency = ham as f64 / self.ham_messages as f64;
+                let probability = spam_frequency / (spam_frequency + ham_frequency);
+                // Rare tokens are pulled towards neutral
+                let observations = (spam + ham) as f64;
+                let probability = (PRIOR_STRENGTH * 0.5 + observations * probability) / (PRIOR_STRENGTH + observations);
+                probability.clamp(MIN_TOKEN_PROBABILITY, MAX_TOKEN_PROBABILITY)
+            })
+            .collect();
+        probabilities.sort_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+        probabilities.truncate(INTERESTING_TOKENS);
+
+        // Product of the probabilities, in logarithms so that it does not underflow
+        let spam: f64 = probabilities.iter().map(|p| p.ln()).sum();
+        let ham: f64 = probabilities.iter().map(|p| (1.0 - p).ln()).sum();
+        Some(1.0 / (1.0 + (ham - spam).exp()))
+    }
+}
+
+/// Database operations for spam scoring.
+impl Database {
+    /// Creates the 'spam_training' and 'spam_tokens' tables if they don't
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_spam_tables().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_spam_tables(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS spam_training (
+                message_id UUID PRIMARY KEY,
+                verdict TEXT NOT NULL CHECK (verdict IN ('spam', 'ham')),
+                tokens TEXT[] NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS spam_tokens (
+                token TEXT PRIMARY KEY,
+                spam_count BIGINT NOT NULL DEFAULT 0,
+                ham_count BIGINT NOT NULL DEFAULT 0
+            );
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Scores a message before it is stored.
+    ///
+    /// # Returns
+    ///
+    /// Returns the probability that the message is spam, or `None` while
+    /// the model is not trained (see [`SpamModel::score`]).
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn spam_score(
+        &self, name: &str, email: &str, company: &str, message: &str
+    ) -> Result<Option<f64>, sqlx::Error> {
+        let (spam_messages, ham_messages): (i64, i64) = sqlx::query_as(r#"
+            SELECT COUNT(*) FILTER (WHERE verdict = 'spam'), COUNT(*) FILTER (WHERE verdict = 'ham')
+            FROM spam_training
+        "#)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let mut model = SpamModel::new(spam_messages, ham_messages);
+        if model.score(&[]).is_none() {
+            return Ok(None);
+        }
+
+        let tokens = tokenize(name, email, company, message);
+        let rows = sqlx::query("SELECT token, spam_count, ham_count FROM spam_tokens WHERE token = ANY($1)")
+            .bind(&tokens)
+            .fetch_all(&self.pool)
+            .await?;
+        for row in rows {
+            model.add_token(row.get("token"), row.get("spam_count"), row.get("ham_count"));
+        }
+
+        Ok(model.score(&tokens))
+    }
+
+    /// Records a moderator verdict on a message and trains the model with
+    /// it. Reporting a message again with another verdict moves its tokens
+    /// to the new verdict; with the same verdict, nothing changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the message
+    /// * `verdict` - Whether the message is spam
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The message does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::spam::SpamVerdict;
+    /// use uuid::Uuid;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let id = Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap();
+    ///     let feedback = db.train_spam(id, SpamVerdict::Spam).await?;
+    ///     println!("{} tokens counted as spam", feedback.tokens);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn train_spam(&self, id: Uuid, verdict: SpamVerdict) -> Result<SpamFeedback, sqlx::Error> {
+        // Loaded outside the transaction, the body may have to be read from the blob store
+        let message = self.get_message_by_id(id).await?;
+        let tokens = message_tokens(&message);
+
+        let mut tx = self.pool.begin().await?;
+
+        // Concurrent verdicts on the message must not both count its tokens
+        sqlx::query("SELECT id FROM messages WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let previous = sqlx::query("SELECT verdict, tokens FROM spam_training WHERE message_id = $1")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| (SpamVerdict::parse(row.get("verdict")), row.get::<Vec<String>, _>("tokens")));
+
+        if let Some((Some(previous), counted)) = &previous {
+            if *previous == verdict {
+                tx.commit().await?;
+                return Ok(SpamFeedback { message_id: id, verdict, previous: Some(*previous), tokens: counted.len() });
+            }
+            // The tokens counted then, in case the tokenizer changed since
+            Self::count_spam_tokens(&mut tx, counted, previous.deltas(-1)).await?;
+        }
+        Self::count_spam_tokens(&mut tx, &tokens, verdict.deltas(1)).await?;
+
+        sqlx::query(r#"
+            INSERT INTO spam_training (message_id, verdict, tokens)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (message_id) DO UPDATE
+            SET verdict = EXCLUDED.verdict, tokens = EXCLUDED.tokens, updated_at = NOW()
+        "#)
+        .bind(id)
+        .bind(verdict.as_str())
+        .bind(&tokens)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(SpamFeedback {
+            message_id: id,
+            verdict,
+            previous: previous.and_then(|(previous, _)| previous),
+            tokens: tokens.len(),
+        })
+    }
+
+    /// Adds `deltas` to the spam and ham counts of `tokens`.
+    async fn count_spam_tokens(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, tokens: &[String], (spam, ham): (i64, i64)
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO spam_tokens (token, spam_count, ham_count)
+            SELECT token, GREATEST($2, 0), GREATEST($3, 0) FROM unnest($1::text[]) AS token
+            ON CONFLICT (token) DO UPDATE
+            SET spam_count = GREATEST(spam_tokens.spam_count + $2, 0),
+                ham_count = GREATEST(spam_tokens.ham_count + $3, 0)
+        "#)
+        .bind(tokens)
+        .bind(spam)
+        .bind(ham)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! # Status Page
+//!
+//! A small HTML page served at `GET /status` summarizing the health of the
+//! running instance: version, uptime, database latency, the number of
+//! pending messages, of recovered handler panics and the mail queue. It is rendered
+//! server-side and has no scripts or external assets, so it loads quickly
+//! on a phone.
+
+use std::time::{Duration, Instant};
+
+use maud::{html, Markup, DOCTYPE};
+
+use crate::mail_queue::{MailQueueStats, PriorityStats};
+
+/// Time at which the server started, shared with the handlers.
+#[derive(Debug, Clone, Copy)]
+pub struct Uptime {
+    started_at: Instant,
+}
+
+impl Uptime {
+    /// Starts counting from now.
+    pub fn start() -> Self {
+        Uptime { started_at: Instant::now() }
+    }
+
+    /// Returns the time elapsed since the server started.
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
+
+/// What the status page shows.
+///
+/// # Fields
+///
+/// * `version` - Crate version of the running build
+/// * `commit` - Git commit of the running build
+/// * `uptime` - Time since the server started
+/// * `database` - Round-trip time of a trivial query, or the error it failed with
+/// * `pending_messages` - Messages waiting for an agent, if the database answered
+/// * `handler_panics` - Handler panics answered with a 500 since the server started
+/// * `mail_queue` - Metrics of the mail queue, when the mailer is enabled
+#[derive(Debug)]
+pub struct StatusReport {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub uptime: Duration,
+    pub database: Result<Duration, String>,
+    pub pending_messages: Option<i64>,
+    pub handler_panics: u64,
+    pub mail_queue: Option<MailQueueStats>,
+}
+
+impl StatusReport {
+    /// Returns whether every checked component is working.
+    pub fn is_healthy(&self) -> bool {
+        self.database.is_ok()
+    }
+}
+
+/// Renders the status page.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use dothtml_backend::status::{render, StatusReport};
+///
+/// let page = render(&StatusReport {
+///     version: "1.2.0",
+///     commit: "4f3c2a1b9e8d",
+///     uptime: Duration::from_secs(90_000),
+///     database: Ok(Duration::from_millis(3)),
+///     pending_messages: Some(4),
+///     handler_panics: 0,
+///     mail_queue: None,
+/// });
+/// assert!(page.into_string().contains("1d 1h 0m"));
+/// ```
+pub fn render(report: &StatusReport) -> Markup {
+    let (summary, colour) = if report.is_healthy() { ("Operational", "#1a7f37") } else { ("Degraded", "#cf222e") };
+
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "dothtml status" }
+                style {
+                    "body{font-family:system-ui,sans-serif;max-width:28rem;margin:2rem auto;padding:0 1rem}"
+                    "dl{display:grid;grid-template-columns:auto 1fr;gap:.5rem 1rem}dt{color:#57606a}dd{margin:0}"
+                }
+            }
+            body {
+                h1 style={ "color:" (colour) } { (summary) }
+                dl {
+                    dt { "Version" }
+                    dd { (report.version) }
+                    dt { "Commit" }
+                    dd { code { (report.commit) } }
+                    dt { "Uptime" }
+                    dd { (format_duration(report.uptime)) }
+                    dt { "Database" }
+                    dd {
+                        @match &report.database {
+                            Ok(latency) => { "Up (" (latency.as_millis()) " ms)" }
+                            Err(error) => { "Down: " (error) }
+                        }
+                    }
+                    dt { "Pending messages" }
+                    dd {
+                        @match report.pending_messages {
+                            Some(count) => { (count) }
+                            None => { "Unknown" }
+                        }
+                    }
+                    dt { "Handler panics" }
+                    dd { (report.handler_panics) }
+                    @if let Some(mail_queue) = &report.mail_queue {
+                        dt { "Transactional emails" }
+                        dd { (format_mail_stats(&mail_queue.transactional)) }
+                        dt { "Digest emails" }
+                        dd { (format_mail_stats(&mail_queue.digest)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Formats the counters of one mail priority.
+fn format_mail_stats(stats: &PriorityStats) -> String {
+    let mut line = format!("{} waiting, {} sent, {} failed", stats.waiting, stats.sent, stats.failed);
+    if let (Some(average), Some(max)) = (stats.average_latency, stats.max_latency) {
+        line.push_str(&format!(" (latency {} ms on average, {} ms at most)", average.as_millis(), max.as_millis()));
+    }
+    line
+}
+
+/// Formats a duration as days, hours and minutes.
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
@@ -0,0 +1,182 @@
+//! # Blob Storage
+//!
+//! Storage backends for large payloads that should not live in PostgreSQL,
+//! such as very long message bodies. Backends implement the [`BlobStore`]
+//! trait and are selected at startup from the configuration.
+//!
+//! ## Backends
+//!
+//! - [`LocalBlobStore`] - Files in a local directory
+//! - `S3BlobStore` - Objects in an S3-compatible bucket (requires the `s3` feature)
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::config::AppConfig;
+
+/// A key/value store for binary payloads.
+///
+/// Keys are slash-separated relative paths such as `messages/<uuid>`.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `data` under `key`, replacing any previous value.
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+
+    /// Returns the data stored under `key`.
+    ///
+    /// Fails with `io::ErrorKind::NotFound` when the key does not exist.
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Removes the data stored under `key`. Removing a missing key succeeds.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Blob store keeping each blob in a file under a root directory.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dothtml_backend::storage::{BlobStore, LocalBlobStore};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let store = LocalBlobStore::new("/var/lib/dothtml/blobs");
+///     store.put("messages/hello", b"Hello, world!".to_vec()).await?;
+///     assert_eq!(store.get("messages/hello").await?, b"Hello, world!");
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    /// Creates a store rooted at `root`. The directory is created on first write.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalBlobStore { root: root.into() }
+    }
+
+    /// Resolves a key to a path inside the root, rejecting keys that would escape it.
+    fn path_for(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid blob key: {}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, data).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.path_for(key)?).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Blob store keeping each blob as an object in an S3-compatible bucket.
+///
+/// Credentials, region and endpoint are read from the standard `AWS_*`
+/// environment variables.
+#[cfg(feature = "s3")]
+#[derive(Debug)]
+pub struct S3BlobStore {
+    store: object_store::aws::AmazonS3,
+}
+
+#[cfg(feature = "s3")]
+impl S3BlobStore {
+    /// Creates a store for `bucket`, configured from the environment.
+    pub fn from_env(bucket: &str) -> io::Result<Self> {
+        let store = object_store::aws::AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(S3BlobStore { store })
+    }
+}
+
+#[cfg(feature = "s3")]
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        use object_store::ObjectStore;
+
+        self.store
+            .put(&object_store::path::Path::from(key), data.into())
+            .await
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        use object_store::ObjectStore;
+
+        match self.store.get(&object_store::path::Path::from(key)).await {
+            Ok(result) => Ok(result.bytes().await.map_err(io::Error::other)?.to_vec()),
+            Err(object_store::Error::NotFound { .. }) => {
+                Err(io::Error::new(io::ErrorKind::NotFound, format!("blob not found: {}", key)))
+            }
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        use object_store::ObjectStore;
+
+        match self.store.delete(&object_store::path::Path::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+/// Builds the blob store selected by `BLOB_STORE`.
+///
+/// # Returns
+///
+/// Returns `Ok(None)` when no blob store is configured.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `BLOB_STORE` names an unknown backend
+/// - `BLOB_STORE=s3` is used without the `s3` feature or without `BLOB_STORE_BUCKET`
+/// - The S3 client cannot be configured
+pub fn from_config(config: &AppConfig) -> io::Result<Option<Arc<dyn BlobStore>>> {
+    match config.blob_store.as_deref() {
+        None => Ok(None),
+        Some("local") => Ok(Some(Arc::new(LocalBlobStore::new(&config.blob_store_path)))),
+        #[cfg(feature = "s3")]
+        Some("s3") => {
+            let bucket = config.blob_store_bucket.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "BLOB_STORE_BUCKET must be set when BLOB_STORE=s3")
+            })?;
+            Ok(Some(Arc::new(S3BlobStore::from_env(bucket)?)))
+        }
+        #[cfg(not(feature = "s3"))]
+        Some("s3") => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "BLOB_STORE=s3 requires building with the `s3` feature",
+        )),
+        Some(other) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown BLOB_STORE: {}", other))),
+    }
+}
@@ -0,0 +1,356 @@
+//! # Do-Not-Contact List
+//!
+//! Email addresses that must not be written to: people who unsubscribed,
+//! legal requests, and addresses that bounced or complained. Replies to a
+//! message whose sender is on the list are refused by
+//! `POST /inbox/{id}/reply`, and they are sent no acknowledgement or
+//! rating email (see the `acknowledgements` and `ratings` modules).
+//!
+//! Admins manage the list through `/admin/suppressions`. Bounces and spam
+//! complaints are added automatically by the email provider's webhook,
+//! `POST /webhooks/email/{provider}`, authenticated with
+//! `EMAIL_WEBHOOK_SECRET`. Supported providers:
+//!
+//! - `generic` - `{"type": "bounce", "email": "jane@example.com"}`, or a list of them
+//! - `sendgrid` - SendGrid event webhook (`bounce`, `dropped` and `spamreport` events)
+//! - `postmark` - Postmark bounce and spam complaint webhooks (hard bounces only)
+//! - `ses` - Amazon SES notifications delivered by SNS (permanent bounces and complaints)
+//!
+//! Soft bounces, such as a full mailbox, are ignored: the address may work
+//! again later. Addresses are stored lowercased, so the list is
+//! case-insensitive.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgRow;
+use sqlx::{PgExecutor, Row};
+
+use crate::database::Database;
+use crate::models::{PageRequest, Paginated};
+
+/// Providers accepted by `POST /webhooks/email/{provider}`.
+pub const EMAIL_PROVIDERS: [&str; 4] = ["generic", "sendgrid", "postmark", "ses"];
+
+/// Why an address is on the list.
+///
+/// * `Unsubscribe` - The person asked not to be contacted
+/// * `Legal` - A legal request, such as a GDPR objection
+/// * `Bounce` - Mail to the address bounced permanently
+/// * `Complaint` - The person reported our mail as spam
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuppressionReason {
+    Unsubscribe,
+    Legal,
+    Bounce,
+    Complaint,
+}
+
+impl SuppressionReason {
+    /// Returns the name stored in the `reason` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Unsubscribe => "unsubscribe",
+            SuppressionReason::Legal => "legal",
+            SuppressionReason::Bounce => "bounce",
+            SuppressionReason::Complaint => "complaint",
+        }
+    }
+
+    /// Parses a reason name.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "unsubscribe" => Some(SuppressionReason::Unsubscribe),
+            "legal" => Some(SuppressionReason::Legal),
+            "bounce" => Some(SuppressionReason::Bounce),
+            "complaint" => Some(SuppressionReason::Complaint),
+            _ => None,
+        }
+    }
+}
+
+/// An address on the do-not-contact list.
+///
+/// # Fields
+///
+/// * `email` - The address, lowercased
+/// * `reason` - Why it is on the list
+/// * `note` - Free-form details, such as the reference of a legal request
+///   or the provider that reported a bounce
+/// * `created_at` - Timestamp when the address was added
+#[derive(Debug, Clone, Serialize)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: SuppressionReason,
+    pub note: Option<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Normalizes an address for storage and lookups.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::suppression::normalize_email;
+///
+/// assert_eq!(normalize_email("  Jane@Example.COM "), "jane@example.com");
+/// ```
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Extracts the addresses to suppress from an email provider's webhook.
+///
+/// # Arguments
+///
+/// * `provider` - One of [`EMAIL_PROVIDERS`]
+/// * `payload` - The JSON body of the webhook
+///
+/// # Returns
+///
+/// Returns the addresses with the reason to suppress them, empty for
+/// events that do not suppress anything (deliveries, soft bounces...), or
+/// `None` if the provider is unknown.
+///
+/// # Examples
+///
+/// ```rust
+/// use serde_json::json;
+/// use dothtml_backend::suppression::{parse_webhook, SuppressionReason};
+///
+/// let payload = json!([
+///     { "event": "delivered", "email": "john@example.com" },
+///     { "event": "bounce", "email": "jane@example.com" },
+/// ]);
+/// assert_eq!(
+///     parse_webhook("sendgrid", &payload),
+///     Some(vec![("jane@example.com".to_string(), SuppressionReason::Bounce)])
+/// );
+/// ```
+pub fn parse_webhook(provider: &str, payload: &Value) -> Option<Vec<(String, SuppressionReason)>> {
+    if !EMAIL_PROVIDERS.contains(&provider) {
+        return None;
+    }
+    let events: Vec<&Value> = match payload {
+        Value::Array(events) => events.iter().collect(),
+        event => vec![event],
+    };
+
+    let mut found = Vec::new();
+    for event in events {
+        match provider {
+            "generic" => {
+                let reason = event["type"].as_str().and_then(SuppressionReason::parse);
+                if let (Some(reason), Some(email)) = (reason, event["email"].as_str()) {
+                    found.push((email.to_string(), reason));
+                }
+            }
+            "sendgrid" => {
+                let reason = match event["event"].as_str() {
+                    Some("bounce" | "dropped") => SuppressionReason::Bounce,
+                    Some("spamreport") => SuppressionReason::Complaint,
+                    _ => continue,
+                };
+                if let Some(email) = event["email"].as_str() {
+                    found.push((email.to_string(), reason));
+                }
+            }
+            "postmark" => {
+                let reason = match (event["RecordType"].as_str(), event["Type"].as_str()) {
+                    (Some("Bounce"), Some("HardBounce")) => SuppressionReason::Bounce,
+                    (Some("SpamComplaint"), _) => SuppressionReason::Complaint,
+                    _ => continue,
+                };
+                if let Some(email) = event["Email"].as_str() {
+                    found.push((email.to_string(), reason));
+                }
+            }
+            "ses" => {
+                // SNS wraps the SES notification, as a JSON string, in its own envelope
+                let notification = match event["Message"].as_str() {
+                    Some(message) => serde_json::from_str(message).unwrap_or(Value::Null),
+                    None => event.clone(),
+                };
+                let (reason, recipients) = match notification["notificationType"].as_str() {
+                    Some("Bounce") if notification["bounce"]["bounceType"] == "Permanent" => {
+                        (SuppressionReason::Bounce, &notification["bounce"]["bouncedRecipients"])
+                    }
+                    Some("Complaint") => (SuppressionReason::Complaint, &notification["complaint"]["complainedRecipients"]),
+                    _ => continue,
+                };
+                for recipient in recipients.as_array().into_iter().flatten() {
+                    if let Some(email) = recipient["emailAddress"].as_str() {
+                        found.push((email.to_string(), reason));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(found)
+}
+
+/// Column list selected for every `Suppression` row.
+const SUPPRESSION_COLUMNS: &str = "email, reason, note, created_at";
+
+fn suppression_from_row(row: &PgRow) -> Suppression {
+    let reason: String = row.get("reason");
+    Suppression {
+        email: row.get("email"),
+        // Written by this module, an unknown value can only come from a manual edit
+        reason: SuppressionReason::parse(&reason).unwrap_or(SuppressionReason::Legal),
+        note: row.get("note"),
+        created_at: row.get("created_at"),
+    }
+}
+
+/// Database operations for the do-not-contact list.
+impl Database {
+    /// Creates the 'suppressions' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_suppressions_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_suppressions_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS suppressions (
+                email TEXT PRIMARY KEY,
+                reason TEXT NOT NULL,
+                note TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Looks up an address on the do-not-contact list.
+    ///
+    /// # Returns
+    ///
+    /// Returns the entry of the address, `None` if it may be contacted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     if let Some(suppression) = db.find_suppression("Jane@Example.com").await? {
+    ///         println!("Do not contact: {}", suppression.reason.as_str());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn find_suppression(&self, email: &str) -> Result<Option<Suppression>, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {SUPPRESSION_COLUMNS} FROM suppressions WHERE email = $1"))
+            .bind(normalize_email(email))
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(suppression_from_row))
+    }
+
+    /// Returns whether an address is on the do-not-contact list.
+    ///
+    /// Takes any executor so that it can run inside the transaction that
+    /// queues an email to the address.
+    pub(crate) async fn is_suppressed<'e, E: PgExecutor<'e>>(executor: E, email: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM suppressions WHERE email = $1)")
+            .bind(normalize_email(email))
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Adds an address to the do-not-contact list.
+    ///
+    /// An address already on the list keeps its original entry, so that a
+    /// bounce does not hide the legal request that listed it first.
+    ///
+    /// # Returns
+    ///
+    /// Returns the entry of the address.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn add_suppression(
+        &self, email: &str, reason: SuppressionReason, note: Option<&str>
+    ) -> Result<Suppression, sqlx::Error> {
+        // The no-op update makes RETURNING yield the existing row on conflict
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO suppressions (email, reason, note)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (email) DO UPDATE SET email = suppressions.email
+            RETURNING {SUPPRESSION_COLUMNS}
+        "#))
+        .bind(normalize_email(email))
+        .bind(reason.as_str())
+        .bind(note)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(suppression_from_row(&row))
+    }
+
+    /// Lists a page of the do-not-contact list, most recent first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_suppressions(&self, page: &PageRequest) -> Result<Paginated<Suppression>, sqlx::Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {SUPPRESSION_COLUMNS}, COUNT(*) OVER () AS total FROM suppressions \
+             ORDER BY created_at DESC, email LIMIT $1 OFFSET $2"
+        ))
+        .bind(page.fetch_limit())
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Paginated::from_rows(&rows, page, suppression_from_row))
+    }
+
+    /// Removes an address from the do-not-contact list.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the address was on the list.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn remove_suppression(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM suppressions WHERE email = $1")
+            .bind(normalize_email(email))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
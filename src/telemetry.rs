@@ -0,0 +1,139 @@
+//! # Telemetry
+//!
+//! Request tracing exported to an OpenTelemetry collector over OTLP/HTTP,
+//! enabled with the `otel` cargo feature and `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//!
+//! Each HTTP request gets a root span (method, route, status, client) which
+//! continues the trace of an incoming `traceparent` header. Inside it, the
+//! hot database methods open child spans, and every SQL statement run by
+//! sqlx is attached to the current span as an event with its text and
+//! elapsed time, so a slow request shows which query it waited on.
+//!
+//! Responses carry `X-Request-Id` and `X-Trace-Id` headers, and the root
+//! span records the same request ID, so a request reported by a client can
+//! be found in the tracing backend.
+
+use std::io;
+
+use crate::config::AppConfig;
+
+#[cfg(feature = "otel")]
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    HttpMessage,
+};
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::SdkTracerProvider;
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Handle on the trace exporter, to flush pending spans on shutdown.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    /// Exports the spans still buffered and stops the exporter.
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+/// Starts exporting traces when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+///
+/// Installs the global tracing subscriber, so it must be called once,
+/// before the HTTP server starts.
+///
+/// # Returns
+///
+/// Returns the telemetry handle, or `None` when tracing is disabled.
+///
+/// # Errors
+///
+/// This function returns an error if:
+/// - `OTEL_EXPORTER_OTLP_ENDPOINT` is set without the `otel` feature
+/// - The exporter cannot be built
+/// - A global tracing subscriber is already installed
+pub fn init(config: &AppConfig) -> io::Result<Option<Telemetry>> {
+    match config.otel_exporter_endpoint.as_deref() {
+        None => Ok(None),
+        #[cfg(feature = "otel")]
+        Some(endpoint) => init_tracing(endpoint, &config.otel_service_name).map(Some),
+        #[cfg(not(feature = "otel"))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "OTEL_EXPORTER_OTLP_ENDPOINT requires building with the `otel` feature",
+        )),
+    }
+}
+
+#[cfg(feature = "otel")]
+fn init_tracing(endpoint: &str, service_name: &str) -> io::Result<Telemetry> {
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use tracing::Level;
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(io::Error::other)?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(opentelemetry_sdk::Resource::builder().with_service_name(service_name.to_string()).build())
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+
+    // sqlx reports each statement at DEBUG level under `sqlx::query`
+    let filter = Targets::new()
+        .with_default(Level::INFO)
+        .with_target("sqlx::query", Level::DEBUG);
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("dothtml-backend")).with_filter(filter))
+        .try_init()
+        .map_err(io::Error::other)?;
+
+    println!("Exporting traces to {}", endpoint);
+    Ok(Telemetry { provider })
+}
+
+/// Middleware adding the `X-Request-Id` and `X-Trace-Id` headers to
+/// responses.
+///
+/// Must be registered inside `tracing_actix_web::TracingLogger`, which
+/// assigns the request ID and opens the span holding the trace ID.
+#[cfg(feature = "otel")]
+pub async fn correlation_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<tracing_actix_web::RequestId>().map(ToString::to_string);
+    let trace_id = tracing::Span::current().context().span().span_context().trace_id();
+
+    let mut res = next.call(req).await?;
+    let headers = res.headers_mut();
+    if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        headers.insert(HeaderName::from_static("x-request-id"), value);
+    }
+    if trace_id != opentelemetry::trace::TraceId::INVALID {
+        if let Ok(value) = HeaderValue::from_str(&trace_id.to_string()) {
+            headers.insert(HeaderName::from_static("x-trace-id"), value);
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,481 @@
+//! # Email Templates
+//!
+//! Templates of the emails sent by the server: auto-replies to senders,
+//! notifications to the team and canned responses used by agents. Admins
+//! edit them through `/admin/templates`, and check the result with
+//! `POST /admin/templates/{id}/preview` before they are used.
+//!
+//! A template has a subject, an HTML body and an optional plain text body,
+//! written in [Handlebars](https://handlebarsjs.com/guide/). Values are
+//! escaped in the HTML body only. Without a plain text body, the text is
+//! generated from the rendered HTML (see the `mailer` module). Templates
+//! are rendered with the data returned by [`template_data`]:
+//!
+//! ```text
+//! Hello {{message.name}},
+//!
+//! We received your message about {{message.company}}
+//! {{#if agent}}and {{agent}} will answer it{{else}}and will answer it soon{{/if}}.
+//! ```
+//!
+//! - `message` - The message, with the fields of the inbox API (`name`, `email`,
+//!   `company`, `message`, `status`, `category`, `tags`...)
+//! - `agent` - Agent the message is assigned to, or replying, if any
+//! - `booking_link` - Link of that agent's booking page, if they set one
+//!   (see the `scheduling` module), for canned responses offering a call:
+//!   `{{#if booking_link}}Book a call: {{booking_link}}{{/if}}`
+//! - `followup_link` - Link letting the sender add information to the
+//!   message, in the auto-replies named `acknowledgement` and
+//!   `acknowledgement_closed` (see the `acknowledgements` module)
+//! - `opens_at` - When the office opens again, in `acknowledgement_closed`
+//!
+//! Rendering is strict: a template using a value that does not exist, such
+//! as a misspelled `{{message.nmae}}`, fails instead of leaving a blank, so
+//! mistakes show up in the preview rather than in a sender's mailbox.
+
+use chrono::{DateTime, TimeZone, Utc};
+use handlebars::{Handlebars, Template};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgRow;
+use sqlx::{PgExecutor, Row};
+use uuid::Uuid;
+
+use crate::caching::RESOURCE_TEMPLATES;
+use crate::database::Database;
+use crate::models::{Message, DEFAULT_FORM};
+
+/// What a template is used for.
+///
+/// * `AutoReply` - Acknowledgement sent to the sender of a new message
+/// * `Notification` - Email sent to the team
+/// * `Canned` - Response an agent can reply with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateKind {
+    AutoReply,
+    Notification,
+    Canned,
+}
+
+impl TemplateKind {
+    /// Returns the name stored in the `kind` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateKind::AutoReply => "auto_reply",
+            TemplateKind::Notification => "notification",
+            TemplateKind::Canned => "canned",
+        }
+    }
+
+    /// Parses a kind name.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto_reply" => Some(TemplateKind::AutoReply),
+            "notification" => Some(TemplateKind::Notification),
+            "canned" => Some(TemplateKind::Canned),
+            _ => None,
+        }
+    }
+}
+
+/// A stored email template.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the template
+/// * `name` - Unique name of the template
+/// * `kind` - What the template is used for
+/// * `subject` - Template of the subject line
+/// * `html` - Template of the HTML body
+/// * `text` - Template of the plain text body, empty to generate it from the HTML
+/// * `created_at` - Timestamp when the template was created
+/// * `updated_at` - Timestamp of the last change of the template
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailTemplate {
+    pub id: i64,
+    pub name: String,
+    pub kind: TemplateKind,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Content of a template, as created or replaced by an admin.
+///
+/// # Fields
+///
+/// * `name` - Unique name of the template
+/// * `kind` - `auto_reply`, `notification` or `canned`
+/// * `subject` - Template of the subject line
+/// * `html` - Template of the HTML body
+/// * `text` - Template of the plain text body (default: generated from the HTML)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateDefinition {
+    pub name: String,
+    pub kind: TemplateKind,
+    pub subject: String,
+    pub html: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// An email rendered from a template.
+///
+/// # Fields
+///
+/// * `subject` - The subject line
+/// * `html` - The HTML body
+/// * `text` - The plain text body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders email templates.
+///
+/// # Examples
+///
+/// ```rust
+/// use serde_json::json;
+/// use dothtml_backend::templates::TemplateRenderer;
+///
+/// let renderer = TemplateRenderer::new();
+/// let data = json!({ "message": { "name": "Tom & Jerry" } });
+/// let email = renderer
+///     .render("Hi {{message.name}}", "<p>Hi {{message.name}}</p>", "Hi {{message.name}}", &data)
+///     .unwrap();
+/// assert_eq!(email.html, "<p>Hi Tom &amp; Jerry</p>");
+/// assert_eq!(email.text, "Hi Tom & Jerry");
+///
+/// assert!(renderer.render("Hi {{message.nmae}}", "", "", &data).is_err());
+/// ```
+pub struct TemplateRenderer {
+    html: Handlebars<'static>,
+    text: Handlebars<'static>,
+}
+
+impl TemplateRenderer {
+    /// Creates a renderer escaping values in HTML bodies only.
+    pub fn new() -> Self {
+        let mut html = Handlebars::new();
+        html.set_strict_mode(true);
+        let mut text = Handlebars::new();
+        text.set_strict_mode(true);
+        text.register_escape_fn(handlebars::no_escape);
+        TemplateRenderer { html, text }
+    }
+
+    /// Checks the syntax of the parts of a template.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first syntax error found.
+    pub fn check(&self, subject: &str, html: &str, text: &str) -> Result<(), String> {
+        for (part, source) in [("subject", subject), ("html", html), ("text", text)] {
+            Template::compile(source).map_err(|e| format!("Invalid {} template: {}", part, e))?;
+        }
+        Ok(())
+    }
+
+    /// Renders the parts of a template with `data`.
+    ///
+    /// The subject is rendered on a single line, whatever the line breaks
+    /// of the values it includes. An empty `text` template is replaced by
+    /// the text of the rendered HTML.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first error found, such
+    /// as a syntax error or a value missing from `data`.
+    pub fn render(&self, subject: &str, html: &str, text: &str, data: &Value) -> Result<RenderedEmail, String> {
+        let render = |registry: &Handlebars, part: &str, source: &str| {
+            registry
+                .render_template(source, data)
+                .map_err(|e| format!("Failed to render the {} template: {}", part, e))
+        };
+        let subject = render(&self.text, "subject", subject)?;
+        let html = render(&self.html, "html", html)?;
+        let text = if text.trim().is_empty() {
+            crate::mailer::html_to_text(&html)
+        } else {
+            render(&self.text, "text", text)?
+        };
+        Ok(RenderedEmail {
+            subject: subject.split_whitespace().collect::<Vec<_>>().join(" "),
+            html,
+            text,
+        })
+    }
+}
+
+impl Default for TemplateRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the data templates are rendered with.
+///
+/// # Arguments
+///
+/// * `message` - The message the email is about
+/// * `agent` - Agent the message is assigned to, or replying, if any
+/// * `booking_link` - Link of the agent's booking page, if they set one
+pub fn template_data(message: &Message, agent: Option<&str>, booking_link: Option<&str>) -> Value {
+    json!({
+        "message": message,
+        "agent": agent,
+        "booking_link": booking_link,
+    })
+}
+
+/// Data used by previews: an example message assigned to an example agent
+/// with a booking link, with every field of real messages.
+pub fn sample_data() -> Value {
+    let received = Utc.with_ymd_and_hms(2025, 1, 15, 10, 30, 0).single().unwrap_or_else(Utc::now);
+    let message = Message {
+        id: Uuid::nil(),
+        name: "Jane Doe".to_string(),
+        email: "jane@example.com".to_string(),
+        country_region: "France".to_string(),
+        phone_number: "+33612345678".to_string(),
+        company: "ACME Corp".to_string(),
+        message: "Hello,\n\nCould you send us a quote for a new website?\n\nThanks,\nJane".to_string(),
+        created_at: received,
+        assigned_to: Some("alice".to_string()),
+        assigned_at: Some(received),
+        status: "assigned".to_string(),
+        priority: "normal".to_string(),
+        merged_into: None,
+        resolved_at: None,
+        tags: vec!["website".to_string()],
+        company_id: None,
+        archived: false,
+        deleted_at: None,
+        opened_by: Some("alice".to_string()),
+        opened_at: Some(received),
+        spam_score: Some(0.02),
+        category: Some("sales".to_string()),
+        form: DEFAULT_FORM.to_string(),
+        body_ref: None,
+    };
+    let mut data = template_data(&message, Some("alice"), Some("https://cal.com/alice"));
+    data["followup_link"] = "https://dotshell.eu/followup?token=EjRWeJq8".into();
+    data["opens_at"] = "Monday, January 20 at 09:00 CET".into();
+    data
+}
+
+/// Column list selected for every `EmailTemplate` row.
+const TEMPLATE_COLUMNS: &str = "id, name, kind, subject, html, text, created_at, updated_at";
+
+fn template_from_row(row: &PgRow) -> EmailTemplate {
+    let kind: String = row.get("kind");
+    EmailTemplate {
+        id: row.get("id"),
+        name: row.get("name"),
+        // Written by this module, an unknown value can only come from a manual edit
+        kind: TemplateKind::parse(&kind).unwrap_or(TemplateKind::Canned),
+        subject: row.get("subject"),
+        html: row.get("html"),
+        text: row.get("text"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Database operations for email templates.
+impl Database {
+    /// Creates the 'email_templates' table if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     db.create_templates_table().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_templates_table(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS email_templates (
+                id BIGSERIAL PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                kind TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                html TEXT NOT NULL,
+                text TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the templates by name.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_templates(&self) -> Result<Vec<EmailTemplate>, sqlx::Error> {
+        let rows = sqlx::query(&format!("SELECT {TEMPLATE_COLUMNS} FROM email_templates ORDER BY name"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(template_from_row).collect())
+    }
+
+    /// Fetches a template.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The template does not exist (`sqlx::Error::RowNotFound`)
+    /// - Database connection issues occur
+    pub async fn get_template(&self, id: i64) -> Result<EmailTemplate, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {TEMPLATE_COLUMNS} FROM email_templates WHERE id = $1"))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(template_from_row(&row))
+    }
+
+    /// Fetches the template of `kind` named `name`, if there is one.
+    ///
+    /// Takes any executor so that it can run inside the transaction that
+    /// queues the email rendered from it.
+    pub(crate) async fn find_template<'e, E: PgExecutor<'e>>(
+        executor: E, name: &str, kind: TemplateKind
+    ) -> Result<Option<EmailTemplate>, sqlx::Error> {
+        let row = sqlx::query(&format!("SELECT {TEMPLATE_COLUMNS} FROM email_templates WHERE name = $1 AND kind = $2"))
+            .bind(name)
+            .bind(kind.as_str())
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(row.as_ref().map(template_from_row))
+    }
+
+    /// Creates a template.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Another template has the same name (a unique violation)
+    /// - Database connection issues occur
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::templates::{TemplateDefinition, TemplateKind};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let template = db.create_template(&TemplateDefinition {
+    ///         name: "acknowledgement".to_string(),
+    ///         kind: TemplateKind::AutoReply,
+    ///         subject: "We received your message".to_string(),
+    ///         html: "<p>Hello {{message.name}}, we will answer shortly.</p>".to_string(),
+    ///         text: "Hello {{message.name}}, we will answer shortly.".to_string(),
+    ///     }).await?;
+    ///     println!("Created template {}", template.id);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_template(&self, template: &TemplateDefinition) -> Result<EmailTemplate, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO email_templates (name, kind, subject, html, text)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {TEMPLATE_COLUMNS}
+        "#))
+        .bind(&template.name)
+        .bind(template.kind.as_str())
+        .bind(&template.subject)
+        .bind(&template.html)
+        .bind(&template.text)
+        .fetch_one(&mut *tx)
+        .await?;
+        Self::touch_resource(&mut *tx, RESOURCE_TEMPLATES).await?;
+        tx.commit().await?;
+
+        Ok(template_from_row(&row))
+    }
+
+    /// Replaces a template.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - The template does not exist (`sqlx::Error::RowNotFound`)
+    /// - Another template has the same name (a unique violation)
+    /// - Database connection issues occur
+    pub async fn update_template(&self, id: i64, template: &TemplateDefinition) -> Result<EmailTemplate, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(&format!(r#"
+            UPDATE email_templates
+            SET name = $2, kind = $3, subject = $4, html = $5, text = $6, updated_at = NOW()
+            WHERE id = $1
+            RETURNING {TEMPLATE_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(&template.name)
+        .bind(template.kind.as_str())
+        .bind(&template.subject)
+        .bind(&template.html)
+        .bind(&template.text)
+        .fetch_one(&mut *tx)
+        .await?;
+        Self::touch_resource(&mut *tx, RESOURCE_TEMPLATES).await?;
+        tx.commit().await?;
+
+        Ok(template_from_row(&row))
+    }
+
+    /// Deletes a template.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the template existed.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_template(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM email_templates WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            Self::touch_resource(&mut *tx, RESOURCE_TEMPLATES).await?;
+        }
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+}
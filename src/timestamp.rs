@@ -0,0 +1,74 @@
+//! # Timestamp Serialization
+//!
+//! Every timestamp in API responses is serialized as RFC 3339 in UTC with
+//! millisecond precision, e.g. `2024-01-15T10:30:00.123Z`, so that clients
+//! can parse them with a single fixed format. (The default chrono format
+//! varies between 0 and 9 fractional digits depending on the value.)
+//!
+//! Use the module with `#[serde(with = "crate::timestamp")]` on
+//! `DateTime<Utc>` fields, and [`option`] on `Option<DateTime<Utc>>`
+//! fields. Deserialization accepts any RFC 3339 timestamp and converts it to
+//! UTC.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use chrono::{DateTime, Utc};
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize)]
+//! struct Event {
+//!     #[serde(with = "dothtml_backend::timestamp")]
+//!     created_at: DateTime<Utc>,
+//! }
+//!
+//! let created_at = DateTime::parse_from_rfc3339("2024-01-15T11:30:00.123456+01:00").unwrap().to_utc();
+//! let json = serde_json::to_string(&Event { created_at }).unwrap();
+//! assert_eq!(json, r#"{"created_at":"2024-01-15T10:30:00.123Z"}"#);
+//! ```
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+/// Formats a timestamp the way the API serializes it.
+pub fn format(value: &DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Parses an RFC 3339 timestamp with any offset into UTC.
+pub fn parse(value: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(value).map(|value| value.to_utc())
+}
+
+/// Serializes a timestamp (see the module documentation).
+pub fn serialize<S: Serializer>(value: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(value))
+}
+
+/// Deserializes an RFC 3339 timestamp.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse(&value).map_err(de::Error::custom)
+}
+
+/// Same as the parent module, for optional timestamps. Add
+/// `#[serde(default)]` on deserialized fields so that they may be omitted.
+pub mod option {
+    use chrono::{DateTime, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    /// Serializes an optional timestamp, `None` as `null`.
+    pub fn serialize<S: Serializer>(value: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&super::format(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Deserializes an optional RFC 3339 timestamp.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| super::parse(&value).map_err(de::Error::custom))
+            .transpose()
+    }
+}
@@ -0,0 +1,149 @@
+//! # Sender Tokens
+//!
+//! Signed, expiring tokens handed to the sender of a message, which let
+//! them act on it from a link without an account: the token names the
+//! message and what it may be used for, and is signed with
+//! `SENDER_TOKEN_SECRET`, so it cannot be forged or reused for another
+//! purpose.
+//!
+//! A token is the URL-safe base64 encoding of the message ID, the
+//! expiration time and an HMAC-SHA256 of both and the purpose. Nothing is
+//! stored: changing the secret invalidates every token issued.
+
+use std::fmt;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Length of a decoded token: message ID, expiration, signature.
+const TOKEN_BYTES: usize = 16 + 8 + 32;
+
+/// What a token allows its holder to do.
+///
+/// * `Followup` - Add information to the message (`POST /contact/followup/{token}`)
+/// * `Rating` - Rate the answer to the message (`POST /contact/rating/{token}`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    Followup,
+    Rating,
+}
+
+impl TokenPurpose {
+    /// Returns the name signed with the token.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Followup => "followup",
+            TokenPurpose::Rating => "rating",
+        }
+    }
+}
+
+/// Why a token was rejected.
+///
+/// * `Malformed` - Not a token
+/// * `Invalid` - Not signed with the secret, or for another purpose
+/// * `Expired` - Genuine but past its expiration time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    Invalid,
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TokenError::Malformed => "malformed token",
+            TokenError::Invalid => "invalid token",
+            TokenError::Expired => "expired token",
+        })
+    }
+}
+
+/// Issues and verifies sender tokens.
+///
+/// # Examples
+///
+/// ```rust
+/// use chrono::{Duration, Utc};
+/// use dothtml_backend::tokens::{SenderTokens, TokenError, TokenPurpose};
+/// use uuid::Uuid;
+///
+/// let tokens = SenderTokens::new("secret");
+/// let id = Uuid::new_v4();
+///
+/// let token = tokens.issue(TokenPurpose::Followup, id, Utc::now() + Duration::days(30));
+/// assert_eq!(tokens.verify(TokenPurpose::Followup, &token), Ok(id));
+/// assert_eq!(SenderTokens::new("other").verify(TokenPurpose::Followup, &token), Err(TokenError::Invalid));
+/// assert_eq!(tokens.verify(TokenPurpose::Rating, &token), Err(TokenError::Invalid));
+///
+/// let expired = tokens.issue(TokenPurpose::Followup, id, Utc::now() - Duration::days(1));
+/// assert_eq!(tokens.verify(TokenPurpose::Followup, &expired), Err(TokenError::Expired));
+/// ```
+#[derive(Clone)]
+pub struct SenderTokens {
+    mac: Hmac<Sha256>,
+}
+
+impl SenderTokens {
+    /// Creates an issuer signing with `secret`.
+    pub fn new(secret: &str) -> Self {
+        SenderTokens {
+            mac: Hmac::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// Returns a token allowing `purpose` on message `id` until `expires_at`.
+    pub fn issue(&self, purpose: TokenPurpose, id: Uuid, expires_at: DateTime<Utc>) -> String {
+        let expires = expires_at.timestamp().to_be_bytes();
+        let signature = self.sign(purpose, id, &expires).finalize().into_bytes();
+
+        let mut token = Vec::with_capacity(TOKEN_BYTES);
+        token.extend_from_slice(id.as_bytes());
+        token.extend_from_slice(&expires);
+        token.extend_from_slice(&signature);
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Checks that `token` was issued for `purpose` and has not expired.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the message the token was issued for.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the token is rejected (see [`TokenError`]).
+    pub fn verify(&self, purpose: TokenPurpose, token: &str) -> Result<Uuid, TokenError> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| TokenError::Malformed)?;
+        if bytes.len() != TOKEN_BYTES {
+            return Err(TokenError::Malformed);
+        }
+        let (id, rest) = bytes.split_at(16);
+        let (expires, signature) = rest.split_at(8);
+        let id = Uuid::from_slice(id).map_err(|_| TokenError::Malformed)?;
+
+        self.sign(purpose, id, expires)
+            .verify_slice(signature)
+            .map_err(|_| TokenError::Invalid)?;
+
+        let expires = i64::from_be_bytes(expires.try_into().map_err(|_| TokenError::Malformed)?);
+        if expires <= Utc::now().timestamp() {
+            return Err(TokenError::Expired);
+        }
+        Ok(id)
+    }
+
+    fn sign(&self, purpose: TokenPurpose, id: Uuid, expires: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(purpose.as_str().as_bytes());
+        mac.update(b":");
+        mac.update(id.as_bytes());
+        mac.update(expires);
+        mac
+    }
+}
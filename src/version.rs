@@ -0,0 +1,58 @@
+//! # Build Information
+//!
+//! Identifies the running build: crate version, git commit, build time and
+//! enabled cargo features. The values are captured at compile time by
+//! `build.rs`; set `GIT_COMMIT` when building outside a git checkout.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Crate version of the running build.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit the running build was made from, or `unknown`.
+pub const GIT_COMMIT: &str = env!("DOTHTML_GIT_COMMIT");
+
+/// Information about the running build, as returned by `GET /version`.
+///
+/// # Fields
+///
+/// * `version` - Crate version
+/// * `commit` - Full git commit hash, or `unknown`
+/// * `built_at` - Time the build ran
+/// * `features` - Enabled cargo features, sorted
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::version::BuildInfo;
+///
+/// let info = BuildInfo::current();
+/// println!("dothtml-backend {} ({})", info.version, info.short_commit());
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    #[serde(with = "crate::timestamp")]
+    pub built_at: DateTime<Utc>,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    /// Returns the information of the running build.
+    pub fn current() -> Self {
+        let timestamp = env!("DOTHTML_BUILD_TIMESTAMP").parse().unwrap_or_default();
+        BuildInfo {
+            version: VERSION,
+            commit: GIT_COMMIT,
+            built_at: DateTime::from_timestamp(timestamp, 0).unwrap_or_default(),
+            features: env!("DOTHTML_FEATURES").split(',').filter(|feature| !feature.is_empty()).collect(),
+        }
+    }
+
+    /// Returns the first 12 characters of the commit hash.
+    pub fn short_commit(&self) -> &'static str {
+        self.commit.get(..12).unwrap_or(self.commit)
+    }
+}
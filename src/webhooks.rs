@@ -0,0 +1,951 @@
+//! # Webhooks
+//!
+//! Outbox entries (see the `outbox` module) delivered as signed HTTP
+//! `POST` requests to the endpoints registered with `/admin/webhooks`.
+//! Enabled with `OUTBOX_PUBLISHER=webhooks`.
+//!
+//! The relay hands each entry to the [`WebhookPublisher`], which stores one
+//! delivery per endpoint in the `webhook_deliveries` table. The delivery job
+//! (see `jobs::spawn_webhook_delivery_job`) then sends them, retrying each
+//! failed delivery with an exponential backoff, so that a failing endpoint
+//! neither delays nor duplicates the deliveries to the others. A delivery
+//! still failing after [`MAX_DELIVERY_ATTEMPTS`] attempts is given up and
+//! recorded as a dead letter, which `POST /admin/dead-letters/{id}/retry`
+//! sends again (see the `dead_letters` module).
+//!
+//! ## Filters and templates
+//!
+//! An endpoint can narrow what it receives:
+//!
+//! - `events` - Topics it receives, such as `message.created` or `message.*` (default: all)
+//! - `fields` - Dotted paths of the payload it receives, such as `message_id` or
+//!   `payload.status` (default: the whole payload)
+//! - `template` - [Handlebars](https://handlebarsjs.com/guide/) template rendering the body
+//!   from `topic`, `dedup_key`, `created_at` and the (narrowed) `payload`, so that targets
+//!   expecting their own format need no glue service in between
+//!
+//! The rendered template must be JSON. Values are escaped for JSON strings,
+//! and `{{{json value}}}` inserts a value as JSON. For a Slack incoming
+//! webhook:
+//!
+//! ```text
+//! { "text": "New {{topic}} on message {{payload.message_id}}" }
+//! ```
+//!
+//! An entry the template cannot be rendered with, for instance because it
+//! lacks a value the template uses, gives a delivery that is given up right
+//! away and recorded as a dead letter (see the `dead_letters` module).
+//!
+//! ## Requests
+//!
+//! The body is the entry's JSON payload, shaped by the endpoint's filters
+//! and template, with these headers:
+//!
+//! - `X-Dothtml-Event` - Topic of the entry, e.g. `message.created`
+//! - `X-Dothtml-Delivery` - Identifier of the delivery, the same across retries
+//! - `X-Dothtml-Dedup-Key` - Dedup key of the entry, to drop duplicates
+//! - `X-Dothtml-Timestamp` - Time of the attempt, in seconds since the epoch
+//! - `X-Dothtml-Signature` - `v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`, once per
+//!   valid secret of the endpoint, comma-separated
+//!
+//! Endpoints should check a signature with [`verify_signature`] or its
+//! equivalent, and refuse timestamps older than a few minutes so that a
+//! captured request cannot be replayed.
+//!
+//! ## Secret rotation
+//!
+//! `POST /admin/webhooks/{id}/rotate-secret` gives an endpoint a new secret.
+//! The previous one keeps signing for `WEBHOOK_SECRET_OVERLAP_HOURS`, each
+//! request carrying both signatures, so that the consumer can switch to
+//! the new secret without missing a delivery.
+
+use std::io;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use handlebars::{handlebars_helper, Handlebars, Template};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::dead_letters::{DeadLetterKind, NewDeadLetter};
+use crate::metrics::PipelineMetrics;
+use crate::outbox::{OutboxEntry, Publisher};
+
+/// Attempts made to deliver a request before giving it up.
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+
+/// Longest delay between two attempts of a failing delivery, in seconds.
+const MAX_RETRY_DELAY_SECS: f64 = 60.0 * 60.0;
+
+/// Time an endpoint has to answer.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Age past which [`verify_signature`] refuses a request, in seconds.
+pub const DEFAULT_TOLERANCE_SECS: i64 = 5 * 60;
+
+/// Longest accepted payload template, in bytes.
+pub const MAX_TEMPLATE_LENGTH: usize = 10_000;
+
+/// A registered webhook endpoint, without its secrets.
+///
+/// # Fields
+///
+/// * `id` - Unique identifier of the endpoint
+/// * `url` - Address the requests are posted to
+/// * `description` - What the endpoint is for
+/// * `events` - Topics the endpoint receives, every topic when empty
+/// * `fields` - Paths of the payload the endpoint receives, the whole payload when `None`
+/// * `template` - Template rendering the body, the payload itself when `None`
+/// * `created_at` - When the endpoint was registered
+/// * `secret_rotated_at` - When the secret was last rotated, if it was
+/// * `previous_secret_expires_at` - When the previous secret stops signing, while it does
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub description: Option<String>,
+    pub events: Vec<String>,
+    pub fields: Option<Vec<String>>,
+    pub template: Option<String>,
+    #[serde(with = "crate::timestamp")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::timestamp::option")]
+    pub secret_rotated_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::timestamp::option")]
+    pub previous_secret_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Column list selected for every `WebhookEndpoint` row.
+const ENDPOINT_COLUMNS: &str = "id, url, description, events, fields, template, created_at, secret_rotated_at, \
+     CASE WHEN previous_secret_expires_at > NOW() THEN previous_secret_expires_at END AS previous_secret_expires_at";
+
+fn endpoint_from_row(row: &PgRow) -> WebhookEndpoint {
+    WebhookEndpoint {
+        id: row.get("id"),
+        url: row.get("url"),
+        description: row.get("description"),
+        events: row.get("events"),
+        fields: row.get("fields"),
+        template: row.get("template"),
+        created_at: row.get("created_at"),
+        secret_rotated_at: row.get("secret_rotated_at"),
+        previous_secret_expires_at: row.get("previous_secret_expires_at"),
+    }
+}
+
+impl WebhookEndpoint {
+    /// Returns the body `entry` is delivered to this endpoint with, once
+    /// narrowed to its fields and rendered with its template.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the error if the template
+    /// cannot be rendered with the entry, or does not render JSON.
+    pub fn shape(&self, entry: &OutboxEntry, renderer: &PayloadRenderer) -> Result<Value, String> {
+        let payload = match &self.fields {
+            Some(fields) => select_fields(&entry.payload, fields),
+            None => entry.payload.clone(),
+        };
+        match &self.template {
+            Some(template) => renderer.render(template, &json!({
+                "topic": entry.topic,
+                "dedup_key": entry.dedup_key,
+                "created_at": crate::timestamp::format(&entry.created_at),
+                "payload": payload,
+            })),
+            None => Ok(payload),
+        }
+    }
+}
+
+/// Settings of an endpoint, as registered or replaced by an admin.
+///
+/// # Fields
+///
+/// * `url` - Absolute `http` or `https` address the requests are posted to
+/// * `description` - What the endpoint is for
+/// * `events` - Topics the endpoint receives, ending with `.*` to match a prefix (default: all)
+/// * `fields` - Dotted paths of the payload the endpoint receives (default: the whole payload)
+/// * `template` - Handlebars template rendering the body (default: the payload itself)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookDefinition {
+    pub url: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl WebhookDefinition {
+    /// Checks the settings and returns them normalized: trimmed, without
+    /// duplicate events or fields, and without empty optional values.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the first problem found.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dothtml_backend::webhooks::WebhookDefinition;
+    ///
+    /// let definition: WebhookDefinition = serde_json::from_value(serde_json::json!({
+    ///     "url": "https://hooks.slack.com/services/T000/B000/XXXX",
+    ///     "events": ["message.*", "message.created"],
+    ///     "template": "{ \"text\": \"{{payload.message_id}} is {{topic}}\" }"
+    /// })).unwrap();
+    /// assert_eq!(definition.check().unwrap().events, ["message.*", "message.created"]);
+    ///
+    /// let definition: WebhookDefinition = serde_json::from_value(serde_json::json!({
+    ///     "url": "ftp://example.com/hooks"
+    /// })).unwrap();
+    /// assert!(definition.check().is_err());
+    /// ```
+    pub fn check(mut self) -> Result<Self, String> {
+        self.url = self.url.trim().to_string();
+        match reqwest::Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {}
+            _ => return Err("URL must be an absolute http or https address".to_string()),
+        }
+        if self.url.len() > 2000 {
+            return Err("URL must be at most 2000 characters".to_string());
+        }
+
+        self.description = self.description.map(|description| description.trim().to_string()).filter(|d| !d.is_empty());
+        if self.description.as_ref().is_some_and(|description| description.chars().count() > 200) {
+            return Err("Description must be at most 200 characters".to_string());
+        }
+
+        self.events = normalize_list(self.events);
+        if let Some(event) = self.events.iter().find(|event| !is_valid_event_pattern(event)) {
+            return Err(format!(
+                "Invalid event `{}`: use a topic such as `message.created`, or a prefix such as `message.*`",
+                event
+            ));
+        }
+
+        if let Some(fields) = self.fields.take() {
+            let fields = normalize_list(fields);
+            if fields.is_empty() {
+                return Err("Fields must name at least one field, or be left out to receive the whole payload".to_string());
+            }
+            if let Some(field) = fields.iter().find(|field| field.split('.').any(str::is_empty)) {
+                return Err(format!("Invalid field `{}`: use a dotted path such as `payload.status`", field));
+            }
+            self.fields = Some(fields);
+        }
+
+        self.template = self.template.filter(|template| !template.trim().is_empty());
+        if let Some(template) = &self.template {
+            if template.len() > MAX_TEMPLATE_LENGTH {
+                return Err(format!("Template must be at most {} bytes", MAX_TEMPLATE_LENGTH));
+            }
+            Template::compile(template).map_err(|e| format!("Invalid template: {}", e))?;
+        }
+
+        Ok(self)
+    }
+}
+
+/// Trims the items of a list, dropping empty ones and duplicates.
+fn normalize_list(items: Vec<String>) -> Vec<String> {
+    let mut items: Vec<String> = items
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect();
+    items.sort();
+    items.dedup();
+    items
+}
+
+fn is_valid_event_pattern(pattern: &str) -> bool {
+    let topic = pattern.strip_suffix(".*").unwrap_or(pattern);
+    pattern == "*"
+        || (!topic.is_empty()
+            && topic.split('.').all(|part| {
+                !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            }))
+}
+
+/// Returns whether an endpoint receiving `events` receives `topic`.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::webhooks::topic_matches;
+///
+/// assert!(topic_matches(&[], "message.created"));
+/// assert!(topic_matches(&["message.*".to_string()], "message.created"));
+/// assert!(!topic_matches(&["message.*".to_string()], "messages.created"));
+/// assert!(!topic_matches(&["message.resolved".to_string()], "message.created"));
+/// ```
+pub fn topic_matches(events: &[String], topic: &str) -> bool {
+    events.is_empty()
+        || events.iter().any(|pattern| {
+            pattern == "*"
+                || pattern == topic
+                || pattern
+                    .strip_suffix('*')
+                    .is_some_and(|prefix| prefix.ends_with('.') && topic.starts_with(prefix))
+        })
+}
+
+/// Returns the parts of `payload` named by `fields`, dotted paths into
+/// nested objects. Paths missing from the payload are left out.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::webhooks::select_fields;
+/// use serde_json::json;
+///
+/// let payload = json!({ "id": 7, "message_id": "m1", "payload": { "status": "resolved", "by": "alice" } });
+/// let fields = ["message_id".to_string(), "payload.status".to_string(), "missing".to_string()];
+/// assert_eq!(select_fields(&payload, &fields), json!({ "message_id": "m1", "payload": { "status": "resolved" } }));
+/// ```
+pub fn select_fields(payload: &Value, fields: &[String]) -> Value {
+    let mut selected = Map::new();
+    for field in fields {
+        let path: Vec<&str> = field.split('.').collect();
+        let Some(value) = path.iter().try_fold(payload, |value, key| value.get(key)) else {
+            continue;
+        };
+        let (last, parents) = path.split_last().expect("split always yields a part");
+        let mut target = &mut selected;
+        for key in parents {
+            let entry = target.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            target = entry.as_object_mut().expect("the entry is an object");
+        }
+        target.insert(last.to_string(), value.clone());
+    }
+    Value::Object(selected)
+}
+
+/// Renders the payload templates of the endpoints.
+///
+/// Rendering is strict: a template using a value that does not exist
+/// fails. Values are escaped for JSON strings, and the `json` helper
+/// inserts a value as JSON when used with triple braces.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::webhooks::PayloadRenderer;
+/// use serde_json::json;
+///
+/// let renderer = PayloadRenderer::new();
+/// let data = json!({ "payload": { "name": "Tom \"TJ\" Jones", "tags": ["sales"] } });
+/// let body = renderer
+///     .render(r#"{ "text": "From {{payload.name}}", "tags": {{{json payload.tags}}} }"#, &data)
+///     .unwrap();
+/// assert_eq!(body, json!({ "text": "From Tom \"TJ\" Jones", "tags": ["sales"] }));
+///
+/// assert!(renderer.render(r#"{ "text": "{{payload.nmae}}" }"#, &data).is_err());
+/// ```
+pub struct PayloadRenderer {
+    registry: Handlebars<'static>,
+}
+
+handlebars_helper!(json_helper: |value: Json| serde_json::to_string(value).unwrap_or_default());
+
+impl PayloadRenderer {
+    /// Creates a renderer escaping values for JSON strings.
+    pub fn new() -> Self {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        registry.register_escape_fn(|value| {
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        });
+        registry.register_helper("json", Box::new(json_helper));
+        PayloadRenderer { registry }
+    }
+
+    /// Renders `template` with `data` into a JSON body.
+    ///
+    /// # Errors
+    ///
+    /// This function returns a description of the error if the template is
+    /// invalid, uses a value missing from `data`, or does not render JSON.
+    pub fn render(&self, template: &str, data: &Value) -> Result<Value, String> {
+        let rendered = self
+            .registry
+            .render_template(template, data)
+            .map_err(|e| format!("Failed to render the template: {}", e))?;
+        serde_json::from_str(&rendered).map_err(|e| format!("The template did not render JSON: {}", e))
+    }
+}
+
+impl Default for PayloadRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A delivery waiting to be sent, with the secrets signing it.
+///
+/// # Fields
+///
+/// * `id` - Identifier of the delivery
+/// * `endpoint_id` - Endpoint the delivery is for
+/// * `url` - Address of the endpoint
+/// * `secrets` - Secrets signing the request: the current one, then the previous one during a rotation
+/// * `topic` - Topic of the outbox entry
+/// * `dedup_key` - Dedup key of the outbox entry
+/// * `body` - Serialized payload of the outbox entry
+/// * `attempts` - Number of attempts made before this one
+/// * `created_at` - When the outbox entry was written
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint_id: Uuid,
+    pub url: String,
+    pub secrets: Vec<String>,
+    pub topic: String,
+    pub dedup_key: String,
+    pub body: String,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returns a new random endpoint secret.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::random();
+    format!("whsec_{}", URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Returns the `X-Dothtml-Signature` header of `body` sent at `timestamp`,
+/// with one signature per secret.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::webhooks::{sign, verify_signature};
+///
+/// let header = sign(&["new", "old"], 1_700_000_000, b"{}");
+/// assert_eq!(header.matches("v1=").count(), 2);
+///
+/// // A consumer still using the old secret accepts the request
+/// assert!(verify_signature("old", "1700000000", b"{}", &header, 1_700_000_060, 300));
+/// // Replays outside the tolerance window and altered bodies are refused
+/// assert!(!verify_signature("old", "1700000000", b"{}", &header, 1_700_001_000, 300));
+/// assert!(!verify_signature("new", "1700000000", b"[]", &header, 1_700_000_060, 300));
+/// ```
+pub fn sign<S: AsRef<str>>(secrets: &[S], timestamp: i64, body: &[u8]) -> String {
+    secrets
+        .iter()
+        .map(|secret| format!("v1={}", hex(&mac(secret.as_ref(), timestamp, body).finalize().into_bytes())))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Checks the `X-Dothtml-Signature` header of a request, as a consumer
+/// would.
+///
+/// # Arguments
+///
+/// * `secret` - Secret of the endpoint
+/// * `timestamp` - `X-Dothtml-Timestamp` header of the request
+/// * `body` - Raw body of the request
+/// * `header` - `X-Dothtml-Signature` header of the request
+/// * `now` - Current time, in seconds since the epoch
+/// * `tolerance_secs` - Largest accepted difference between `timestamp` and
+///   `now`, such as [`DEFAULT_TOLERANCE_SECS`]
+///
+/// # Returns
+///
+/// Returns whether one of the signatures was made with `secret`, over this
+/// body, within the tolerance window.
+pub fn verify_signature(secret: &str, timestamp: &str, body: &[u8], header: &str, now: i64, tolerance_secs: i64) -> bool {
+    let Ok(timestamp) = timestamp.trim().parse::<i64>() else {
+        return false;
+    };
+    if (now - timestamp).abs() > tolerance_secs {
+        return false;
+    }
+    header
+        .split(',')
+        .filter_map(|part| part.trim().strip_prefix("v1="))
+        .filter_map(unhex)
+        .any(|candidate| mac(secret, timestamp, body).verify_slice(&candidate).is_ok())
+}
+
+/// Returns the HMAC of `body` sent at `timestamp`, ready to finalize or verify.
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Publisher storing every outbox entry as one delivery per registered
+/// endpoint receiving its topic (`OUTBOX_PUBLISHER=webhooks`).
+pub struct WebhookPublisher {
+    db: Database,
+    renderer: PayloadRenderer,
+}
+
+impl WebhookPublisher {
+    /// Creates a publisher storing the deliveries in `db`.
+    pub fn new(db: Database) -> Self {
+        WebhookPublisher { db, renderer: PayloadRenderer::new() }
+    }
+}
+
+#[async_trait]
+impl Publisher for WebhookPublisher {
+    async fn publish(&self, entry: &OutboxEntry) -> io::Result<()> {
+        self.db.enqueue_webhook_deliveries(entry, &self.renderer).await.map_err(io::Error::other)
+    }
+}
+
+/// Sends the webhook deliveries.
+#[derive(Clone)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+    metrics: PipelineMetrics,
+}
+
+impl WebhookSender {
+    /// Creates a sender giving endpoints ten seconds to answer, and
+    /// recording the latency of the accepted deliveries in `metrics`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the HTTP client cannot be built.
+    pub fn new(metrics: PipelineMetrics) -> io::Result<Self> {
+        let client = reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build().map_err(io::Error::other)?;
+        Ok(WebhookSender { client, metrics })
+    }
+
+    /// Posts a delivery to its endpoint.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the endpoint cannot be reached or
+    /// does not answer with a 2xx status.
+    pub async fn send(&self, delivery: &WebhookDelivery) -> io::Result<()> {
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header("content-type", "application/json")
+            .header("x-dothtml-event", &delivery.topic)
+            .header("x-dothtml-delivery", delivery.id.to_string())
+            .header("x-dothtml-dedup-key", &delivery.dedup_key)
+            .header("x-dothtml-timestamp", timestamp.to_string())
+            .header("x-dothtml-signature", sign(&delivery.secrets, timestamp, delivery.body.as_bytes()))
+            .body(delivery.body.clone())
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(io::Error::other(format!("the endpoint answered {}", status)));
+        }
+        self.metrics.observe_webhook((Utc::now() - delivery.created_at).to_std().unwrap_or_default());
+        Ok(())
+    }
+}
+
+/// Database operations for the webhooks.
+impl Database {
+    /// Creates the 'webhook_endpoints' and 'webhook_deliveries' tables if
+    /// they don't exist.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if:
+    /// - Database connection issues occur
+    /// - Insufficient permissions for table creation
+    pub async fn create_webhook_tables(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql(r#"
+            CREATE TABLE IF NOT EXISTS webhook_endpoints (
+                id UUID PRIMARY KEY,
+                url TEXT NOT NULL,
+                description TEXT,
+                events TEXT[] NOT NULL DEFAULT '{}',
+                fields TEXT[],
+                template TEXT,
+                secret TEXT NOT NULL,
+                previous_secret TEXT,
+                previous_secret_expires_at TIMESTAMPTZ,
+                secret_rotated_at TIMESTAMPTZ,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGSERIAL PRIMARY KEY,
+                endpoint_id UUID NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+                topic TEXT NOT NULL,
+                dedup_key TEXT NOT NULL,
+                payload JSONB NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                last_error TEXT,
+                delivered_at TIMESTAMPTZ,
+                failed_at TIMESTAMPTZ,
+                UNIQUE (endpoint_id, dedup_key)
+            );
+
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_pending_idx
+                ON webhook_deliveries (next_attempt_at, id)
+                WHERE delivered_at IS NULL AND failed_at IS NULL;
+        "#)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Registers a webhook endpoint.
+    ///
+    /// # Returns
+    ///
+    /// Returns the endpoint and its secret, which cannot be read again.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use dothtml_backend::database::Database;
+    /// use dothtml_backend::webhooks::WebhookDefinition;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), sqlx::Error> {
+    ///     let db = Database::new().await?;
+    ///     let (endpoint, secret) = db.create_webhook_endpoint(&WebhookDefinition {
+    ///         url: "https://crm.example.com/hooks/dothtml".to_string(),
+    ///         description: None,
+    ///         events: vec!["message.created".to_string()],
+    ///         fields: None,
+    ///         template: None,
+    ///     }).await?;
+    ///     println!("Endpoint {} signs with {}", endpoint.id, secret);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_webhook_endpoint(
+        &self, definition: &WebhookDefinition
+    ) -> Result<(WebhookEndpoint, String), sqlx::Error> {
+        let secret = generate_secret();
+        let row = sqlx::query(&format!(r#"
+            INSERT INTO webhook_endpoints (id, url, description, events, fields, template, secret)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING {ENDPOINT_COLUMNS}
+        "#))
+        .bind(Uuid::new_v4())
+        .bind(&definition.url)
+        .bind(&definition.description)
+        .bind(&definition.events)
+        .bind(&definition.fields)
+        .bind(&definition.template)
+        .bind(&secret)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((endpoint_from_row(&row), secret))
+    }
+
+    /// Replaces the settings of a webhook endpoint, keeping its secrets.
+    /// Deliveries already stored keep the shape they were stored with.
+    ///
+    /// # Returns
+    ///
+    /// Returns the updated endpoint, `None` if there is no such endpoint.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn update_webhook_endpoint(
+        &self, id: Uuid, definition: &WebhookDefinition
+    ) -> Result<Option<WebhookEndpoint>, sqlx::Error> {
+        let row = sqlx::query(&format!(r#"
+            UPDATE webhook_endpoints
+            SET url = $2, description = $3, events = $4, fields = $5, template = $6
+            WHERE id = $1
+            RETURNING {ENDPOINT_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(&definition.url)
+        .bind(&definition.description)
+        .bind(&definition.events)
+        .bind(&definition.fields)
+        .bind(&definition.template)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(endpoint_from_row))
+    }
+
+    /// Lists the webhook endpoints, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn list_webhook_endpoints(&self) -> Result<Vec<WebhookEndpoint>, sqlx::Error> {
+        let rows = sqlx::query(&format!("SELECT {ENDPOINT_COLUMNS} FROM webhook_endpoints ORDER BY created_at"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(endpoint_from_row).collect())
+    }
+
+    /// Removes a webhook endpoint and its pending deliveries.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether there was such an endpoint.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn delete_webhook_endpoint(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM webhook_endpoints WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Gives an endpoint a new secret, the current one signing along with it
+    /// for `overlap_hours`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the endpoint and its new secret, `None` if there is no such
+    /// endpoint.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn rotate_webhook_secret(
+        &self, id: Uuid, overlap_hours: u32
+    ) -> Result<Option<(WebhookEndpoint, String)>, sqlx::Error> {
+        let secret = generate_secret();
+        let row = sqlx::query(&format!(r#"
+            UPDATE webhook_endpoints
+            SET previous_secret = secret,
+                previous_secret_expires_at = NOW() + make_interval(hours => $3),
+                secret = $2,
+                secret_rotated_at = NOW()
+            WHERE id = $1
+            RETURNING {ENDPOINT_COLUMNS}
+        "#))
+        .bind(id)
+        .bind(&secret)
+        .bind(overlap_hours as i32)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| (endpoint_from_row(&row), secret)))
+    }
+
+    /// Stores one delivery of `entry` per endpoint receiving its topic,
+    /// shaped by the endpoint's fields and template. Storing an entry
+    /// again, when the relay retries it, adds nothing.
+    ///
+    /// A delivery whose template cannot be rendered is stored given up,
+    /// with the entry's payload and the rendering error, and recorded as a
+    /// dead letter (see the `dead_letters` module).
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn enqueue_webhook_deliveries(
+        &self, entry: &OutboxEntry, renderer: &PayloadRenderer
+    ) -> Result<(), sqlx::Error> {
+        let endpoints = self.list_webhook_endpoints().await?;
+
+        let mut tx = self.pool.begin().await?;
+        for endpoint in endpoints.iter().filter(|endpoint| topic_matches(&endpoint.events, &entry.topic)) {
+            let (payload, error) = match endpoint.shape(entry, renderer) {
+                Ok(payload) => (payload, None),
+                Err(e) => (entry.payload.clone(), Some(e)),
+            };
+            let inserted = sqlx::query(r#"
+                INSERT INTO webhook_deliveries (endpoint_id, topic, dedup_key, payload, created_at, last_error, failed_at)
+                VALUES ($1, $2, $3, $4, $5, $6::TEXT, CASE WHEN $6::TEXT IS NOT NULL THEN NOW() END)
+                ON CONFLICT (endpoint_id, dedup_key) DO NOTHING
+                RETURNING id
+            "#)
+            .bind(endpoint.id)
+            .bind(&entry.topic)
+            .bind(&entry.dedup_key)
+            .bind(&payload)
+            .bind(entry.created_at)
+            .bind(&error)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let (Some(row), Some(error)) = (inserted, &error) {
+                Self::insert_dead_letter(&mut *tx, &NewDeadLetter {
+                    kind: DeadLetterKind::Webhook,
+                    source_id: Some(row.get("id")),
+                    topic: &entry.topic,
+                    destination: &endpoint.url,
+                    payload: &payload,
+                    error,
+                    attempts: 0,
+                })
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Sends up to `batch_size` due deliveries, oldest first.
+    ///
+    /// Deliveries are locked while they are sent so that every replica can
+    /// run the delivery job. A failed delivery is retried with an
+    /// exponential backoff capped at one hour, and given up after
+    /// [`MAX_DELIVERY_ATTEMPTS`] attempts, becoming a dead letter.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of deliveries attempted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    /// Delivery failures are recorded on the delivery instead.
+    pub async fn deliver_webhooks(&self, sender: &WebhookSender, batch_size: i64) -> Result<usize, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(r#"
+            SELECT d.id, d.endpoint_id, e.url, e.secret,
+                   CASE WHEN e.previous_secret_expires_at > NOW() THEN e.previous_secret END AS previous_secret,
+                   d.topic, d.dedup_key, d.payload::TEXT AS body, d.attempts, d.created_at
+            FROM webhook_deliveries d
+            JOIN webhook_endpoints e ON e.id = d.endpoint_id
+            WHERE d.delivered_at IS NULL AND d.failed_at IS NULL AND d.next_attempt_at <= NOW()
+            ORDER BY d.id
+            LIMIT $1
+            FOR UPDATE OF d SKIP LOCKED
+        "#)
+        .bind(batch_size)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let attempted = rows.len();
+        for row in rows {
+            let previous_secret: Option<String> = row.get("previous_secret");
+            let delivery = WebhookDelivery {
+                id: row.get("id"),
+                endpoint_id: row.get("endpoint_id"),
+                url: row.get("url"),
+                secrets: std::iter::once(row.get("secret")).chain(previous_secret).collect(),
+                topic: row.get("topic"),
+                dedup_key: row.get("dedup_key"),
+                body: row.get("body"),
+                attempts: row.get("attempts"),
+                created_at: row.get("created_at"),
+            };
+
+            match sender.send(&delivery).await {
+                Ok(()) => {
+                    sqlx::query(r#"
+                        UPDATE webhook_deliveries
+                        SET delivered_at = NOW(), attempts = attempts + 1, last_error = NULL
+                        WHERE id = $1
+                    "#)
+                    .bind(delivery.id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    let row = sqlx::query(r#"
+                        UPDATE webhook_deliveries
+                        SET attempts = attempts + 1,
+                            last_error = $2,
+                            next_attempt_at = NOW() + make_interval(secs => LEAST(power(2, attempts), $3)),
+                            failed_at = CASE WHEN attempts + 1 >= $4 THEN NOW() END
+                        WHERE id = $1
+                        RETURNING attempts, failed_at IS NOT NULL AS given_up, payload
+                    "#)
+                    .bind(delivery.id)
+                    .bind(&error)
+                    .bind(MAX_RETRY_DELAY_SECS)
+                    .bind(MAX_DELIVERY_ATTEMPTS)
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+                    if row.get::<bool, _>("given_up") {
+                        Self::insert_dead_letter(&mut *tx, &NewDeadLetter {
+                            kind: DeadLetterKind::Webhook,
+                            source_id: Some(delivery.id),
+                            topic: &delivery.topic,
+                            destination: &delivery.url,
+                            payload: &row.get("payload"),
+                            error: &error,
+                            attempts: row.get("attempts"),
+                        })
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(attempted)
+    }
+
+    /// Deletes the deliveries made more than `after_days` days ago.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of deleted deliveries.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if database connection issues occur.
+    pub async fn purge_delivered_webhooks(&self, after_days: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM webhook_deliveries WHERE delivered_at < NOW() - make_interval(days => $1)")
+            .bind(after_days as i32)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,207 @@
+//! # Contact Widget
+//!
+//! A contact form that sites without a frontend of their own embed with a
+//! single script tag:
+//!
+//! ```html
+//! <script src="https://api.dotshell.eu/widget/quote-request/embed.js" async></script>
+//! ```
+//!
+//! - `GET /widget/{form}/embed.js` is a small script inserting an iframe
+//!   in place of its own tag, and growing it to the height of the form.
+//! - `GET /widget/{form}` is the form itself, a self-contained HTML page
+//!   (inline style and script) that can also be linked to or framed
+//!   directly. It submits to `POST /contact` with `form` set to `{form}`,
+//!   so that messages are filed under that form like the website's.
+//!
+//! The page is served from the API's own origin, which is why the CORS
+//! middleware lets same-origin requests through (see [`is_same_origin`]).
+//! Submissions cannot be forged by another site: `POST /contact` only
+//! accepts JSON bodies, which a cross-site HTML form cannot send, and the
+//! page sends no cookies with them (see the `csrf` module). The sites
+//! allowed to frame the form are set with `WIDGET_FRAME_ANCESTORS`
+//! (default: any).
+
+use actix_web::dev::RequestHead;
+use actix_web::http::header::{self, HeaderValue};
+
+/// Form page, `{{FORM}}` standing for the escaped form name.
+const PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Contact us</title>
+<style>
+body { margin: 0; padding: 16px; font: 15px/1.4 system-ui, sans-serif; color: #1f2933; background: transparent; }
+form { display: grid; gap: 12px; max-width: 560px; }
+label { display: grid; gap: 4px; font-weight: 600; }
+input, textarea { font: inherit; padding: 8px 10px; border: 1px solid #cbd2d9; border-radius: 6px; }
+textarea { min-height: 120px; resize: vertical; }
+button { font: inherit; font-weight: 600; padding: 10px 16px; border: 0; border-radius: 6px; color: #fff; background: #2563eb; cursor: pointer; }
+button:disabled { opacity: .6; cursor: default; }
+.status { margin: 0; }
+.status.error { color: #b91c1c; }
+</style>
+</head>
+<body>
+<form id="dothtml-widget" data-form="{{FORM}}" novalidate>
+<label>Name <input name="name" required maxlength="100" autocomplete="name"></label>
+<label>Email <input name="email" type="email" required autocomplete="email"></label>
+<label>Company <input name="company" maxlength="100" autocomplete="organization"></label>
+<label>Phone <input name="phone_number" maxlength="20" autocomplete="tel"></label>
+<label>Country or region <input name="country_region" maxlength="50" autocomplete="country-name"></label>
+<label>Message <textarea name="message" required maxlength="2000"></textarea></label>
+<button type="submit">Send</button>
+<p class="status" role="status" aria-live="polite"></p>
+</form>
+<script>
+(function () {
+  var form = document.getElementById("dothtml-widget");
+  var status = form.querySelector(".status");
+  var button = form.querySelector("button");
+  function resize() {
+    if (window.parent !== window) {
+      window.parent.postMessage({ source: "dothtml-widget", height: document.documentElement.scrollHeight }, "*");
+    }
+  }
+  function show(text, error) {
+    status.textContent = text;
+    status.className = error ? "status error" : "status";
+    resize();
+  }
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    var body = { form: form.dataset.form };
+    ["name", "email", "company", "phone_number", "country_region", "message"].forEach(function (field) {
+      body[field] = form.elements[field].value.trim();
+    });
+    button.disabled = true;
+    fetch("../contact", {
+      method: "POST",
+      credentials: "omit",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(body)
+    }).then(function (response) {
+      if (response.ok) {
+        form.reset();
+        show("Thank you, your message was sent. We will get back to you soon.", false);
+      } else if (response.status === 429) {
+        show("Too many messages were sent from your network, please try again later.", true);
+      } else if (response.status === 400) {
+        show("Please check the fields: a name, a valid email and a message are required.", true);
+      } else {
+        show("Your message could not be sent, please try again later.", true);
+      }
+    }, function () {
+      show("Your message could not be sent, please check your connection.", true);
+    }).then(function () {
+      button.disabled = false;
+    });
+  });
+  window.addEventListener("load", resize);
+  window.addEventListener("resize", resize);
+})();
+</script>
+</body>
+</html>
+"#;
+
+/// Script inserting the widget iframe in place of its own tag.
+///
+/// The iframe's address is the script's own without `/embed.js`, so that
+/// the snippet works whatever host or path prefix the API is served at.
+pub const EMBED_SCRIPT: &str = r#"(function () {
+  var script = document.currentScript;
+  if (!script) return;
+  var src = script.src.replace(/\/embed\.js(?:[?#].*)?$/, "");
+  var frame = document.createElement("iframe");
+  frame.src = src;
+  frame.title = "Contact form";
+  frame.loading = "lazy";
+  frame.style.cssText = "width:100%;max-width:600px;height:640px;border:0;overflow:hidden";
+  window.addEventListener("message", function (event) {
+    var data = event.data;
+    if (event.source === frame.contentWindow && data && data.source === "dothtml-widget" && data.height > 0) {
+      frame.style.height = Math.ceil(data.height) + "px";
+    }
+  });
+  script.parentNode.insertBefore(frame, script);
+})();
+"#;
+
+/// Writes the form page of the widget for `form`, which must be a valid
+/// form name (see `is_valid_form_name`).
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::widget::widget_page;
+///
+/// let page = widget_page("quote-request");
+/// assert!(page.starts_with("<!DOCTYPE html>"));
+/// assert!(page.contains(r#"data-form="quote-request""#));
+/// ```
+pub fn widget_page(form: &str) -> String {
+    // Form names are made of characters that need no escaping
+    debug_assert!(crate::models::is_valid_form_name(form));
+    PAGE_TEMPLATE.replace("{{FORM}}", form)
+}
+
+/// Returns the `Content-Security-Policy` of the form page: only its inline
+/// style and script run, it only connects to the API, and it may only be
+/// framed by `frame_ancestors`, or by any site when the list is empty.
+///
+/// # Examples
+///
+/// ```rust
+/// use dothtml_backend::widget::content_security_policy;
+///
+/// assert!(content_security_policy(&[]).ends_with("frame-ancestors *"));
+/// let policy = content_security_policy(&["https://dotshell.eu".to_string()]);
+/// assert!(policy.ends_with("frame-ancestors https://dotshell.eu"));
+/// ```
+pub fn content_security_policy(frame_ancestors: &[String]) -> String {
+    let ancestors = if frame_ancestors.is_empty() { "*".to_string() } else { frame_ancestors.join(" ") };
+    format!(
+        "default-src 'none'; style-src 'unsafe-inline'; script-src 'unsafe-inline'; connect-src 'self'; \
+         form-action 'self'; base-uri 'none'; frame-ancestors {}",
+        ancestors
+    )
+}
+
+/// Returns whether `origin` is the origin the request was sent to, as for
+/// the submissions of the form page served by the API itself.
+///
+/// The scheme is not compared, since TLS may end at a reverse proxy.
+///
+/// # Examples
+///
+/// ```rust
+/// use actix_web::http::header::{self, HeaderValue};
+/// use actix_web::test::TestRequest;
+/// use dothtml_backend::widget::is_same_origin;
+///
+/// let request = TestRequest::default().insert_header((header::HOST, "api.dotshell.eu")).to_http_request();
+/// assert!(is_same_origin(&HeaderValue::from_static("https://api.dotshell.eu"), request.head()));
+/// assert!(!is_same_origin(&HeaderValue::from_static("https://evil.example"), request.head()));
+/// ```
+pub fn is_same_origin(origin: &HeaderValue, head: &RequestHead) -> bool {
+    // HTTP/2 requests give the host as the URI's authority
+    let host = head
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| head.uri.authority().map(|authority| authority.as_str()));
+    let Some(host) = host else {
+        return false;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .is_some_and(|origin_host| origin_host.eq_ignore_ascii_case(host))
+}
@@ -0,0 +1,146 @@
+//! Snapshot tests of the emails composed by the mailer: the MIME structure,
+//! headers and encoded parts of a message are compared with the reviewed
+//! copies in `tests/snapshots`. After an intended change, review and accept
+//! the new snapshots with `cargo insta review`.
+//!
+//! The date, Message-ID and MIME boundaries are random, so they are
+//! replaced by placeholders before comparing.
+
+use dothtml_backend::mailer::{FileAttachment, Mailer};
+use dothtml_backend::templates::{sample_data, RenderedEmail, TemplateRenderer};
+use lettre::message::dkim::{DkimSigningAlgorithm, DkimSigningKey};
+
+/// A 1x1 transparent PNG.
+const LOGO: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4, 0x89, 0x00, 0x00, 0x00,
+    0x0d, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x00, 0x01, 0x00, 0x00, 0x05, 0x00, 0x01, 0x0d, 0x0a, 0x2d,
+    0xb4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+const ACKNOWLEDGEMENT_HTML: &str = r#"<html><body>
+<img src="cid:logo" alt="dotshell">
+<h1>Hello {{message.name}},</h1>
+<p>We received your message about <b>{{message.company}}</b> and
+{{agent}} will answer it shortly.</p>
+<p>Meanwhile, our <a href="https://dotshell.eu/faq">FAQ</a> may help.</p>
+</body></html>"#;
+
+fn mailer() -> Mailer {
+    Mailer::new("dotshell <hello@dotshell.eu>".parse().unwrap())
+}
+
+fn render(html: &str, text: &str) -> RenderedEmail {
+    TemplateRenderer::new()
+        .render("Re: your message about {{message.company}}", html, text, &sample_data())
+        .unwrap()
+}
+
+fn compose(mailer: &Mailer, email: &RenderedEmail) -> String {
+    let message = mailer.compose("Jane Doe <jane@example.com>", email).unwrap();
+    String::from_utf8(message.formatted()).unwrap().replace("\r\n", "\n")
+}
+
+macro_rules! assert_email_snapshot {
+    ($name:expr, $email:expr) => {
+        insta::with_settings!({
+            filters => vec![
+                (r"(?m)^Date: .*$", "Date: [date]"),
+                (r"(?m)^Message-ID: .*$", "Message-ID: [message-id]"),
+                (r#"boundary="[0-9A-Za-z]{40}""#, r#"boundary="[boundary]""#),
+                (r"(?m)^--[0-9A-Za-z]{40}", "--[boundary]"),
+            ],
+        }, {
+            insta::assert_snapshot!($name, $email);
+        });
+    };
+}
+
+#[test]
+fn generates_the_text_part_from_html() {
+    let email = render("<p>Hello {{message.name}},</p><p>We will answer <em>shortly</em>.</p>", "");
+    assert_email_snapshot!("generated_text", compose(&mailer(), &email));
+}
+
+#[test]
+fn keeps_the_text_part_of_the_template() {
+    let email = render(
+        "<p>Hello {{message.name}},</p>",
+        "Hello {{message.name}},\n\nYour reference is {{message.id}}.",
+    );
+    assert_email_snapshot!("template_text", compose(&mailer(), &email));
+}
+
+#[test]
+fn appends_the_footer_to_both_parts() {
+    let mailer = mailer().with_footer("dotshell - 1 rue de la Paix, 75002 Paris\nYou wrote to us through dotshell.eu");
+    let email = render(ACKNOWLEDGEMENT_HTML, "");
+    assert_email_snapshot!("footer", compose(&mailer, &email));
+}
+
+#[test]
+fn embeds_the_logo_shown_by_the_html() {
+    let mailer = mailer().with_logo("image/png", LOGO.to_vec()).unwrap();
+    let email = render(ACKNOWLEDGEMENT_HTML, "");
+    assert_email_snapshot!("inline_logo", compose(&mailer, &email));
+}
+
+#[test]
+fn leaves_out_the_logo_not_shown_by_the_html() {
+    let mailer = mailer().with_logo("image/png", LOGO.to_vec()).unwrap();
+    let email = render("<p>Hello {{message.name}},</p>", "");
+    let raw = compose(&mailer, &email);
+    assert!(!raw.contains("multipart/related"));
+    assert!(!raw.contains("image/png"));
+}
+
+#[test]
+fn signs_with_dkim_for_the_sender_domain() {
+    // A fixed Ed25519 seed, only used by this test
+    let key = DkimSigningKey::new("AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA=", DkimSigningAlgorithm::Ed25519).unwrap();
+    let mailer = mailer().with_dkim("mail2025", key);
+    let raw = compose(&mailer, &render("<p>Hello {{message.name}},</p>", ""));
+    let signature = raw.split("\n\n").next().unwrap().split("DKIM-Signature:").nth(1).expect("DKIM-Signature header");
+    assert!(signature.contains("a=ed25519-sha256"));
+    assert!(signature.contains("d=dotshell.eu"));
+    assert!(signature.contains("s=mail2025"));
+    assert!(signature.contains("c=relaxed/relaxed"));
+}
+
+#[test]
+fn rejects_invalid_recipients() {
+    let email = render("<p>Hello</p>", "");
+    assert!(mailer().compose("not an address", &email).is_err());
+}
+
+#[test]
+fn keeps_the_parts_for_api_transports() {
+    let mailer = mailer().with_logo("image/png", LOGO.to_vec()).unwrap();
+    let email = mailer.prepare("Jane Doe <jane@example.com>", &render(ACKNOWLEDGEMENT_HTML, "")).unwrap();
+    assert_eq!(email.to.email.to_string(), "jane@example.com");
+    assert!(email.html.contains("cid:logo"));
+    assert!(email.text.contains("Hello Jane"));
+    assert_eq!(email.inline.len(), 1);
+    assert_eq!(email.inline[0].content_id, "logo");
+    assert_eq!(email.inline[0].content_type, "image/png");
+}
+
+#[test]
+fn attaches_files_after_the_body() {
+    let report = FileAttachment {
+        filename: "weekly-stats-2026-10-19.csv".to_string(),
+        content_type: "text/csv; charset=utf-8".to_string(),
+        data: b"date,received,resolved\n2026-10-18,4,3\n".to_vec(),
+    };
+    let email = mailer()
+        .prepare_with_attachments("Jane Doe <jane@example.com>", &render("<p>Hello {{message.name}},</p>", ""), vec![report])
+        .unwrap();
+    assert_eq!(email.attachments.len(), 1);
+
+    let raw = String::from_utf8(email.message.formatted()).unwrap().replace("\r\n", "\n");
+    let mixed = raw.find("multipart/mixed").expect("multipart/mixed part");
+    let alternative = raw.find("multipart/alternative").expect("multipart/alternative part");
+    let attachment = raw.find("Content-Disposition: attachment; filename=\"weekly-stats-2026-10-19.csv\"").expect("attachment");
+    assert!(mixed < alternative && alternative < attachment);
+    assert!(raw.contains("date,received,resolved"));
+}
@@ -0,0 +1,249 @@
+//! Property-based tests for the input handling that sits in front of the
+//! database: contact form validation, inbox filter expressions, page
+//! cursors, JSON body limits and session tokens. None of them may panic on arbitrary input, filter
+//! values must only ever reach PostgreSQL as bind parameters, and only tokens signed by a known key
+//! may verify. The `fuzz/` crate runs the token and signature parsers
+//! under cargo-fuzz as well.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use dothtml_backend::jwt::{self, JwtKey};
+use dothtml_backend::handlers::ContactForm;
+use dothtml_backend::json_limits::{check_json, MAX_DEPTH};
+use dothtml_backend::models::{normalize_company_name, PageCursor};
+use dothtml_backend::query::FilterExpr;
+use ed25519_dalek::SigningKey;
+use proptest::prelude::*;
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Filter names accepted by `FilterExpr::parse`, except the date ones.
+const TEXT_KEYS: [&str; 6] = ["status", "tag", "country", "company", "email", "assigned"];
+
+/// Compiles an expression to the SQL sent to PostgreSQL.
+fn to_sql(filter: &FilterExpr) -> String {
+    let mut builder = QueryBuilder::<Postgres>::new("SELECT id FROM messages WHERE TRUE");
+    filter.push_conditions(&mut builder);
+    builder.sql().to_string()
+}
+
+/// Checks that the placeholders of `sql` are `$1`, `$2`, ... in order.
+fn placeholders_are_sequential(sql: &str) -> bool {
+    let mut expected = 1;
+    let mut rest = sql;
+    while let Some(index) = rest.find('$') {
+        let digits: String = rest[index + 1..].chars().take_while(char::is_ascii_digit).collect();
+        if digits.parse() != Ok(expected) {
+            return false;
+        }
+        expected += 1;
+        rest = &rest[index + 1 + digits.len()..];
+    }
+    true
+}
+
+/// The key verifying the session tokens of the tests.
+fn session_key() -> JwtKey {
+    JwtKey { kid: "k1".to_string(), signing_key: SigningKey::from_bytes(&[7; 32]), created_at: Utc::now(), retired_at: None }
+}
+
+/// A filter value, including quotes, SQL metacharacters and non-ASCII text.
+fn value() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z0-9@._-]{1,20}",
+        "[^\\s\"]{1,20}",
+        Just("'; DROP TABLE messages; --".to_string()),
+        Just("$1) OR (TRUE".to_string()),
+        Just("\\x00\\\\".to_string()),
+    ]
+}
+
+/// A term of a filter expression, with the same value for every rendering.
+#[derive(Debug, Clone)]
+enum TermSpec {
+    Keyed { negated: bool, key: &'static str, value: String },
+    Date { negated: bool, before: bool, day: u32 },
+    Text { negated: bool, value: String },
+}
+
+impl TermSpec {
+    /// Renders the term, replacing its value with `placeholder` if given.
+    fn render(&self, placeholder: Option<&str>) -> String {
+        let sign = |negated: bool| if negated { "-" } else { "" };
+        match self {
+            TermSpec::Keyed { negated, key, value } => {
+                format!("{}{}:\"{}\"", sign(*negated), key, placeholder.unwrap_or(value))
+            }
+            TermSpec::Date { negated, before, day } => {
+                let key = if *before { "before" } else { "after" };
+                format!("{}{}:2024-01-{:02}", sign(*negated), key, day)
+            }
+            TermSpec::Text { negated, value } => {
+                format!("{}\"{}\"", sign(*negated), placeholder.unwrap_or(value))
+            }
+        }
+    }
+}
+
+fn term() -> impl Strategy<Value = TermSpec> {
+    // `assigned:none` and `status:` change the SQL shape or the bound value, so
+    // keep keyed values away from the special `none` keyword
+    let keyed = (any::<bool>(), prop::sample::select(TEXT_KEYS.to_vec()), value())
+        .prop_filter("`none` is a keyword", |(_, _, value)| !value.eq_ignore_ascii_case("none"))
+        .prop_map(|(negated, key, value)| TermSpec::Keyed { negated, key, value });
+    let date = (any::<bool>(), any::<bool>(), 1u32..=28)
+        .prop_map(|(negated, before, day)| TermSpec::Date { negated, before, day });
+    let text = (any::<bool>(), value()).prop_map(|(negated, value)| TermSpec::Text { negated, value });
+    prop_oneof![keyed, date, text]
+}
+
+fn render(terms: &[TermSpec], placeholder: Option<&str>) -> String {
+    terms.iter().map(|term| term.render(placeholder)).collect::<Vec<_>>().join(" ")
+}
+
+proptest! {
+    #[test]
+    fn parsing_arbitrary_input_never_panics(input in any::<String>()) {
+        if let Ok(filter) = FilterExpr::parse(&input) {
+            let sql = to_sql(&filter);
+            prop_assert!(placeholders_are_sequential(&sql));
+        }
+    }
+
+    #[test]
+    fn parse_errors_point_inside_the_input(input in "[a-z:\" -]{0,40}") {
+        if let Err(error) = FilterExpr::parse(&input) {
+            prop_assert!(error.column >= 1);
+            prop_assert!(error.column <= input.chars().count().max(1));
+        }
+    }
+
+    #[test]
+    fn filter_values_only_reach_sql_as_parameters(terms in prop::collection::vec(term(), 1..6)) {
+        let filter = FilterExpr::parse(&render(&terms, None)).unwrap();
+        prop_assert_eq!(filter.terms().len(), terms.len());
+
+        // The SQL must not depend on the values, only on the shape of the expression
+        let neutral = FilterExpr::parse(&render(&terms, Some("x"))).unwrap();
+        let sql = to_sql(&filter);
+        prop_assert_eq!(&sql, &to_sql(&neutral));
+        prop_assert!(placeholders_are_sequential(&sql));
+    }
+
+    #[test]
+    fn company_normalization_never_panics(name in any::<String>()) {
+        let normalized = normalize_company_name(&name);
+        prop_assert_eq!(normalize_company_name(&normalized), normalized);
+    }
+
+    #[test]
+    fn page_cursors_round_trip(
+        rank in proptest::option::of(any::<i32>()),
+        micros in 0i64..4_102_444_800_000_000,
+        id in any::<u128>(),
+        backward in any::<bool>(),
+    ) {
+        let cursor = PageCursor {
+            rank,
+            created_at: DateTime::<Utc>::from_timestamp_micros(micros).unwrap(),
+            id: Uuid::from_u128(id),
+            backward,
+        };
+        prop_assert_eq!(PageCursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn decoding_arbitrary_cursors_never_panics(value in any::<String>()) {
+        let _ = PageCursor::decode(&value);
+    }
+
+    #[test]
+    fn contact_validation_never_panics(
+        name in any::<String>(),
+        email in any::<String>(),
+        country_region in any::<String>(),
+        phone_number in any::<String>(),
+        company in any::<String>(),
+        message in any::<String>(),
+        form in proptest::option::of(any::<String>()),
+    ) {
+        let form = ContactForm { name, email, country_region, phone_number, company, message, form };
+        let _ = form.validate();
+    }
+
+    #[test]
+    fn contact_name_and_message_lengths_are_enforced(
+        name in "\\PC{0,120}",
+        message in "\\PC{0,2100}",
+    ) {
+        let name_ok = (1..=100).contains(&name.chars().count());
+        let message_ok = (1..=2000).contains(&message.chars().count());
+        let form = ContactForm {
+            name,
+            email: "john@example.com".to_string(),
+            country_region: String::new(),
+            phone_number: String::new(),
+            company: String::new(),
+            message,
+            form: None,
+        };
+        prop_assert_eq!(form.validate().is_ok(), name_ok && message_ok);
+    }
+
+    #[test]
+    fn checking_arbitrary_bodies_never_panics(body in any::<Vec<u8>>()) {
+        let _ = check_json(&body);
+    }
+
+    #[test]
+    fn string_contents_do_not_count_as_nesting(
+        values in prop::collection::vec(any::<String>(), 0..5),
+        depth in 1..=MAX_DEPTH,
+    ) {
+        let mut body = serde_json::json!(values);
+        for _ in 1..depth {
+            body = serde_json::json!({ "[{\\\"": body });
+        }
+        prop_assert_eq!(check_json(&serde_json::to_vec(&body).unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn verifying_arbitrary_tokens_never_panics(token in any::<String>()) {
+        prop_assert!(jwt::verify(&[session_key()], &token).is_err());
+    }
+
+    #[test]
+    fn unsigned_tokens_never_verify(
+        claims in any::<Vec<u8>>(),
+        signature in any::<Vec<u8>>(),
+        alg in prop_oneof![Just("EdDSA".to_string()), Just("none".to_string()), "[A-Za-z0-9]{0,8}"],
+    ) {
+        // A well-formed header naming the known key, so that the signature is checked
+        let header = serde_json::json!({ "alg": alg, "typ": "JWT", "kid": "k1" });
+        let token = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).unwrap()),
+            URL_SAFE_NO_PAD.encode(&claims),
+            URL_SAFE_NO_PAD.encode(&signature),
+        );
+        prop_assert!(jwt::verify(&[session_key()], &token).is_err());
+    }
+
+    #[test]
+    fn tampered_tokens_never_verify(
+        account in "[a-z]{1,12}",
+        index in any::<prop::sample::Index>(),
+        replacement in "[A-Za-z0-9_.-]",
+    ) {
+        let key = session_key();
+        let token = jwt::sign(&key, &jwt::session_claims(&account, Utc::now() + Duration::hours(1)));
+        let index = index.index(token.len());
+        let mut tampered = token.clone();
+        tampered.replace_range(index..=index, &replacement);
+        if tampered != token {
+            prop_assert!(jwt::verify(std::slice::from_ref(&key), &tampered).is_err());
+        }
+    }
+}